@@ -0,0 +1,42 @@
+//! Synthetic key traces shared by the benches.
+#![allow(dead_code)]
+
+use cachez::workload::{Keys, Workload};
+
+/// Zipf distributed keys over `0..items` with exponent `s`
+pub fn zipf(items: u64, s: f64, len: usize, seed: u64) -> Vec<u64> {
+    Workload {
+        keys: Keys::Zipf(s),
+        seed,
+        ..Workload::new(items)
+    }
+    .keys(len)
+}
+
+/// Uniformly distributed keys over `0..items`
+pub fn uniform(items: u64, len: usize, seed: u64) -> Vec<u64> {
+    Workload {
+        seed,
+        ..Workload::new(items)
+    }
+    .keys(len)
+}
+
+/// Sequential keys cycling over `0..items`, the classic LRU killer
+pub fn scan(items: u64, len: usize) -> Vec<u64> {
+    Workload {
+        keys: Keys::Sequential,
+        ..Workload::new(items)
+    }
+    .keys(len)
+}
+
+/// Named traces used by the workload benches
+pub fn workloads(items: u64, len: usize) -> Vec<(&'static str, Vec<u64>)> {
+    vec![
+        ("zipf_0.9", zipf(items, 0.9, len, 42)),
+        ("zipf_1.1", zipf(items, 1.1, len, 42)),
+        ("uniform", uniform(items, len, 42)),
+        ("scan", scan(items, len)),
+    ]
+}
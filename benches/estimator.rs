@@ -0,0 +1,54 @@
+mod common;
+
+use cachez::tinyufo::{Estimator, Key, TinyLFU};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const ITEMS: u64 = 100_000;
+const TRACE_LEN: usize = 100_000;
+
+fn estimator(c: &mut Criterion) {
+    let trace = common::zipf(ITEMS, 0.9, TRACE_LEN, 42);
+    let mut group = c.benchmark_group("estimator");
+    group.throughput(Throughput::Elements(TRACE_LEN as u64));
+
+    for size in [1_000, 10_000, 100_000] {
+        let mut estimator = Estimator::new_optimal(size);
+        group.bench_function(format!("incr/{size}"), |b| {
+            b.iter(|| {
+                for key in &trace {
+                    black_box(estimator.incr(key));
+                }
+            })
+        });
+        group.bench_function(format!("get/{size}"), |b| {
+            b.iter(|| {
+                for key in &trace {
+                    black_box(estimator.get(key));
+                }
+            })
+        });
+        group.bench_function(format!("age/{size}"), |b| b.iter(|| estimator.age(1)));
+    }
+    group.finish();
+}
+
+fn tinylfu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tinylfu");
+    group.throughput(Throughput::Elements(TRACE_LEN as u64));
+
+    for (name, trace) in common::workloads(ITEMS, TRACE_LEN) {
+        // small cache so the aging window rolls over during the trace
+        let mut lfu = TinyLFU::new(1_000);
+        group.bench_function(format!("incr/{name}"), |b| {
+            b.iter(|| {
+                for &key in &trace {
+                    black_box(lfu.incr(key as Key));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, estimator, tinylfu);
+criterion_main!(benches);
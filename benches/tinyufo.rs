@@ -0,0 +1,173 @@
+mod common;
+
+use cachez::tinyufo::{LruTinyUFO, TinyUFO};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const CACHE_SIZE: usize = 10_000;
+const ITEMS: u64 = 100_000;
+const TRACE_LEN: usize = 100_000;
+
+/// Minimal surface the benches need, so baseline policies can be plugged in next to TinyUFO.
+trait BenchCache {
+    fn name() -> &'static str;
+    fn with_size(size: usize) -> Self;
+    fn get(&mut self, key: &u64) -> bool;
+    fn put(&mut self, key: u64, value: u64);
+}
+
+impl BenchCache for TinyUFO<u64, u64> {
+    fn name() -> &'static str {
+        "tinyufo"
+    }
+
+    fn with_size(size: usize) -> Self {
+        TinyUFO::new(size, size)
+    }
+
+    fn get(&mut self, key: &u64) -> bool {
+        TinyUFO::get(self, key).is_some()
+    }
+
+    fn put(&mut self, key: u64, value: u64) {
+        TinyUFO::put(self, key, 1, value)
+    }
+}
+
+impl BenchCache for LruTinyUFO<u64, u64> {
+    fn name() -> &'static str {
+        "lru"
+    }
+
+    fn with_size(size: usize) -> Self {
+        LruTinyUFO::new(size, size)
+    }
+
+    fn get(&mut self, key: &u64) -> bool {
+        LruTinyUFO::get(self, key).is_some()
+    }
+
+    fn put(&mut self, key: u64, value: u64) {
+        LruTinyUFO::put(self, key, 1, value)
+    }
+}
+
+fn filled<C: BenchCache>() -> C {
+    let mut cache = C::with_size(CACHE_SIZE);
+    for key in 0..CACHE_SIZE as u64 {
+        cache.put(key, key);
+    }
+    cache
+}
+
+fn bench_get<C: BenchCache>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("{}/get", C::name()));
+    let mut cache = filled::<C>();
+    let hits = common::uniform(CACHE_SIZE as u64, TRACE_LEN, 7);
+    let misses: Vec<u64> = hits.iter().map(|k| k + ITEMS).collect();
+
+    group.throughput(Throughput::Elements(TRACE_LEN as u64));
+    group.bench_function("hit", |b| {
+        b.iter(|| {
+            for key in &hits {
+                black_box(cache.get(key));
+            }
+        })
+    });
+    group.bench_function("miss", |b| {
+        b.iter(|| {
+            for key in &misses {
+                black_box(cache.get(key));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_put<C: BenchCache>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("{}/put", C::name()));
+    let existing = common::uniform(CACHE_SIZE as u64, TRACE_LEN, 7);
+    let fresh: Vec<u64> = (ITEMS..ITEMS + TRACE_LEN as u64).collect();
+
+    group.throughput(Throughput::Elements(TRACE_LEN as u64));
+    group.bench_function("hit", |b| {
+        b.iter_batched_ref(
+            filled::<C>,
+            |cache| {
+                for &key in &existing {
+                    cache.put(key, key);
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    // every put on a full cache has to evict, this is the churn path
+    group.bench_function("evict_churn", |b| {
+        b.iter_batched_ref(
+            filled::<C>,
+            |cache| {
+                for &key in &fresh {
+                    cache.put(key, key);
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// Read-through loop: get, and put on a miss. Reports the hit ratio once per trace.
+fn bench_workloads<C: BenchCache>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("{}/workload", C::name()));
+    group.throughput(Throughput::Elements(TRACE_LEN as u64));
+
+    for (name, trace) in common::workloads(ITEMS, TRACE_LEN) {
+        let mut cache = C::with_size(CACHE_SIZE);
+        let hits = trace
+            .iter()
+            .filter(|&&key| {
+                let hit = cache.get(&key);
+                if !hit {
+                    cache.put(key, key);
+                }
+                hit
+            })
+            .count();
+        println!(
+            "{}/{}: hit ratio {:.4}",
+            C::name(),
+            name,
+            hits as f64 / trace.len() as f64
+        );
+
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || C::with_size(CACHE_SIZE),
+                |cache| {
+                    for &key in &trace {
+                        if !cache.get(&key) {
+                            cache.put(key, key);
+                        }
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn tinyufo(c: &mut Criterion) {
+    bench_get::<TinyUFO<u64, u64>>(c);
+    bench_put::<TinyUFO<u64, u64>>(c);
+    bench_workloads::<TinyUFO<u64, u64>>(c);
+}
+
+/// Strict LRU over the same map and accounting, the baseline the hit ratios compare against
+fn lru(c: &mut Criterion) {
+    bench_get::<LruTinyUFO<u64, u64>>(c);
+    bench_put::<LruTinyUFO<u64, u64>>(c);
+    bench_workloads::<LruTinyUFO<u64, u64>>(c);
+}
+
+criterion_group!(benches, tinyufo, lru);
+criterion_main!(benches);
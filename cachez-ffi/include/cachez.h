@@ -0,0 +1,63 @@
+/* C ABI for the cachez TinyUFO cache. Build with `cargo build -p cachez-ffi --release`,
+ * link against libcachez_ffi.so / libcachez_ffi.a. */
+#ifndef CACHEZ_H
+#define CACHEZ_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque, internally locked: a cache can be shared between threads. */
+typedef struct cachez_cache cachez_cache_t;
+
+typedef struct cachez_stats_s {
+    uint64_t hits;
+    uint64_t misses;
+    uint64_t inserts;
+    uint64_t updates;
+    uint64_t evictions;
+    uint64_t removals;
+    uint64_t entries;
+    uint64_t weight;
+} cachez_stats_t;
+
+/* Called once for every entry evicted to make room. The buffers are only valid during the
+ * call. Runs outside the cache lock, calling back into the cache is allowed. */
+typedef void (*cachez_evict_cb)(void *user_data,
+                                const uint8_t *key, size_t key_len,
+                                const uint8_t *value, size_t value_len);
+
+/* Create a cache holding at most `weight_limit` total weight, sized for about `capacity`
+ * entries. `on_evict` may be NULL. */
+cachez_cache_t *cachez_new(size_t weight_limit, size_t capacity,
+                         cachez_evict_cb on_evict, void *user_data);
+
+/* Destroy a cache, on_evict is not called for the remaining entries. NULL is a no-op. */
+void cachez_free(cachez_cache_t *cache);
+
+/* On a hit, returns the value length and copies the value to `out` if `out_len` is large
+ * enough; `out` may be NULL to only query the length. Returns -1 on a miss. */
+int64_t cachez_get(const cachez_cache_t *cache,
+                   const uint8_t *key, size_t key_len,
+                   uint8_t *out, size_t out_len);
+
+/* Insert or replace `key`. */
+void cachez_put(const cachez_cache_t *cache,
+                const uint8_t *key, size_t key_len,
+                const uint8_t *value, size_t value_len,
+                uint64_t weight);
+
+/* Returns true if `key` was cached. on_evict is not called. */
+bool cachez_remove(const cachez_cache_t *cache, const uint8_t *key, size_t key_len);
+
+void cachez_stats(const cachez_cache_t *cache, cachez_stats_t *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CACHEZ_H */
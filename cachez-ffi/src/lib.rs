@@ -0,0 +1,258 @@
+//! C ABI over [`TinyUFO`] with byte string keys and values, see `include/cachez.h`.
+//!
+//! A `cachez_cache_t` is internally locked, it can be shared between threads. The eviction
+//! callback runs after the lock is released, it may call back into the cache.
+use cachez::tinyufo::{TinyUFO, Weight};
+use std::ffi::c_void;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+/// Called once for every entry evicted to make room, with the `user_data` given at creation
+pub type EvictCallback = extern "C" fn(
+    user_data: *mut c_void,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+);
+
+/// Statistics, mirror of `cachez_stats_t` in the header
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub updates: u64,
+    pub evictions: u64,
+    pub removals: u64,
+    pub entries: u64,
+    pub weight: u64,
+}
+
+// the full key is kept next to the value: the C side wants it back on eviction, and it
+// guards lookups against 64 bit hash collisions
+type Value = (Box<[u8]>, Box<[u8]>);
+
+pub struct Cache {
+    inner: Mutex<TinyUFO<Box<[u8]>, Value>>,
+    on_evict: Option<EvictCallback>,
+    user_data: *mut c_void,
+}
+
+// user_data is only handed back to on_evict, the caller vouches for it being usable from any
+// thread it calls the cache from
+unsafe impl Send for Cache {}
+unsafe impl Sync for Cache {}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// Create a cache holding at most `weight_limit` total weight, sized for about `capacity`
+/// entries. `on_evict` may be NULL.
+#[no_mangle]
+pub extern "C" fn cachez_new(
+    weight_limit: usize,
+    capacity: usize,
+    on_evict: Option<EvictCallback>,
+    user_data: *mut c_void,
+) -> *mut Cache {
+    let cache = Cache {
+        inner: Mutex::new(TinyUFO::new(weight_limit, capacity)),
+        on_evict,
+        user_data,
+    };
+    Box::into_raw(Box::new(cache))
+}
+
+/// Destroy a cache, the eviction callback is not called for the remaining entries.
+///
+/// # Safety
+/// `cache` must come from `cachez_new` and not be used afterwards. NULL is a no-op.
+#[no_mangle]
+pub unsafe extern "C" fn cachez_free(cache: *mut Cache) {
+    if !cache.is_null() {
+        drop(Box::from_raw(cache));
+    }
+}
+
+/// Look up `key`. On a hit, returns the value length and copies the value to `out` if it is at
+/// least that long, `out` may be NULL to only query the length. Returns -1 on a miss.
+///
+/// # Safety
+/// `cache` must be valid, `key` valid for `key_len` bytes and `out` for `out_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cachez_get(
+    cache: *const Cache,
+    key: *const u8,
+    key_len: usize,
+    out: *mut u8,
+    out_len: usize,
+) -> i64 {
+    let cache = &*cache;
+    let key = bytes(key, key_len);
+    let mut inner = cache.inner.lock().unwrap();
+    match inner.get(key) {
+        Some((stored_key, value)) if **stored_key == *key => {
+            if !out.is_null() && out_len >= value.len() {
+                ptr::copy_nonoverlapping(value.as_ptr(), out, value.len());
+            }
+            value.len() as i64
+        }
+        _ => -1,
+    }
+}
+
+/// Insert or replace `key`. Entries evicted to make room are reported to the eviction callback
+/// before this returns.
+///
+/// # Safety
+/// `cache` must be valid, `key` and `value` valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn cachez_put(
+    cache: *const Cache,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+    weight: Weight,
+) {
+    let cache = &*cache;
+    let key: Box<[u8]> = bytes(key, key_len).into();
+    let value: Box<[u8]> = bytes(value, value_len).into();
+
+    let mut evicted = vec![];
+    {
+        let mut inner = cache.inner.lock().unwrap();
+        let put_key = key.clone();
+        let on_evict = |_, entry: (Box<[u8]>, Box<[u8]>)| {
+            // the caller's own value if the put was turned down, not an eviction
+            if cache.on_evict.is_some() && entry.0 != put_key {
+                evicted.push(entry)
+            }
+        };
+        inner.put_evicting(key.clone(), weight, (key, value), on_evict);
+    }
+
+    if let Some(on_evict) = cache.on_evict {
+        for (key, value) in evicted {
+            on_evict(
+                cache.user_data,
+                key.as_ptr(),
+                key.len(),
+                value.as_ptr(),
+                value.len(),
+            );
+        }
+    }
+}
+
+/// Remove `key`, returns true if it was cached. The eviction callback is not called.
+///
+/// # Safety
+/// `cache` must be valid, `key` valid for `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cachez_remove(
+    cache: *const Cache,
+    key: *const u8,
+    key_len: usize,
+) -> bool {
+    let cache = &*cache;
+    let key = bytes(key, key_len);
+    let mut inner = cache.inner.lock().unwrap();
+    // another key colliding on the hash must stay, as a get misses it
+    match inner.peek(key) {
+        Some((stored_key, _)) if **stored_key == *key => inner.remove(key).is_some(),
+        _ => false,
+    }
+}
+
+/// Fill `out` with the cache statistics
+///
+/// # Safety
+/// `cache` and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn cachez_stats(cache: *const Cache, out: *mut Stats) {
+    let cache = &*cache;
+    let stats = cache.inner.lock().unwrap().stats();
+    *out = Stats {
+        hits: stats.hits,
+        misses: stats.misses,
+        inserts: stats.inserts,
+        updates: stats.updates,
+        evictions: stats.evictions,
+        removals: stats.removals,
+        entries: stats.entries as u64,
+        weight: stats.weight as u64,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn count_evictions(
+        user_data: *mut c_void,
+        _key: *const u8,
+        _key_len: usize,
+        _value: *const u8,
+        value_len: usize,
+    ) {
+        let evicted = unsafe { &mut *(user_data as *mut Vec<usize>) };
+        evicted.push(value_len);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        unsafe {
+            let cache = cachez_new(100, 10, None, ptr::null_mut());
+            let key = b"key";
+            let value = b"some value";
+            cachez_put(cache, key.as_ptr(), 3, value.as_ptr(), value.len(), 1);
+
+            assert_eq!(cachez_get(cache, key.as_ptr(), 3, ptr::null_mut(), 0), 10);
+            let mut out = [0u8; 16];
+            assert_eq!(cachez_get(cache, key.as_ptr(), 3, out.as_mut_ptr(), 16), 10);
+            assert_eq!(&out[..10], value);
+            assert_eq!(
+                cachez_get(cache, b"nope".as_ptr(), 4, out.as_mut_ptr(), 16),
+                -1
+            );
+
+            assert!(cachez_remove(cache, key.as_ptr(), 3));
+            assert!(!cachez_remove(cache, key.as_ptr(), 3));
+
+            let mut stats = Stats::default();
+            cachez_stats(cache, &mut stats);
+            assert_eq!((stats.hits, stats.misses, stats.removals), (2, 1, 1));
+            assert_eq!(stats.entries, 0);
+            cachez_free(cache);
+        }
+    }
+
+    #[test]
+    fn test_eviction_callback() {
+        let mut evicted: Vec<usize> = vec![];
+        unsafe {
+            let user_data = &mut evicted as *mut Vec<usize> as *mut c_void;
+            let cache = cachez_new(4, 4, Some(count_evictions), user_data);
+            for i in 0u32..20 {
+                let key = i.to_le_bytes();
+                cachez_put(cache, key.as_ptr(), 4, key.as_ptr(), 4, 1);
+            }
+            let mut stats = Stats::default();
+            cachez_stats(cache, &mut stats);
+            assert_eq!(stats.evictions as usize, evicted.len());
+            assert!(stats.weight <= 4);
+            cachez_free(cache);
+        }
+        assert!(!evicted.is_empty());
+        assert!(evicted.iter().all(|&len| len == 4));
+    }
+}
@@ -0,0 +1,37 @@
+const test = require('node:test')
+const assert = require('node:assert')
+const { Cache } = require('../index.js')
+
+test('get, set and delete', () => {
+  const cache = new Cache({ maxWeight: 1024 })
+  assert.strictEqual(cache.get('missing'), null)
+  cache.set('key', Buffer.from('value'))
+  assert.deepStrictEqual(cache.get('key'), Buffer.from('value'))
+  assert.strictEqual(cache.delete('key'), true)
+  assert.strictEqual(cache.get('key'), null)
+
+  const stats = cache.stats()
+  assert.strictEqual(stats.hits, 1)
+  assert.strictEqual(stats.misses, 2)
+  assert.strictEqual(stats.entries, 0)
+})
+
+test('ttl', async () => {
+  const cache = new Cache({ maxWeight: 1024, ttlMs: 10 })
+  cache.set('short', Buffer.from('a'))
+  cache.set('long', Buffer.from('b'), { ttlMs: 60_000 })
+  await new Promise((resolve) => setTimeout(resolve, 20))
+  assert.strictEqual(cache.get('short'), null)
+  assert.deepStrictEqual(cache.get('long'), Buffer.from('b'))
+  assert.strictEqual(cache.stats().expirations, 1)
+})
+
+test('max weight', () => {
+  const cache = new Cache({ maxWeight: 100 })
+  for (let i = 0; i < 100; i++) {
+    cache.set(`key${i}`, Buffer.alloc(10))
+  }
+  const stats = cache.stats()
+  assert.ok(stats.weight <= 100)
+  assert.ok(stats.evictions > 0)
+})
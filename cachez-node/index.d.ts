@@ -0,0 +1,35 @@
+export interface CacheOptions {
+  /** Upper bound of the total weight of the cached entries */
+  maxWeight: number
+  /** Expected number of entries, used to size the cache. Defaults to `maxWeight` */
+  capacity?: number
+  /** TTL applied to entries set without one, in milliseconds. Entries never expire by default */
+  ttlMs?: number
+}
+export interface SetOptions {
+  /** Defaults to the value's byte length */
+  weight?: number
+  /** Overrides the cache's default TTL, in milliseconds */
+  ttlMs?: number
+}
+export interface CacheStats {
+  hits: number
+  misses: number
+  inserts: number
+  updates: number
+  evictions: number
+  removals: number
+  /** Entries dropped because their TTL passed */
+  expirations: number
+  entries: number
+  weight: number
+}
+export class Cache {
+  constructor(options: CacheOptions)
+  /** Returns the cached value, or null on a miss */
+  get(key: string): Buffer | null
+  set(key: string, value: Buffer, options?: SetOptions): void
+  /** Returns true if the key was cached */
+  delete(key: string): boolean
+  stats(): CacheStats
+}
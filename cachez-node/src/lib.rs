@@ -0,0 +1,103 @@
+//! Node.js bindings for [`ConcurrentTinyUFO`], built with napi-rs.
+//!
+//! Keys are strings and values are Buffers, callers serialize anything richer themselves.
+//! An expired entry is a miss from its TTL on, puts take the expired entries out.
+use cachez::tinyufo::{ConcurrentTinyUFO, Weight};
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+use std::time::Duration;
+
+#[napi(object)]
+pub struct CacheOptions {
+    /// Upper bound of the total weight of the cached entries
+    pub max_weight: u32,
+    /// Expected number of entries, used to size the cache. Defaults to `maxWeight`
+    pub capacity: Option<u32>,
+    /// TTL applied to entries set without one, in milliseconds. Entries never expire by default
+    pub ttl_ms: Option<u32>,
+}
+
+#[napi(object)]
+pub struct SetOptions {
+    /// Defaults to the value's byte length
+    pub weight: Option<u32>,
+    /// Overrides the cache's default TTL, in milliseconds
+    pub ttl_ms: Option<u32>,
+}
+
+#[napi(object)]
+pub struct CacheStats {
+    pub hits: i64,
+    pub misses: i64,
+    pub inserts: i64,
+    pub updates: i64,
+    pub evictions: i64,
+    pub removals: i64,
+    /// Entries dropped because their TTL passed
+    pub expirations: i64,
+    pub entries: i64,
+    pub weight: i64,
+}
+
+#[napi]
+pub struct Cache {
+    inner: ConcurrentTinyUFO<String, Vec<u8>>,
+    default_ttl: Option<Duration>,
+}
+
+#[napi]
+impl Cache {
+    #[napi(constructor)]
+    pub fn new(options: CacheOptions) -> Self {
+        let capacity = options.capacity.unwrap_or(options.max_weight);
+        Self {
+            inner: ConcurrentTinyUFO::new(options.max_weight as usize, capacity as usize),
+            default_ttl: options.ttl_ms.map(|ms| Duration::from_millis(ms as u64)),
+        }
+    }
+
+    /// Returns the cached value, or null on a miss
+    #[napi]
+    pub fn get(&self, key: String) -> Option<Buffer> {
+        self.inner.get(&key).map(Buffer::from)
+    }
+
+    #[napi]
+    pub fn set(&self, key: String, value: Buffer, options: Option<SetOptions>) {
+        let (weight, ttl) = match options {
+            Some(options) => (
+                options.weight,
+                options.ttl_ms.map(|ms| Duration::from_millis(ms as u64)),
+            ),
+            None => (None, None),
+        };
+        let data = value.to_vec();
+        let weight = weight.map_or(data.len() as Weight, Weight::from);
+        match ttl.or(self.default_ttl) {
+            Some(ttl) => self.inner.put_with_ttl(key, weight, data, ttl),
+            None => self.inner.put(key, weight, data),
+        }
+    }
+
+    /// Returns true if the key was cached
+    #[napi]
+    pub fn delete(&self, key: String) -> bool {
+        self.inner.remove(&key).is_some()
+    }
+
+    #[napi]
+    pub fn stats(&self) -> CacheStats {
+        let stats = self.inner.stats();
+        CacheStats {
+            hits: stats.hits as i64,
+            misses: stats.misses as i64,
+            inserts: stats.inserts as i64,
+            updates: stats.updates as i64,
+            evictions: stats.evictions as i64,
+            removals: stats.removals as i64,
+            expirations: stats.expirations as i64,
+            entries: stats.entries as i64,
+            weight: stats.weight as i64,
+        }
+    }
+}
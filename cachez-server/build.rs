@@ -0,0 +1,9 @@
+// protox compiles the protos in Rust, no protoc needed on the build machine
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let descriptors = protox::compile(["cachez/v1/cache.proto"], ["proto"])?;
+    tonic_build::configure()
+        .bytes(["."])
+        .compile_fds(descriptors)?;
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
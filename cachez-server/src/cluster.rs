@@ -0,0 +1,336 @@
+//! Client side of a cachez cluster: keys spread over nodes by consistent hashing.
+
+use crate::node::fingerprint;
+use crate::proto::cache_client::CacheClient;
+use crate::proto::{DeleteRequest, GetRequest, SetRequest};
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Status;
+
+/// Virtual nodes per node by default, enough for keys to spread within a few percent
+pub const DEFAULT_VIRTUAL_NODES: usize = 160;
+
+/// Consistent hash ring: adding or removing a node only moves the keys it gains or loses.
+///
+/// Every node is placed at `virtual_nodes` points of the ring, hashed from its `Display` form,
+/// and a key belongs to the nodes of the first points at or after its [`fingerprint`].
+#[derive(Debug, Clone)]
+pub struct HashRing<N> {
+    points: BTreeMap<u64, N>,
+    nodes: Vec<N>,
+    virtual_nodes: usize,
+    replicas: usize,
+}
+
+impl<N: Clone + Eq + Display> HashRing<N> {
+    /// Keys are owned by `replicas` distinct nodes, fewer if the ring has less
+    pub fn new(virtual_nodes: usize, replicas: usize) -> Self {
+        Self {
+            points: BTreeMap::new(),
+            nodes: Vec::new(),
+            virtual_nodes: virtual_nodes.max(1),
+            replicas: replicas.max(1),
+        }
+    }
+
+    /// Returns false if the node was on the ring already
+    pub fn add(&mut self, node: N) -> bool {
+        if self.nodes.contains(&node) {
+            return false;
+        }
+        for point in self.node_points(&node) {
+            // on the rare collision the first node keeps the point, like every client does
+            self.points.entry(point).or_insert_with(|| node.clone());
+        }
+        self.nodes.push(node);
+        true
+    }
+
+    /// Returns false if the node wasn't on the ring
+    pub fn remove(&mut self, node: &N) -> bool {
+        let Some(index) = self.nodes.iter().position(|n| n == node) else {
+            return false;
+        };
+        self.nodes.remove(index);
+        for point in self.node_points(node) {
+            if self.points.get(&point) == Some(node) {
+                self.points.remove(&point);
+            }
+        }
+        // points this node won from a colliding one go back to it
+        for other in self.nodes.clone() {
+            for point in self.node_points(&other) {
+                self.points.entry(point).or_insert_with(|| other.clone());
+            }
+        }
+        true
+    }
+
+    /// The nodes owning `key`, primary first
+    pub fn nodes_for(&self, key: &[u8]) -> Vec<&N> {
+        let hash = fingerprint(key);
+        let replicas = self.replicas.min(self.nodes.len());
+        let mut owners: Vec<&N> = Vec::with_capacity(replicas);
+        let clockwise = self.points.range(hash..).chain(self.points.range(..hash));
+        for (_, node) in clockwise {
+            if owners.len() == replicas {
+                break;
+            }
+            if !owners.contains(&node) {
+                owners.push(node);
+            }
+        }
+        owners
+    }
+
+    /// The node owning `key` first, `None` on an empty ring
+    pub fn primary(&self, key: &[u8]) -> Option<&N> {
+        self.nodes_for(key).into_iter().next()
+    }
+
+    pub fn nodes(&self) -> &[N] {
+        &self.nodes
+    }
+
+    pub fn replicas(&self) -> usize {
+        self.replicas
+    }
+
+    fn node_points(&self, node: &N) -> Vec<u64> {
+        (0..self.virtual_nodes)
+            .map(|i| fingerprint(format!("{node}#{i}").as_bytes()))
+            .collect()
+    }
+}
+
+/// gRPC client of a cachez cluster, routing every key to its nodes on a [`HashRing`].
+///
+/// Writes go to every replica of the key, reads to the first replica that answers.
+pub struct ClusterClient {
+    ring: HashRing<String>,
+    clients: HashMap<String, CacheClient<Channel>>,
+}
+
+impl ClusterClient {
+    pub fn new(virtual_nodes: usize, replicas: usize) -> Self {
+        Self {
+            ring: HashRing::new(virtual_nodes, replicas),
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Add the node serving gRPC at `uri`, like `http://10.0.0.1:50051`. It is connected to on
+    /// first use
+    pub fn add_node(&mut self, uri: impl Into<String>) -> Result<(), tonic::transport::Error> {
+        let uri = uri.into();
+        let channel = Endpoint::from_shared(uri.clone())?.connect_lazy();
+        self.clients.insert(uri.clone(), CacheClient::new(channel));
+        self.ring.add(uri);
+        Ok(())
+    }
+
+    /// Stop using the node at `uri`, its keys move to the next nodes of the ring
+    pub fn remove_node(&mut self, uri: &str) -> bool {
+        self.clients.remove(uri);
+        self.ring.remove(&uri.to_owned())
+    }
+
+    pub fn ring(&self) -> &HashRing<String> {
+        &self.ring
+    }
+
+    pub async fn get(&self, key: impl Into<Bytes>) -> Result<Option<Bytes>, Status> {
+        let key = key.into();
+        let mut last_error = None;
+        for mut client in self.clients_for(&key) {
+            match client.get(GetRequest { key: key.clone() }).await {
+                Ok(response) => return Ok(response.into_inner().value),
+                // try the next replica
+                Err(status) => last_error = Some(status),
+            }
+        }
+        Err(last_error.unwrap_or_else(no_node))
+    }
+
+    /// Succeeds if at least one replica stored the value
+    pub async fn set(
+        &self,
+        key: impl Into<Bytes>,
+        value: impl Into<Bytes>,
+        ttl: Option<Duration>,
+    ) -> Result<(), Status> {
+        let request = SetRequest {
+            key: key.into(),
+            value: value.into(),
+            weight: None,
+            ttl_ms: ttl.map(|ttl| ttl.as_millis() as u64),
+        };
+        let key = request.key.clone();
+        let replies = self
+            .on_replicas(&key, |mut client| {
+                let request = request.clone();
+                async move { client.set(request).await.map(|_| ()) }
+            })
+            .await;
+        let mut last_error = None;
+        for reply in replies {
+            match reply {
+                Ok(()) => return Ok(()),
+                Err(status) => last_error = Some(status),
+            }
+        }
+        Err(last_error.unwrap_or_else(no_node))
+    }
+
+    /// Delete from every replica, returns whether any of them had the key
+    pub async fn delete(&self, key: impl Into<Bytes>) -> Result<bool, Status> {
+        let key = key.into();
+        let replies = self.on_replicas(&key, |mut client| {
+            let request = DeleteRequest { key: key.clone() };
+            async move { client.delete(request).await.map(|r| r.into_inner().deleted) }
+        });
+        let mut deleted = None;
+        let mut last_error = None;
+        for reply in replies.await {
+            match reply {
+                Ok(reply) => deleted = Some(deleted.unwrap_or(false) | reply),
+                Err(status) => last_error = Some(status),
+            }
+        }
+        deleted.ok_or_else(|| last_error.unwrap_or_else(no_node))
+    }
+
+    fn clients_for(&self, key: &[u8]) -> Vec<CacheClient<Channel>> {
+        self.ring
+            .nodes_for(key)
+            .into_iter()
+            .map(|uri| self.clients[uri].clone())
+            .collect()
+    }
+
+    /// Run `call` on every replica of `key` concurrently, erroring replicas included
+    async fn on_replicas<T, F>(
+        &self,
+        key: &[u8],
+        call: impl Fn(CacheClient<Channel>) -> F,
+    ) -> Vec<Result<T, Status>>
+    where
+        T: Send + 'static,
+        F: Future<Output = Result<T, Status>> + Send + 'static,
+    {
+        let calls: Vec<_> = self
+            .clients_for(key)
+            .into_iter()
+            .map(|client| tokio::spawn(call(client)))
+            .collect();
+        let mut replies = Vec::with_capacity(calls.len());
+        for call in calls {
+            let reply = call
+                .await
+                .unwrap_or_else(|error| Err(Status::internal(error.to_string())));
+            replies.push(reply);
+        }
+        replies
+    }
+}
+
+fn no_node() -> Status {
+    Status::unavailable("no cachez node in the cluster")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::CacheService;
+    use crate::node::Node;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+
+    fn owners(ring: &HashRing<String>, keys: usize) -> Vec<String> {
+        (0..keys)
+            .map(|i| ring.primary(format!("key{i}").as_bytes()).unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_ring_balance_and_movement() {
+        let mut ring = HashRing::new(DEFAULT_VIRTUAL_NODES, 1);
+        for i in 0..10 {
+            ring.add(format!("node{i}"));
+        }
+        let before = owners(&ring, 10_000);
+        for i in 0..10 {
+            let owned = before.iter().filter(|n| **n == format!("node{i}")).count();
+            assert!((600..1400).contains(&owned), "node{i} owns {owned}");
+        }
+
+        ring.add("node10".to_string());
+        let after = owners(&ring, 10_000);
+        let moved: Vec<_> = (0..10_000).filter(|&i| before[i] != after[i]).collect();
+        // only what the new node takes over, about a 11th
+        assert!(moved.iter().all(|&i| after[i] == "node10"));
+        assert!((500..1400).contains(&moved.len()), "{} moved", moved.len());
+
+        ring.remove(&"node10".to_string());
+        assert_eq!(owners(&ring, 10_000), before);
+    }
+
+    #[test]
+    fn test_ring_replicas() {
+        let mut ring = HashRing::new(16, 3);
+        assert!(ring.nodes_for(b"k").is_empty());
+        ring.add("a");
+        ring.add("b");
+        assert_eq!(ring.nodes_for(b"k").len(), 2);
+        ring.add("c");
+        ring.add("d");
+        let mut owners = ring.nodes_for(b"k");
+        assert_eq!(owners.len(), 3);
+        owners.sort();
+        owners.dedup();
+        assert_eq!(owners.len(), 3);
+        assert!(!ring.add("a"));
+    }
+
+    async fn serve(node: Arc<Node>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(CacheService::server(node))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_cluster_client() {
+        let nodes = [
+            Arc::new(Node::new(1024, 100)),
+            Arc::new(Node::new(1024, 100)),
+        ];
+        let mut client = ClusterClient::new(DEFAULT_VIRTUAL_NODES, 2);
+        for node in &nodes {
+            client.add_node(serve(node.clone()).await).unwrap();
+        }
+
+        client.set("a", "1", None).await.unwrap();
+        assert!(nodes.iter().all(|node| node.get(b"a").is_some()));
+        assert_eq!(client.get("a").await.unwrap(), Some(Bytes::from("1")));
+
+        // the primary goes away, the replica answers
+        let primary = client.ring().primary(b"a").unwrap().clone();
+        client.clients.insert(
+            primary,
+            CacheClient::new(Endpoint::from_static("http://127.0.0.1:1").connect_lazy()),
+        );
+        assert_eq!(client.get("a").await.unwrap(), Some(Bytes::from("1")));
+        assert!(client.delete("a").await.unwrap());
+    }
+}
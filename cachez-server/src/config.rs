@@ -0,0 +1,377 @@
+//! Settings of the `cachez-server` binary, from a TOML file with environment overrides.
+//!
+//! ```toml
+//! grpc = "0.0.0.0:50051"
+//! resp = "0.0.0.0:6379"
+//! password_file = "/etc/cachez/password"
+//!
+//! stats_interval_s = 60
+//!
+//! [cache]
+//! weight_limit_kib = 4194304
+//! capacity = 4000000
+//! default_ttl_ms = 3600000
+//!
+//! [tls]
+//! cert = "/etc/cachez/cert.pem"
+//! key = "/etc/cachez/key.pem"
+//!
+//! [gossip]
+//! listen = "0.0.0.0:7946"
+//! peers = ["10.0.0.2:7946", "10.0.0.3:7946"]
+//! ```
+//!
+//! Every setting can be overridden by a `CACHEZ_` environment variable named after its path,
+//! sections separated by a double underscore: `CACHEZ_GRPC`, `CACHEZ_CACHE__CAPACITY`. Values
+//! are read as TOML, `CACHEZ_GOSSIP__PEERS='["10.0.0.2:7946"]'`, or else as a string.
+//!
+//! The [`CacheSettings`] but `capacity`, and `stats_interval_s`, can be changed on a running
+//! node by reloading the configuration, see [`CacheSettings::apply`]. Other changes need a
+//! restart.
+
+use crate::node::Node;
+use cachez::tinyufo::DEFAULT_SMALL_QUEUE_PERCENT;
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Prefix of the overriding environment variables
+pub const ENV_PREFIX: &str = "CACHEZ_";
+/// Separator of the sections in their names
+const ENV_SEPARATOR: &str = "__";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address of the gRPC service
+    pub grpc: SocketAddr,
+    /// Address of the RESP frontend, if any
+    pub resp: Option<SocketAddr>,
+    /// Address of the memcached frontend, if any
+    pub memcache: Option<SocketAddr>,
+    /// Address of the HTTP API, if any
+    pub http: Option<SocketAddr>,
+    /// File holding the password clients must present
+    pub password_file: Option<PathBuf>,
+    /// gRPC URI of the primary to stand by for
+    pub replicate_from: Option<String>,
+    /// Print the node's statistics this often, in seconds
+    pub stats_interval_s: Option<u64>,
+    pub cache: CacheSettings,
+    pub tls: Option<TlsSettings>,
+    pub invalidation: InvalidationSettings,
+    pub gossip: GossipSettings,
+    pub proxy: ProxySettings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            grpc: ([127, 0, 0, 1], 50051).into(),
+            resp: None,
+            memcache: None,
+            http: None,
+            password_file: None,
+            replicate_from: None,
+            stats_interval_s: None,
+            cache: CacheSettings::default(),
+            tls: None,
+            invalidation: InvalidationSettings::default(),
+            gossip: GossipSettings::default(),
+            proxy: ProxySettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    /// Total size of the cached keys and values, in KiB
+    pub weight_limit_kib: usize,
+    /// Expected number of cached entries
+    pub capacity: usize,
+    /// TTL of the entries set without one, in milliseconds
+    pub default_ttl_ms: Option<u64>,
+    /// Share of the weight limit for new entries, in percent
+    pub small_queue_percent: u8,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            weight_limit_kib: 1024 * 1024,
+            capacity: 1_000_000,
+            default_ttl_ms: None,
+            small_queue_percent: DEFAULT_SMALL_QUEUE_PERCENT,
+        }
+    }
+}
+
+impl CacheSettings {
+    /// Set the tunable settings on `node`, without dropping what it caches. A smaller weight
+    /// limit evicts down to it
+    pub fn apply(&self, node: &Node) {
+        if node.weight_limit() != self.weight_limit_kib {
+            node.set_weight_limit(self.weight_limit_kib);
+        }
+        node.set_small_queue_percent(self.small_queue_percent);
+        node.set_default_ttl(self.default_ttl_ms.map(Duration::from_millis));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InvalidationSettings {
+    /// Redis server sharing the invalidations, `host:port`
+    pub redis: Option<String>,
+    /// Or a NATS server, `host:port`, with the `nats` feature
+    pub nats: Option<String>,
+    /// Or the brokers of a Kafka cluster, `host:port` each, with the `kafka` feature
+    pub kafka_brokers: Vec<String>,
+    /// The Redis channel, NATS subject or Kafka topic
+    pub channel: String,
+}
+
+impl Default for InvalidationSettings {
+    fn default() -> Self {
+        Self {
+            redis: None,
+            nats: None,
+            kafka_brokers: Vec::new(),
+            channel: "cachez:invalidations".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipSettings {
+    /// UDP address to gossip from, if any
+    pub listen: Option<SocketAddr>,
+    pub peers: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxySettings {
+    /// Run as a sharding proxy on this address instead of a node
+    pub listen: Option<SocketAddr>,
+    /// Nodes speaking RESP at `host:port` to spread keys over
+    pub backends: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => write!(f, "{}: {error}", path.display()),
+            ConfigError::Parse(error) => write!(f, "invalid configuration: {error}"),
+            ConfigError::Invalid(message) => write!(f, "invalid configuration: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// The file at `path`, defaults without one, overridden by the process' environment
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let text = match path {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|error| ConfigError::Read(path.to_owned(), error))?,
+            None => String::new(),
+        };
+        Self::parse(&text, std::env::vars())
+    }
+
+    /// The TOML `text` overridden by the `CACHEZ_` variables of `env`, to [`validate`] once
+    /// complete
+    ///
+    /// [`validate`]: Self::validate
+    pub fn parse(
+        text: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut table: toml::Table = text.parse().map_err(ConfigError::Parse)?;
+        for (name, value) in env {
+            if let Some(path) = name.strip_prefix(ENV_PREFIX) {
+                let path = path.to_ascii_lowercase();
+                let path: Vec<&str> = path.split(ENV_SEPARATOR).collect();
+                override_value(&mut table, &path, &value);
+            }
+        }
+        table.try_into().map_err(ConfigError::Parse)
+    }
+
+    pub fn stats_interval(&self) -> Option<Duration> {
+        self.stats_interval_s.map(Duration::from_secs)
+    }
+
+    /// Settings that can't go together, or out of range
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.password_file.is_some() && self.memcache.is_some() {
+            return Err(ConfigError::Invalid(
+                "the memcached protocol has no authentication, it can't be served with a password",
+            ));
+        }
+        if self.cache.small_queue_percent > 100 {
+            return Err(ConfigError::Invalid("small_queue_percent is over 100"));
+        }
+        if self.stats_interval_s == Some(0) {
+            return Err(ConfigError::Invalid("stats_interval_s is 0"));
+        }
+        if self.proxy.listen.is_some() && self.proxy.backends.is_empty() {
+            return Err(ConfigError::Invalid("the proxy needs backends"));
+        }
+        let invalidation = &self.invalidation;
+        let transports = [
+            invalidation.redis.is_some(),
+            invalidation.nats.is_some(),
+            !invalidation.kafka_brokers.is_empty(),
+        ];
+        if transports.iter().filter(|&&used| used).count() > 1 {
+            return Err(ConfigError::Invalid(
+                "invalidations go through one of redis, nats or kafka_brokers",
+            ));
+        }
+        if invalidation.nats.is_some() && !cfg!(feature = "nats") {
+            return Err(ConfigError::Invalid("built without the nats feature"));
+        }
+        if !invalidation.kafka_brokers.is_empty() && !cfg!(feature = "kafka") {
+            return Err(ConfigError::Invalid("built without the kafka feature"));
+        }
+        Ok(())
+    }
+}
+
+/// Set the value at `path` in `table`, creating the sections on the way
+fn override_value(table: &mut toml::Table, path: &[&str], value: &str) {
+    match path {
+        [] => {}
+        [key] => {
+            table.insert(key.to_string(), parse_value(value));
+        }
+        [key, rest @ ..] => {
+            let section = table
+                .entry(key.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            // a variable naming a section of a plain value replaces the value
+            if !section.is_table() {
+                *section = toml::Value::Table(toml::Table::new());
+            }
+            if let toml::Value::Table(section) = section {
+                override_value(section, rest, value);
+            }
+        }
+    }
+}
+
+/// `value` as TOML, or as a string if it isn't
+fn parse_value(value: &str) -> toml::Value {
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Config::parse("", []).unwrap(), Config::default());
+
+        let text = r#"
+            grpc = "0.0.0.0:50051"
+            resp = "0.0.0.0:6379"
+
+            [cache]
+            capacity = 1000
+
+            [gossip]
+            listen = "0.0.0.0:7946"
+            peers = ["10.0.0.2:7946"]
+        "#;
+        let config = Config::parse(text, []).unwrap();
+        assert_eq!(config.grpc, "0.0.0.0:50051".parse().unwrap());
+        assert_eq!(config.resp, Some("0.0.0.0:6379".parse().unwrap()));
+        assert_eq!(config.cache.capacity, 1000);
+        assert_eq!(config.cache.weight_limit_kib, 1024 * 1024);
+        assert_eq!(config.gossip.peers, ["10.0.0.2:7946".parse().unwrap()]);
+        assert_eq!(config.invalidation.channel, "cachez:invalidations");
+
+        assert!(matches!(
+            Config::parse("grcp = \"0.0.0.0:50051\"", []),
+            Err(ConfigError::Parse(_))
+        ));
+        let config = Config::parse("memcache = \"0.0.0.0:11211\"\npassword_file = \"pw\"", []);
+        assert!(matches!(
+            config.unwrap().validate(),
+            Err(ConfigError::Invalid(_))
+        ));
+        let text = "[invalidation]\nredis = \"a:6379\"\nnats = \"b:4222\"";
+        let config = Config::parse(text, []).unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let text = "grpc = \"0.0.0.0:50051\"\n[cache]\ncapacity = 1000\n";
+        let vars = env(&[
+            ("CACHEZ_GRPC", "127.0.0.1:1"),
+            ("CACHEZ_CACHE__CAPACITY", "5"),
+            ("CACHEZ_TLS__CERT", "/cert.pem"),
+            ("CACHEZ_TLS__KEY", "/key.pem"),
+            ("CACHEZ_PROXY__BACKENDS", r#"["a:6379", "b:6379"]"#),
+            ("HOME", "/root"),
+        ]);
+        let config = Config::parse(text, vars).unwrap();
+        assert_eq!(config.grpc, "127.0.0.1:1".parse().unwrap());
+        assert_eq!(config.cache.capacity, 5);
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.cert, Path::new("/cert.pem"));
+        assert_eq!(config.proxy.backends, ["a:6379", "b:6379"]);
+
+        let error = Config::parse("", env(&[("CACHEZ_CACHE__SIZE", "1")])).unwrap_err();
+        assert!(error.to_string().contains("size"), "{error}");
+    }
+
+    #[test]
+    fn test_apply() {
+        let node = Node::new(1024, 100);
+        let mut config =
+            Config::parse("[cache]\nweight_limit_kib = 512\ndefault_ttl_ms = 1000", []).unwrap();
+        config.cache.apply(&node);
+        assert_eq!(node.weight_limit(), 512);
+        assert_eq!(node.default_ttl(), Some(Duration::from_secs(1)));
+
+        config.cache.small_queue_percent = 101;
+        assert!(config.validate().is_err());
+    }
+}
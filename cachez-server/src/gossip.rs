@@ -0,0 +1,281 @@
+//! Broker-less invalidation: nodes gossip digests of their recent invalidations over UDP.
+//!
+//! Every invalidation is a [`Record`] of the key's [`fingerprint`] and an epoch, the wall
+//! clock milliseconds when it happened. Each round a node sends the records it learned within
+//! the retention window to a few random peers, which apply the new ones and pass them on in
+//! their own rounds, so an invalidation reaches every node in a few rounds.
+//!
+//! Deleting from a cache is always safe, a record arriving late only costs a miss. Lost
+//! datagrams are made up for by the next rounds. Records expire by their epoch, so that they
+//! can't circulate forever, which expects the nodes' clocks to agree within a fraction of the
+//! retention.
+
+use crate::node::{fingerprint, unix_ms, Invalidation, Node};
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{self, MissedTickBehavior};
+
+const MAGIC: &[u8; 4] = b"CZG1";
+const RECORD_LEN: usize = 16;
+/// Stays below common MTUs to avoid fragmentation
+const MAX_DATAGRAM: usize = 1400;
+const RECORDS_PER_DATAGRAM: usize = (MAX_DATAGRAM - MAGIC.len()) / RECORD_LEN;
+
+/// An invalidation as gossiped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Record {
+    pub fingerprint: u64,
+    /// Unix time of the invalidation in milliseconds, tells invalidations of a key apart
+    pub epoch: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// Nodes to gossip with
+    pub peers: Vec<SocketAddr>,
+    /// Time between two rounds
+    pub interval: Duration,
+    /// Peers sent to per round
+    pub fanout: usize,
+    /// How long after its epoch a record is passed on, bounds the time to converge
+    pub retention: Duration,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            interval: Duration::from_millis(200),
+            fanout: 3,
+            retention: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Gossips the invalidations of a [`Node`] with its peers
+pub struct Gossip {
+    node: Arc<Node>,
+    socket: UdpSocket,
+    config: GossipConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // records to pass on, in the order they were learned
+    recent: VecDeque<Record>,
+    // the records of `recent`, applied already
+    seen: HashSet<Record>,
+}
+
+impl Gossip {
+    pub async fn bind(addr: SocketAddr, node: Arc<Node>, config: GossipConfig) -> io::Result<Self> {
+        Ok(Self {
+            node,
+            socket: UdpSocket::bind(addr).await?,
+            config,
+            state: Mutex::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Record the node's invalidations, gossip and apply the peers' ones until the socket fails
+    pub async fn run(&self) -> io::Result<()> {
+        let mut watch = self.node.watch();
+        let mut rounds = time::interval(self.config.interval);
+        rounds.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut datagram = vec![0; MAX_DATAGRAM];
+        loop {
+            tokio::select! {
+                _ = rounds.tick() => self.round().await?,
+                received = self.socket.recv_from(&mut datagram) => {
+                    let (len, _) = received?;
+                    for record in decode(&datagram[..len]) {
+                        if self.learn(record) {
+                            self.node.delete_fingerprint_remote(record.fingerprint);
+                        }
+                    }
+                }
+                local = watch.recv() => match local {
+                    Ok(Invalidation { remote: false, key, .. }) => {
+                        self.learn(Record { fingerprint: fingerprint(&key), epoch: unix_ms() });
+                    }
+                    // applied for a peer, its record is passed on already
+                    Ok(_) => {}
+                    // invalidations too fast to follow, the peers may keep stale copies
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Remember `record`, returns false if it was known or is too old to matter
+    fn learn(&self, record: Record) -> bool {
+        if self.expired(&record, unix_ms()) {
+            return false;
+        }
+        let mut state = self.state();
+        if !state.seen.insert(record) {
+            return false;
+        }
+        state.recent.push_back(record);
+        true
+    }
+
+    fn expired(&self, record: &Record, now_ms: u64) -> bool {
+        now_ms.saturating_sub(record.epoch) > self.config.retention.as_millis() as u64
+    }
+
+    /// Send the records within retention to `fanout` random peers
+    async fn round(&self) -> io::Result<()> {
+        let datagrams = {
+            let mut state = self.state();
+            let now = unix_ms();
+            let State { recent, seen } = &mut *state;
+            recent.retain(|record| {
+                let expired = self.expired(record, now);
+                if expired {
+                    seen.remove(record);
+                }
+                !expired
+            });
+            let records: Vec<_> = recent.iter().copied().collect();
+            records
+                .chunks(RECORDS_PER_DATAGRAM)
+                .map(encode)
+                .collect::<Vec<_>>()
+        };
+        if datagrams.is_empty() {
+            return Ok(());
+        }
+        let mut peers = self.config.peers.clone();
+        fastrand::shuffle(&mut peers);
+        for peer in peers.iter().take(self.config.fanout) {
+            for datagram in &datagrams {
+                // an unreachable peer catches up from the others
+                if let Err(error) = self.socket.send_to(datagram, peer).await {
+                    if error.kind() != io::ErrorKind::ConnectionRefused {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn encode(records: &[Record]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(MAGIC.len() + records.len() * RECORD_LEN);
+    datagram.extend_from_slice(MAGIC);
+    for record in records {
+        datagram.extend_from_slice(&record.fingerprint.to_le_bytes());
+        datagram.extend_from_slice(&record.epoch.to_le_bytes());
+    }
+    datagram
+}
+
+/// The records of a datagram, none if it isn't one of ours
+fn decode(datagram: &[u8]) -> impl Iterator<Item = Record> + '_ {
+    let records = match datagram.strip_prefix(MAGIC) {
+        Some(records) if records.len() % RECORD_LEN == 0 => records,
+        _ => &[],
+    };
+    records.chunks_exact(RECORD_LEN).map(|record| Record {
+        fingerprint: u64::from_le_bytes(record[..8].try_into().unwrap()),
+        epoch: u64::from_le_bytes(record[8..].try_into().unwrap()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[tokio::test]
+    async fn test_learn() {
+        let node = Arc::new(Node::new(1024, 100));
+        let localhost = "127.0.0.1:0".parse().unwrap();
+        let gossip = Gossip::bind(localhost, node, GossipConfig::default());
+        let gossip = gossip.await.unwrap();
+        let record = Record {
+            fingerprint: 1,
+            epoch: unix_ms(),
+        };
+        assert!(gossip.learn(record));
+        assert!(!gossip.learn(record));
+        // already passed on long enough by others
+        let old = Record {
+            fingerprint: 2,
+            epoch: record.epoch - 60_000,
+        };
+        assert!(!gossip.learn(old));
+    }
+
+    #[test]
+    fn test_encode() {
+        let records = [
+            Record {
+                fingerprint: 1,
+                epoch: 2,
+            },
+            Record {
+                fingerprint: u64::MAX,
+                epoch: 3,
+            },
+        ];
+        assert_eq!(decode(&encode(&records)).collect::<Vec<_>>(), records);
+        assert_eq!(decode(b"CZG1 short").count(), 0);
+        assert_eq!(decode(b"nope").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_gossip() {
+        let config = GossipConfig {
+            interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let localhost: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let mut nodes = Vec::new();
+        let mut gossips = Vec::new();
+        for _ in 0..3 {
+            let node = Arc::new(Node::new(1024, 100));
+            node.set(Bytes::from("k"), Bytes::from("v"), None, None);
+            let gossip = Gossip::bind(localhost, node.clone(), config.clone());
+            gossips.push(gossip.await.unwrap());
+            nodes.push(node);
+        }
+        // a line: 0 only reaches 2 through 1
+        let addrs: Vec<_> = gossips.iter().map(|g| g.local_addr().unwrap()).collect();
+        gossips[0].config.peers = vec![addrs[1]];
+        gossips[1].config.peers = vec![addrs[0], addrs[2]];
+        gossips[2].config.peers = vec![addrs[1]];
+        for gossip in gossips {
+            tokio::spawn(async move { gossip.run().await });
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(nodes[0].delete(b"k"));
+        for _ in 0..100 {
+            if nodes.iter().all(|node| node.get(b"k").is_none()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the invalidation didn't reach every node");
+    }
+}
@@ -0,0 +1,270 @@
+//! gRPC frontend, see `proto/cachez/v1/cache.proto`.
+
+use crate::listener::verify_password;
+use crate::node::{self, unix_ms, Change, Node};
+use crate::proto::cache_server::{Cache, CacheServer};
+use crate::proto::{
+    invalidation, replication_event, DeleteRequest, DeleteResponse, Entry, GetRequest, GetResponse,
+    Invalidation, ReplicateRequest, ReplicationEvent, SetRequest, SetResponse, StatsRequest,
+    StatsResponse, WatchRequest,
+};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::{Request, Response, Status};
+
+/// Implementation of the `cachez.v1.Cache` service over a [`Node`]
+pub struct CacheService {
+    node: Arc<Node>,
+}
+
+impl CacheService {
+    pub fn new(node: Arc<Node>) -> Self {
+        Self { node }
+    }
+
+    /// The service ready to be added to a tonic router
+    pub fn server(node: Arc<Node>) -> CacheServer<Self> {
+        CacheServer::new(Self::new(node))
+    }
+
+    /// [`server`](Self::server), requiring `authorization: Bearer <password>` metadata on every
+    /// call when there is a `password`
+    pub fn server_with_password(
+        node: Arc<Node>,
+        password: Option<Arc<str>>,
+    ) -> InterceptedService<CacheServer<Self>, Authenticate> {
+        CacheServer::with_interceptor(Self::new(node), Authenticate { password })
+    }
+}
+
+/// Interceptor rejecting calls without the password, see [`CacheService::server_with_password`]
+#[derive(Clone)]
+pub struct Authenticate {
+    password: Option<Arc<str>>,
+}
+
+impl Interceptor for Authenticate {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(password) = &self.password else {
+            return Ok(request);
+        };
+        let given = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+        match given {
+            Some(given) if verify_password(password, given) => Ok(request),
+            _ => Err(Status::unauthenticated("invalid or missing password")),
+        }
+    }
+}
+
+type InvalidationStream = Pin<Box<dyn Stream<Item = Result<Invalidation, Status>> + Send>>;
+type ReplicationStream = Pin<Box<dyn Stream<Item = Result<ReplicationEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Cache for CacheService {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let value = self.node.get(&request.into_inner().key);
+        Ok(Response::new(GetResponse { value }))
+    }
+
+    async fn set(&self, request: Request<SetRequest>) -> Result<Response<SetResponse>, Status> {
+        let request = request.into_inner();
+        let ttl = request.ttl_ms.map(Duration::from_millis);
+        self.node
+            .set(request.key, request.value, request.weight, ttl);
+        Ok(Response::new(SetResponse {}))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let deleted = self.node.delete(&request.into_inner().key);
+        Ok(Response::new(DeleteResponse { deleted }))
+    }
+
+    async fn stats(&self, _: Request<StatsRequest>) -> Result<Response<StatsResponse>, Status> {
+        let stats = self.node.stats();
+        Ok(Response::new(StatsResponse {
+            hits: stats.cache.hits,
+            misses: stats.cache.misses,
+            inserts: stats.cache.inserts,
+            updates: stats.cache.updates,
+            evictions: stats.cache.evictions,
+            removals: stats.cache.removals,
+            expirations: stats.expirations,
+            entries: stats.cache.entries as u64,
+            weight: stats.cache.weight as u64,
+        }))
+    }
+
+    type WatchStream = InvalidationStream;
+
+    async fn watch(&self, _: Request<WatchRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let stream = BroadcastStream::new(self.node.watch()).map(|invalidation| {
+            let invalidation = invalidation.map_err(|lagged| {
+                // the watcher can't tell which keys it missed, let it resync
+                Status::data_loss(lagged.to_string())
+            })?;
+            let reason = match invalidation.reason {
+                node::Reason::Deleted => invalidation::Reason::Deleted,
+                node::Reason::Updated => invalidation::Reason::Updated,
+            };
+            Ok(Invalidation {
+                key: invalidation.key,
+                reason: reason.into(),
+            })
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    type ReplicateStream = ReplicationStream;
+
+    async fn replicate(
+        &self,
+        _: Request<ReplicateRequest>,
+    ) -> Result<Response<Self::ReplicateStream>, Status> {
+        // followed before the snapshot is taken, a change made meanwhile comes after it
+        let changes = self.node.changes();
+        let snapshot_sequence = self.node.sequence();
+        let node = self.node.clone();
+        let snapshot = tokio_stream::iter(self.node.keys()).filter_map(move |key| {
+            let entry = node.entry(&key)?;
+            Some(Ok(ReplicationEvent {
+                sequence: snapshot_sequence,
+                primary_sequence: node.sequence(),
+                changed_at_ms: unix_ms(),
+                change: Some(replication_event::Change::Set(entry.into())),
+            }))
+        });
+        let node = self.node.clone();
+        let live = BroadcastStream::new(changes).map(move |event| {
+            let event = event.map_err(|lagged| {
+                // the replica can't tell which changes it missed, let it start over
+                Status::data_loss(lagged.to_string())
+            })?;
+            let change = match event.change {
+                Change::Set(entry) => replication_event::Change::Set(entry.into()),
+                Change::Delete(key) => replication_event::Change::Delete(key),
+            };
+            Ok(ReplicationEvent {
+                sequence: event.sequence,
+                primary_sequence: node.sequence(),
+                changed_at_ms: event.at_ms,
+                change: Some(change),
+            })
+        });
+        Ok(Response::new(Box::pin(snapshot.chain(live))))
+    }
+}
+
+impl From<node::Entry> for Entry {
+    fn from(entry: node::Entry) -> Self {
+        Self {
+            key: entry.key,
+            value: entry.data,
+            flags: entry.flags,
+            weight: entry.weight,
+            ttl_ms: entry.ttl.map(|ttl| ttl.as_millis() as u64),
+            tags: entry.tags,
+        }
+    }
+}
+
+impl From<Entry> for node::Entry {
+    fn from(entry: Entry) -> Self {
+        Self {
+            key: entry.key,
+            data: entry.value,
+            flags: entry.flags,
+            weight: entry.weight.max(1),
+            ttl: entry.ttl_ms.map(Duration::from_millis),
+            tags: entry.tags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::cache_client::CacheClient;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, Server};
+
+    async fn serve() -> CacheClient<Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node = Arc::new(Node::new(1024, 100));
+        tokio::spawn(
+            Server::builder()
+                .add_service(CacheService::server(node))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        CacheClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_service() {
+        let mut client = serve().await;
+        let mut watch = client.watch(WatchRequest {}).await.unwrap().into_inner();
+
+        let get = |key: &str| GetRequest {
+            key: key.as_bytes().to_vec().into(),
+        };
+        assert_eq!(client.get(get("a")).await.unwrap().into_inner().value, None);
+        client
+            .set(SetRequest {
+                key: "a".into(),
+                value: "1".into(),
+                weight: Some(2),
+                ttl_ms: None,
+            })
+            .await
+            .unwrap();
+        let value = client.get(get("a")).await.unwrap().into_inner().value;
+        assert_eq!(value.as_deref(), Some(&b"1"[..]));
+
+        let delete = DeleteRequest { key: "a".into() };
+        assert!(client.delete(delete).await.unwrap().into_inner().deleted);
+        let invalidation = watch.message().await.unwrap().unwrap();
+        assert_eq!(&invalidation.key[..], b"a");
+        assert_eq!(invalidation.reason(), invalidation::Reason::Deleted);
+
+        let stats = client.stats(StatsRequest {}).await.unwrap().into_inner();
+        assert_eq!((stats.hits, stats.misses, stats.removals), (1, 1, 1));
+        assert_eq!(stats.weight, 0);
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let request = |authorization: Option<&str>| {
+            let mut request = Request::new(());
+            if let Some(authorization) = authorization {
+                let value = authorization.parse().unwrap();
+                request.metadata_mut().insert("authorization", value);
+            }
+            request
+        };
+        let mut open = Authenticate { password: None };
+        assert!(open.call(request(None)).is_ok());
+
+        let mut guarded = Authenticate {
+            password: Some("secret".into()),
+        };
+        assert!(guarded.call(request(Some("Bearer secret"))).is_ok());
+        for authorization in [None, Some("Bearer nope"), Some("secret")] {
+            let status = guarded.call(request(authorization)).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! HTTP admin and data API, for debugging, smoke tests and ops tooling.
+//!
+//! - `GET /keys/{key}`: the value as `application/octet-stream`, 404 on a miss
+//! - `PUT /keys/{key}?ttl_ms=&weight=&tags=`: cache the request body, 204. `tags` is comma
+//!   separated
+//! - `DELETE /keys/{key}`: 204, or 404 if the key wasn't cached
+//! - `DELETE /tags/{tag}`: delete every key set with the tag, 204, or 404 if there was none
+//! - `DELETE /prefixes/{prefix}`: delete every key starting with the prefix, 204, or 404 if
+//!   there was none
+//! - `GET /stats`: [`NodeStats`](crate::node::NodeStats) as JSON
+//! - `GET /hot-keys?limit=`: the most read keys, 10 by default
+//! - `GET /config`: the node's sizing
+//! - `GET /metrics`: the statistics in Prometheus' text format
+//! - `GET /healthz`: 200 while the node serves, without password so that probes needn't know it
+//! - `POST /config/reload`: with [`with_reload`], reload the server's configuration, 204, or 500
+//!   and why it failed
+//!
+//! Keys are the percent-decoded path segment, so they must be UTF-8 here. When the listener has
+//! a password, every request must carry it as `Authorization: Bearer <password>` or gets a 401.
+
+use crate::listener::{verify_password, Listener};
+use crate::node::{Node, SetOptions};
+use axum::body::Bytes;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use cachez::tinyufo::Weight;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `/hot-keys` default `limit`
+const DEFAULT_HOT_KEYS: usize = 10;
+
+/// The API's routes over `node`
+pub fn router(node: Arc<Node>) -> Router {
+    Router::new()
+        .route("/keys/{key}", get(get_key).put(put_key).delete(delete_key))
+        .route("/tags/{tag}", delete(delete_tag))
+        .route("/prefixes/{prefix}", delete(delete_prefix))
+        .route("/stats", get(stats))
+        .route("/hot-keys", get(hot_keys))
+        .route("/config", get(config))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(|| async { "ok" }))
+        .with_state(node)
+}
+
+/// Add `POST /config/reload` to `router`, running `reload`
+pub fn with_reload(
+    router: Router,
+    reload: impl Fn() -> Result<(), String> + Clone + Send + Sync + 'static,
+) -> Router {
+    router.route(
+        "/config/reload",
+        post(move || async move {
+            match reload() {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error).into_response(),
+            }
+        }),
+    )
+}
+
+/// Serve the API on `listener` until it fails
+pub async fn serve(listener: impl Into<Listener>, node: Arc<Node>) -> io::Result<()> {
+    serve_router(listener, router(node)).await
+}
+
+/// Serve `router`, the API's or an extended one, on `listener` until it fails
+pub async fn serve_router(listener: impl Into<Listener>, mut router: Router) -> io::Result<()> {
+    let listener = listener.into();
+    if let Some(password) = listener.password() {
+        router = require_password(router, password.clone());
+    }
+    loop {
+        let accepted = listener.accept().await?;
+        let service = TowerToHyperService::new(router.clone());
+        tokio::spawn(async move {
+            if let Ok(stream) = accepted.stream().await {
+                let _ = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            }
+        });
+    }
+}
+
+fn require_password(router: Router, password: Arc<str>) -> Router {
+    router.layer(middleware::from_fn_with_state(password, authorize))
+}
+
+async fn authorize(State(password): State<Arc<str>>, request: Request, next: Next) -> Response {
+    if request.uri().path() == "/healthz" {
+        return next.run(request).await;
+    }
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+    if given.is_some_and(|given| verify_password(&password, given)) {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response()
+    }
+}
+
+async fn get_key(State(node): State<Arc<Node>>, Path(key): Path<String>) -> impl IntoResponse {
+    match node.get(key.as_bytes()) {
+        Some(data) => Ok(([(header::CONTENT_TYPE, "application/octet-stream")], data)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[derive(Deserialize)]
+struct PutParams {
+    ttl_ms: Option<u64>,
+    weight: Option<Weight>,
+    tags: Option<String>,
+}
+
+async fn put_key(
+    State(node): State<Arc<Node>>,
+    Path(key): Path<String>,
+    Query(params): Query<PutParams>,
+    data: Bytes,
+) -> StatusCode {
+    let tags = params.tags.as_deref().unwrap_or_default();
+    let options = SetOptions {
+        weight: params.weight,
+        ttl: params.ttl_ms.map(Duration::from_millis),
+        tags: tags
+            .split(',')
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| tag.to_owned().into())
+            .collect(),
+        ..Default::default()
+    };
+    node.set_with(key.into(), data, options);
+    StatusCode::NO_CONTENT
+}
+
+async fn delete_key(State(node): State<Arc<Node>>, Path(key): Path<String>) -> StatusCode {
+    match node.delete(key.as_bytes()) {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn delete_tag(State(node): State<Arc<Node>>, Path(tag): Path<String>) -> StatusCode {
+    match node.delete_tag(tag.as_bytes()) {
+        0 => StatusCode::NOT_FOUND,
+        _ => StatusCode::NO_CONTENT,
+    }
+}
+
+async fn delete_prefix(State(node): State<Arc<Node>>, Path(prefix): Path<String>) -> StatusCode {
+    match node.delete_prefix(prefix.as_bytes()) {
+        0 => StatusCode::NOT_FOUND,
+        _ => StatusCode::NO_CONTENT,
+    }
+}
+
+#[derive(Serialize)]
+struct Stats {
+    hits: u64,
+    misses: u64,
+    inserts: u64,
+    updates: u64,
+    evictions: u64,
+    removals: u64,
+    expirations: u64,
+    entries: usize,
+    weight: usize,
+}
+
+async fn stats(State(node): State<Arc<Node>>) -> Json<Stats> {
+    let stats = node.stats();
+    Json(Stats {
+        hits: stats.cache.hits,
+        misses: stats.cache.misses,
+        inserts: stats.cache.inserts,
+        updates: stats.cache.updates,
+        evictions: stats.cache.evictions,
+        removals: stats.cache.removals,
+        expirations: stats.expirations,
+        entries: stats.cache.entries,
+        weight: stats.cache.weight,
+    })
+}
+
+#[derive(Deserialize)]
+struct HotKeysParams {
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct HotKey {
+    // lossy, keys set through other frontends may not be UTF-8
+    key: String,
+    reads: u64,
+}
+
+async fn hot_keys(
+    State(node): State<Arc<Node>>,
+    Query(params): Query<HotKeysParams>,
+) -> Json<Vec<HotKey>> {
+    let hot = node.hot_keys(params.limit.unwrap_or(DEFAULT_HOT_KEYS));
+    Json(
+        hot.into_iter()
+            .map(|(key, reads)| HotKey {
+                key: String::from_utf8_lossy(&key).into_owned(),
+                reads,
+            })
+            .collect(),
+    )
+}
+
+#[derive(Serialize)]
+struct Config {
+    weight_limit: usize,
+    capacity: usize,
+    shards: usize,
+}
+
+async fn config(State(node): State<Arc<Node>>) -> Json<Config> {
+    Json(Config {
+        weight_limit: node.weight_limit(),
+        capacity: node.capacity(),
+        shards: node.shards(),
+    })
+}
+
+/// Prometheus text exposition format, version 0.0.4. Counters are the `_total` ones
+async fn metrics(State(node): State<Arc<Node>>) -> impl IntoResponse {
+    let stats = node.stats();
+    let cache = stats.cache;
+    let metrics = [
+        ("hits_total", cache.hits),
+        ("misses_total", cache.misses),
+        ("inserts_total", cache.inserts),
+        ("updates_total", cache.updates),
+        ("evictions_total", cache.evictions),
+        ("removals_total", cache.removals),
+        ("expirations_total", stats.expirations),
+        ("entries", cache.entries as u64),
+        ("weight", cache.weight as u64),
+        ("weight_limit", node.weight_limit() as u64),
+        ("capacity", node.capacity() as u64),
+    ];
+    let mut out = String::new();
+    for (name, value) in metrics {
+        let kind = if name.ends_with("_total") {
+            "counter"
+        } else {
+            "gauge"
+        };
+        out.push_str(&format!(
+            "# TYPE cachez_{name} {kind}\ncachez_{name} {value}\n"
+        ));
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use tower::ServiceExt;
+
+    async fn call(router: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, Bytes) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let router = router(Arc::new(Node::new(1024, 100)));
+
+        let (status, _) = call(&router, Method::PUT, "/keys/a%20b?ttl_ms=60000", "1").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = call(&router, Method::GET, "/keys/a%20b", "").await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"1"[..]));
+        let (status, _) = call(&router, Method::GET, "/keys/nope", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = call(&router, Method::GET, "/hot-keys?limit=1", "").await;
+        assert_eq!(&body[..], br#"[{"key":"a b","reads":1}]"#);
+        let (_, body) = call(&router, Method::GET, "/stats", "").await;
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            (stats["hits"].as_u64(), stats["misses"].as_u64()),
+            (Some(1), Some(1))
+        );
+        let (_, body) = call(&router, Method::GET, "/config", "").await;
+        let config: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(config["capacity"], 100);
+        let (_, body) = call(&router, Method::GET, "/metrics", "").await;
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        assert!(metrics.contains("# TYPE cachez_hits_total counter\ncachez_hits_total 1\n"));
+        assert!(metrics.contains("\ncachez_entries 1\n"), "{metrics}");
+        let (status, _) = call(&router, Method::GET, "/healthz", "").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = call(&router, Method::DELETE, "/keys/a%20b", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&router, Method::DELETE, "/keys/a%20b", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        call(&router, Method::PUT, "/keys/u1?tags=users,admins", "1").await;
+        call(&router, Method::PUT, "/keys/u2?tags=users", "2").await;
+        let (status, _) = call(&router, Method::DELETE, "/tags/users", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&router, Method::GET, "/keys/u1", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&router, Method::DELETE, "/tags/admins", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        call(&router, Method::PUT, "/keys/user:1:a", "1").await;
+        let (status, _) = call(&router, Method::DELETE, "/prefixes/user:1:", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&router, Method::DELETE, "/prefixes/user:1:", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_password() {
+        let router = require_password(router(Arc::new(Node::new(1024, 100))), "secret".into());
+        let (status, _) = call(&router, Method::GET, "/stats", "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(&router, Method::GET, "/healthz", "").await;
+        assert_eq!(status, StatusCode::OK);
+
+        for (authorization, expected) in [
+            ("Bearer nope", StatusCode::UNAUTHORIZED),
+            ("secret", StatusCode::UNAUTHORIZED),
+            ("Bearer secret", StatusCode::OK),
+        ] {
+            let request = Request::builder()
+                .uri("/stats")
+                .header(header::AUTHORIZATION, authorization)
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), expected, "{authorization}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let router = with_reload(router(Arc::new(Node::new(1024, 100))), || Ok(()));
+        let (status, _) = call(&router, Method::POST, "/config/reload", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let router = with_reload(Router::new(), || Err("bad file".to_owned()));
+        let (status, body) = call(&router, Method::POST, "/config/reload", "").await;
+        assert_eq!(
+            (status, &body[..]),
+            (StatusCode::INTERNAL_SERVER_ERROR, &b"bad file"[..])
+        );
+    }
+}
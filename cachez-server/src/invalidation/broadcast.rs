@@ -0,0 +1,39 @@
+use super::{InvalidationTransport, Message, Subscription};
+use std::io;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+
+/// In-process [`InvalidationTransport`], for nodes sharing a process and for tests
+///
+/// Clones publish to and subscribe from the same channel.
+#[derive(Clone)]
+pub struct BroadcastTransport {
+    sender: broadcast::Sender<Message>,
+}
+
+impl BroadcastTransport {
+    /// A subscriber more than `capacity` messages behind loses its subscription
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl InvalidationTransport for BroadcastTransport {
+    async fn publish(&self, message: &Message) -> io::Result<()> {
+        // no subscriber is fine
+        let _ = self.sender.send(message.clone());
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> io::Result<Subscription> {
+        let stream = BroadcastStream::new(self.sender.subscribe()).map(|message| {
+            // what was missed is unknown
+            message.map_err(io::Error::other)
+        });
+        Ok(Box::pin(stream))
+    }
+}
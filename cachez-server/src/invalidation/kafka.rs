@@ -0,0 +1,97 @@
+use super::{InvalidationTransport, Message, Subscription};
+use bytes::Bytes;
+use rskafka::chrono::{DateTime, Utc};
+use rskafka::client::consumer::{StartOffset, StreamConsumerBuilder};
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::ClientBuilder;
+use rskafka::record::Record;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+use tokio_stream::StreamExt;
+
+/// Longest a consumer waits on the broker for new records
+const MAX_WAIT_MS: i32 = 500;
+
+/// [`InvalidationTransport`] over the first partition of a Kafka topic.
+///
+/// Every node consumes the whole partition from its end, a single partition keeps the
+/// invalidations in order. Connects in plaintext without SASL.
+pub struct KafkaTransport {
+    brokers: Vec<String>,
+    topic: String,
+    // connected on first use, rskafka reconnects by itself afterwards
+    partition: OnceCell<Arc<PartitionClient>>,
+}
+
+impl KafkaTransport {
+    /// Use `topic` on the cluster bootstrapped from `brokers`, each `host:port`
+    pub fn new(brokers: Vec<String>, topic: impl Into<String>) -> Self {
+        Self {
+            brokers,
+            topic: topic.into(),
+            partition: OnceCell::new(),
+        }
+    }
+
+    async fn partition(&self) -> io::Result<Arc<PartitionClient>> {
+        let partition = self
+            .partition
+            .get_or_try_init(|| async {
+                let client = ClientBuilder::new(self.brokers.clone())
+                    .build()
+                    .await
+                    .map_err(io::Error::other)?;
+                let partition = client
+                    .partition_client(self.topic.clone(), 0, UnknownTopicHandling::Retry)
+                    .await
+                    .map_err(io::Error::other)?;
+                Ok::<_, io::Error>(Arc::new(partition))
+            })
+            .await?;
+        Ok(partition.clone())
+    }
+}
+
+/// rskafka's chrono comes without the clock
+fn now() -> DateTime<Utc> {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    DateTime::from_timestamp_millis(since_epoch.as_millis() as i64).unwrap_or_default()
+}
+
+#[async_trait::async_trait]
+impl InvalidationTransport for KafkaTransport {
+    async fn publish(&self, message: &Message) -> io::Result<()> {
+        let record = Record {
+            key: None,
+            value: Some(message.encode().to_vec()),
+            headers: BTreeMap::new(),
+            timestamp: now(),
+        };
+        self.partition()
+            .await?
+            .produce(vec![record], Compression::NoCompression)
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> io::Result<Subscription> {
+        let consumer = StreamConsumerBuilder::new(self.partition().await?, StartOffset::Latest)
+            .with_max_wait_ms(MAX_WAIT_MS)
+            .build();
+        let messages = consumer.filter_map(|record| match record {
+            Ok((record, _)) => {
+                let value = Bytes::from(record.record.value.unwrap_or_default());
+                // someone else's use of the topic, not ours to fail on
+                Message::decode(&value).map(Ok)
+            }
+            Err(error) => Some(Err(io::Error::other(error))),
+        });
+        Ok(Box::pin(messages))
+    }
+}
@@ -0,0 +1,254 @@
+//! Invalidation shared by the nodes of a deployment through a pub/sub channel.
+//!
+//! An [`Invalidator`] publishes the keys its node deletes or overwrites, and applies the
+//! [`Message`]s of the other nodes, so that no node keeps serving data deleted elsewhere.
+//! Applications can publish to the same channel, typically to drop a whole tag once the data
+//! behind it changed:
+//!
+//! ```text
+//! PUBLISH cachez:invalidations "tag 0 user:42"
+//! ```
+//!
+//! The channel is an [`InvalidationTransport`]: Redis pub/sub, or with the `nats` and `kafka`
+//! features a NATS subject or a Kafka topic, for deployments already running one of those.
+//!
+//! Losing the subscription means messages may have been missed, the node then drops everything
+//! it caches rather than serve stale data.
+
+mod broadcast;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "nats")]
+mod nats;
+mod redis;
+
+pub use self::broadcast::BroadcastTransport;
+#[cfg(feature = "kafka")]
+pub use self::kafka::KafkaTransport;
+#[cfg(feature = "nats")]
+pub use self::nats::NatsTransport;
+pub use self::redis::RedisTransport;
+
+use crate::node::{Invalidation, Node};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::{Stream, StreamExt};
+
+/// Wait before subscribing again once a subscription was lost
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// What a [`Message`] invalidates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Key(Bytes),
+    /// Every key set with this tag
+    Tag(Bytes),
+    /// Every key starting with this prefix
+    Prefix(Bytes),
+}
+
+/// An invalidation on the channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// [`Invalidator::origin`] of the publisher, 0 when published by something else than a node
+    pub origin: u64,
+    pub target: Target,
+}
+
+impl Message {
+    /// `key <origin> <key>`, `tag <origin> <tag>` or `prefix <origin> <prefix>`, the name runs to
+    /// the end and may hold spaces
+    pub fn encode(&self) -> Bytes {
+        let (kind, name) = match &self.target {
+            Target::Key(key) => ("key", key),
+            Target::Tag(tag) => ("tag", tag),
+            Target::Prefix(prefix) => ("prefix", prefix),
+        };
+        let mut out = BytesMut::with_capacity(name.len() + 24);
+        out.put_slice(format!("{kind} {} ", self.origin).as_bytes());
+        out.put_slice(name);
+        out.freeze()
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<Self> {
+        let mut parts = payload.splitn(3, |&b| b == b' ');
+        let kind = parts.next()?;
+        let origin = std::str::from_utf8(parts.next()?).ok()?.parse().ok()?;
+        let name = parts.next()?;
+        let name = payload.slice_ref(name);
+        let target = match kind {
+            b"key" => Target::Key(name),
+            b"tag" => Target::Tag(name),
+            b"prefix" => Target::Prefix(name),
+            _ => return None,
+        };
+        Some(Self { origin, target })
+    }
+}
+
+/// Messages received by a subscriber, an error or the end of the stream means some may be lost
+pub type Subscription = Pin<Box<dyn Stream<Item = io::Result<Message>> + Send>>;
+
+/// A pub/sub channel carrying [`Message`]s, implement it to plug in another message bus
+#[async_trait::async_trait]
+pub trait InvalidationTransport: Send + Sync {
+    /// Send `message` to every subscriber, including this one
+    async fn publish(&self, message: &Message) -> io::Result<()>;
+
+    async fn subscribe(&self) -> io::Result<Subscription>;
+}
+
+/// Keeps a [`Node`] in sync with the other nodes on an [`InvalidationTransport`]
+pub struct Invalidator<T> {
+    node: Arc<Node>,
+    transport: T,
+    origin: u64,
+}
+
+impl<T: InvalidationTransport> Invalidator<T> {
+    pub fn new(node: Arc<Node>, transport: T) -> Self {
+        Self {
+            node,
+            transport,
+            // tells this node's messages apart when the channel echoes them back
+            origin: fastrand::u64(1..),
+        }
+    }
+
+    /// Identifies the messages published by this invalidator
+    pub fn origin(&self) -> u64 {
+        self.origin
+    }
+
+    /// Invalidate `target` on this node and every other one
+    pub async fn invalidate(&self, target: Target) -> io::Result<()> {
+        self.apply(&target);
+        let message = Message {
+            origin: self.origin,
+            target,
+        };
+        self.transport.publish(&message).await
+    }
+
+    /// Publish the local invalidations and apply the remote ones, runs until dropped.
+    ///
+    /// Publishing failures lose the invalidation, other nodes serve the old value until it
+    /// leaves their cache.
+    pub async fn run(&self) {
+        let mut watch = self.node.watch();
+        loop {
+            let mut subscription = match self.transport.subscribe().await {
+                Ok(subscription) => subscription,
+                Err(_) => {
+                    tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+                    continue;
+                }
+            };
+            loop {
+                tokio::select! {
+                    local = watch.recv() => match local {
+                        Ok(Invalidation { remote: false, key, .. }) => {
+                            let message = Message {
+                                origin: self.origin,
+                                target: Target::Key(key),
+                            };
+                            let _ = self.transport.publish(&message).await;
+                        }
+                        // applied on behalf of the channel, or more than can be published
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return,
+                    },
+                    remote = subscription.next() => match remote {
+                        Some(Ok(message)) if message.origin != self.origin => {
+                            self.apply(&message.target);
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(_)) | None => break,
+                    },
+                }
+            }
+            // whatever was invalidated in the meantime is unknown
+            self.node.clear_remote();
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    fn apply(&self, target: &Target) {
+        match target {
+            Target::Key(key) => {
+                self.node.delete_remote(key);
+            }
+            Target::Tag(tag) => {
+                self.node.delete_tag_remote(tag);
+            }
+            Target::Prefix(prefix) => {
+                self.node.delete_prefix_remote(prefix);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let message = Message {
+            origin: 42,
+            target: Target::Key(Bytes::from("user 1")),
+        };
+        assert_eq!(&message.encode()[..], b"key 42 user 1");
+        assert_eq!(Message::decode(&message.encode()), Some(message));
+        let tag = Message::decode(&Bytes::from("tag 0 users")).unwrap();
+        assert_eq!(tag.target, Target::Tag(Bytes::from("users")));
+        let prefix = Message::decode(&Bytes::from("prefix 0 user:42:")).unwrap();
+        assert_eq!(prefix.target, Target::Prefix(Bytes::from("user:42:")));
+        assert_eq!(Message::decode(&Bytes::from("nope 0 users")), None);
+        assert_eq!(Message::decode(&Bytes::from("key users")), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalidator() {
+        let transport = BroadcastTransport::new(16);
+        let (a, b) = (
+            Arc::new(Node::new(1024, 100)),
+            Arc::new(Node::new(1024, 100)),
+        );
+        let a_sync = Arc::new(Invalidator::new(a.clone(), transport.clone()));
+        let b_sync = Arc::new(Invalidator::new(b.clone(), transport.clone()));
+        for invalidator in [a_sync.clone(), b_sync.clone()] {
+            tokio::spawn(async move { invalidator.run().await });
+        }
+        // let both subscribe
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        for node in [&a, &b] {
+            node.set(Bytes::from("k"), Bytes::from("old"), None, None);
+        }
+        let tagged = crate::node::SetOptions {
+            tags: vec![Bytes::from("t")],
+            ..Default::default()
+        };
+        b.set_with(Bytes::from("t1"), Bytes::from("v"), tagged);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // the update on a drops the copy of b, and b's deletion doesn't come back to a
+        a.set(Bytes::from("k"), Bytes::from("new"), None, None);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(b.get(b"k"), None);
+        assert_eq!(a.get(b"k"), Some(Bytes::from("new")));
+
+        a_sync
+            .invalidate(Target::Tag(Bytes::from("t")))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(b.get(b"t1"), None);
+    }
+}
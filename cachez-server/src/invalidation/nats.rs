@@ -0,0 +1,264 @@
+use super::{InvalidationTransport, Message, Subscription};
+use bytes::{Buf, Bytes, BytesMut};
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Messages buffered between the connection of a subscriber and its stream
+const SUBSCRIPTION_BUFFER: usize = 1024;
+/// Sent on connect: no `+OK` for every command, and no headers in the messages
+const CONNECT: &[u8] =
+    b"CONNECT {\"verbose\":false,\"pedantic\":false,\"headers\":false,\"name\":\"cachez\"}\r\n";
+
+/// [`InvalidationTransport`] over a NATS subject, spoken in the core NATS protocol without a
+/// client library.
+///
+/// Connects without authentication nor TLS. Subjects can't hold whitespace.
+pub struct NatsTransport {
+    addr: String,
+    subject: String,
+    // reused between publishes, reconnected after a failure
+    publisher: Mutex<Option<Connection>>,
+}
+
+impl NatsTransport {
+    /// Use `subject` of the NATS server at `addr`, `host:port`
+    pub fn new(addr: impl Into<String>, subject: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            subject: subject.into(),
+            publisher: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl InvalidationTransport for NatsTransport {
+    async fn publish(&self, message: &Message) -> io::Result<()> {
+        let mut publisher = self.publisher.lock().await;
+        let connection = match publisher.as_mut() {
+            Some(connection) => connection,
+            None => publisher.insert(Connection::connect(&self.addr).await?),
+        };
+        let payload = message.encode();
+        let header = format!("PUB {} {}\r\n", self.subject, payload.len());
+        let command = [header.as_bytes(), &payload, b"\r\nPING\r\n"].concat();
+        // the PONG comes after the server handled the PUB, or after its error
+        let result = match connection.stream.write_all(&command).await {
+            Ok(()) => connection.pong().await,
+            Err(error) => Err(error),
+        };
+        if result.is_err() {
+            *publisher = None;
+        }
+        result
+    }
+
+    async fn subscribe(&self) -> io::Result<Subscription> {
+        let mut connection = Connection::connect(&self.addr).await?;
+        let command = format!("SUB {} 1\r\n", self.subject);
+        connection.stream.write_all(command.as_bytes()).await?;
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let message = match connection.next_message().await {
+                    // someone else's use of the subject, not ours to fail on
+                    Ok(payload) => match Message::decode(&payload) {
+                        Some(message) => Ok(message),
+                        None => continue,
+                    },
+                    Err(error) => Err(error),
+                };
+                let failed = message.is_err();
+                // the subscriber went away
+                if sender.send(message).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(receiver)))
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    input: BytesMut,
+}
+
+impl Connection {
+    /// Connect and handshake, the server speaks first with its `INFO`
+    async fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let _ = stream.set_nodelay(true);
+        let mut connection = Self {
+            stream,
+            input: BytesMut::with_capacity(4096),
+        };
+        let info = connection.line().await?;
+        if !info.starts_with(b"INFO ") {
+            return Err(unexpected(&info));
+        }
+        connection.stream.write_all(CONNECT).await?;
+        connection.stream.write_all(b"PING\r\n").await?;
+        connection.pong().await?;
+        Ok(connection)
+    }
+
+    /// Wait for the `PONG` to our `PING`, answering the server's
+    async fn pong(&mut self) -> io::Result<()> {
+        loop {
+            let line = self.line().await?;
+            match self.control(&line).await? {
+                Some(b"PONG") => return Ok(()),
+                Some(_) => return Err(unexpected(&line)),
+                None => {}
+            }
+        }
+    }
+
+    /// The payload of the next `MSG`
+    async fn next_message(&mut self) -> io::Result<Bytes> {
+        loop {
+            let line = self.line().await?;
+            let Some(kind) = self.control(&line).await? else {
+                continue;
+            };
+            if kind != b"MSG" {
+                return Err(unexpected(&line));
+            }
+            // MSG <subject> <sid> [reply-to] <#bytes>
+            let len = line
+                .rsplit(|&b| b == b' ')
+                .next()
+                .and_then(|len| std::str::from_utf8(len).ok()?.parse().ok())
+                .ok_or_else(|| unexpected(&line))?;
+            return self.payload(len).await;
+        }
+    }
+
+    /// Handle the lines that need no caller: `PING`, `+OK`, `INFO` and `-ERR`. Returns the
+    /// operation of the others
+    async fn control<'a>(&mut self, line: &'a [u8]) -> io::Result<Option<&'a [u8]>> {
+        let operation = line.split(|&b| b == b' ').next().unwrap_or_default();
+        match operation {
+            b"PING" => self.stream.write_all(b"PONG\r\n").await?,
+            b"+OK" | b"INFO" => {}
+            b"-ERR" => {
+                let error = String::from_utf8_lossy(line).into_owned();
+                return Err(io::Error::other(error));
+            }
+            _ => return Ok(Some(operation)),
+        }
+        Ok(None)
+    }
+
+    /// The next line, without its CRLF
+    async fn line(&mut self) -> io::Result<Bytes> {
+        loop {
+            if let Some(end) = self.input.windows(2).position(|w| w == b"\r\n") {
+                let line = self.input.split_to(end).freeze();
+                self.input.advance(2);
+                return Ok(line);
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// A payload of `len` bytes, without its CRLF
+    async fn payload(&mut self, len: usize) -> io::Result<Bytes> {
+        while self.input.len() < len + 2 {
+            self.fill().await?;
+        }
+        let payload = self.input.split_to(len).freeze();
+        self.input.advance(2);
+        Ok(payload)
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        if self.stream.read_buf(&mut self.input).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+fn unexpected(line: &[u8]) -> io::Error {
+    let line = String::from_utf8_lossy(line);
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected {line}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invalidation::Target;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_stream::StreamExt;
+
+    /// Just enough of NATS for one subject: CONNECT, PING, SUB and PUB
+    async fn fake_nats() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let subscribers = Arc::new(Mutex::new(Vec::<mpsc::UnboundedSender<Bytes>>::new()));
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let subscribers = subscribers.clone();
+                tokio::spawn(async move {
+                    let (sender, mut receiver) = mpsc::unbounded_channel();
+                    let mut connection = Connection {
+                        stream,
+                        input: BytesMut::new(),
+                    };
+                    connection.stream.write_all(b"INFO {}\r\n").await.unwrap();
+                    loop {
+                        tokio::select! {
+                            line = connection.line() => {
+                                let Ok(line) = line else { return };
+                                let args: Vec<&[u8]> = line.split(|&b| b == b' ').collect();
+                                match args[0] {
+                                    b"PING" => connection.stream.write_all(b"PONG\r\n").await.unwrap(),
+                                    b"SUB" => subscribers.lock().await.push(sender.clone()),
+                                    b"PUB" => {
+                                        let len = std::str::from_utf8(args[2]).unwrap().parse().unwrap();
+                                        let payload = connection.payload(len).await.unwrap();
+                                        for subscriber in subscribers.lock().await.iter() {
+                                            let _ = subscriber.send(payload.clone());
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            Some(payload) = receiver.recv() => {
+                                // a PING in between, as servers do
+                                let header = format!("PING\r\nMSG s 1 {}\r\n", payload.len());
+                                let out = [header.as_bytes(), &payload, b"\r\n"].concat();
+                                connection.stream.write_all(&out).await.unwrap();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_nats_transport() {
+        let transport = NatsTransport::new(fake_nats().await, "s");
+        let mut subscription = transport.subscribe().await.unwrap();
+        // the SUB is handled before the PUB of another connection
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let message = Message {
+            origin: 7,
+            target: Target::Key(Bytes::from("user 42\r\n")),
+        };
+        transport.publish(&message).await.unwrap();
+        transport.publish(&message).await.unwrap();
+        assert_eq!(subscription.next().await.unwrap().unwrap(), message);
+        assert_eq!(subscription.next().await.unwrap().unwrap(), message);
+    }
+}
@@ -0,0 +1,168 @@
+use super::{InvalidationTransport, Message, Subscription};
+use crate::resp::{Connection, Reply};
+use bytes::Bytes;
+use std::io;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Messages buffered between the connection of a subscriber and its stream
+const SUBSCRIPTION_BUFFER: usize = 1024;
+
+/// [`InvalidationTransport`] over a Redis pub/sub channel, spoken in RESP without a client
+/// library.
+///
+/// Connects without authentication, put a proxy in front of a Redis that requires it.
+pub struct RedisTransport {
+    addr: String,
+    channel: Bytes,
+    // reused between publishes, reconnected after a failure
+    publisher: Mutex<Option<Connection>>,
+}
+
+impl RedisTransport {
+    /// Use `channel` of the Redis server at `addr`, `host:port`
+    pub fn new(addr: impl Into<String>, channel: impl Into<Bytes>) -> Self {
+        Self {
+            addr: addr.into(),
+            channel: channel.into(),
+            publisher: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl InvalidationTransport for RedisTransport {
+    async fn publish(&self, message: &Message) -> io::Result<()> {
+        let mut publisher = self.publisher.lock().await;
+        let connection = match publisher.as_mut() {
+            Some(connection) => connection,
+            None => publisher.insert(Connection::connect(&self.addr).await?),
+        };
+        let command = [&b"PUBLISH"[..], &self.channel, &message.encode()];
+        let reply = match connection.call(&command).await {
+            Ok(reply) => reply,
+            Err(error) => {
+                *publisher = None;
+                return Err(error);
+            }
+        };
+        match reply {
+            Reply::Error(error) => Err(io::Error::other(error)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn subscribe(&self) -> io::Result<Subscription> {
+        let mut connection = Connection::connect(&self.addr).await?;
+        connection.send(&[&b"SUBSCRIBE"[..], &self.channel]).await?;
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let message = match connection.receive().await {
+                    Ok(Reply::Array(items)) => match &items[..] {
+                        [Reply::Bulk(Some(kind)), _, Reply::Bulk(Some(payload))]
+                            if &kind[..] == b"message" =>
+                        {
+                            // someone else's use of the channel, not ours to fail on
+                            let Some(message) = Message::decode(payload) else {
+                                continue;
+                            };
+                            Ok(message)
+                        }
+                        // the subscription confirmation
+                        _ => continue,
+                    },
+                    Ok(Reply::Error(error)) => Err(io::Error::other(error)),
+                    Ok(_) => continue,
+                    Err(error) => Err(error),
+                };
+                let failed = message.is_err();
+                // the subscriber went away
+                if sender.send(message).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(receiver)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invalidation::Target;
+    use crate::resp::parse_command;
+    use bytes::BytesMut;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_stream::StreamExt;
+
+    /// Just enough of Redis for one channel: SUBSCRIBE and PUBLISH
+    async fn fake_redis() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let subscribers = Arc::new(Mutex::new(Vec::<mpsc::UnboundedSender<Bytes>>::new()));
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let subscribers = subscribers.clone();
+                tokio::spawn(async move {
+                    let (sender, mut receiver) = mpsc::unbounded_channel();
+                    let mut input = BytesMut::new();
+                    loop {
+                        let mut out = BytesMut::new();
+                        tokio::select! {
+                            read = stream.read_buf(&mut input) => {
+                                if read.unwrap() == 0 {
+                                    return;
+                                }
+                                while let Some(args) = parse_command(&mut input).unwrap() {
+                                    let reply = match &args[0][..] {
+                                        b"SUBSCRIBE" => {
+                                            subscribers.lock().await.push(sender.clone());
+                                            let kind = Reply::bulk("subscribe");
+                                            let channel = Reply::bulk(args[1].clone());
+                                            Reply::Array(vec![kind, channel, Reply::Integer(1)])
+                                        }
+                                        _ => {
+                                            let subscribers = subscribers.lock().await;
+                                            for subscriber in subscribers.iter() {
+                                                let _ = subscriber.send(args[2].clone());
+                                            }
+                                            Reply::Integer(subscribers.len() as i64)
+                                        }
+                                    };
+                                    reply.encode(&mut out);
+                                }
+                            }
+                            Some(payload) = receiver.recv() => {
+                                let items = vec![Reply::bulk("message"), Reply::bulk("c"), Reply::bulk(payload)];
+                                Reply::Array(items).encode(&mut out);
+                            }
+                        }
+                        stream.write_all(&out).await.unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_redis_transport() {
+        let transport = RedisTransport::new(fake_redis().await, "c");
+        let mut subscription = transport.subscribe().await.unwrap();
+        // the subscription is registered once its confirmation went through
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let message = Message {
+            origin: 7,
+            target: Target::Tag(Bytes::from("users")),
+        };
+        transport.publish(&message).await.unwrap();
+        transport.publish(&message).await.unwrap();
+        assert_eq!(subscription.next().await.unwrap().unwrap(), message);
+        assert_eq!(subscription.next().await.unwrap().unwrap(), message);
+    }
+}
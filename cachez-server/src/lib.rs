@@ -0,0 +1,24 @@
+//! A cachez node: a [`ConcurrentTinyUFO`](cachez::tinyufo::ConcurrentTinyUFO) of byte keys and
+//! values served over the network.
+
+// tonic's Status is large and returned everywhere
+#![allow(clippy::result_large_err)]
+
+pub mod cluster;
+pub mod config;
+pub mod gossip;
+pub mod grpc;
+pub mod http;
+pub mod invalidation;
+pub mod listener;
+pub mod memcache;
+pub mod node;
+pub mod proxy;
+pub mod replica;
+pub mod resp;
+pub mod tiered;
+
+/// Code generated from `proto/cachez/v1/cache.proto`
+pub mod proto {
+    tonic::include_proto!("cachez.v1");
+}
@@ -0,0 +1,217 @@
+//! What the frontends listen on: a TCP socket, optionally behind TLS and a password.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Listening socket of a frontend.
+///
+/// A plain [`TcpListener`] converts into one, without TLS nor password.
+pub struct Listener {
+    tcp: TcpListener,
+    tls: Option<TlsAcceptor>,
+    password: Option<Arc<str>>,
+}
+
+impl Listener {
+    pub fn new(tcp: TcpListener) -> Self {
+        Self {
+            tcp,
+            tls: None,
+            password: None,
+        }
+    }
+
+    /// Only accept TLS connections, see [`tls_acceptor`]
+    pub fn with_tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    /// Require clients to present `password` before anything else, the way the protocol does it
+    pub fn with_password(mut self, password: impl Into<Arc<str>>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    pub fn password(&self) -> Option<&Arc<str>> {
+        self.password.as_ref()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
+    /// The next connection, the TLS handshake left to [`Accepted::stream`] so that a slow client
+    /// doesn't hold the others up
+    pub async fn accept(&self) -> io::Result<Accepted> {
+        let (tcp, _) = self.tcp.accept().await?;
+        let _ = tcp.set_nodelay(true);
+        Ok(Accepted {
+            tcp,
+            tls: self.tls.clone(),
+        })
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(tcp: TcpListener) -> Self {
+        Self::new(tcp)
+    }
+}
+
+/// A connection accepted by a [`Listener`]
+pub struct Accepted {
+    tcp: TcpStream,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Accepted {
+    /// The connection, once through the TLS handshake if any
+    pub async fn stream(self) -> io::Result<Stream> {
+        match self.tls {
+            Some(acceptor) => Ok(Stream::Tls(Box::new(acceptor.accept(self.tcp).await?))),
+            None => Ok(Stream::Plain(self.tcp)),
+        }
+    }
+}
+
+/// A client connection, TLS or not
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            Stream::Tls(tls) => Pin::new(tls).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            Stream::Tls(tls) => Pin::new(tls).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(tcp) => Pin::new(tcp).poll_flush(cx),
+            Stream::Tls(tls) => Pin::new(tls).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            Stream::Tls(tls) => Pin::new(tls).poll_shutdown(cx),
+        }
+    }
+}
+
+/// TLS with the PEM certificate chain at `cert` and private key at `key`
+pub fn tls_acceptor(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+    let invalid = |error| io::Error::new(io::ErrorKind::InvalidData, error);
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| invalid(format!("{}: {error}", cert.display())))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|error| invalid(format!("{}: {error}", key.display())))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|error| invalid(error.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Compare a password in constant time, not to leak how much of it is right
+pub fn verify_password(expected: &str, given: &[u8]) -> bool {
+    let expected = expected.as_bytes();
+    let difference = expected
+        .iter()
+        .zip(given)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    expected.len() == given.len() && difference == 0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    /// A self-signed certificate for localhost, its key, and a client trusting it
+    pub(crate) fn self_signed() -> (TlsAcceptor, TlsConnector) {
+        let cert = rcgen::generate_simple_self_signed(["localhost".to_owned()]).unwrap();
+        let der = cert.cert.der().clone();
+        let key = PrivateKeyDer::try_from(cert.key_pair.serialize_der()).unwrap();
+        let server = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![der.clone()], key)
+            .unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(der).unwrap();
+        let client = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        (
+            TlsAcceptor::from(Arc::new(server)),
+            TlsConnector::from(Arc::new(client)),
+        )
+    }
+
+    #[test]
+    fn test_verify_password() {
+        assert!(verify_password("secret", b"secret"));
+        assert!(!verify_password("secret", b"secreT"));
+        assert!(!verify_password("secret", b"secret!"));
+        assert!(!verify_password("secret", b"secre"));
+        assert!(!verify_password("secret", b""));
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        let (acceptor, connector) = self_signed();
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = Listener::new(tcp).with_tls(acceptor);
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut stream = listener.accept().await.unwrap().stream().await.unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let name = "localhost".try_into().unwrap();
+        let mut stream = connector.connect(name, tcp).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
@@ -0,0 +1,352 @@
+use cachez_server::config::{Config, ConfigError, TlsSettings};
+use cachez_server::gossip::{Gossip, GossipConfig};
+use cachez_server::grpc::CacheService;
+#[cfg(feature = "kafka")]
+use cachez_server::invalidation::KafkaTransport;
+#[cfg(feature = "nats")]
+use cachez_server::invalidation::NatsTransport;
+use cachez_server::invalidation::{InvalidationTransport, Invalidator, RedisTransport};
+use cachez_server::listener::{tls_acceptor, Listener};
+use cachez_server::node::Node;
+use cachez_server::proxy::{self, Proxy, ProxyConfig};
+use cachez_server::replica::Replica;
+use cachez_server::{http, memcache, resp};
+use clap::Parser;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+
+/// Serve a TinyUFO cache over gRPC, and optionally the Redis protocol.
+///
+/// Settings come from `--config`, then `CACHEZ_` environment variables, then these flags.
+/// SIGHUP or `POST /config/reload` over HTTP reload them, applying the cache's tunable settings
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// TOML configuration file
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Address of the gRPC service [default: 127.0.0.1:50051]
+    #[arg(long)]
+    grpc: Option<SocketAddr>,
+    /// Also speak a subset of the Redis protocol (RESP) on this address
+    #[arg(long)]
+    resp: Option<SocketAddr>,
+    /// Also speak the memcached text protocol on this address
+    #[arg(long)]
+    memcache: Option<SocketAddr>,
+    /// Also serve the HTTP admin and data API on this address
+    #[arg(long)]
+    http: Option<SocketAddr>,
+    /// Share invalidations with other nodes through the Redis server at this `host:port`
+    #[arg(long)]
+    invalidation_redis: Option<String>,
+    /// Redis pub/sub channel of the invalidations [default: cachez:invalidations]
+    #[arg(long)]
+    invalidation_channel: Option<String>,
+    /// Gossip invalidations with peers over UDP from this address, without a broker
+    #[arg(long)]
+    gossip: Option<SocketAddr>,
+    /// A node to gossip with, repeat for every peer
+    #[arg(long)]
+    gossip_peer: Vec<SocketAddr>,
+    /// Stand by for the node serving gRPC at this URI, following its sets and deletions
+    #[arg(long)]
+    replicate_from: Option<String>,
+    /// Run as a sharding proxy on this address instead of a node, speaking RESP to clients
+    #[arg(long)]
+    proxy: Option<SocketAddr>,
+    /// A node speaking RESP at `host:port` to spread keys over, repeat for every shard
+    #[arg(long)]
+    backend: Vec<String>,
+    /// Serve every frontend over TLS with this PEM certificate chain
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Require clients to present the password in this file: `AUTH` over RESP,
+    /// `authorization: Bearer` over gRPC and HTTP. The memcached protocol can't be served then
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+    /// Total size of the cached keys and values, in KiB [default: 1048576]
+    #[arg(long)]
+    weight_limit_kib: Option<usize>,
+    /// Expected number of cached entries [default: 1000000]
+    #[arg(long)]
+    capacity: Option<usize>,
+}
+
+impl Args {
+    /// The configuration file and environment, overridden by the flags given
+    fn config(&self) -> Result<Config, ConfigError> {
+        let mut config = Config::load(self.config.as_deref())?;
+        config.grpc = self.grpc.unwrap_or(config.grpc);
+        config.resp = self.resp.or(config.resp);
+        config.memcache = self.memcache.or(config.memcache);
+        config.http = self.http.or(config.http);
+        config.password_file = self.password_file.clone().or(config.password_file);
+        config.replicate_from = self.replicate_from.clone().or(config.replicate_from);
+        if let Some(weight_limit_kib) = self.weight_limit_kib {
+            config.cache.weight_limit_kib = weight_limit_kib;
+        }
+        if let Some(capacity) = self.capacity {
+            config.cache.capacity = capacity;
+        }
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config.tls = Some(TlsSettings {
+                cert: cert.clone(),
+                key: key.clone(),
+            });
+        }
+        config.invalidation.redis = self
+            .invalidation_redis
+            .clone()
+            .or(config.invalidation.redis);
+        if let Some(channel) = &self.invalidation_channel {
+            config.invalidation.channel = channel.clone();
+        }
+        config.gossip.listen = self.gossip.or(config.gossip.listen);
+        if !self.gossip_peer.is_empty() {
+            config.gossip.peers = self.gossip_peer.clone();
+        }
+        config.proxy.listen = self.proxy.or(config.proxy.listen);
+        if !self.backend.is_empty() {
+            config.proxy.backends = self.backend.clone();
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let config = args.config()?;
+    let security = Security::new(&config)?;
+    if let Some(addr) = config.proxy.listen {
+        let proxy = Arc::new(Proxy::new(config.proxy.backends, ProxyConfig::default()));
+        let listener = security.bind(addr).await?;
+        eprintln!("cachez-server: proxy on {addr}");
+        let checks = proxy.clone();
+        tokio::spawn(async move { checks.run().await });
+        spawn_frontend("proxy", proxy::serve(listener, proxy));
+        tokio::signal::ctrl_c().await?;
+        return Ok(());
+    }
+    let node = Arc::new(Node::new(
+        config.cache.weight_limit_kib,
+        config.cache.capacity,
+    ));
+    config.cache.apply(&node);
+    let reloader = Arc::new(Reloader {
+        args,
+        node: node.clone(),
+        stats_interval: watch::Sender::new(config.stats_interval()),
+    });
+    tokio::spawn(log_stats(node.clone(), reloader.stats_interval.subscribe()));
+    #[cfg(unix)]
+    {
+        let mut hangups = signal(SignalKind::hangup())?;
+        let reloader = reloader.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                // errors are logged, nothing else to do with them
+                let _ = reloader.reload();
+            }
+        });
+    }
+
+    if let Some(addr) = config.resp {
+        let listener = security.bind(addr).await?;
+        eprintln!("cachez-server: RESP on {addr}");
+        spawn_frontend("RESP", resp::serve(listener, node.clone()));
+    }
+    if let Some(addr) = config.memcache {
+        let listener = security.bind(addr).await?;
+        eprintln!("cachez-server: memcached on {addr}");
+        spawn_frontend("memcached", memcache::serve(listener, node.clone()));
+    }
+    if let Some(addr) = config.http {
+        let listener = security.bind(addr).await?;
+        eprintln!("cachez-server: HTTP on {addr}");
+        let reloader = reloader.clone();
+        let router = http::with_reload(http::router(node.clone()), move || {
+            reloader.reload().map_err(|error| error.to_string())
+        });
+        spawn_frontend("HTTP", http::serve_router(listener, router));
+    }
+    if let Some(addr) = config.invalidation.redis {
+        eprintln!(
+            "cachez-server: invalidations on redis://{addr} {}",
+            config.invalidation.channel
+        );
+        let transport = RedisTransport::new(addr, config.invalidation.channel.clone());
+        spawn_invalidator(node.clone(), transport);
+    }
+    #[cfg(feature = "nats")]
+    if let Some(addr) = config.invalidation.nats {
+        eprintln!(
+            "cachez-server: invalidations on nats://{addr} {}",
+            config.invalidation.channel
+        );
+        let transport = NatsTransport::new(addr, config.invalidation.channel.clone());
+        spawn_invalidator(node.clone(), transport);
+    }
+    #[cfg(feature = "kafka")]
+    if !config.invalidation.kafka_brokers.is_empty() {
+        eprintln!(
+            "cachez-server: invalidations on kafka {}",
+            config.invalidation.channel
+        );
+        let brokers = config.invalidation.kafka_brokers;
+        let transport = KafkaTransport::new(brokers, config.invalidation.channel.clone());
+        spawn_invalidator(node.clone(), transport);
+    }
+    if let Some(addr) = config.gossip.listen {
+        let gossip_config = GossipConfig {
+            peers: config.gossip.peers,
+            ..Default::default()
+        };
+        let gossip = Gossip::bind(addr, node.clone(), gossip_config).await?;
+        eprintln!("cachez-server: gossip on {addr}");
+        spawn_frontend("gossip", async move { gossip.run().await });
+    }
+    if let Some(primary) = config.replicate_from {
+        eprintln!("cachez-server: replicating {primary}");
+        let replica = Replica::new(node.clone(), primary)?;
+        tokio::spawn(async move { replica.run().await });
+    }
+    eprintln!("cachez-server: gRPC on {}", config.grpc);
+    let mut server = Server::builder();
+    if let Some(tls) = &config.tls {
+        let identity = Identity::from_pem(std::fs::read(&tls.cert)?, std::fs::read(&tls.key)?);
+        server = server.tls_config(ServerTlsConfig::new().identity(identity))?;
+    }
+    server
+        .add_service(CacheService::server_with_password(node, security.password))
+        .serve_with_shutdown(config.grpc, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
+
+/// Applies the runtime-tunable settings of a fresh configuration to the node
+struct Reloader {
+    args: Args,
+    node: Arc<Node>,
+    stats_interval: watch::Sender<Option<Duration>>,
+}
+
+impl Reloader {
+    fn reload(&self) -> Result<(), ConfigError> {
+        let config = self.args.config().inspect_err(|error| {
+            eprintln!("cachez-server: reload failed: {error}");
+        })?;
+        config.cache.apply(&self.node);
+        self.stats_interval.send_replace(config.stats_interval());
+        eprintln!("cachez-server: configuration reloaded");
+        Ok(())
+    }
+}
+
+/// Print the node's statistics every interval, none while it is unset
+async fn log_stats(node: Arc<Node>, mut interval: watch::Receiver<Option<Duration>>) {
+    loop {
+        let current = *interval.borrow_and_update();
+        let Some(period) = current else {
+            if interval.changed().await.is_err() {
+                return;
+            }
+            continue;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(period) => {
+                let stats = node.stats();
+                let cache = stats.cache;
+                eprintln!(
+                    "cachez-server: entries={} weight={}/{} hits={} misses={} evictions={} \
+                     expirations={}",
+                    cache.entries,
+                    cache.weight,
+                    node.weight_limit(),
+                    cache.hits,
+                    cache.misses,
+                    cache.evictions,
+                    stats.expirations,
+                );
+            }
+            changed = interval.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// TLS and password shared by the frontends
+struct Security {
+    tls: Option<TlsAcceptor>,
+    password: Option<Arc<str>>,
+}
+
+impl Security {
+    fn new(config: &Config) -> std::io::Result<Self> {
+        let tls = match &config.tls {
+            Some(tls) => Some(tls_acceptor(&tls.cert, &tls.key)?),
+            None => None,
+        };
+        let password = match &config.password_file {
+            Some(path) => {
+                let password = std::fs::read_to_string(path)?;
+                let password = password.trim_end();
+                if password.is_empty() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{} is empty", path.display()),
+                    ));
+                }
+                Some(password.into())
+            }
+            None => None,
+        };
+        Ok(Self { tls, password })
+    }
+
+    async fn bind(&self, addr: SocketAddr) -> std::io::Result<Listener> {
+        let mut listener = Listener::new(TcpListener::bind(addr).await?);
+        if let Some(tls) = &self.tls {
+            listener = listener.with_tls(tls.clone());
+        }
+        if let Some(password) = &self.password {
+            listener = listener.with_password(password.clone());
+        }
+        Ok(listener)
+    }
+}
+
+fn spawn_invalidator(node: Arc<Node>, transport: impl InvalidationTransport + 'static) {
+    let invalidator = Invalidator::new(node, transport);
+    tokio::spawn(async move { invalidator.run().await });
+}
+
+fn spawn_frontend(
+    name: &'static str,
+    serve: impl Future<Output = std::io::Result<()>> + Send + 'static,
+) {
+    tokio::spawn(async move {
+        if let Err(error) = serve.await {
+            eprintln!("cachez-server: {name} stopped: {error}");
+        }
+    });
+}
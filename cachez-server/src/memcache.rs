@@ -0,0 +1,564 @@
+//! memcached text protocol frontend, so that memcached client pools can be pointed at a node.
+//!
+//! Supported: `get`/`gets` (without CAS, every item reports a CAS of 0), `set`, `delete`,
+//! `flush_all` without delay, `stats`, `version` and `quit`, all with `noreply` where memcached
+//! takes it. Other storage commands and the meta protocol are answered with `ERROR`.
+//!
+//! Leases (see [`Node::lease_get`]) are spoken the way mcrouter does: `lease-get <key>` answers a
+//! miss with `LVALUE <key> <token> 0 0` and an empty block, a token of 1 meaning that another
+//! client is filling the key. `lease-set <key> <token> <flags> <exptime> <bytes> [noreply]` is a
+//! `set` answered `NOT_STORED` once the lease is gone.
+
+use crate::listener::Listener;
+use crate::node::{LeaseGet, Node, SetOptions};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// memcached's default item size limit (`-I`)
+pub const MAX_ITEM_SIZE: usize = 1024 * 1024;
+/// memcached's key length limit
+const MAX_KEY_LEN: usize = 250;
+/// Longest command line accepted
+const MAX_LINE_LEN: usize = 2048;
+/// An `exptime` above 30 days is a unix timestamp rather than a number of seconds
+const RELATIVE_EXPTIME_MAX: i64 = 60 * 60 * 24 * 30;
+/// Lease token of a miss another client is filling
+const HOT_MISS_TOKEN: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Get {
+        keys: Vec<Bytes>,
+        cas: bool,
+    },
+    Set {
+        key: Bytes,
+        flags: u32,
+        exptime: i64,
+        data: Bytes,
+        noreply: bool,
+    },
+    Delete {
+        key: Bytes,
+        noreply: bool,
+    },
+    LeaseGet {
+        key: Bytes,
+    },
+    LeaseSet {
+        key: Bytes,
+        token: u64,
+        flags: u32,
+        exptime: i64,
+        data: Bytes,
+        noreply: bool,
+    },
+    FlushAll {
+        noreply: bool,
+    },
+    Stats,
+    Version,
+    Quit,
+}
+
+/// Why a command line couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Unknown command, answered `ERROR`
+    Unknown,
+    /// Malformed command, answered `CLIENT_ERROR`
+    Client(&'static str),
+    /// The stream can't be resynchronized, answered `SERVER_ERROR` then the connection is closed
+    Fatal(&'static str),
+}
+
+/// Take the next complete command out of `buf`, `None` if more bytes are needed
+pub fn parse_command(buf: &mut BytesMut) -> Result<Option<Command>, Error> {
+    let Some(end) = buf.windows(2).position(|w| w == b"\r\n") else {
+        if buf.len() > MAX_LINE_LEN {
+            return Err(Error::Fatal("line too long"));
+        }
+        return Ok(None);
+    };
+    if end > MAX_LINE_LEN {
+        return Err(Error::Fatal("line too long"));
+    }
+    let line = buf[..end].to_vec();
+    let mut tokens = line
+        .split(|&b| b == b' ')
+        .filter(|token| !token.is_empty())
+        .map(Bytes::copy_from_slice);
+    let Some(name) = tokens.next() else {
+        buf.advance(end + 2);
+        return Err(Error::Unknown);
+    };
+    let args: Vec<Bytes> = tokens.collect();
+
+    if matches!(&name[..], b"set" | b"lease-set") {
+        // the data block follows the line, nothing is consumed until both arrived
+        return parse_set(buf, end + 2, args, &name[..] == b"lease-set");
+    }
+    buf.advance(end + 2);
+    let (args, noreply) = split_noreply(args);
+    let command = match (&name[..], &args[..]) {
+        (b"get" | b"gets", [_, ..]) => {
+            if args.iter().any(|key| key.len() > MAX_KEY_LEN) {
+                return Err(Error::Client("key too long"));
+            }
+            Command::Get {
+                cas: &name[..] == b"gets",
+                keys: args,
+            }
+        }
+        (b"delete", [key]) => Command::Delete {
+            key: key.clone(),
+            noreply,
+        },
+        // `delete <key> 0` is still sent by old clients
+        (b"delete", [key, zero]) if &zero[..] == b"0" => Command::Delete {
+            key: key.clone(),
+            noreply,
+        },
+        (b"lease-get", [key]) if key.len() > MAX_KEY_LEN => {
+            return Err(Error::Client("key too long"));
+        }
+        (b"lease-get", [key]) => Command::LeaseGet { key: key.clone() },
+        (b"flush_all", []) => Command::FlushAll { noreply },
+        (b"flush_all", [delay]) if &delay[..] == b"0" => Command::FlushAll { noreply },
+        (b"flush_all", [_]) => return Err(Error::Client("flush_all delay is not supported")),
+        (b"stats", []) => Command::Stats,
+        (b"version", []) => Command::Version,
+        (b"quit", []) => Command::Quit,
+        (
+            b"get" | b"gets" | b"delete" | b"lease-get" | b"flush_all" | b"stats" | b"version"
+            | b"quit",
+            _,
+        ) => {
+            return Err(Error::Client("bad command line format"));
+        }
+        _ => return Err(Error::Unknown),
+    };
+    Ok(Some(command))
+}
+
+/// `set <key> <flags> <exptime> <bytes> [noreply]\r\n<data>\r\n`, or with `lease`
+/// `lease-set <key> <token> <flags> <exptime> <bytes> [noreply]\r\n<data>\r\n`
+fn parse_set(
+    buf: &mut BytesMut,
+    line_len: usize,
+    args: Vec<Bytes>,
+    lease: bool,
+) -> Result<Option<Command>, Error> {
+    let (mut args, noreply) = split_noreply(args);
+    // the token goes after the key
+    let token = match lease {
+        true if args.len() > 1 => parse::<u64>(&args.remove(1)),
+        true => None,
+        false => Some(0),
+    };
+    let [key, flags, exptime, len] = &args[..] else {
+        buf.advance(line_len);
+        return Err(Error::Client("bad command line format"));
+    };
+    let (Some(token), Some(flags), Some(exptime), Some(len)) =
+        (token, parse(flags), parse(exptime), parse::<usize>(len))
+    else {
+        buf.advance(line_len);
+        return Err(Error::Client("bad command line format"));
+    };
+    if len > MAX_ITEM_SIZE {
+        // the data block can't be told apart from commands without buffering it, give up
+        return Err(Error::Fatal("object too large for cache"));
+    }
+    if buf.len() < line_len + len + 2 {
+        return Ok(None);
+    }
+    let valid_key = key.len() <= MAX_KEY_LEN;
+    let valid_data = &buf[line_len + len..line_len + len + 2] == b"\r\n";
+    let key = key.clone();
+    buf.advance(line_len);
+    let data = buf.split_to(len).freeze();
+    buf.advance(2);
+    match (valid_key, valid_data) {
+        (false, _) => Err(Error::Client("key too long")),
+        (_, false) => Err(Error::Client("bad data chunk")),
+        _ if lease => Ok(Some(Command::LeaseSet {
+            key,
+            token,
+            flags,
+            exptime,
+            data,
+            noreply,
+        })),
+        _ => Ok(Some(Command::Set {
+            key,
+            flags,
+            exptime,
+            data,
+            noreply,
+        })),
+    }
+}
+
+fn split_noreply(mut args: Vec<Bytes>) -> (Vec<Bytes>, bool) {
+    let noreply = args.last().is_some_and(|arg| &arg[..] == b"noreply");
+    if noreply {
+        args.pop();
+    }
+    (args, noreply)
+}
+
+fn parse<T: std::str::FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+/// Accept memcached clients on `listener` until it fails. The text protocol has no
+/// authentication, a listener with a password is refused
+pub async fn serve(listener: impl Into<Listener>, node: Arc<Node>) -> io::Result<()> {
+    let listener = listener.into();
+    if listener.password().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the memcached text protocol has no authentication",
+        ));
+    }
+    loop {
+        let accepted = listener.accept().await?;
+        let node = node.clone();
+        tokio::spawn(async move {
+            // a client going away is not the server's problem
+            if let Ok(stream) = accepted.stream().await {
+                let _ = connection(stream, &node).await;
+            }
+        });
+    }
+}
+
+async fn connection(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    node: &Node,
+) -> io::Result<()> {
+    let mut input = BytesMut::with_capacity(4096);
+    let mut output = BytesMut::new();
+    loop {
+        if stream.read_buf(&mut input).await? == 0 {
+            return Ok(());
+        }
+        // answer every pipelined command that arrived before writing
+        loop {
+            match parse_command(&mut input) {
+                Ok(Some(Command::Quit)) => {
+                    stream.write_all(&output).await?;
+                    return stream.shutdown().await;
+                }
+                Ok(Some(command)) => execute(node, command, &mut output),
+                Ok(None) => break,
+                Err(Error::Unknown) => output.put_slice(b"ERROR\r\n"),
+                Err(Error::Client(message)) => put_line(&mut output, b"CLIENT_ERROR ", message),
+                Err(Error::Fatal(message)) => {
+                    put_line(&mut output, b"SERVER_ERROR ", message);
+                    stream.write_all(&output).await?;
+                    return stream.shutdown().await;
+                }
+            }
+        }
+        stream.write_all(&output).await?;
+        // TLS may hold on to part of it otherwise
+        stream.flush().await?;
+        output.clear();
+    }
+}
+
+/// Run one command, writing its reply to `out`
+pub fn execute(node: &Node, command: Command, out: &mut BytesMut) {
+    match command {
+        Command::Get { keys, cas } => {
+            for key in keys {
+                if let Some((data, flags)) = node.get_with_flags(&key) {
+                    put_value(out, &key, flags, &data, cas);
+                }
+            }
+            out.put_slice(b"END\r\n");
+        }
+        Command::LeaseGet { key } => {
+            let token = match node.lease_get(&key) {
+                LeaseGet::Hit(data, flags) => {
+                    put_value(out, &key, flags, &data, false);
+                    out.put_slice(b"END\r\n");
+                    return;
+                }
+                LeaseGet::Miss(token) => token,
+                LeaseGet::HotMiss => HOT_MISS_TOKEN,
+            };
+            out.put_slice(b"LVALUE ");
+            out.put_slice(&key);
+            out.put_slice(format!(" {token} 0 0\r\n\r\nEND\r\n").as_bytes());
+        }
+        Command::Set {
+            key,
+            flags,
+            exptime,
+            data,
+            noreply,
+        } => {
+            match ttl(exptime) {
+                Some(ttl) => {
+                    let options = SetOptions {
+                        flags,
+                        ttl,
+                        ..Default::default()
+                    };
+                    node.set_with(key, data, options);
+                }
+                // already expired, it replaces and hides any older value
+                None => {
+                    node.delete(&key);
+                }
+            }
+            if !noreply {
+                out.put_slice(b"STORED\r\n");
+            }
+        }
+        Command::LeaseSet {
+            key,
+            token,
+            flags,
+            exptime,
+            data,
+            noreply,
+        } => {
+            let stored = match ttl(exptime) {
+                Some(ttl) => {
+                    let options = SetOptions {
+                        flags,
+                        ttl,
+                        ..Default::default()
+                    };
+                    node.lease_set(key, data, options, token)
+                }
+                // as with `set`, which any client may delete with anyway
+                None => {
+                    node.delete(&key);
+                    true
+                }
+            };
+            if !noreply {
+                out.put_slice(if stored {
+                    b"STORED\r\n"
+                } else {
+                    b"NOT_STORED\r\n"
+                });
+            }
+        }
+        Command::Delete { key, noreply } => {
+            let deleted = node.delete(&key);
+            if !noreply {
+                out.put_slice(if deleted {
+                    b"DELETED\r\n"
+                } else {
+                    b"NOT_FOUND\r\n"
+                });
+            }
+        }
+        Command::FlushAll { noreply } => {
+            node.clear();
+            if !noreply {
+                out.put_slice(b"OK\r\n");
+            }
+        }
+        Command::Stats => stats(node, out),
+        Command::Version => {
+            out.put_slice(format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).as_bytes())
+        }
+        Command::Quit => {}
+    }
+}
+
+/// `Some(None)` never expires, `None` is already expired
+fn ttl(exptime: i64) -> Option<Option<Duration>> {
+    match exptime {
+        0 => Some(None),
+        ..0 => None,
+        1..=RELATIVE_EXPTIME_MAX => Some(Some(Duration::from_secs(exptime as u64))),
+        _ => {
+            let at = UNIX_EPOCH + Duration::from_secs(exptime as u64);
+            at.duration_since(SystemTime::now()).ok().map(Some)
+        }
+    }
+}
+
+fn stats(node: &Node, out: &mut BytesMut) {
+    let stats = node.stats();
+    let cache = stats.cache;
+    let lines = [
+        ("pid", std::process::id() as u64),
+        ("curr_items", cache.entries as u64),
+        ("total_items", cache.inserts + cache.updates),
+        // weights are KiB unless clients override them, this is an estimate
+        ("bytes", cache.weight as u64 * 1024),
+        ("limit_maxbytes", node.weight_limit() as u64 * 1024),
+        ("get_hits", cache.hits),
+        ("get_misses", cache.misses),
+        ("get_expired", stats.expirations),
+        ("evictions", cache.evictions),
+    ];
+    for (name, value) in lines {
+        out.put_slice(format!("STAT {name} {value}\r\n").as_bytes());
+    }
+    out.put_slice(format!("STAT version {}\r\n", env!("CARGO_PKG_VERSION")).as_bytes());
+    out.put_slice(b"END\r\n");
+}
+
+fn put_value(out: &mut BytesMut, key: &[u8], flags: u32, data: &[u8], cas: bool) {
+    out.put_slice(b"VALUE ");
+    out.put_slice(key);
+    let cas = if cas { " 0" } else { "" };
+    out.put_slice(format!(" {flags} {}{cas}\r\n", data.len()).as_bytes());
+    out.put_slice(data);
+    out.put_slice(b"\r\n");
+}
+
+fn put_line(out: &mut BytesMut, prefix: &[u8], message: &str) {
+    out.put_slice(prefix);
+    out.put_slice(message.as_bytes());
+    out.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_parse() {
+        let mut buf =
+            BytesMut::from(&b"set a 5 0 2 noreply\r\nhi\r\nget a b\r\nset b 0 0 3\r\nab"[..]);
+        assert_eq!(
+            parse_command(&mut buf),
+            Ok(Some(Command::Set {
+                key: Bytes::from("a"),
+                flags: 5,
+                exptime: 0,
+                data: Bytes::from("hi"),
+                noreply: true,
+            }))
+        );
+        assert_eq!(
+            parse_command(&mut buf),
+            Ok(Some(Command::Get {
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+                cas: false,
+            }))
+        );
+        // the data block is incomplete
+        assert_eq!(parse_command(&mut buf), Ok(None));
+        buf.extend_from_slice(b"cX\r\nincr a 1\r\ndelete\r\n");
+        assert_eq!(
+            parse_command(&mut buf),
+            Err(Error::Client("bad data chunk"))
+        );
+        assert_eq!(parse_command(&mut buf), Err(Error::Unknown));
+        assert_eq!(
+            parse_command(&mut buf),
+            Err(Error::Client("bad command line format"))
+        );
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"lease-set a 7 1 0 2\r\nhi\r\nlease-set a 1 0 2\r\n"[..]);
+        assert_eq!(
+            parse_command(&mut buf),
+            Ok(Some(Command::LeaseSet {
+                key: Bytes::from("a"),
+                token: 7,
+                flags: 1,
+                exptime: 0,
+                data: Bytes::from("hi"),
+                noreply: false,
+            }))
+        );
+        assert_eq!(
+            parse_command(&mut buf),
+            Err(Error::Client("bad command line format"))
+        );
+
+        let mut buf = BytesMut::from(&b"set a 0 0 99999999\r\n"[..]);
+        assert!(matches!(parse_command(&mut buf), Err(Error::Fatal(_))));
+    }
+
+    #[tokio::test]
+    async fn test_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node = Arc::new(Node::new(1024, 100));
+        tokio::spawn(serve(listener, node.clone()));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream
+            .write_all(
+                b"set a 42 0 1\r\n1\r\nset b 0 100 2 noreply\r\n22\r\nset c 0 -1 1\r\n3\r\n\
+                  get a b c\r\ngets a\r\ndelete a\r\ndelete a\r\nflush_all\r\nget b\r\nquit\r\n",
+            )
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        stream.read_to_end(&mut replies).await.unwrap();
+        assert_eq!(
+            String::from_utf8(replies).unwrap(),
+            "STORED\r\nSTORED\r\n\
+             VALUE a 42 1\r\n1\r\nVALUE b 0 2\r\n22\r\nEND\r\n\
+             VALUE a 42 1 0\r\n1\r\nEND\r\n\
+             DELETED\r\nNOT_FOUND\r\nOK\r\nEND\r\n"
+        );
+        assert_eq!(node.stats().cache.entries, 0);
+    }
+
+    #[test]
+    fn test_leases() {
+        let node = Node::new(1024, 100);
+        let mut out = BytesMut::new();
+        execute(
+            &node,
+            Command::LeaseGet {
+                key: Bytes::from("a"),
+            },
+            &mut out,
+        );
+        let reply = String::from_utf8(out.split().to_vec()).unwrap();
+        let token: u64 = reply.split(' ').nth(2).unwrap().parse().unwrap();
+        assert_eq!(reply, format!("LVALUE a {token} 0 0\r\n\r\nEND\r\n"));
+        execute(
+            &node,
+            Command::LeaseGet {
+                key: Bytes::from("a"),
+            },
+            &mut out,
+        );
+        assert_eq!(&out.split()[..], b"LVALUE a 1 0 0\r\n\r\nEND\r\n");
+
+        let lease_set = |token| Command::LeaseSet {
+            key: Bytes::from("a"),
+            token,
+            flags: 3,
+            exptime: 0,
+            data: Bytes::from("1"),
+            noreply: false,
+        };
+        execute(&node, lease_set(token + 1), &mut out);
+        execute(&node, lease_set(token), &mut out);
+        execute(&node, lease_set(token), &mut out);
+        execute(
+            &node,
+            Command::LeaseGet {
+                key: Bytes::from("a"),
+            },
+            &mut out,
+        );
+        assert_eq!(
+            &out[..],
+            b"NOT_STORED\r\nSTORED\r\nNOT_STORED\r\nVALUE a 3 1\r\n1\r\nEND\r\n"
+        );
+    }
+}
@@ -0,0 +1,916 @@
+//! The cache a node serves, shared by every frontend.
+
+use crate::resp::glob_match;
+use bytes::Bytes;
+use cachez::clock::{Clock, StdClock};
+use cachez::tinyufo::{CacheStats, ConcurrentTinyUFO, Weight};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+const FINGERPRINT_SEED: u64 = 0x6361_6368_657a_6670;
+
+/// Invalidations buffered per watcher, slower watchers miss older ones
+const WATCH_BUFFER: usize = 1024;
+/// Changes buffered per follower of [`Node::changes`], slower followers must start over
+const CHANGES_BUFFER: usize = 64 * 1024;
+/// How long a lease from [`Node::lease_get`] holds off other fillers
+pub const LEASE_TTL: Duration = Duration::from_secs(10);
+/// Leases outstanding before the expired ones are dropped
+const LEASE_PRUNE_AT: usize = 1024;
+
+#[derive(Clone)]
+struct Value {
+    // TinyUFO only keeps key hashes, the full key tells collisions apart
+    key: Bytes,
+    data: Bytes,
+    // opaque to the node, memcached clients keep their serialization format in there
+    flags: u32,
+    weight: Weight,
+    expires_at: Option<Duration>,
+    // shared by the clones handed out on reads, reset by a set
+    reads: Arc<AtomicU64>,
+    tags: Arc<[Bytes]>,
+}
+
+/// Why a key was invalidated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Deleted,
+    Updated,
+}
+
+/// A key whose cached value must not be served anymore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalidation {
+    pub key: Bytes,
+    pub reason: Reason,
+    /// Applied on behalf of another node, see [`Node::delete_remote`]
+    pub remote: bool,
+}
+
+/// A change to the content of a node, in the order of [`ChangeEvent::sequence`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Set(Entry),
+    Delete(Bytes),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// Counts the changes of the node, see [`Node::sequence`]
+    pub sequence: u64,
+    /// Wall clock time of the change, in ms since the unix epoch
+    pub at_ms: u64,
+    pub change: Change,
+}
+
+/// A cached value with what it was set with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: Bytes,
+    pub data: Bytes,
+    pub flags: u32,
+    pub weight: Weight,
+    /// Time left before it expires
+    pub ttl: Option<Duration>,
+    pub tags: Vec<Bytes>,
+}
+
+impl Entry {
+    /// Options to set the entry again with, on another node
+    pub fn options(&self) -> SetOptions {
+        SetOptions {
+            flags: self.flags,
+            weight: Some(self.weight),
+            ttl: self.ttl,
+            tags: self.tags.clone(),
+        }
+    }
+}
+
+/// How [`Node::set_with`] caches a value
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetOptions {
+    /// Opaque to the node, returned by [`Node::get_with_flags`]
+    pub flags: u32,
+    /// Defaults to the size of the key and value in KiB
+    pub weight: Option<Weight>,
+    pub ttl: Option<Duration>,
+    /// Groups the key can be deleted with, see [`Node::delete_tag`]
+    pub tags: Vec<Bytes>,
+}
+
+/// The cached keys, ordered by a hash of themselves, which is the cursor of `scan`, and by
+/// themselves for prefixes
+#[derive(Default)]
+struct KeyIndex {
+    by_hash: BTreeSet<(u64, Bytes)>,
+    by_name: BTreeSet<Bytes>,
+}
+
+impl KeyIndex {
+    fn insert(&mut self, key: &Bytes) {
+        self.by_hash.insert((fingerprint(key), key.clone()));
+        self.by_name.insert(key.clone());
+    }
+
+    fn remove(&mut self, key: &Bytes) {
+        self.by_hash.remove(&(fingerprint(key), key.clone()));
+        self.by_name.remove(key);
+    }
+
+    fn with_prefix(&self, prefix: &[u8]) -> Vec<Bytes> {
+        self.by_name
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+/// What [`Node::lease_get`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseGet {
+    /// The value, with its flags
+    Hit(Bytes, u32),
+    /// Not cached, the caller is to fill it with [`Node::lease_set`] and this token
+    Miss(u64),
+    /// Not cached and already leased to another caller, who is filling it: retry shortly
+    HotMiss,
+}
+
+/// Outstanding leases by key
+#[derive(Default)]
+struct Leases {
+    by_key: HashMap<Bytes, (u64, Duration)>,
+    // doubles with the leases that aren't expired, so that pruning stays amortized
+    prune_at: usize,
+}
+
+/// Statistics of a [`Node`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeStats {
+    /// Expired reads count as misses
+    pub cache: CacheStats,
+    pub expirations: u64,
+}
+
+/// Byte keys and values in a [`ConcurrentTinyUFO`], with per entry TTLs checked lazily
+pub struct Node {
+    cache: ConcurrentTinyUFO<Bytes, Value>,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+    expirations: AtomicU64,
+    invalidations: broadcast::Sender<Invalidation>,
+    keys: Mutex<KeyIndex>,
+    tags: Mutex<HashMap<Bytes, HashSet<Bytes>>>,
+    changes: broadcast::Sender<ChangeEvent>,
+    sequence: AtomicU64,
+    // TTL of the sets without one in ms, 0 for none
+    default_ttl_ms: AtomicU64,
+    leases: Mutex<Leases>,
+}
+
+impl Node {
+    /// `weight_limit_kib` bounds the total size of the keys and values, in KiB
+    pub fn new(weight_limit_kib: usize, capacity: usize) -> Self {
+        Self::with_clock(weight_limit_kib, capacity, Arc::new(StdClock::new()))
+    }
+
+    pub fn with_clock(weight_limit_kib: usize, capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::new(weight_limit_kib, capacity),
+            capacity,
+            clock,
+            expirations: AtomicU64::new(0),
+            invalidations: broadcast::channel(WATCH_BUFFER).0,
+            keys: Mutex::default(),
+            tags: Mutex::default(),
+            changes: broadcast::channel(CHANGES_BUFFER).0,
+            sequence: AtomicU64::new(0),
+            default_ttl_ms: AtomicU64::new(0),
+            leases: Mutex::default(),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.get_with_flags(key).map(|(data, _)| data)
+    }
+
+    /// [`Self::get`] with the flags the value was set with
+    pub fn get_with_flags(&self, key: &[u8]) -> Option<(Bytes, u32)> {
+        let value = self.cache.get(key)?;
+        if value.key != key {
+            return None;
+        }
+        if self.expired(&value) {
+            if self.cache.remove(key).is_some() {
+                self.unindex(&value);
+                self.expirations.fetch_add(1, Relaxed);
+            }
+            return None;
+        }
+        value.reads.fetch_add(1, Relaxed);
+        Some((value.data, value.flags))
+    }
+
+    /// Time left before `key` expires: `None` if it isn't cached, `Some(None)` if it never expires
+    ///
+    /// Doesn't count as an access.
+    pub fn ttl(&self, key: &[u8]) -> Option<Option<Duration>> {
+        let value = self.cache.peek(key).filter(|value| value.key == key)?;
+        let now = self.clock.now();
+        match value.expires_at {
+            Some(at) if at <= now => None,
+            Some(at) => Some(Some(at - now)),
+            None => Some(None),
+        }
+    }
+
+    /// Look at up to `count` keys from `cursor` on, starting from 0. Returns the cursor to continue
+    /// from, 0 once every key was visited, and the keys still cached among them.
+    ///
+    /// Like Redis' SCAN, a key cached during the whole iteration is returned at least once, keys
+    /// set or evicted meanwhile may or may not be.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        let mut visited = self
+            .index()
+            .by_hash
+            .range((cursor, Bytes::new())..)
+            .take(count.max(1) + 1)
+            .map(|(_, key)| key.clone())
+            .collect::<Vec<_>>();
+        let next = match visited.len() > count.max(1) {
+            true => visited.pop().map_or(0, |key| fingerprint(&key)),
+            false => 0,
+        };
+        visited.retain(|key| self.ttl(key).is_some());
+        (next, visited)
+    }
+
+    /// The cached value of `key` with what it was set with, doesn't count as an access
+    pub fn entry(&self, key: &[u8]) -> Option<Entry> {
+        let value = self.cache.peek(key).filter(|value| value.key == key)?;
+        let now = self.clock.now();
+        let ttl = match value.expires_at {
+            Some(at) if at <= now => return None,
+            Some(at) => Some(at - now),
+            None => None,
+        };
+        Some(Entry {
+            key: value.key,
+            data: value.data,
+            flags: value.flags,
+            weight: value.weight,
+            ttl,
+            tags: value.tags.to_vec(),
+        })
+    }
+
+    /// Every key in the cache, some possibly expired
+    pub fn keys(&self) -> Vec<Bytes> {
+        let index = self.index();
+        index.by_hash.iter().map(|(_, key)| key.clone()).collect()
+    }
+
+    /// The `limit` most read keys still cached, with their reads since they were last set
+    ///
+    /// Walks every key, meant for debugging rather than serving traffic.
+    pub fn hot_keys(&self, limit: usize) -> Vec<(Bytes, u64)> {
+        let now = self.clock.now();
+        let mut hot: Vec<_> = self
+            .keys()
+            .into_iter()
+            .filter_map(|key| {
+                let value = self.cache.peek(&key).filter(|value| value.key == key)?;
+                let expired = value.expires_at.is_some_and(|at| at <= now);
+                (!expired).then(|| (key, value.reads.load(Relaxed)))
+            })
+            .collect();
+        hot.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hot.truncate(limit);
+        hot
+    }
+
+    /// Cache `data` under `key`, `weight` defaults to the size of both in KiB and `ttl` to the
+    /// [default one](Self::set_default_ttl)
+    pub fn set(&self, key: Bytes, data: Bytes, weight: Option<Weight>, ttl: Option<Duration>) {
+        let options = SetOptions {
+            weight,
+            ttl,
+            ..Default::default()
+        };
+        self.set_with(key, data, options);
+    }
+
+    /// [`Self::set`] with flags and tags. Supersedes any lease on the key
+    pub fn set_with(&self, key: Bytes, data: Bytes, options: SetOptions) {
+        self.leases().by_key.remove(&key);
+        self.store(key, data, options);
+    }
+
+    /// [`Self::get_with_flags`], handing out a lease on a miss so that a single caller fills the
+    /// key while the others wait for it.
+    ///
+    /// The lease lasts [`LEASE_TTL`] unless the key is set or deleted in the meantime, so that a
+    /// filler that read its data before a deletion can't cache it afterwards. Tokens are never 0
+    /// nor 1.
+    pub fn lease_get(&self, key: &[u8]) -> LeaseGet {
+        if let Some((data, flags)) = self.get_with_flags(key) {
+            return LeaseGet::Hit(data, flags);
+        }
+        let now = self.clock.now();
+        let mut leases = self.leases();
+        if let Some((_, expires_at)) = leases.by_key.get(key) {
+            if *expires_at > now {
+                return LeaseGet::HotMiss;
+            }
+        }
+        if leases.by_key.len() >= leases.prune_at {
+            leases.by_key.retain(|_, (_, expires_at)| *expires_at > now);
+            leases.prune_at = (leases.by_key.len() * 2).max(LEASE_PRUNE_AT);
+        }
+        let token = fastrand::u64(2..);
+        let lease = (token, now + LEASE_TTL);
+        leases.by_key.insert(Bytes::copy_from_slice(key), lease);
+        LeaseGet::Miss(token)
+    }
+
+    /// [`Self::set_with`] on behalf of the holder of the lease `token`, returns whether it still
+    /// held it. Otherwise the key was set or deleted since, or the lease expired, and nothing is
+    /// cached
+    pub fn lease_set(&self, key: Bytes, data: Bytes, options: SetOptions, token: u64) -> bool {
+        let mut leases = self.leases();
+        let held = leases
+            .by_key
+            .get(&key)
+            .is_some_and(|&(held, expires_at)| held == token && expires_at > self.clock.now());
+        if !held {
+            return false;
+        }
+        leases.by_key.remove(&key);
+        // under the lock, so that a deletion is either seen by the lease or applied after the set
+        self.store(key, data, options);
+        true
+    }
+
+    fn store(&self, key: Bytes, data: Bytes, options: SetOptions) {
+        let weight = options
+            .weight
+            .unwrap_or_else(|| default_weight(key.len() + data.len()));
+        let value = Value {
+            key: key.clone(),
+            data,
+            flags: options.flags,
+            weight,
+            expires_at: options
+                .ttl
+                .or_else(|| self.default_ttl())
+                .map(|ttl| self.clock.now() + ttl),
+            reads: Arc::default(),
+            tags: options.tags.into(),
+        };
+        self.record(|| {
+            Change::Set(Entry {
+                key: key.clone(),
+                data: value.data.clone(),
+                flags: value.flags,
+                weight,
+                ttl: options.ttl,
+                tags: value.tags.to_vec(),
+            })
+        });
+        let replaced = self.cache.peek(&key);
+        // indexed before being cached so that an eviction racing with this put can't leave a
+        // stale key in the indexes
+        self.index().insert(&key);
+        self.index_tags(&key, &value.tags);
+        let tags = value.tags.clone();
+        self.cache
+            .put_evicting(key.clone(), weight, value, |_, evicted| {
+                self.unindex(&evicted);
+            });
+        match &replaced {
+            // a colliding key was overwritten
+            Some(replaced) if replaced.key != key => self.unindex(replaced),
+            Some(replaced) => {
+                let dropped: Vec<_> = replaced
+                    .tags
+                    .iter()
+                    .filter(|tag| !tags.contains(tag))
+                    .cloned()
+                    .collect();
+                self.unindex_tags(&key, &dropped);
+            }
+            None => {}
+        }
+        if replaced.is_some() {
+            self.invalidate(key, Reason::Updated, false);
+        }
+    }
+
+    /// Returns whether the key was cached, an expired key is dropped but wasn't
+    pub fn delete(&self, key: &[u8]) -> bool {
+        self.remove(key, false)
+    }
+
+    /// [`Self::delete`] on behalf of another node, watchers see the invalidation as remote so
+    /// that it isn't sent back
+    pub fn delete_remote(&self, key: &[u8]) -> bool {
+        self.remove(key, true)
+    }
+
+    /// Delete every key set with `tag`, returns how many were cached
+    pub fn delete_tag(&self, tag: &[u8]) -> usize {
+        self.remove_tag(tag, false)
+    }
+
+    /// [`Self::delete_tag`] on behalf of another node, see [`Self::delete_remote`]
+    pub fn delete_tag_remote(&self, tag: &[u8]) -> usize {
+        self.remove_tag(tag, true)
+    }
+
+    fn remove(&self, key: &[u8], remote: bool) -> bool {
+        // whoever is filling the key read its data before the deletion
+        self.leases().by_key.remove(key);
+        // leave another key with the same hash alone
+        if self.cache.peek(key).is_none_or(|value| value.key != key) {
+            return false;
+        }
+        match self.cache.remove(key) {
+            Some(value) => {
+                let expired = self.expired(&value);
+                self.record(|| Change::Delete(value.key.clone()));
+                self.unindex(&value);
+                self.invalidate(value.key, Reason::Deleted, remote);
+                !expired
+            }
+            None => false,
+        }
+    }
+
+    fn remove_tag(&self, tag: &[u8], remote: bool) -> usize {
+        let keys = self.tag_index().remove(tag).unwrap_or_default();
+        keys.iter()
+            .filter(|key| {
+                // the key may have been set again without the tag
+                let tagged = self
+                    .cache
+                    .peek(&key[..])
+                    .is_some_and(|value| value.key == key && value.tags.iter().any(|t| t == tag));
+                tagged && self.remove(key, remote)
+            })
+            .count()
+    }
+
+    /// Delete every key starting with `prefix`, returns how many were cached. Looks up the
+    /// keys in an ordered index rather than scanning them all
+    pub fn delete_prefix(&self, prefix: &[u8]) -> usize {
+        self.remove_prefix(prefix, false)
+    }
+
+    /// [`Self::delete_prefix`] on behalf of another node, see [`Self::delete_remote`]
+    pub fn delete_prefix_remote(&self, prefix: &[u8]) -> usize {
+        self.remove_prefix(prefix, true)
+    }
+
+    /// Delete every key matching the glob `pattern` (`*`, `?`, `[...]` and `\` escapes, as in
+    /// Redis), returns how many were cached. Only the keys starting with the pattern's literal
+    /// prefix are visited
+    pub fn delete_matching(&self, pattern: &[u8]) -> usize {
+        let literal = pattern
+            .iter()
+            .position(|b| b"*?[\\".contains(b))
+            .unwrap_or(pattern.len());
+        let prefix = &pattern[..literal];
+        // matched without the lock, the leases are only held to take the matches out
+        let leased: Vec<Bytes> = self
+            .leases()
+            .by_key
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        let leased: Vec<_> = leased
+            .into_iter()
+            .filter(|key| glob_match(pattern, key))
+            .collect();
+        let mut leases = self.leases();
+        for key in &leased {
+            leases.by_key.remove(key);
+        }
+        drop(leases);
+        let keys = self.index().with_prefix(prefix);
+        keys.iter()
+            .filter(|key| glob_match(pattern, key) && self.remove(key, false))
+            .count()
+    }
+
+    fn remove_prefix(&self, prefix: &[u8], remote: bool) -> usize {
+        self.leases()
+            .by_key
+            .retain(|key, _| !key.starts_with(prefix));
+        let keys = self.index().with_prefix(prefix);
+        keys.iter().filter(|key| self.remove(key, remote)).count()
+    }
+
+    /// Delete every key, returns how many were cached
+    pub fn clear(&self) -> usize {
+        self.remove_all(false)
+    }
+
+    /// [`Self::clear`] on behalf of another node, see [`Self::delete_remote`]
+    pub fn clear_remote(&self) -> usize {
+        self.remove_all(true)
+    }
+
+    /// Delete the keys whose [`fingerprint`] is `fingerprint` on behalf of another node, see
+    /// [`Self::delete_remote`]. Returns how many were cached
+    pub fn delete_fingerprint_remote(&self, fingerprint: u64) -> usize {
+        let keys: Vec<_> = self
+            .index()
+            .by_hash
+            .range((fingerprint, Bytes::new())..)
+            .take_while(|(hash, _)| *hash == fingerprint)
+            .map(|(_, key)| key.clone())
+            .collect();
+        self.leases()
+            .by_key
+            .retain(|key, _| self::fingerprint(key) != fingerprint);
+        keys.iter().filter(|key| self.remove(key, true)).count()
+    }
+
+    fn remove_all(&self, remote: bool) -> usize {
+        self.leases().by_key.clear();
+        let keys = self.keys();
+        keys.iter().filter(|key| self.remove(key, remote)).count()
+    }
+
+    /// Receive the keys deleted or overwritten from now on
+    pub fn watch(&self) -> broadcast::Receiver<Invalidation> {
+        self.invalidations.subscribe()
+    }
+
+    /// Follow the sets and deletions from now on, evictions and expirations aren't changes.
+    ///
+    /// A follower more than 64Ki changes behind misses some and must start over.
+    pub fn changes(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.changes.subscribe()
+    }
+
+    /// Number of changes so far, the sequence of the last one
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Relaxed)
+    }
+
+    /// Upper bound of the total weight, in KiB unless values were set with explicit weights
+    pub fn weight_limit(&self) -> usize {
+        self.cache.weight_limit()
+    }
+
+    /// Change the weight limit of the live cache, evicting down to a smaller one right away
+    pub fn set_weight_limit(&self, weight_limit_kib: usize) {
+        self.cache.set_weight_limit(weight_limit_kib, |_, evicted| {
+            self.unindex(&evicted);
+        });
+    }
+
+    /// Share of the weight limit for new entries, see
+    /// [`TinyUFO::set_small_queue_percent`](cachez::tinyufo::TinyUFO::set_small_queue_percent)
+    pub fn set_small_queue_percent(&self, percent: u8) {
+        self.cache.set_small_queue_percent(percent);
+    }
+
+    /// TTL of the entries set without one from now on, `None` to keep them until evicted
+    pub fn set_default_ttl(&self, ttl: Option<Duration>) {
+        let ms = ttl.map_or(0, |ttl| (ttl.as_millis() as u64).max(1));
+        self.default_ttl_ms.store(ms, Relaxed);
+    }
+
+    pub fn default_ttl(&self) -> Option<Duration> {
+        match self.default_ttl_ms.load(Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Expected number of entries the node was sized for
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn shards(&self) -> usize {
+        self.cache.shards()
+    }
+
+    pub fn stats(&self) -> NodeStats {
+        let mut cache = self.cache.stats();
+        let expirations = self.expirations.load(Relaxed);
+        // an expired entry was read as a hit then removed, account it as a miss
+        cache.hits = cache.hits.saturating_sub(expirations);
+        cache.misses += expirations;
+        cache.removals = cache.removals.saturating_sub(expirations);
+        NodeStats { cache, expirations }
+    }
+
+    fn expired(&self, value: &Value) -> bool {
+        value.expires_at.is_some_and(|at| at <= self.clock.now())
+    }
+
+    fn index(&self) -> MutexGuard<'_, KeyIndex> {
+        self.keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn leases(&self) -> MutexGuard<'_, Leases> {
+        self.leases
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn tag_index(&self) -> MutexGuard<'_, HashMap<Bytes, HashSet<Bytes>>> {
+        self.tags
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn index_tags(&self, key: &Bytes, tags: &[Bytes]) {
+        if tags.is_empty() {
+            return;
+        }
+        let mut index = self.tag_index();
+        for tag in tags {
+            index.entry(tag.clone()).or_default().insert(key.clone());
+        }
+    }
+
+    fn unindex_tags(&self, key: &Bytes, tags: &[Bytes]) {
+        if tags.is_empty() {
+            return;
+        }
+        let mut index = self.tag_index();
+        for tag in tags {
+            if let Some(keys) = index.get_mut(tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    index.remove(tag);
+                }
+            }
+        }
+    }
+
+    /// Forget a value that left the cache
+    fn unindex(&self, value: &Value) {
+        self.index().remove(&value.key);
+        self.unindex_tags(&value.key, &value.tags);
+    }
+
+    fn record(&self, change: impl FnOnce() -> Change) {
+        let sequence = self.sequence.fetch_add(1, Relaxed) + 1;
+        // building the change clones the entry, only for followers
+        if self.changes.receiver_count() > 0 {
+            let _ = self.changes.send(ChangeEvent {
+                sequence,
+                at_ms: unix_ms(),
+                change: change(),
+            });
+        }
+    }
+
+    fn invalidate(&self, key: Bytes, reason: Reason, remote: bool) {
+        // no watcher is fine
+        let invalidation = Invalidation {
+            key,
+            reason,
+            remote,
+        };
+        let _ = self.invalidations.send(invalidation);
+    }
+}
+
+/// Milliseconds since the unix epoch
+pub fn unix_ms() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH);
+    since_epoch.map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Hash of a key, the same in every process so that nodes can refer to keys by it
+pub fn fingerprint(key: &[u8]) -> u64 {
+    // t1ha0 picks an implementation per CPU, t1ha1 hashes the same everywhere
+    t1ha::t1ha1_le(key, FINGERPRINT_SEED)
+}
+
+fn default_weight(bytes: usize) -> Weight {
+    bytes.div_ceil(1024).max(1) as Weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cachez::clock::ManualClock;
+
+    #[test]
+    fn test_node() {
+        let clock = Arc::new(ManualClock::new());
+        let node = Node::with_clock(1024, 100, clock.clone());
+        let mut watch = node.watch();
+
+        node.set(Bytes::from("a"), Bytes::from("1"), None, None);
+        node.set(
+            Bytes::from("b"),
+            Bytes::from("2"),
+            None,
+            Some(Duration::from_secs(1)),
+        );
+        assert_eq!(node.get(b"a"), Some(Bytes::from("1")));
+        node.set(Bytes::from("a"), Bytes::from("3"), None, None);
+        assert!(node.delete(b"a"));
+        assert!(!node.delete(b"a"));
+        assert_eq!(watch.try_recv().unwrap().reason, Reason::Updated);
+        assert_eq!(
+            watch.try_recv().unwrap(),
+            Invalidation {
+                key: Bytes::from("a"),
+                reason: Reason::Deleted,
+                remote: false,
+            }
+        );
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(node.get(b"b"), None);
+        let stats = node.stats();
+        assert_eq!(stats.expirations, 1);
+        assert_eq!((stats.cache.hits, stats.cache.misses), (1, 1));
+        assert_eq!(stats.cache.entries, 0);
+    }
+
+    #[test]
+    fn test_ttl_and_scan() {
+        let clock = Arc::new(ManualClock::new());
+        let node = Node::with_clock(1024, 100, clock.clone());
+        for i in 0..10 {
+            let ttl = (i == 0).then_some(Duration::from_secs(1));
+            node.set(Bytes::from(format!("k{i}")), Bytes::from("v"), None, ttl);
+        }
+        assert_eq!(node.ttl(b"k0"), Some(Some(Duration::from_secs(1))));
+        assert_eq!(node.ttl(b"k1"), Some(None));
+        assert_eq!(node.ttl(b"nope"), None);
+        assert!(node.delete(b"k9"));
+        let options = SetOptions {
+            flags: 7,
+            ..Default::default()
+        };
+        node.set_with(Bytes::from("f"), Bytes::from("v"), options);
+        assert_eq!(node.get_with_flags(b"f"), Some((Bytes::from("v"), 7)));
+        assert!(node.delete(b"f"));
+        node.get(b"k2");
+        node.get(b"k2");
+        node.get(b"k1");
+        let hot = node.hot_keys(2);
+        assert_eq!(hot, vec![(Bytes::from("k2"), 2), (Bytes::from("k1"), 1)]);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(node.ttl(b"k0"), None);
+        let (mut cursor, mut keys) = node.scan(0, 3);
+        while cursor != 0 {
+            let (next, more) = node.scan(cursor, 3);
+            cursor = next;
+            keys.extend(more);
+        }
+        keys.sort();
+        let expected: Vec<_> = (1..9).map(|i| Bytes::from(format!("k{i}"))).collect();
+        assert_eq!(keys, expected);
+        let tagged = |tags: &[&'static str]| SetOptions {
+            tags: tags.iter().map(|tag| Bytes::from(*tag)).collect(),
+            ..Default::default()
+        };
+        node.set_with(Bytes::from("t1"), Bytes::from("v"), tagged(&["a", "b"]));
+        node.set_with(Bytes::from("t2"), Bytes::from("v"), tagged(&["a"]));
+        // set again without the tag
+        node.set_with(Bytes::from("t2"), Bytes::from("v"), tagged(&[]));
+        assert_eq!(node.delete_tag(b"a"), 1);
+        assert_eq!(node.get(b"t1"), None);
+        assert!(node.get(b"t2").is_some());
+        assert!(node.delete(b"t2"));
+        assert!(node.tag_index().is_empty());
+        assert_eq!(node.clear(), 8);
+        assert_eq!(node.scan(0, 100), (0, Vec::new()));
+    }
+
+    #[test]
+    fn test_delete_prefix() {
+        let node = Node::new(1024, 100);
+        for key in [
+            "user:1:name",
+            "user:1:mail",
+            "user:10:name",
+            "user:2:name",
+            "users",
+        ] {
+            node.set(Bytes::from(key), Bytes::from("v"), None, None);
+        }
+        assert_eq!(node.delete_prefix(b"user:1:"), 2);
+        assert_eq!(node.get(b"user:1:name"), None);
+        assert!(node.get(b"user:10:name").is_some());
+        assert_eq!(node.delete_prefix(b"user:1:"), 0);
+
+        assert_eq!(node.delete_matching(b"user:*:name"), 2);
+        assert_eq!(node.keys(), vec![Bytes::from("users")]);
+        assert_eq!(node.delete_matching(b"*s"), 1);
+        assert!(node.index().by_name.is_empty());
+    }
+
+    #[test]
+    fn test_changes() {
+        let node = Node::new(1024, 100);
+        node.set(Bytes::from("a"), Bytes::from("1"), None, None);
+        let mut changes = node.changes();
+        node.set(Bytes::from("b"), Bytes::from("2"), Some(3), None);
+        node.delete(b"a");
+
+        let set = changes.try_recv().unwrap();
+        assert_eq!(set.sequence, 2);
+        let Change::Set(entry) = set.change else {
+            panic!("expected a set");
+        };
+        assert_eq!(Some(entry.clone()), node.entry(b"b"));
+        assert_eq!(entry.options().weight, Some(3));
+        let delete = changes.try_recv().unwrap();
+        assert_eq!(delete.change, Change::Delete(Bytes::from("a")));
+        assert_eq!(node.sequence(), 3);
+    }
+
+    #[test]
+    fn test_leases() {
+        let clock = Arc::new(ManualClock::new());
+        let node = Node::with_clock(1024, 100, clock.clone());
+        let set = |token| {
+            node.lease_set(
+                Bytes::from("a"),
+                Bytes::from("1"),
+                SetOptions::default(),
+                token,
+            )
+        };
+
+        let LeaseGet::Miss(token) = node.lease_get(b"a") else {
+            panic!("expected a lease");
+        };
+        assert!(token > 1);
+        assert_eq!(node.lease_get(b"a"), LeaseGet::HotMiss);
+        assert!(!set(token + 1));
+        assert!(set(token));
+        assert_eq!(node.lease_get(b"a"), LeaseGet::Hit(Bytes::from("1"), 0));
+        // used up
+        assert!(!set(token));
+
+        // a deletion while filling makes the data read before it stale
+        node.delete(b"a");
+        let LeaseGet::Miss(token) = node.lease_get(b"a") else {
+            panic!("expected a lease");
+        };
+        assert!(!node.delete(b"a"));
+        assert!(!set(token));
+
+        let LeaseGet::Miss(token) = node.lease_get(b"a") else {
+            panic!("expected a lease");
+        };
+        clock.advance(LEASE_TTL);
+        assert!(!set(token));
+        assert!(matches!(node.lease_get(b"a"), LeaseGet::Miss(_)));
+    }
+
+    #[test]
+    fn test_runtime_settings() {
+        let clock = Arc::new(ManualClock::new());
+        let node = Node::with_clock(1024, 1000, clock.clone());
+        node.set_default_ttl(Some(Duration::from_secs(10)));
+        node.set(Bytes::from("a"), Bytes::from("1"), None, None);
+        node.set(
+            Bytes::from("b"),
+            Bytes::from("2"),
+            None,
+            Some(Duration::from_secs(60)),
+        );
+        assert_eq!(node.ttl(b"a"), Some(Some(Duration::from_secs(10))));
+        assert_eq!(node.ttl(b"b"), Some(Some(Duration::from_secs(60))));
+        node.set_default_ttl(None);
+        node.set(Bytes::from("c"), Bytes::from("3"), None, None);
+        assert_eq!(node.ttl(b"c"), Some(None));
+
+        for i in 0..500 {
+            node.set(Bytes::from(format!("k{i}")), Bytes::from("v"), None, None);
+        }
+        node.set_weight_limit(100);
+        assert_eq!(node.weight_limit(), 100);
+        let stats = node.stats();
+        assert!(stats.cache.weight <= 100);
+        // the evicted keys left the index
+        assert_eq!(node.keys().len(), stats.cache.entries);
+    }
+}
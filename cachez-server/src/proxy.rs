@@ -0,0 +1,416 @@
+//! Sharding proxy: a RESP frontend spreading keys over backend nodes by consistent hashing.
+//!
+//! Clients talk to the proxy as they would to a single node. Every key command goes to the node
+//! owning the key on a [`HashRing`], `DEL` and `EXISTS` are split per key and `DBSIZE` is summed
+//! over the backends. `SCAN` isn't supported. Clients authenticate to the proxy with `AUTH`
+//! when its listener has a password, the backends are reached without TLS nor password.
+//!
+//! Backends are pinged on an interval. A backend failing `failure_threshold` checks or requests in a
+//! row is ejected from the ring, its keys moving to the next nodes, until a check succeeds again.
+
+use crate::cluster::{HashRing, DEFAULT_VIRTUAL_NODES};
+use crate::listener::Listener;
+use crate::resp::{authenticate, parse_command, Connection, Reply};
+use bytes::{Bytes, BytesMut};
+use futures_util::future::join_all;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{self, MissedTickBehavior};
+
+/// Idle connections kept per backend
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// Time between two health checks of a backend
+    pub health_interval: Duration,
+    /// How long a backend has to answer a check or a request
+    pub timeout: Duration,
+    /// Failures in a row ejecting a backend
+    pub failure_threshold: u32,
+    /// Points of every backend on the ring
+    pub virtual_nodes: usize,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            health_interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(500),
+            failure_threshold: 3,
+            virtual_nodes: DEFAULT_VIRTUAL_NODES,
+        }
+    }
+}
+
+/// Health of a backend, as seen by the proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendStatus {
+    pub addr: String,
+    /// Whether it is on the ring
+    pub healthy: bool,
+    /// Failed checks or requests since the last success
+    pub failures: u32,
+}
+
+struct Backend {
+    idle: Mutex<Vec<Connection>>,
+    failures: AtomicU32,
+    ejected: AtomicBool,
+}
+
+pub struct Proxy {
+    /// Every backend, healthy or not, by `host:port`
+    backends: HashMap<String, Backend>,
+    /// The healthy backends
+    ring: RwLock<HashRing<String>>,
+    config: ProxyConfig,
+}
+
+impl Proxy {
+    /// Proxy to the nodes speaking RESP at `backends`, `host:port` each. All of them start
+    /// healthy
+    pub fn new(backends: impl IntoIterator<Item = impl Into<String>>, config: ProxyConfig) -> Self {
+        let mut ring = HashRing::new(config.virtual_nodes, 1);
+        let backends = backends
+            .into_iter()
+            .map(|addr| {
+                let addr = addr.into();
+                ring.add(addr.clone());
+                let backend = Backend {
+                    idle: Mutex::new(Vec::new()),
+                    failures: AtomicU32::new(0),
+                    ejected: AtomicBool::new(false),
+                };
+                (addr, backend)
+            })
+            .collect();
+        Self {
+            backends,
+            ring: RwLock::new(ring),
+            config,
+        }
+    }
+
+    /// Every backend, in no particular order
+    pub fn backends(&self) -> Vec<BackendStatus> {
+        self.backends
+            .iter()
+            .map(|(addr, backend)| BackendStatus {
+                addr: addr.clone(),
+                healthy: !backend.ejected.load(Ordering::Relaxed),
+                failures: backend.failures.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Check the backends every `health_interval`, forever
+    pub async fn run(&self) {
+        let mut rounds = time::interval(self.config.health_interval);
+        rounds.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            rounds.tick().await;
+            self.check_health().await;
+        }
+    }
+
+    /// Ping every backend once, ejecting or restoring them
+    pub async fn check_health(&self) {
+        let checks = self.backends.keys().map(|addr| async move {
+            match self.call(addr, &[b"PING"]).await {
+                Ok(Reply::Simple(_)) => self.succeeded(addr),
+                // call() counted transport errors already
+                Ok(_) => self.failed(addr),
+                Err(_) => {}
+            }
+        });
+        join_all(checks).await;
+    }
+
+    /// Run one command, returns the reply and whether the connection must be closed after it
+    pub async fn execute(&self, args: &[Bytes]) -> (Reply, bool) {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let reply = match (name.as_str(), args.len() - 1) {
+            ("QUIT", _) => return (Reply::OK, true),
+            ("PING", 0) => Reply::simple("PONG"),
+            ("PING" | "ECHO", 1) => Reply::bulk(args[1].clone()),
+            ("GET" | "TTL" | "PTTL", 1) | ("SET", 2..) => self.forward(&args[1], args).await,
+            ("DEL" | "EXISTS", 1..) => self.count(&args[0], &args[1..]).await,
+            ("DBSIZE", 0) => self.dbsize().await,
+            ("INFO", _) => Reply::bulk(self.info()),
+            ("SELECT", 1) if &args[1][..] == b"0" => Reply::OK,
+            ("SELECT", 1) => Reply::error("ERR DB index is out of range"),
+            ("COMMAND", _) => Reply::Array(Vec::new()),
+            ("CLIENT", 1..) => Reply::OK,
+            ("SCAN", _) => Reply::error("ERR SCAN is not supported through the proxy"),
+            (
+                "PING" | "ECHO" | "GET" | "SET" | "DEL" | "EXISTS" | "TTL" | "PTTL" | "DBSIZE"
+                | "SELECT" | "CLIENT",
+                _,
+            ) => Reply::error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            )),
+            _ => Reply::error(format!(
+                "ERR unknown command '{}'",
+                name.to_ascii_lowercase()
+            )),
+        };
+        (reply, false)
+    }
+
+    /// Send `args` to the owner of `key`
+    async fn forward(&self, key: &[u8], args: &[Bytes]) -> Reply {
+        let Some(addr) = self.owner(key) else {
+            return Reply::error("ERR no backend available");
+        };
+        let args: Vec<&[u8]> = args.iter().map(|arg| &arg[..]).collect();
+        match self.call(&addr, &args).await {
+            Ok(reply) => reply,
+            Err(error) => Reply::error(format!("ERR backend {addr}: {error}")),
+        }
+    }
+
+    /// `DEL` or `EXISTS` of every key on its owner, summed
+    async fn count(&self, command: &Bytes, keys: &[Bytes]) -> Reply {
+        let mut total = 0;
+        for key in keys {
+            match self.forward(key, &[command.clone(), key.clone()]).await {
+                Reply::Integer(n) => total += n,
+                error @ Reply::Error(_) => return error,
+                reply => return Reply::error(format!("ERR unexpected backend reply {reply:?}")),
+            }
+        }
+        Reply::Integer(total)
+    }
+
+    async fn dbsize(&self) -> Reply {
+        let mut total = 0;
+        let nodes = self.ring.read().unwrap().nodes().to_vec();
+        for addr in nodes {
+            match self.call(&addr, &[b"DBSIZE"]).await {
+                Ok(Reply::Integer(n)) => total += n,
+                Ok(Reply::Error(error)) => return Reply::Error(error),
+                Ok(reply) => {
+                    return Reply::error(format!("ERR unexpected backend reply {reply:?}"))
+                }
+                Err(error) => return Reply::error(format!("ERR backend {addr}: {error}")),
+            }
+        }
+        Reply::Integer(total)
+    }
+
+    fn info(&self) -> String {
+        let mut backends = self.backends();
+        backends.sort_by(|a, b| a.addr.cmp(&b.addr));
+        let mut info = format!(
+            "# Server\r\ncachez_version:{}\r\nmode:proxy\r\n\r\n# Backends\r\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        for (i, backend) in backends.iter().enumerate() {
+            let status = if backend.healthy { "up" } else { "ejected" };
+            info.push_str(&format!(
+                "backend{i}:addr={},status={status},failures={}\r\n",
+                backend.addr, backend.failures
+            ));
+        }
+        info
+    }
+
+    fn owner(&self, key: &[u8]) -> Option<String> {
+        self.ring.read().unwrap().primary(key).cloned()
+    }
+
+    /// Send `command` to the backend at `addr` on an idle connection or a new one
+    async fn call(&self, addr: &str, command: &[&[u8]]) -> io::Result<Reply> {
+        let backend = &self.backends[addr];
+        let idle = backend.idle.lock().unwrap().pop();
+        let result = time::timeout(self.config.timeout, async {
+            let mut connection = match idle {
+                Some(connection) => connection,
+                None => Connection::connect(addr).await?,
+            };
+            let reply = connection.call(command).await?;
+            Ok::<_, io::Error>((connection, reply))
+        })
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+        match result {
+            Ok((connection, reply)) => {
+                let mut idle = backend.idle.lock().unwrap();
+                if idle.len() < MAX_IDLE_CONNECTIONS {
+                    idle.push(connection);
+                }
+                backend.failures.store(0, Ordering::Relaxed);
+                Ok(reply)
+            }
+            Err(error) => {
+                // the connection may be mid reply, it is dropped
+                self.failed(addr);
+                Err(error)
+            }
+        }
+    }
+
+    fn succeeded(&self, addr: &str) {
+        let backend = &self.backends[addr];
+        backend.failures.store(0, Ordering::Relaxed);
+        if backend.ejected.swap(false, Ordering::Relaxed) {
+            self.ring.write().unwrap().add(addr.to_owned());
+        }
+    }
+
+    fn failed(&self, addr: &str) {
+        let backend = &self.backends[addr];
+        let failures = backend.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.config.failure_threshold
+            && !backend.ejected.swap(true, Ordering::Relaxed)
+        {
+            self.ring.write().unwrap().remove(&addr.to_owned());
+            // the connections of a failing backend aren't worth keeping
+            backend.idle.lock().unwrap().clear();
+        }
+    }
+}
+
+/// Accept Redis clients on `listener` until it fails
+pub async fn serve(listener: impl Into<Listener>, proxy: Arc<Proxy>) -> io::Result<()> {
+    let listener = listener.into();
+    loop {
+        let accepted = listener.accept().await?;
+        let proxy = proxy.clone();
+        let password = listener.password().cloned();
+        tokio::spawn(async move {
+            if let Ok(stream) = accepted.stream().await {
+                let _ = connection(stream, &proxy, password.as_deref()).await;
+            }
+        });
+    }
+}
+
+async fn connection(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    proxy: &Proxy,
+    password: Option<&str>,
+) -> io::Result<()> {
+    let mut authenticated = password.is_none();
+    let mut input = BytesMut::with_capacity(4096);
+    let mut output = BytesMut::new();
+    loop {
+        if stream.read_buf(&mut input).await? == 0 {
+            return Ok(());
+        }
+        loop {
+            let args = match parse_command(&mut input) {
+                Ok(Some(args)) => args,
+                Ok(None) => break,
+                Err(error) => {
+                    Reply::error(format!("ERR {error}")).encode(&mut output);
+                    stream.write_all(&output).await?;
+                    return stream.shutdown().await;
+                }
+            };
+            let (reply, quit) = match authenticate(password, &mut authenticated, &args) {
+                Some(reply) => (reply, false),
+                None => proxy.execute(&args).await,
+            };
+            reply.encode(&mut output);
+            if quit {
+                stream.write_all(&output).await?;
+                return stream.shutdown().await;
+            }
+        }
+        stream.write_all(&output).await?;
+        // TLS may hold on to part of it otherwise
+        stream.flush().await?;
+        output.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use tokio::net::TcpListener;
+
+    async fn backend() -> (String, Arc<Node>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let node = Arc::new(Node::new(1024, 1000));
+        tokio::spawn(crate::resp::serve(listener, node.clone()));
+        (addr, node)
+    }
+
+    fn command(args: &[&str]) -> Vec<Bytes> {
+        args.iter()
+            .map(|arg| Bytes::copy_from_slice(arg.as_bytes()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let (a, node_a) = backend().await;
+        let (b, node_b) = backend().await;
+        let proxy = Proxy::new([a, b], ProxyConfig::default());
+
+        for i in 0..100 {
+            let key = format!("key{i}");
+            let (reply, _) = proxy
+                .execute(&command(&["SET", &key, "v", "EX", "60"]))
+                .await;
+            assert_eq!(reply, Reply::OK);
+        }
+        let (a_keys, b_keys) = (node_a.keys().len(), node_b.keys().len());
+        assert_eq!(a_keys + b_keys, 100);
+        assert!(a_keys > 20 && b_keys > 20, "{a_keys} {b_keys}");
+
+        let (reply, _) = proxy.execute(&command(&["GET", "key7"])).await;
+        assert_eq!(reply, Reply::bulk("v"));
+        let (reply, _) = proxy.execute(&command(&["DBSIZE"])).await;
+        assert_eq!(reply, Reply::Integer(100));
+        let (reply, _) = proxy
+            .execute(&command(&["DEL", "key1", "key2", "key3", "nope"]))
+            .await;
+        assert_eq!(reply, Reply::Integer(3));
+        let (reply, _) = proxy.execute(&command(&["EXISTS", "key1", "key4"])).await;
+        assert_eq!(reply, Reply::Integer(1));
+    }
+
+    #[tokio::test]
+    async fn test_ejection() {
+        let (live, _) = backend().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let proxy = Proxy::new([live.clone(), dead.clone()], ProxyConfig::default());
+
+        for _ in 0..3 {
+            proxy.check_health().await;
+        }
+        let mut backends = proxy.backends();
+        backends.sort_by_key(|backend| backend.addr != live);
+        assert!(backends[0].healthy);
+        assert!(!backends[1].healthy);
+        assert_eq!(backends[1].failures, 3);
+        // every key goes to the live backend
+        for i in 0..20 {
+            let key = format!("key{i}");
+            let (reply, _) = proxy.execute(&command(&["SET", &key, "v"])).await;
+            assert_eq!(reply, Reply::OK);
+        }
+
+        // the dead backend is back
+        let listener = TcpListener::bind(&dead).await.unwrap();
+        tokio::spawn(crate::resp::serve(
+            listener,
+            Arc::new(Node::new(1024, 1000)),
+        ));
+        proxy.check_health().await;
+        assert!(proxy.backends().iter().all(|backend| backend.healthy));
+        assert_eq!(proxy.ring.read().unwrap().nodes().len(), 2);
+    }
+}
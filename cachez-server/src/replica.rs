@@ -0,0 +1,175 @@
+//! Hot standby: a node following the sets and deletions of a primary over gRPC.
+//!
+//! The replica starts from a snapshot of the primary's entries, then applies its changes as
+//! they stream in, so that it is warm when it takes over. Replication is asynchronous: the
+//! primary doesn't wait for the replica, which lags by [`ReplicaStats::lag_events`]. Once it
+//! falls more than the primary's change buffer behind, it starts over from a new snapshot.
+
+use crate::node::{unix_ms, Node};
+use crate::proto::cache_client::CacheClient;
+use crate::proto::{replication_event, ReplicateRequest};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::Endpoint;
+use tonic::Status;
+
+/// Wait before reconnecting to the primary
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// How far behind its primary a replica is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplicaStats {
+    /// Following the primary's changes right now
+    pub connected: bool,
+    /// Events applied, snapshot entries included
+    pub applied: u64,
+    /// Changes made on the primary and not applied yet, as of the last event
+    pub lag_events: u64,
+    /// Age of the last change applied when it was, skewed by the clocks' difference
+    pub lag: Duration,
+    /// Times replication started over, after a disconnection or falling too far behind
+    pub resyncs: u64,
+}
+
+/// Keeps a [`Node`] a copy of the node serving gRPC at `primary`
+pub struct Replica {
+    node: Arc<Node>,
+    primary: Endpoint,
+    connected: AtomicBool,
+    applied: AtomicU64,
+    lag_events: AtomicU64,
+    lag_ms: AtomicU64,
+    resyncs: AtomicU64,
+}
+
+impl Replica {
+    /// Follow `primary`, like `http://10.0.0.1:50051`
+    pub fn new(
+        node: Arc<Node>,
+        primary: impl Into<String>,
+    ) -> Result<Self, tonic::transport::Error> {
+        Ok(Self {
+            node,
+            primary: Endpoint::from_shared(primary.into())?,
+            connected: AtomicBool::new(false),
+            applied: AtomicU64::new(0),
+            lag_events: AtomicU64::new(0),
+            lag_ms: AtomicU64::new(0),
+            resyncs: AtomicU64::new(0),
+        })
+    }
+
+    pub fn stats(&self) -> ReplicaStats {
+        ReplicaStats {
+            connected: self.connected.load(Relaxed),
+            applied: self.applied.load(Relaxed),
+            lag_events: self.lag_events.load(Relaxed),
+            lag: Duration::from_millis(self.lag_ms.load(Relaxed)),
+            resyncs: self.resyncs.load(Relaxed),
+        }
+    }
+
+    /// Follow the primary, reconnecting whenever replication breaks, runs until dropped
+    pub async fn run(&self) {
+        let mut first = true;
+        loop {
+            if !first {
+                self.resyncs.fetch_add(1, Relaxed);
+            }
+            first = false;
+            // the error only tells when to start over
+            let _ = self.follow().await;
+            self.connected.store(false, Relaxed);
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn follow(&self) -> Result<(), Status> {
+        let channel = self
+            .primary
+            .connect()
+            .await
+            .map_err(|error| Status::unavailable(error.to_string()))?;
+        let mut events = CacheClient::new(channel)
+            .replicate(ReplicateRequest {})
+            .await?
+            .into_inner();
+        // deletions missed while disconnected are unknown, the snapshot brings the rest back
+        self.node.clear_remote();
+        self.connected.store(true, Relaxed);
+        while let Some(event) = events.message().await? {
+            match event.change {
+                Some(replication_event::Change::Set(entry)) => {
+                    let entry = crate::node::Entry::from(entry);
+                    let options = entry.options();
+                    self.node.set_with(entry.key, entry.data, options);
+                }
+                Some(replication_event::Change::Delete(key)) => {
+                    self.node.delete_remote(&key);
+                }
+                None => {}
+            }
+            self.applied.fetch_add(1, Relaxed);
+            let lag_events = event.primary_sequence.saturating_sub(event.sequence);
+            self.lag_events.store(lag_events, Relaxed);
+            let lag_ms = unix_ms().saturating_sub(event.changed_at_ms);
+            self.lag_ms.store(lag_ms, Relaxed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::CacheService;
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+
+    #[tokio::test]
+    async fn test_replica() {
+        let primary = Arc::new(Node::new(1024, 100));
+        primary.set(Bytes::from("warm"), Bytes::from("1"), Some(7), None);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(CacheService::server(primary.clone()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let standby = Arc::new(Node::new(1024, 100));
+        let replica = Arc::new(Replica::new(standby.clone(), format!("http://{addr}")).unwrap());
+        tokio::spawn({
+            let replica = replica.clone();
+            async move { replica.run().await }
+        });
+        let caught_up = |applied| {
+            let replica = replica.clone();
+            async move {
+                for _ in 0..100 {
+                    if replica.stats().applied >= applied {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                panic!("the replica didn't catch up: {:?}", replica.stats());
+            }
+        };
+
+        caught_up(1).await;
+        assert_eq!(standby.entry(b"warm"), primary.entry(b"warm"));
+        primary.set(Bytes::from("live"), Bytes::from("2"), None, None);
+        primary.delete(b"warm");
+        caught_up(3).await;
+        assert_eq!(standby.get(b"live"), Some(Bytes::from("2")));
+        assert_eq!(standby.get(b"warm"), None);
+
+        let stats = replica.stats();
+        assert!(stats.connected);
+        assert_eq!((stats.lag_events, stats.resyncs), (0, 0));
+    }
+}
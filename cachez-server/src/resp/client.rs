@@ -0,0 +1,52 @@
+//! Client side of RESP, to talk to Redis.
+
+use super::{parse_reply, Reply};
+use bytes::{Bytes, BytesMut};
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A client connection to a RESP server
+pub struct Connection {
+    stream: TcpStream,
+    input: BytesMut,
+}
+
+impl Connection {
+    pub async fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let _ = stream.set_nodelay(true);
+        Ok(Self {
+            stream,
+            input: BytesMut::with_capacity(4096),
+        })
+    }
+
+    /// Send `command` and wait for its reply
+    pub async fn call(&mut self, command: &[&[u8]]) -> io::Result<Reply> {
+        self.send(command).await?;
+        self.receive().await
+    }
+
+    pub async fn send(&mut self, command: &[&[u8]]) -> io::Result<()> {
+        let command = command
+            .iter()
+            .map(|arg| Reply::bulk(Bytes::copy_from_slice(arg)))
+            .collect();
+        let mut out = BytesMut::new();
+        Reply::Array(command).encode(&mut out);
+        self.stream.write_all(&out).await
+    }
+
+    /// The next reply, or message pushed to a subscriber
+    pub async fn receive(&mut self) -> io::Result<Reply> {
+        loop {
+            if let Some(reply) = parse_reply(&mut self.input).map_err(io::Error::other)? {
+                return Ok(reply);
+            }
+            if self.stream.read_buf(&mut self.input).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
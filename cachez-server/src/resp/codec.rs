@@ -0,0 +1,305 @@
+//! RESP2 framing: commands in, replies out.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::borrow::Cow;
+use std::fmt;
+
+/// Longest bulk string accepted, same as Redis' default `proto-max-bulk-len`
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Most arguments accepted in a command
+const MAX_ARGS: usize = 1024 * 1024;
+/// Longest inline command or length line
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// The client sent something that isn't RESP, the connection can't be resynchronized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolError(pub &'static str);
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Take the next complete command out of `buf`, `None` if more bytes are needed.
+///
+/// Commands are arrays of bulk strings, as every client library sends them, or inline commands
+/// split on whitespace, as typed in a telnet session. An empty inline command is skipped.
+pub fn parse_command(buf: &mut BytesMut) -> Result<Option<Vec<Bytes>>, ProtocolError> {
+    loop {
+        let Some(&first) = buf.first() else {
+            return Ok(None);
+        };
+        let parsed = match first {
+            b'*' => parse_array(buf)?,
+            _ => parse_inline(buf)?,
+        };
+        match parsed {
+            Some((args, consumed)) => {
+                buf.advance(consumed);
+                if !args.is_empty() {
+                    return Ok(Some(args));
+                }
+            }
+            None => return Ok(None),
+        }
+    }
+}
+
+fn parse_array(buf: &BytesMut) -> Result<Option<(Vec<Bytes>, usize)>, ProtocolError> {
+    let Some((len, mut pos)) = parse_length(buf, 0, b'*')? else {
+        return Ok(None);
+    };
+    if len > MAX_ARGS {
+        return Err(ProtocolError("invalid multibulk length"));
+    }
+    let mut args = Vec::with_capacity(len.min(64));
+    for _ in 0..len {
+        if pos >= buf.len() {
+            return Ok(None);
+        }
+        if buf[pos] != b'$' {
+            return Err(ProtocolError("expected '$'"));
+        }
+        let Some((len, start)) = parse_length(buf, pos, b'$')? else {
+            return Ok(None);
+        };
+        if len > MAX_BULK_LEN {
+            return Err(ProtocolError("invalid bulk length"));
+        }
+        let end = start + len;
+        if buf.len() < end + 2 {
+            return Ok(None);
+        }
+        if &buf[end..end + 2] != b"\r\n" {
+            return Err(ProtocolError("expected CRLF after bulk string"));
+        }
+        args.push(Bytes::copy_from_slice(&buf[start..end]));
+        pos = end + 2;
+    }
+    Ok(Some((args, pos)))
+}
+
+/// Parse a `<prefix><length>\r\n` line at `pos`, returns the length and where the line ends
+fn parse_length(
+    buf: &BytesMut,
+    pos: usize,
+    prefix: u8,
+) -> Result<Option<(usize, usize)>, ProtocolError> {
+    debug_assert_eq!(buf[pos], prefix);
+    let Some(line) = line(&buf[pos..])? else {
+        return Ok(None);
+    };
+    let len = std::str::from_utf8(&line[1..])
+        .ok()
+        .and_then(|len| len.parse::<i64>().ok())
+        .ok_or(ProtocolError("invalid length"))?;
+    // a negative length is a null array or bulk string, which no command is
+    let len = usize::try_from(len).map_err(|_| ProtocolError("invalid length"))?;
+    Ok(Some((len, pos + line.len() + 2)))
+}
+
+fn parse_inline(buf: &BytesMut) -> Result<Option<(Vec<Bytes>, usize)>, ProtocolError> {
+    let Some(line) = line(buf)? else {
+        return Ok(None);
+    };
+    let args = line
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .map(Bytes::copy_from_slice)
+        .collect();
+    Ok(Some((args, line.len() + 2)))
+}
+
+/// The line at the start of `buf` without its CRLF
+fn line(buf: &[u8]) -> Result<Option<&[u8]>, ProtocolError> {
+    match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(end) if end <= MAX_LINE_LEN => Ok(Some(&buf[..end])),
+        Some(_) => Err(ProtocolError("too big inline request")),
+        None if buf.len() > MAX_LINE_LEN => Err(ProtocolError("too big inline request")),
+        None => Ok(None),
+    }
+}
+
+/// Take the next complete reply out of `buf`, `None` if more bytes are needed.
+///
+/// The client side of [`Reply::encode`], a null array is read as [`Reply::NULL`].
+pub fn parse_reply(buf: &mut BytesMut) -> Result<Option<Reply>, ProtocolError> {
+    match reply_at(buf, 0)? {
+        Some((reply, consumed)) => {
+            buf.advance(consumed);
+            Ok(Some(reply))
+        }
+        None => Ok(None),
+    }
+}
+
+fn reply_at(buf: &BytesMut, pos: usize) -> Result<Option<(Reply, usize)>, ProtocolError> {
+    let Some(line) = line(&buf[pos..])? else {
+        return Ok(None);
+    };
+    let Some((&kind, content)) = line.split_first() else {
+        return Err(ProtocolError("empty reply"));
+    };
+    let text = || String::from_utf8_lossy(content).into_owned();
+    let integer = || {
+        std::str::from_utf8(content)
+            .ok()
+            .and_then(|i| i.parse::<i64>().ok())
+            .ok_or(ProtocolError("invalid integer"))
+    };
+    let next = pos + line.len() + 2;
+    let reply = match kind {
+        b'+' => (Reply::Simple(Cow::Owned(text())), next),
+        b'-' => (Reply::Error(text()), next),
+        b':' => (Reply::Integer(integer()?), next),
+        b'$' | b'*' if integer()? < 0 => (Reply::NULL, next),
+        b'$' => {
+            let end = next + integer()? as usize;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+            let data = Bytes::copy_from_slice(&buf[next..end]);
+            (Reply::bulk(data), end + 2)
+        }
+        b'*' => {
+            let len = integer()? as usize;
+            if len > MAX_ARGS {
+                return Err(ProtocolError("invalid multibulk length"));
+            }
+            let mut items = Vec::with_capacity(len.min(64));
+            let mut pos = next;
+            for _ in 0..len {
+                let Some((item, next)) = reply_at(buf, pos)? else {
+                    return Ok(None);
+                };
+                items.push(item);
+                pos = next;
+            }
+            (Reply::Array(items), pos)
+        }
+        _ => return Err(ProtocolError("unknown reply type")),
+    };
+    Ok(Some(reply))
+}
+
+/// A RESP2 reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Simple(Cow<'static, str>),
+    Error(String),
+    Integer(i64),
+    /// `None` is the null bulk string
+    Bulk(Option<Bytes>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub const OK: Reply = Reply::Simple(Cow::Borrowed("OK"));
+    pub const NULL: Reply = Reply::Bulk(None);
+
+    pub const fn simple(s: &'static str) -> Self {
+        Reply::Simple(Cow::Borrowed(s))
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Reply::Error(message.into())
+    }
+
+    pub fn bulk(data: impl Into<Bytes>) -> Self {
+        Reply::Bulk(Some(data.into()))
+    }
+
+    pub fn encode(&self, out: &mut BytesMut) {
+        match self {
+            Reply::Simple(s) => put_line(out, b'+', s.as_bytes()),
+            Reply::Error(message) => {
+                // a line break would end the error early and desync the client
+                let message = message.replace(['\r', '\n'], " ");
+                put_line(out, b'-', message.as_bytes())
+            }
+            Reply::Integer(i) => put_line(out, b':', i.to_string().as_bytes()),
+            Reply::Bulk(None) => out.put_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(data)) => {
+                put_line(out, b'$', data.len().to_string().as_bytes());
+                out.put_slice(data);
+                out.put_slice(b"\r\n");
+            }
+            Reply::Array(items) => {
+                put_line(out, b'*', items.len().to_string().as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+fn put_line(out: &mut BytesMut, prefix: u8, line: &[u8]) {
+    out.put_u8(prefix);
+    out.put_slice(line);
+    out.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut buf =
+            BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$1\r\na\r\n\r\nPING  x\r\n*1\r\n$4\r\nPI"[..]);
+        let args = parse_command(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec![Bytes::from("GET"), Bytes::from("a")]);
+        // the empty inline command is skipped
+        let args = parse_command(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec![Bytes::from("PING"), Bytes::from("x")]);
+        assert_eq!(parse_command(&mut buf), Ok(None));
+        buf.extend_from_slice(b"NG\r\n");
+        let args = parse_command(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec![Bytes::from("PING")]);
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"*1\r\n$2\r\nabc\r\n"[..]);
+        assert!(parse_command(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"*1\r\n:1\r\n"[..]);
+        assert!(parse_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = Reply::Array(vec![
+            Reply::bulk("message"),
+            Reply::OK,
+            Reply::error("ERR no"),
+            Reply::Integer(3),
+            Reply::NULL,
+        ]);
+        let mut buf = BytesMut::new();
+        reply.encode(&mut buf);
+        let mut partial = buf.split_to(buf.len() - 3);
+        assert_eq!(parse_reply(&mut partial), Ok(None));
+        partial.unsplit(buf);
+        assert_eq!(parse_reply(&mut partial), Ok(Some(reply)));
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut out = BytesMut::new();
+        Reply::Array(vec![
+            Reply::OK,
+            Reply::error("ERR a\r\nb"),
+            Reply::Integer(-2),
+            Reply::NULL,
+            Reply::bulk("hi"),
+        ])
+        .encode(&mut out);
+        assert_eq!(
+            &out[..],
+            b"*5\r\n+OK\r\n-ERR a  b\r\n:-2\r\n$-1\r\n$2\r\nhi\r\n"
+        );
+    }
+}
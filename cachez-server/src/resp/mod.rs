@@ -0,0 +1,399 @@
+//! Redis protocol (RESP2) frontend, so that Redis clients can use a node as a plain cache.
+//!
+//! The supported subset is what simple caching needs: `GET`, `SET` with `EX`/`PX` and `NX`/`XX`,
+//! `DEL`, `EXISTS`, `TTL`/`PTTL`, `SCAN` with `MATCH` and `COUNT`, `DBSIZE` and `INFO`, plus
+//! `PING`, `ECHO`, `SELECT 0`, `QUIT` and no-op `COMMAND`/`CLIENT` for client handshakes.
+//! Anything else is answered with an error. There is a single database, and `AUTH` when the
+//! listener has a password.
+
+mod client;
+mod codec;
+
+pub use client::Connection;
+pub use codec::{parse_command, parse_reply, ProtocolError, Reply};
+
+use crate::listener::{verify_password, Listener};
+use crate::node::Node;
+use bytes::{Bytes, BytesMut};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// `SCAN`'s default `COUNT`
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Accept Redis clients on `listener` until it fails
+pub async fn serve(listener: impl Into<Listener>, node: Arc<Node>) -> io::Result<()> {
+    let listener = listener.into();
+    loop {
+        let accepted = listener.accept().await?;
+        let node = node.clone();
+        let password = listener.password().cloned();
+        tokio::spawn(async move {
+            // a client going away is not the server's problem
+            if let Ok(stream) = accepted.stream().await {
+                let _ = connection(stream, &node, password.as_deref()).await;
+            }
+        });
+    }
+}
+
+async fn connection(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    node: &Node,
+    password: Option<&str>,
+) -> io::Result<()> {
+    let mut authenticated = password.is_none();
+    let mut input = BytesMut::with_capacity(4096);
+    let mut output = BytesMut::new();
+    loop {
+        if stream.read_buf(&mut input).await? == 0 {
+            return Ok(());
+        }
+        // answer every pipelined command that arrived before writing
+        loop {
+            let args = match parse_command(&mut input) {
+                Ok(Some(args)) => args,
+                Ok(None) => break,
+                Err(error) => {
+                    Reply::error(format!("ERR {error}")).encode(&mut output);
+                    stream.write_all(&output).await?;
+                    return stream.shutdown().await;
+                }
+            };
+            let (reply, quit) = match authenticate(password, &mut authenticated, &args) {
+                Some(reply) => (reply, false),
+                None => execute(node, &args),
+            };
+            reply.encode(&mut output);
+            if quit {
+                stream.write_all(&output).await?;
+                return stream.shutdown().await;
+            }
+        }
+        stream.write_all(&output).await?;
+        // TLS may hold on to part of it otherwise
+        stream.flush().await?;
+        output.clear();
+    }
+}
+
+/// Answer `AUTH`, and any command but `QUIT` before it succeeded when there is a `password`.
+/// `None` for commands to execute
+pub(crate) fn authenticate(
+    password: Option<&str>,
+    authenticated: &mut bool,
+    args: &[Bytes],
+) -> Option<Reply> {
+    let name = args[0].to_ascii_uppercase();
+    match (&name[..], &args[1..]) {
+        (b"AUTH", [_] | [_, _]) if password.is_none() => Some(Reply::error(
+            "ERR AUTH called without any password configured",
+        )),
+        // `AUTH password` or `AUTH default password`, there are no other users
+        (b"AUTH", [given]) | (b"AUTH", [_, given]) => {
+            let user_ok = args.len() == 2 || args[1].eq_ignore_ascii_case(b"default");
+            *authenticated = user_ok && verify_password(password.unwrap_or_default(), given);
+            Some(if *authenticated {
+                Reply::OK
+            } else {
+                Reply::error("WRONGPASS invalid username-password pair")
+            })
+        }
+        (b"AUTH", _) => Some(Reply::error(
+            "ERR wrong number of arguments for 'auth' command",
+        )),
+        (b"QUIT", _) => None,
+        _ if !*authenticated => Some(Reply::error("NOAUTH Authentication required.")),
+        _ => None,
+    }
+}
+
+/// Run one command, returns the reply and whether the connection must be closed after it
+pub fn execute(node: &Node, args: &[Bytes]) -> (Reply, bool) {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+    let args = &args[1..];
+    let reply = match (name.as_str(), args.len()) {
+        ("QUIT", _) => return (Reply::OK, true),
+        ("PING", 0) => Reply::simple("PONG"),
+        ("PING" | "ECHO", 1) => Reply::bulk(args[0].clone()),
+        ("GET", 1) => Reply::Bulk(node.get(&args[0])),
+        ("SET", 2..) => set(node, args),
+        ("DEL", 1..) => Reply::Integer(args.iter().filter(|key| node.delete(key)).count() as i64),
+        ("EXISTS", 1..) => {
+            Reply::Integer(args.iter().filter(|key| node.ttl(key).is_some()).count() as i64)
+        }
+        ("TTL", 1) => ttl(node, &args[0], |ttl| ttl.as_millis().div_ceil(1000)),
+        ("PTTL", 1) => ttl(node, &args[0], |ttl| ttl.as_millis()),
+        ("SCAN", 1..) => scan(node, args),
+        ("DBSIZE", 0) => Reply::Integer(node.stats().cache.entries as i64),
+        ("INFO", _) => Reply::bulk(info(node)),
+        ("SELECT", 1) if &args[0][..] == b"0" => Reply::OK,
+        ("SELECT", 1) => Reply::error("ERR DB index is out of range"),
+        // handshakes of client libraries, nothing to tell them
+        ("COMMAND", _) => Reply::Array(Vec::new()),
+        ("CLIENT", 1..) => Reply::OK,
+        (
+            "PING" | "ECHO" | "GET" | "SET" | "DEL" | "EXISTS" | "TTL" | "PTTL" | "SCAN" | "DBSIZE"
+            | "SELECT" | "CLIENT",
+            _,
+        ) => Reply::error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name.to_ascii_lowercase()
+        )),
+        _ => Reply::error(format!(
+            "ERR unknown command '{}'",
+            name.to_ascii_lowercase()
+        )),
+    };
+    (reply, false)
+}
+
+/// `SET key value [NX | XX] [EX seconds | PX milliseconds]`
+fn set(node: &Node, args: &[Bytes]) -> Reply {
+    let mut ttl = None;
+    let mut only_if = None;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let option = option.to_ascii_uppercase();
+        match &option[..] {
+            b"EX" | b"PX" if ttl.is_none() => {
+                let Some(amount) = options.next().and_then(|amount| parse_u64(amount)) else {
+                    return Reply::error("ERR value is not an integer or out of range");
+                };
+                if amount == 0 {
+                    return Reply::error("ERR invalid expire time in 'set' command");
+                }
+                ttl = Some(match &option[..] {
+                    b"EX" => Duration::from_secs(amount),
+                    _ => Duration::from_millis(amount),
+                });
+            }
+            b"NX" | b"XX" if only_if.is_none() => only_if = Some(&option[..] == b"XX"),
+            _ => return Reply::error("ERR syntax error"),
+        }
+    }
+    // not atomic with the write, racing writers of the same key may both succeed
+    if only_if.is_some_and(|exists| exists != node.ttl(&args[0]).is_some()) {
+        return Reply::NULL;
+    }
+    node.set(args[0].clone(), args[1].clone(), None, ttl);
+    Reply::OK
+}
+
+/// -2 for a missing key, -1 for one that never expires
+fn ttl(node: &Node, key: &[u8], unit: impl Fn(Duration) -> u128) -> Reply {
+    match node.ttl(key) {
+        None => Reply::Integer(-2),
+        Some(None) => Reply::Integer(-1),
+        Some(Some(ttl)) => Reply::Integer(unit(ttl).min(i64::MAX as u128) as i64),
+    }
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count]`
+fn scan(node: &Node, args: &[Bytes]) -> Reply {
+    let Some(cursor) = parse_u64(&args[0]) else {
+        return Reply::error("ERR invalid cursor");
+    };
+    let mut pattern = None;
+    let mut count = DEFAULT_SCAN_COUNT;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match (&option.to_ascii_uppercase()[..], options.next()) {
+            (b"MATCH", Some(glob)) => pattern = Some(glob),
+            (b"COUNT", Some(n)) => match parse_u64(n) {
+                Some(n) if n > 0 => count = n.try_into().unwrap_or(usize::MAX),
+                _ => return Reply::error("ERR value is not an integer or out of range"),
+            },
+            _ => return Reply::error("ERR syntax error"),
+        }
+    }
+    let (next, mut keys) = node.scan(cursor, count);
+    if let Some(pattern) = pattern {
+        keys.retain(|key| glob_match(pattern, key));
+    }
+    Reply::Array(vec![
+        Reply::bulk(next.to_string()),
+        Reply::Array(keys.into_iter().map(Reply::bulk).collect()),
+    ])
+}
+
+fn info(node: &Node) -> String {
+    let stats = node.stats();
+    let cache = stats.cache;
+    format!(
+        "# Server\r\ncachez_version:{}\r\n\r\n\
+         # Stats\r\nkeyspace_hits:{}\r\nkeyspace_misses:{}\r\nevicted_keys:{}\r\nexpired_keys:{}\r\n\
+         inserts:{}\r\nupdates:{}\r\nremovals:{}\r\n\r\n\
+         # Keyspace\r\ndb0:keys={},weight={}\r\n",
+        env!("CARGO_PKG_VERSION"),
+        cache.hits,
+        cache.misses,
+        cache.evictions,
+        stats.expirations,
+        cache.inserts,
+        cache.updates,
+        cache.removals,
+        cache.entries,
+        cache.weight,
+    )
+}
+
+fn parse_u64(arg: &[u8]) -> Option<u64> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+/// Redis glob-style matching: `*`, `?`, `[abc]`, `[^a-z]` and `\` escapes
+///
+/// Iterative, as Redis' `stringmatchlen`: on a mismatch the last `*` swallows one more byte and
+/// matching resumes after it, earlier stars never need to be retried. O(pattern * s) at worst.
+pub fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // the pattern after the last star, and where in `s` it is tried next
+    let mut star = None;
+    loop {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, i));
+            continue;
+        }
+        match (pattern.get(p), s.get(i)) {
+            (None, None) => return true,
+            (Some(_), Some(&c)) => {
+                let (matched, len) = match_one(&pattern[p..], c);
+                if matched {
+                    p += len;
+                    i += 1;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        match star {
+            Some((after_star, tried)) if tried < s.len() => {
+                star = Some((after_star, tried + 1));
+                p = after_star;
+                i = tried + 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Whether the first token of `pattern`, not a star, matches `c`, and its length
+fn match_one(pattern: &[u8], c: u8) -> (bool, usize) {
+    match pattern {
+        [b'?', ..] => (true, 1),
+        [b'[', rest @ ..] => {
+            let Some(end) = rest.iter().position(|&b| b == b']') else {
+                // no closing bracket, a literal '['
+                return (c == b'[', 1);
+            };
+            let (negate, class) = match rest[..end].split_first() {
+                Some((b'^', class)) => (true, class),
+                _ => (false, &rest[..end]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    let (lo, hi) = (class[i].min(class[i + 2]), class[i].max(class[i + 2]));
+                    matched |= (lo..=hi).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            (matched != negate, end + 2)
+        }
+        [b'\\', escaped, ..] => (c == *escaped, 2),
+        [p, ..] => (c == *p, 1),
+        [] => (false, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"user:*", b"user:1"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"h?llo", b"hallo"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"h[ae]llo", b"hello"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(glob_match(b"a\\*", b"a*"));
+        assert!(!glob_match(b"a\\*", b"ab"));
+        assert!(glob_match(b"*a*b", b"xaxxb"));
+        assert!(!glob_match(b"*a*b", b"xaxxbc"));
+        assert!(glob_match(b"a**", b"a"));
+        assert!(glob_match(b"[*", b"[x"));
+        // exponential for a matcher retrying every star
+        let s = [b'a'; 64];
+        assert!(!glob_match(&[b"a*".repeat(32), b"b".to_vec()].concat(), &s));
+    }
+
+    #[tokio::test]
+    async fn test_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(Node::new(1024, 100))));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // pipelined, mixing the array and inline forms
+        stream
+            .write_all(
+                b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n\
+                  SET b 2 EX 100\r\nSET a 3 NX\r\nGET a\r\nGET nope\r\n\
+                  TTL a\r\nTTL b\r\nTTL nope\r\nEXISTS a b nope\r\n\
+                  SCAN 0 MATCH b COUNT 100\r\nDEL a b nope\r\nDBSIZE\r\nSET a\r\nFLUSHALL\r\nQUIT\r\n",
+            )
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        stream.read_to_end(&mut replies).await.unwrap();
+        assert_eq!(
+            String::from_utf8(replies).unwrap(),
+            "+OK\r\n+OK\r\n$-1\r\n$1\r\n1\r\n$-1\r\n\
+             :-1\r\n:100\r\n:-2\r\n:2\r\n\
+             *2\r\n$1\r\n0\r\n*1\r\n$1\r\nb\r\n:2\r\n:0\r\n\
+             -ERR wrong number of arguments for 'set' command\r\n\
+             -ERR unknown command 'flushall'\r\n+OK\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auth_over_tls() {
+        let (acceptor, connector) = crate::listener::tests::self_signed();
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let listener = Listener::new(tcp)
+            .with_tls(acceptor)
+            .with_password("secret");
+        tokio::spawn(serve(listener, Arc::new(Node::new(1024, 100))));
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let name = "localhost".try_into().unwrap();
+        let mut stream = connector.connect(name, tcp).await.unwrap();
+
+        stream
+            .write_all(b"GET a\r\nAUTH nope\r\nAUTH admin secret\r\nGET a\r\nAUTH default secret\r\nGET a\r\nQUIT\r\n")
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        // the server closes without close_notify
+        let _ = stream.read_to_end(&mut replies).await;
+        assert_eq!(
+            String::from_utf8(replies).unwrap(),
+            "-NOAUTH Authentication required.\r\n\
+             -WRONGPASS invalid username-password pair\r\n\
+             -WRONGPASS invalid username-password pair\r\n\
+             -NOAUTH Authentication required.\r\n+OK\r\n$-1\r\n+OK\r\n"
+        );
+    }
+}
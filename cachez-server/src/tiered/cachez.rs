@@ -0,0 +1,64 @@
+use super::Store;
+use crate::proto::cache_client::CacheClient;
+use crate::proto::{DeleteRequest, GetRequest, SetRequest};
+use bytes::Bytes;
+use std::io;
+use std::time::Duration;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Status;
+
+/// [`Store`] on a remote cachez node, over gRPC
+#[derive(Clone)]
+pub struct CachezStore {
+    client: CacheClient<Channel>,
+}
+
+impl CachezStore {
+    /// Use the node serving gRPC at `uri`, like `http://10.0.0.1:50051`. It is connected to on
+    /// first use
+    pub fn new(uri: impl Into<String>) -> Result<Self, tonic::transport::Error> {
+        let channel = Endpoint::from_shared(uri.into())?.connect_lazy();
+        Ok(Self {
+            client: CacheClient::new(channel),
+        })
+    }
+}
+
+fn io_error(status: Status) -> io::Error {
+    io::Error::other(status)
+}
+
+#[async_trait::async_trait]
+impl Store for CachezStore {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Bytes>> {
+        let request = GetRequest {
+            key: Bytes::copy_from_slice(key),
+        };
+        let response = self.client.clone().get(request).await.map_err(io_error)?;
+        Ok(response.into_inner().value)
+    }
+
+    async fn set(&self, key: &[u8], value: Bytes, ttl: Option<Duration>) -> io::Result<()> {
+        let request = SetRequest {
+            key: Bytes::copy_from_slice(key),
+            value,
+            weight: None,
+            ttl_ms: ttl.map(|ttl| ttl.as_millis() as u64),
+        };
+        self.client.clone().set(request).await.map_err(io_error)?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        let request = DeleteRequest {
+            key: Bytes::copy_from_slice(key),
+        };
+        let response = self
+            .client
+            .clone()
+            .delete(request)
+            .await
+            .map_err(io_error)?;
+        Ok(response.into_inner().deleted)
+    }
+}
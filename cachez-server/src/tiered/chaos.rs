@@ -0,0 +1,44 @@
+use super::Store;
+use bytes::Bytes;
+use cachez::chaos::Chaos;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// [`Store`] injecting the faults of a [`Chaos`] into the calls of another, to see how a
+/// [`TieredCache`](super::TieredCache) copes with a slow, failing or stuck tier
+pub struct ChaosStore {
+    store: Arc<dyn Store>,
+    chaos: Arc<Chaos>,
+}
+
+impl ChaosStore {
+    pub fn new(store: Arc<dyn Store>, chaos: Arc<Chaos>) -> Self {
+        Self { store, chaos }
+    }
+
+    async fn inject(&self) -> io::Result<()> {
+        self.chaos
+            .inject_async(tokio::time::sleep)
+            .await
+            .map_err(io::Error::other)
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for ChaosStore {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Bytes>> {
+        self.inject().await?;
+        self.store.get(key).await
+    }
+
+    async fn set(&self, key: &[u8], value: Bytes, ttl: Option<Duration>) -> io::Result<()> {
+        self.inject().await?;
+        self.store.set(key, value, ttl).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        self.inject().await?;
+        self.store.delete(key).await
+    }
+}
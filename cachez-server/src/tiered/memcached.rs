@@ -0,0 +1,214 @@
+use super::Store;
+use bytes::{Buf, Bytes, BytesMut};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// memcached's key length limit
+const MAX_KEY_LEN: usize = 250;
+/// Longer expiration times are read by memcached as unix timestamps
+const MAX_RELATIVE_EXPTIME: u64 = 60 * 60 * 24 * 30;
+
+/// [`Store`] on a memcached server, or a cachez node speaking its text protocol.
+///
+/// Requests share one connection, reconnected after a failure. memcached keys can't hold
+/// whitespace or control characters nor be longer than 250 bytes, such keys fail with
+/// [`io::ErrorKind::InvalidInput`].
+pub struct MemcachedStore {
+    addr: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl MemcachedStore {
+    /// Use the server at `addr`, `host:port`. It is connected to on first use
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Send `request`, then read the reply with `reply`
+    async fn call<T>(
+        &self,
+        request: &[&[u8]],
+        reply: impl AsyncFnOnce(&mut Connection) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut connection = self.connection.lock().await;
+        let open = match connection.as_mut() {
+            Some(open) => open,
+            None => connection.insert(Connection::connect(&self.addr).await?),
+        };
+        let result = match open.send(request).await {
+            Ok(()) => reply(open).await,
+            Err(error) => Err(error),
+        };
+        if result
+            .as_ref()
+            .is_err_and(|error| error.kind() != io::ErrorKind::InvalidInput)
+        {
+            // the stream may be mid reply, start over on a new one
+            *connection = None;
+        }
+        result
+    }
+}
+
+fn check_key(key: &[u8]) -> io::Result<()> {
+    if key.is_empty()
+        || key.len() > MAX_KEY_LEN
+        || key
+            .iter()
+            .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid memcached key",
+        ));
+    }
+    Ok(())
+}
+
+fn unexpected(line: &[u8]) -> io::Error {
+    let line = String::from_utf8_lossy(line);
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected reply {line}"),
+    )
+}
+
+#[async_trait::async_trait]
+impl Store for MemcachedStore {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Bytes>> {
+        check_key(key)?;
+        self.call(&[b"get ", key, b"\r\n"], async |connection| {
+            let mut value = None;
+            loop {
+                let line = connection.line().await?;
+                if &line[..] == b"END" {
+                    return Ok(value);
+                }
+                // VALUE <key> <flags> <bytes>
+                let len = line
+                    .strip_prefix(b"VALUE ")
+                    .and_then(|rest| rest.split(|&b| b == b' ').nth(2))
+                    .and_then(|len| std::str::from_utf8(len).ok()?.parse().ok())
+                    .ok_or_else(|| unexpected(&line))?;
+                value = Some(connection.data(len).await?);
+            }
+        })
+        .await
+    }
+
+    async fn set(&self, key: &[u8], value: Bytes, ttl: Option<Duration>) -> io::Result<()> {
+        check_key(key)?;
+        let exptime = ttl.map_or(0, |ttl| {
+            ttl.as_secs_f64()
+                .ceil()
+                .clamp(1.0, MAX_RELATIVE_EXPTIME as f64) as u64
+        });
+        let header = format!(" 0 {exptime} {}\r\n", value.len());
+        let request = [&b"set "[..], key, header.as_bytes(), &value, b"\r\n"];
+        self.call(&request, async |connection| {
+            let line = connection.line().await?;
+            match &line[..] {
+                b"STORED" => Ok(()),
+                _ => Err(unexpected(&line)),
+            }
+        })
+        .await
+    }
+
+    async fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        check_key(key)?;
+        self.call(&[b"delete ", key, b"\r\n"], async |connection| {
+            let line = connection.line().await?;
+            match &line[..] {
+                b"DELETED" => Ok(true),
+                b"NOT_FOUND" => Ok(false),
+                _ => Err(unexpected(&line)),
+            }
+        })
+        .await
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    input: BytesMut,
+}
+
+impl Connection {
+    async fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let _ = stream.set_nodelay(true);
+        Ok(Self {
+            stream,
+            input: BytesMut::with_capacity(4096),
+        })
+    }
+
+    async fn send(&mut self, parts: &[&[u8]]) -> io::Result<()> {
+        self.stream.write_all(&parts.concat()).await
+    }
+
+    /// The next line, without its CRLF
+    async fn line(&mut self) -> io::Result<Bytes> {
+        loop {
+            if let Some(end) = self.input.windows(2).position(|w| w == b"\r\n") {
+                let line = self.input.split_to(end).freeze();
+                self.input.advance(2);
+                return Ok(line);
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// A data block of `len` bytes, without its CRLF
+    async fn data(&mut self, len: usize) -> io::Result<Bytes> {
+        while self.input.len() < len + 2 {
+            self.fill().await?;
+        }
+        let data = self.input.split_to(len).freeze();
+        self.input.advance(2);
+        Ok(data)
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        if self.stream.read_buf(&mut self.input).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_memcached_store() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node = Arc::new(Node::new(1024, 100));
+        tokio::spawn(crate::memcache::serve(listener, node.clone()));
+
+        let store = MemcachedStore::new(addr.to_string());
+        assert_eq!(store.get(b"a").await.unwrap(), None);
+        let ttl = Some(Duration::from_millis(1500));
+        store.set(b"a", Bytes::from("1\r\n2"), ttl).await.unwrap();
+        assert_eq!(store.get(b"a").await.unwrap(), Some(Bytes::from("1\r\n2")));
+        // rounded up to whole seconds
+        assert!(node.ttl(b"a").unwrap().unwrap() > Duration::from_millis(1500));
+        assert!(store.delete(b"a").await.unwrap());
+        assert!(!store.delete(b"a").await.unwrap());
+
+        let error = store.get(b"a b").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
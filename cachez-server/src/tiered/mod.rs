@@ -0,0 +1,247 @@
+//! Tiered caching: the local node first, remote caches next, the origin last.
+//!
+//! Every tier is a [`Store`]. A hit in a lower tier is copied back into the tiers above it and
+//! a value loaded from the origin is written to every tier, so the next read is served as close
+//! as possible. A failing remote tier counts as a miss, it slows requests down rather than
+//! failing them.
+
+mod cachez;
+mod chaos;
+mod memcached;
+mod redis;
+
+pub use self::cachez::CachezStore;
+pub use self::chaos::ChaosStore;
+pub use self::memcached::MemcachedStore;
+pub use self::redis::RedisStore;
+
+use crate::node::Node;
+use bytes::Bytes;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A cache of byte keys and values a [`TieredCache`] can use as a tier
+#[async_trait::async_trait]
+pub trait Store: Send + Sync {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Bytes>>;
+
+    /// The value never expires without `ttl`, unless the store evicts it
+    async fn set(&self, key: &[u8], value: Bytes, ttl: Option<Duration>) -> io::Result<()>;
+
+    /// Returns whether the key was cached
+    async fn delete(&self, key: &[u8]) -> io::Result<bool>;
+}
+
+#[async_trait::async_trait]
+impl Store for Node {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Bytes>> {
+        Ok(Node::get(self, key))
+    }
+
+    async fn set(&self, key: &[u8], value: Bytes, ttl: Option<Duration>) -> io::Result<()> {
+        Node::set(self, Bytes::copy_from_slice(key), value, None, ttl);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        Ok(Node::delete(self, key))
+    }
+}
+
+/// Statistics of a tier of a [`TieredCache`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TierStats {
+    pub name: String,
+    pub hits: u64,
+    pub misses: u64,
+    /// Failed operations, failed reads are counted as misses too
+    pub errors: u64,
+}
+
+/// Statistics of a [`TieredCache`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TieredStats {
+    /// In the order the tiers are looked up
+    pub tiers: Vec<TierStats>,
+    /// Values loaded from the origin
+    pub loads: u64,
+}
+
+struct Tier {
+    name: String,
+    store: Arc<dyn Store>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Looks keys up tier after tier, see the [module](self) documentation
+pub struct TieredCache {
+    tiers: Vec<Tier>,
+    ttl: Option<Duration>,
+    loads: AtomicU64,
+}
+
+impl TieredCache {
+    /// A cache with `local` as its first tier, named `local`
+    pub fn new(local: Arc<Node>) -> Self {
+        let cache = Self {
+            tiers: Vec::new(),
+            ttl: None,
+            loads: AtomicU64::new(0),
+        };
+        cache.with_tier("local", local)
+    }
+
+    /// Add a tier looked up after the current ones
+    pub fn with_tier(mut self, name: impl Into<String>, store: Arc<dyn Store>) -> Self {
+        self.tiers.push(Tier {
+            name: name.into(),
+            store,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        });
+        self
+    }
+
+    /// TTL of the values written by the cache itself: sets, copies to upper tiers and loads
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Look `key` up tier after tier, a hit is copied to the tiers above
+    pub async fn get(&self, key: &[u8]) -> Option<Bytes> {
+        for (level, tier) in self.tiers.iter().enumerate() {
+            let value = match tier.store.get(key).await {
+                Ok(value) => value,
+                Err(_) => {
+                    tier.errors.fetch_add(1, Relaxed);
+                    None
+                }
+            };
+            let Some(value) = value else {
+                tier.misses.fetch_add(1, Relaxed);
+                continue;
+            };
+            tier.hits.fetch_add(1, Relaxed);
+            self.write(&self.tiers[..level], key, &value).await;
+            return Some(value);
+        }
+        None
+    }
+
+    /// [`Self::get`], loading the value from the origin with `load` when no tier has it
+    pub async fn get_or_load<F, Fut, E>(&self, key: &[u8], load: F) -> Result<Bytes, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Bytes, E>>,
+    {
+        if let Some(value) = self.get(key).await {
+            return Ok(value);
+        }
+        let value = load().await?;
+        self.loads.fetch_add(1, Relaxed);
+        self.write(&self.tiers, key, &value).await;
+        Ok(value)
+    }
+
+    /// Write `value` to every tier
+    pub async fn set(&self, key: &[u8], value: Bytes) {
+        self.write(&self.tiers, key, &value).await;
+    }
+
+    /// Delete `key` from every tier, returns whether any had it
+    pub async fn delete(&self, key: &[u8]) -> bool {
+        let mut deleted = false;
+        for tier in &self.tiers {
+            match tier.store.delete(key).await {
+                Ok(had) => deleted |= had,
+                Err(_) => {
+                    tier.errors.fetch_add(1, Relaxed);
+                }
+            }
+        }
+        deleted
+    }
+
+    pub fn stats(&self) -> TieredStats {
+        TieredStats {
+            tiers: self
+                .tiers
+                .iter()
+                .map(|tier| TierStats {
+                    name: tier.name.clone(),
+                    hits: tier.hits.load(Relaxed),
+                    misses: tier.misses.load(Relaxed),
+                    errors: tier.errors.load(Relaxed),
+                })
+                .collect(),
+            loads: self.loads.load(Relaxed),
+        }
+    }
+
+    async fn write(&self, tiers: &[Tier], key: &[u8], value: &Bytes) {
+        for tier in tiers {
+            if tier.store.set(key, value.clone(), self.ttl).await.is_err() {
+                tier.errors.fetch_add(1, Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tiers() {
+        let local = Arc::new(Node::new(1024, 100));
+        let remote = Arc::new(Node::new(1024, 100));
+        let cache = TieredCache::new(local.clone()).with_tier("remote", remote.clone());
+
+        remote.set(Bytes::from("a"), Bytes::from("1"), None, None);
+        assert_eq!(cache.get(b"a").await, Some(Bytes::from("1")));
+        // copied to the local tier
+        assert_eq!(local.get(b"a"), Some(Bytes::from("1")));
+        assert_eq!(cache.get(b"a").await, Some(Bytes::from("1")));
+
+        let loaded = cache
+            .get_or_load(b"b", || async { Ok::<_, io::Error>(Bytes::from("2")) })
+            .await;
+        assert_eq!(loaded.unwrap(), Bytes::from("2"));
+        assert_eq!(remote.get(b"b"), Some(Bytes::from("2")));
+        assert!(cache.delete(b"b").await);
+        assert_eq!(local.get(b"b"), None);
+
+        let stats = cache.stats();
+        let counts: Vec<_> = stats.tiers.iter().map(|t| (t.hits, t.misses)).collect();
+        assert_eq!(counts, vec![(1, 2), (1, 1)]);
+        assert_eq!(stats.loads, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failing_tier() {
+        let local = Arc::new(Node::new(1024, 100));
+        let remote = Arc::new(Node::new(1024, 100));
+        let chaos = Arc::new(::cachez::chaos::Chaos::new(1).error(1.0));
+        let cache = TieredCache::new(local.clone())
+            .with_tier("remote", Arc::new(ChaosStore::new(remote.clone(), chaos)));
+
+        remote.set(Bytes::from("a"), Bytes::from("1"), None, None);
+        // a miss rather than a failure
+        assert_eq!(cache.get(b"a").await, None);
+        let loaded = cache
+            .get_or_load(b"a", || async { Ok::<_, io::Error>(Bytes::from("2")) })
+            .await;
+        assert_eq!(loaded.unwrap(), Bytes::from("2"));
+        assert_eq!(local.get(b"a"), Some(Bytes::from("2")));
+        assert_eq!(remote.get(b"a"), Some(Bytes::from("1")));
+        // the failed read and the failed write of the load
+        assert_eq!(cache.stats().tiers[1].errors, 3);
+    }
+}
@@ -0,0 +1,106 @@
+use super::Store;
+use crate::resp::{Connection, Reply};
+use bytes::Bytes;
+use std::io;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// [`Store`] on a Redis server, or a cachez node speaking RESP.
+///
+/// Requests share one connection, reconnected after a failure. Connects without
+/// authentication.
+pub struct RedisStore {
+    addr: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl RedisStore {
+    /// Use the server at `addr`, `host:port`. It is connected to on first use
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn call(&self, command: &[&[u8]]) -> io::Result<Reply> {
+        let mut connection = self.connection.lock().await;
+        let open = match connection.as_mut() {
+            Some(open) => open,
+            None => connection.insert(Connection::connect(&self.addr).await?),
+        };
+        match open.call(command).await {
+            Ok(Reply::Error(error)) => Err(io::Error::other(error)),
+            Ok(reply) => Ok(reply),
+            Err(error) => {
+                // the stream may be mid reply, start over on a new one
+                *connection = None;
+                Err(error)
+            }
+        }
+    }
+}
+
+fn unexpected(reply: Reply) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected reply {reply:?}"),
+    )
+}
+
+#[async_trait::async_trait]
+impl Store for RedisStore {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Bytes>> {
+        match self.call(&[b"GET", key]).await? {
+            Reply::Bulk(value) => Ok(value),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn set(&self, key: &[u8], value: Bytes, ttl: Option<Duration>) -> io::Result<()> {
+        let reply = match ttl {
+            Some(ttl) => {
+                let ms = ttl.as_millis().max(1).to_string();
+                self.call(&[b"SET", key, &value, b"PX", ms.as_bytes()])
+                    .await?
+            }
+            None => self.call(&[b"SET", key, &value]).await?,
+        };
+        match reply {
+            Reply::Simple(_) => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        match self.call(&[b"DEL", key]).await? {
+            Reply::Integer(deleted) => Ok(deleted > 0),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_redis_store() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node = Arc::new(Node::new(1024, 100));
+        tokio::spawn(crate::resp::serve(listener, node.clone()));
+
+        let store = RedisStore::new(addr.to_string());
+        assert_eq!(store.get(b"a").await.unwrap(), None);
+        let ttl = Some(Duration::from_secs(60));
+        store.set(b"a", Bytes::from("1"), ttl).await.unwrap();
+        assert_eq!(store.get(b"a").await.unwrap(), Some(Bytes::from("1")));
+        assert!(node.ttl(b"a").unwrap().is_some());
+        assert!(store.delete(b"a").await.unwrap());
+        assert!(!store.delete(b"a").await.unwrap());
+    }
+}
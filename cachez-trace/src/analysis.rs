@@ -0,0 +1,399 @@
+//! What a trace says about the cache it needs, independently of any policy.
+//!
+//! - reuse distances: between two requests of a key, how many other distinct keys, and how many
+//!   bytes of them, were requested. An LRU cache holding that many objects (bytes) would have hit
+//!   the second request, so the cumulative histogram is LRU's hit ratio curve, a guide to
+//!   `total_weight_limit`
+//! - one hit wonders: keys requested only once, which is what TinyUFO's small queue exists to
+//!   filter out. The more of them, the more a small queue pays off
+//! - the working set over time: distinct keys and bytes of every window of requests
+//!
+//! Deletions forget their key, its next request counts as a first one.
+
+use crate::format::{Op, Request};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Requests per working set window by default
+pub const DEFAULT_WINDOW: usize = 100_000;
+
+/// Reuses at a distance under `below` and at least the previous bucket's `below`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bucket {
+    pub below: u64,
+    pub reuses: u64,
+}
+
+/// Distinct keys and bytes requested in the window starting at request `start`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkingSet {
+    pub start: u64,
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub requests: u64,
+    /// Distinct keys requested
+    pub keys: u64,
+    /// Bytes of all the distinct keys, at their last size
+    pub footprint: u64,
+    /// Requests of a key not requested before, or since it was deleted
+    pub first_requests: u64,
+    /// Keys requested once only
+    pub one_hit_wonders: u64,
+    /// By powers of 2, in distinct objects
+    pub reuse_distances: Vec<Bucket>,
+    /// By powers of 2, in bytes of distinct objects
+    pub reuse_bytes: Vec<Bucket>,
+    pub window: usize,
+    pub working_sets: Vec<WorkingSet>,
+}
+
+/// Sums over request positions, to count what was requested between two of them
+struct Fenwick(Vec<u64>);
+
+impl Fenwick {
+    fn new(len: usize) -> Self {
+        Self(vec![0; len + 1])
+    }
+
+    fn add(&mut self, position: usize, value: i64) {
+        let mut i = position + 1;
+        while i < self.0.len() {
+            self.0[i] = self.0[i].wrapping_add_signed(value);
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Sum of the positions before `position`
+    fn prefix(&self, position: usize) -> u64 {
+        let mut i = position;
+        let mut sum = 0u64;
+        while i > 0 {
+            sum = sum.wrapping_add(self.0[i]);
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+}
+
+/// Count `distance` in its power of 2 bucket
+fn record(buckets: &mut Vec<Bucket>, distance: u64) {
+    let index = (u64::BITS - distance.leading_zeros()) as usize;
+    while buckets.len() <= index {
+        let below = 1u64.checked_shl(buckets.len() as u32).unwrap_or(u64::MAX);
+        buckets.push(Bucket { below, reuses: 0 });
+    }
+    buckets[index].reuses += 1;
+}
+
+/// Analyze `requests`, measuring the working set every `window` requests
+pub fn analyze(requests: &[Request], window: usize) -> Analysis {
+    let window = window.max(1);
+    let mut analysis = Analysis {
+        requests: requests.len() as u64,
+        window,
+        ..Analysis::default()
+    };
+    // the latest request of every key is marked, with its size in `bytes`
+    let mut objects = Fenwick::new(requests.len());
+    let mut bytes = Fenwick::new(requests.len());
+    // position and size of the latest request of each key
+    let mut latest: HashMap<u64, (usize, u32)> = HashMap::new();
+    // last size and requests of every key
+    let mut requested: HashMap<u64, (u32, u64)> = HashMap::new();
+    let mut window_keys: HashMap<u64, u32> = HashMap::new();
+
+    for (position, request) in requests.iter().enumerate() {
+        if position % window == 0 && position > 0 {
+            analysis
+                .working_sets
+                .push(working_set(position - window, &window_keys));
+            window_keys.clear();
+        }
+        if request.op == Op::Delete {
+            if let Some((previous, size)) = latest.remove(&request.key) {
+                objects.add(previous, -1);
+                bytes.add(previous, -(size as i64));
+            }
+            continue;
+        }
+        window_keys.insert(request.key, request.size);
+        let (size, count) = requested.entry(request.key).or_default();
+        (*size, *count) = (request.size, *count + 1);
+
+        match latest.insert(request.key, (position, request.size)) {
+            Some((previous, size)) => {
+                let distance = objects.prefix(position) - objects.prefix(previous + 1);
+                let distance_bytes = bytes.prefix(position) - bytes.prefix(previous + 1);
+                record(&mut analysis.reuse_distances, distance);
+                record(&mut analysis.reuse_bytes, distance_bytes);
+                objects.add(previous, -1);
+                bytes.add(previous, -(size as i64));
+            }
+            None => analysis.first_requests += 1,
+        }
+        objects.add(position, 1);
+        bytes.add(position, request.size as i64);
+    }
+    if !window_keys.is_empty() {
+        let start = (requests.len() - 1) / window * window;
+        analysis.working_sets.push(working_set(start, &window_keys));
+    }
+
+    analysis.keys = requested.len() as u64;
+    analysis.footprint = requested.values().map(|(size, _)| *size as u64).sum();
+    analysis.one_hit_wonders = requested.values().filter(|(_, count)| *count == 1).count() as u64;
+    analysis
+}
+
+fn working_set(start: usize, keys: &HashMap<u64, u32>) -> WorkingSet {
+    WorkingSet {
+        start: start as u64,
+        keys: keys.len() as u64,
+        bytes: keys.values().map(|size| *size as u64).sum(),
+    }
+}
+
+/// The distance under which `share` of the reuses fall, the size of an LRU cache hitting them
+fn percentile(buckets: &[Bucket], share: f64) -> u64 {
+    let reuses: u64 = buckets.iter().map(|bucket| bucket.reuses).sum();
+    let mut seen = 0;
+    for bucket in buckets {
+        seen += bucket.reuses;
+        if seen as f64 >= reuses as f64 * share {
+            return bucket.below;
+        }
+    }
+    0
+}
+
+impl Analysis {
+    /// Reuses over all the requests but deletions, the hit ratio of an infinite cache
+    pub fn reuse_ratio(&self) -> f64 {
+        let reuses: u64 = self.reuse_distances.iter().map(|b| b.reuses).sum();
+        ratio(reuses, reuses + self.first_requests)
+    }
+
+    /// Share of the keys requested once only
+    pub fn one_hit_wonder_ratio(&self) -> f64 {
+        ratio(self.one_hit_wonders, self.keys)
+    }
+
+    /// Objects an LRU cache needs to hit `share` of the reuses, rounded up to a power of 2
+    pub fn objects_for(&self, share: f64) -> u64 {
+        percentile(&self.reuse_distances, share)
+    }
+
+    /// Like [`Self::objects_for`] in bytes
+    pub fn bytes_for(&self, share: f64) -> u64 {
+        percentile(&self.reuse_bytes, share)
+    }
+
+    /// Write the analysis as a JSON object
+    pub fn write_json(&self, mut out: impl Write) -> io::Result<()> {
+        let buckets = |buckets: &[Bucket]| {
+            let buckets: Vec<_> = buckets
+                .iter()
+                .map(|b| format!("{{\"below\":{},\"reuses\":{}}}", b.below, b.reuses))
+                .collect();
+            buckets.join(",")
+        };
+        let working_sets: Vec<_> = self
+            .working_sets
+            .iter()
+            .map(|w| {
+                format!(
+                    "{{\"start\":{},\"keys\":{},\"bytes\":{}}}",
+                    w.start, w.keys, w.bytes
+                )
+            })
+            .collect();
+        writeln!(
+            out,
+            "{{\"requests\":{},\"keys\":{},\"footprint\":{},\"first_requests\":{},\
+             \"one_hit_wonders\":{},\"reuse_distances\":[{}],\"reuse_bytes\":[{}],\
+             \"window\":{},\"working_sets\":[{}]}}",
+            self.requests,
+            self.keys,
+            self.footprint,
+            self.first_requests,
+            self.one_hit_wonders,
+            buckets(&self.reuse_distances),
+            buckets(&self.reuse_bytes),
+            self.window,
+            working_sets.join(",")
+        )
+    }
+
+    /// Write the histograms and working sets as CSV in long form, `metric,x,value`, the
+    /// totals having no `x`
+    pub fn write_csv(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "metric,x,value")?;
+        for (metric, value) in [
+            ("requests", self.requests),
+            ("keys", self.keys),
+            ("footprint", self.footprint),
+            ("first_requests", self.first_requests),
+            ("one_hit_wonders", self.one_hit_wonders),
+        ] {
+            writeln!(out, "{metric},,{value}")?;
+        }
+        for bucket in &self.reuse_distances {
+            writeln!(out, "reuse_distance,{},{}", bucket.below, bucket.reuses)?;
+        }
+        for bucket in &self.reuse_bytes {
+            writeln!(out, "reuse_bytes,{},{}", bucket.below, bucket.reuses)?;
+        }
+        for set in &self.working_sets {
+            writeln!(out, "working_set_keys,{},{}", set.start, set.keys)?;
+            writeln!(out, "working_set_bytes,{},{}", set.start, set.bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Write a summary for people
+    pub fn write_text(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{} requests of {} keys, {} bytes in all",
+            self.requests, self.keys, self.footprint
+        )?;
+        writeln!(
+            out,
+            "reuse ratio {:.4}, one hit wonders {:.4} of the keys",
+            self.reuse_ratio(),
+            self.one_hit_wonder_ratio()
+        )?;
+        writeln!(out, "{:<8} {:>14} {:>16}", "reuses", "objects", "bytes")?;
+        for share in [0.5, 0.9, 0.99] {
+            writeln!(
+                out,
+                "{:<8} {:>14} {:>16}",
+                format!("{}%", share * 100.0),
+                self.objects_for(share),
+                self.bytes_for(share)
+            )?;
+        }
+        let peak = self.working_sets.iter().max_by_key(|set| set.bytes);
+        if let Some(peak) = peak {
+            writeln!(
+                out,
+                "largest working set of {} requests: {} keys, {} bytes from request {}",
+                self.window, peak.keys, peak.bytes, peak.start
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the analysis in `format`
+    pub fn write(&self, format: ReportFormat, out: impl Write) -> io::Result<()> {
+        match format {
+            ReportFormat::Text => self.write_text(out),
+            ReportFormat::Json => self.write_json(out),
+            ReportFormat::Csv => self.write_csv(out),
+        }
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!(
+                "unknown report format {s}, expected text, json or csv"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Csv => "csv",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(key: u64, op: Op) -> Request {
+        Request { key, size: 10, op }
+    }
+
+    #[test]
+    fn test_analyze() {
+        // a b c a b a, then d deleted and requested again
+        let keys = [1, 2, 3, 1, 2, 1];
+        let mut requests: Vec<_> = keys.iter().map(|key| request(*key, Op::Get)).collect();
+        requests.push(request(4, Op::Set));
+        requests.push(request(4, Op::Delete));
+        requests.push(request(4, Op::Get));
+        let analysis = analyze(&requests, 4);
+
+        assert_eq!((analysis.keys, analysis.footprint), (4, 40));
+        // 1, 2, 3 and 4 twice
+        assert_eq!(analysis.first_requests, 5);
+        assert_eq!(analysis.one_hit_wonders, 1);
+        // distances: 1 after b c = 2, 2 after c a = 2, 1 after b = 1
+        let reuses: Vec<_> = analysis
+            .reuse_distances
+            .iter()
+            .map(|b| (b.below, b.reuses))
+            .collect();
+        assert_eq!(reuses, vec![(1, 0), (2, 1), (4, 2)]);
+        assert_eq!(
+            analysis.reuse_bytes.last(),
+            Some(&Bucket {
+                below: 32,
+                reuses: 2
+            })
+        );
+        assert_eq!(analysis.objects_for(0.5), 4);
+        assert_eq!(analysis.objects_for(0.3), 2);
+
+        let sets: Vec<_> = analysis
+            .working_sets
+            .iter()
+            .map(|w| (w.start, w.keys))
+            .collect();
+        assert_eq!(sets, vec![(0, 3), (4, 3), (8, 1)]);
+
+        let mut json = Vec::new();
+        analysis.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"requests\":9,\"keys\":4,"), "{json}");
+        let mut csv = Vec::new();
+        analysis.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.contains("\nreuse_distance,4,2\n"), "{csv}");
+        assert!(csv.ends_with("working_set_bytes,8,10\n"), "{csv}");
+    }
+}
@@ -0,0 +1,251 @@
+//! Readers of the common cache trace formats.
+//!
+//! - `twitter`: Twitter's production traces, `timestamp,key,key_size,value_size,client_id,op,ttl`
+//! - `cachelib`: CSV with a header naming its columns, as Meta's CacheLib traces: `key` and
+//!   optionally `op`, `op_count`, `key_size` and `size`, `value_size` or `object_size`
+//! - `arc`: the block traces of the ARC paper, `start_block block_count ignored request_id`, a
+//!   line reading [`ARC_BLOCK_SIZE`] byte blocks
+//! - `wiki`: Wikimedia's CDN traces, `timestamp id size` separated by whitespace
+//!
+//! Keys are hashed to `u64`s, like the cache does anyway.
+
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+use t1ha::T1haBuildHasher;
+
+/// Size of the blocks of the `arc` traces
+pub const ARC_BLOCK_SIZE: u32 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Twitter,
+    Cachelib,
+    Arc,
+    Wiki,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "twitter" => Ok(Self::Twitter),
+            "cachelib" => Ok(Self::Cachelib),
+            "arc" => Ok(Self::Arc),
+            "wiki" => Ok(Self::Wiki),
+            _ => Err(format!(
+                "unknown format {s}, expected twitter, cachelib, arc or wiki"
+            )),
+        }
+    }
+}
+
+pub use cachez::workload::{Op, Request};
+
+fn parse_op(op: &str) -> Op {
+    match op.to_ascii_lowercase().as_str() {
+        "delete" | "del" => Op::Delete,
+        "set" | "add" | "replace" | "cas" | "append" | "prepend" | "incr" | "decr" => Op::Set,
+        _ => Op::Get,
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    /// A line that doesn't fit the format, numbered from 1
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "reading the trace: {e}"),
+            Self::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Read up to `limit` requests of a trace in `format`
+pub fn read(
+    format: Format,
+    reader: impl BufRead,
+    limit: Option<usize>,
+) -> Result<Vec<Request>, TraceError> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut requests = Vec::new();
+    let mut columns = None;
+    for (i, line) in reader.lines().enumerate() {
+        if requests.len() >= limit {
+            break;
+        }
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = match format {
+            Format::Twitter => twitter(line, &mut requests),
+            Format::Cachelib => match &columns {
+                Some(columns) => cachelib(columns, line, &mut requests),
+                None => Columns::parse(line).map(|parsed| columns = Some(parsed)),
+            },
+            Format::Arc => arc(line, &mut requests),
+            Format::Wiki => wiki(line, &mut requests),
+        };
+        parsed.map_err(|message| TraceError::Parse {
+            line: i + 1,
+            message,
+        })?;
+    }
+    requests.truncate(limit);
+    Ok(requests)
+}
+
+fn hash(key: &str) -> u64 {
+    T1haBuildHasher::default().hash_one(key)
+}
+
+fn number<T: FromStr>(field: Option<&str>, name: &str) -> Result<T, String> {
+    let field = field.ok_or_else(|| format!("missing {name}"))?;
+    field
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid {name} {field:?}"))
+}
+
+fn twitter(line: &str, requests: &mut Vec<Request>) -> Result<(), String> {
+    let mut fields = line.split(',');
+    fields.next();
+    let key = fields.next().ok_or("missing key")?;
+    let key_size: u32 = number(fields.next(), "key size")?;
+    let value_size: u32 = number(fields.next(), "value size")?;
+    fields.next();
+    let op = fields.next().ok_or("missing operation")?;
+    requests.push(Request {
+        key: hash(key),
+        size: key_size.saturating_add(value_size),
+        op: parse_op(op),
+    });
+    Ok(())
+}
+
+/// Positions of the columns of a `cachelib` trace, from its header
+struct Columns {
+    key: usize,
+    key_size: Option<usize>,
+    size: Option<usize>,
+    op: Option<usize>,
+    op_count: Option<usize>,
+}
+
+impl Columns {
+    fn parse(header: &str) -> Result<Self, String> {
+        let names: Vec<_> = header.split(',').map(str::trim).collect();
+        let find = |wanted: &[&str]| names.iter().position(|name| wanted.contains(name));
+        Ok(Self {
+            key: find(&["key"]).ok_or("no key column in the header")?,
+            key_size: find(&["key_size"]),
+            size: find(&["size", "value_size", "object_size"]),
+            op: find(&["op"]),
+            op_count: find(&["op_count"]),
+        })
+    }
+}
+
+fn cachelib(columns: &Columns, line: &str, requests: &mut Vec<Request>) -> Result<(), String> {
+    let fields: Vec<_> = line.split(',').collect();
+    let field = |column: Option<usize>| column.and_then(|i| fields.get(i).copied());
+    let key = field(Some(columns.key)).ok_or("missing key")?;
+    let size = match columns.size {
+        Some(_) => number(field(columns.size), "size")?,
+        None => 0u32,
+    };
+    let key_size = match columns.key_size {
+        Some(_) => number(field(columns.key_size), "key size")?,
+        None => 0u32,
+    };
+    let count = match columns.op_count {
+        Some(_) => number(field(columns.op_count), "op count")?,
+        None => 1usize,
+    };
+    let request = Request {
+        key: hash(key),
+        size: size.saturating_add(key_size).max(1),
+        op: field(columns.op).map_or(Op::Get, parse_op),
+    };
+    requests.extend(std::iter::repeat_n(request, count));
+    Ok(())
+}
+
+fn arc(line: &str, requests: &mut Vec<Request>) -> Result<(), String> {
+    let mut fields = line.split_whitespace();
+    let start: u64 = number(fields.next(), "start block")?;
+    let count: u64 = number(fields.next(), "block count")?;
+    requests.extend((start..start + count).map(|block| Request {
+        key: block,
+        size: ARC_BLOCK_SIZE,
+        op: Op::Get,
+    }));
+    Ok(())
+}
+
+fn wiki(line: &str, requests: &mut Vec<Request>) -> Result<(), String> {
+    let mut fields = line.split_whitespace();
+    fields.next();
+    let key = fields.next().ok_or("missing id")?;
+    let size = number(fields.next(), "size")?;
+    requests.push(Request {
+        key: hash(key),
+        size,
+        op: Op::Get,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let twitter = "0,abc,3,100,7,get,0\n1,abc,3,120,7,set,3600\n2,xyz,3,10,7,delete,0\n";
+        let requests = read(Format::Twitter, twitter.as_bytes(), None).unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].key, requests[1].key);
+        assert_eq!((requests[1].size, requests[1].op), (123, Op::Set));
+        assert_eq!(requests[2].op, Op::Delete);
+
+        let cachelib = "key,op,size,op_count,key_size\nk1,GET,100,2,10\nk2,SET,50,1,10\n";
+        let requests = read(Format::Cachelib, cachelib.as_bytes(), None).unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!((requests[1].size, requests[1].op), (110, Op::Get));
+        assert_eq!(requests[2].op, Op::Set);
+
+        let arc = "100 3 0 1\n7 1 0 2\n";
+        let requests = read(Format::Arc, arc.as_bytes(), Some(2)).unwrap();
+        let keys: Vec<_> = requests.iter().map(|request| request.key).collect();
+        assert_eq!(keys, vec![100, 101]);
+
+        let wiki = "1 42 1024\n2 43 2048\n";
+        let requests = read(Format::Wiki, wiki.as_bytes(), None).unwrap();
+        assert_eq!(requests[1].size, 2048);
+
+        let error = read(Format::Wiki, "1 42 big\n".as_bytes(), None).unwrap_err();
+        assert_eq!(error.to_string(), "line 1: invalid size \"big\"");
+        assert!(read(Format::Cachelib, "op,size\n".as_bytes(), None).is_err());
+    }
+}
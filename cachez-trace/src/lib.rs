@@ -0,0 +1,7 @@
+//! Replays of cache traces through [`TinyUFO`](cachez::tinyufo::TinyUFO) and reference
+//! policies, to compare their hit ratios on a workload before adopting one. Synthetic workloads
+//! come from [`cachez::workload`], and [`analysis`] sizes a cache from a trace.
+
+pub mod analysis;
+pub mod format;
+pub mod replay;
@@ -0,0 +1,182 @@
+use cachez::workload::{Keys, ScanBursts, Sizes, Workload};
+use cachez_trace::analysis::{self, ReportFormat};
+use cachez_trace::format::{self, Format, Request};
+use cachez_trace::replay::{self, Config, Policy};
+use clap::Parser;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Replay a cache trace through TinyUFO and reference policies, printing their hit ratio,
+/// byte hit ratio and throughput.
+///
+/// Reads fill the cache on a miss, writes put and deletions remove. `--synthetic` replays a
+/// seeded synthetic workload instead of a trace. `--analyze` reports the reuse distances and
+/// working set of the requests instead of replaying them.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// The trace file
+    #[arg(required_unless_present = "synthetic")]
+    trace: Option<PathBuf>,
+    /// Format of the trace: twitter, cachelib, arc or wiki
+    #[arg(long, required_unless_present = "synthetic")]
+    format: Option<Format>,
+    /// Replay this many requests of a synthetic workload instead of a trace
+    #[arg(long, conflicts_with_all = ["trace", "format"])]
+    synthetic: Option<usize>,
+    /// Keys of the synthetic workload
+    #[arg(long, default_value_t = 100_000)]
+    items: u64,
+    /// Zipf exponent of the synthetic keys, uniform when unset
+    #[arg(long)]
+    skew: Option<f64>,
+    /// Share of reads of the synthetic workload, the others are writes
+    #[arg(long, default_value_t = 1.0)]
+    read_ratio: f64,
+    /// Size of the synthetic objects in bytes, the minimum with `--pareto-shape`
+    #[arg(long, default_value_t = 1)]
+    size: u32,
+    /// Draw the synthetic sizes from a Pareto distribution of this shape
+    #[arg(long)]
+    pareto_shape: Option<f64>,
+    /// Move the popular synthetic keys every that many requests
+    #[arg(long)]
+    hotspot_shift: Option<usize>,
+    /// Scan once through new keys every that many synthetic requests
+    #[arg(long, requires = "scan_len")]
+    scan_every: Option<usize>,
+    /// Requests of each scan
+    #[arg(long, requires = "scan_every")]
+    scan_len: Option<usize>,
+    /// Seed of the synthetic workload
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+    /// Policies to compare, separated by commas: tinyufo, lru or fifo
+    #[arg(long, value_delimiter = ',', default_value = "tinyufo")]
+    policy: Vec<Policy>,
+    /// Size of the cache in bytes
+    #[arg(
+        long,
+        conflicts_with = "objects",
+        required_unless_present_any = ["objects", "analyze"]
+    )]
+    cache_size: Option<u64>,
+    /// Size of the cache in objects, ignoring their sizes
+    #[arg(long)]
+    objects: Option<usize>,
+    /// Bytes per unit of weight with `--cache-size`
+    #[arg(long, default_value_t = 1)]
+    unit: u32,
+    /// Expected number of cached objects [default: the cache size over the mean object size]
+    #[arg(long)]
+    capacity: Option<usize>,
+    /// Share of the weight given to TinyUFO's small queue
+    #[arg(long)]
+    small_queue_percent: Option<u8>,
+    /// Replay only the first requests of the trace
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Report the reuse distances and working sets of the requests rather than replaying them:
+    /// text, json or csv
+    #[arg(long, num_args = 0..=1, default_missing_value = "text")]
+    analyze: Option<ReportFormat>,
+    /// Requests per working set window of `--analyze`
+    #[arg(long, default_value_t = analysis::DEFAULT_WINDOW)]
+    window: usize,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let (name, requests) = match read(&args) {
+        Ok(read) => read,
+        Err((name, e)) => {
+            eprintln!("{name}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if requests.is_empty() {
+        eprintln!("{name}: no requests");
+        return ExitCode::FAILURE;
+    }
+    if let Some(report) = args.analyze {
+        let analysis = analysis::analyze(&requests, args.window);
+        if let Err(e) = analysis.write(report, std::io::stdout().lock()) {
+            eprintln!("{name}: {e}");
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let (weight_limit, unit, capacity) = match (args.objects, args.cache_size) {
+        (Some(objects), _) => (objects, None, args.capacity.unwrap_or(objects)),
+        (None, cache_size) => {
+            let cache_size = cache_size.unwrap_or_default();
+            let unit = args.unit.max(1);
+            let mean = requests.iter().map(|r| r.size as u64).sum::<u64>() / requests.len() as u64;
+            let capacity = args
+                .capacity
+                .unwrap_or((cache_size / mean.max(1)).max(1) as usize);
+            ((cache_size / unit as u64) as usize, Some(unit), capacity)
+        }
+    };
+
+    println!(
+        "{:<8} {:>10} {:>10} {:>14}",
+        "policy", "hit ratio", "byte hits", "requests/s"
+    );
+    for policy in args.policy {
+        let config = Config {
+            policy,
+            weight_limit,
+            capacity,
+            unit,
+            small_queue_percent: args.small_queue_percent,
+        };
+        let report = replay::replay(&requests, &config);
+        println!(
+            "{:<8} {:>10.4} {:>10.4} {:>14.0}",
+            policy,
+            report.hit_ratio(),
+            report.byte_hit_ratio(),
+            report.throughput(requests.len())
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+/// The requests to replay and where they come from
+fn read(args: &Args) -> Result<(String, Vec<Request>), (String, format::TraceError)> {
+    let (Some(trace), Some(format)) = (&args.trace, args.format) else {
+        let workload = Workload {
+            keys: args.skew.map_or(Keys::Uniform, Keys::Zipf),
+            read_ratio: args.read_ratio,
+            sizes: match args.pareto_shape {
+                Some(shape) => Sizes::Pareto {
+                    min: args.size,
+                    shape,
+                },
+                None => Sizes::Fixed(args.size),
+            },
+            hotspot_shift: args.hotspot_shift,
+            scans: args
+                .scan_every
+                .zip(args.scan_len)
+                .map(|(every, len)| ScanBursts { every, len }),
+            seed: args.seed,
+            ..Workload::new(args.items)
+        };
+        let len = args
+            .synthetic
+            .unwrap_or_default()
+            .min(args.limit.unwrap_or(usize::MAX));
+        return Ok(("synthetic".to_string(), workload.generate(len)));
+    };
+    let name = trace.display().to_string();
+    File::open(trace)
+        .map_err(format::TraceError::from)
+        .and_then(|file| format::read(format, BufReader::new(file), args.limit))
+        .map(|requests| (name.clone(), requests))
+        .map_err(|e| (name, e))
+}
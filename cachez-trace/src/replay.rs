@@ -0,0 +1,287 @@
+//! Replay of requests through a cache policy.
+
+use crate::format::{Op, Request};
+use cachez::tinyufo::{CacheConfig, TinyUFO, Weight};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    TinyUfo,
+    /// Reference least recently used eviction
+    Lru,
+    /// Reference first in first out eviction
+    Fifo,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tinyufo" => Ok(Self::TinyUfo),
+            "lru" => Ok(Self::Lru),
+            "fifo" => Ok(Self::Fifo),
+            _ => Err(format!("unknown policy {s}, expected tinyufo, lru or fifo")),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::TinyUfo => "tinyufo",
+            Self::Lru => "lru",
+            Self::Fifo => "fifo",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub policy: Policy,
+    /// Total weight of the cached objects
+    pub weight_limit: usize,
+    /// Expected number of cached objects
+    pub capacity: usize,
+    /// Bytes per unit of weight, `None` weighs every object 1 to count objects instead
+    pub unit: Option<u32>,
+    /// Share of the weight given to TinyUFO's small queue
+    pub small_queue_percent: Option<u8>,
+}
+
+impl Config {
+    fn weight(&self, size: u32) -> Weight {
+        match self.unit {
+            Some(unit) => size.div_ceil(unit.max(1)).max(1).into(),
+            None => 1,
+        }
+    }
+}
+
+/// Outcome of a replay, counting the reads only
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub requests: u64,
+    pub hits: u64,
+    pub bytes: u64,
+    pub hit_bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    pub fn hit_ratio(&self) -> f64 {
+        ratio(self.hits, self.requests)
+    }
+
+    pub fn byte_hit_ratio(&self) -> f64 {
+        ratio(self.hit_bytes, self.bytes)
+    }
+
+    /// Requests replayed per second, writes included
+    pub fn throughput(&self, replayed: usize) -> f64 {
+        replayed as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+trait Replayed {
+    fn get(&mut self, key: u64) -> bool;
+    fn put(&mut self, key: u64, weight: Weight);
+    fn remove(&mut self, key: u64);
+}
+
+impl Replayed for TinyUFO<u64, ()> {
+    fn get(&mut self, key: u64) -> bool {
+        TinyUFO::get(self, &key).is_some()
+    }
+
+    fn put(&mut self, key: u64, weight: Weight) {
+        TinyUFO::put(self, key, weight, ());
+    }
+
+    fn remove(&mut self, key: u64) {
+        TinyUFO::remove(self, &key);
+    }
+}
+
+/// LRU or FIFO: evicts the oldest entry, where hits refresh the age of LRU's entries
+struct Reference {
+    entries: HashMap<u64, (u64, Weight)>,
+    order: BTreeMap<u64, u64>,
+    tick: u64,
+    weight: usize,
+    weight_limit: usize,
+    promote: bool,
+}
+
+impl Reference {
+    fn new(weight_limit: usize, capacity: usize, promote: bool) -> Self {
+        Self {
+            entries: HashMap::with_capacity(capacity),
+            order: BTreeMap::new(),
+            tick: 0,
+            weight: 0,
+            weight_limit,
+            promote,
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl Replayed for Reference {
+    fn get(&mut self, key: u64) -> bool {
+        let tick = self.next_tick();
+        let Some((age, _)) = self.entries.get_mut(&key) else {
+            return false;
+        };
+        if self.promote {
+            self.order.remove(age);
+            self.order.insert(tick, key);
+            *age = tick;
+        }
+        true
+    }
+
+    fn put(&mut self, key: u64, weight: Weight) {
+        self.remove(key);
+        let tick = self.next_tick();
+        self.entries.insert(key, (tick, weight));
+        self.order.insert(tick, key);
+        self.weight += weight as usize;
+        while self.weight > self.weight_limit {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((_, weight)) = self.entries.remove(&oldest) {
+                self.weight -= weight as usize;
+            }
+        }
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some((age, weight)) = self.entries.remove(&key) {
+            self.order.remove(&age);
+            self.weight -= weight as usize;
+        }
+    }
+}
+
+/// Replay `requests` through the cache `config` describes
+pub fn replay(requests: &[Request], config: &Config) -> Report {
+    match config.policy {
+        Policy::TinyUfo => {
+            let cache_config = CacheConfig::new(config.weight_limit, config.capacity);
+            let mut cache = TinyUFO::from_config(&cache_config);
+            if let Some(percent) = config.small_queue_percent {
+                cache.set_small_queue_percent(percent);
+            }
+            run(&mut cache, requests, config)
+        }
+        Policy::Lru => run(
+            &mut Reference::new(config.weight_limit, config.capacity, true),
+            requests,
+            config,
+        ),
+        Policy::Fifo => run(
+            &mut Reference::new(config.weight_limit, config.capacity, false),
+            requests,
+            config,
+        ),
+    }
+}
+
+fn run(cache: &mut impl Replayed, requests: &[Request], config: &Config) -> Report {
+    let mut report = Report::default();
+    let start = Instant::now();
+    for request in requests {
+        match request.op {
+            Op::Get => {
+                report.requests += 1;
+                report.bytes += request.size as u64;
+                if cache.get(request.key) {
+                    report.hits += 1;
+                    report.hit_bytes += request.size as u64;
+                } else {
+                    cache.put(request.key, config.weight(request.size));
+                }
+            }
+            Op::Set => cache.put(request.key, config.weight(request.size)),
+            Op::Delete => cache.remove(request.key),
+        }
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(key: u64, size: u32) -> Request {
+        Request {
+            key,
+            size,
+            op: Op::Get,
+        }
+    }
+
+    fn config(policy: Policy) -> Config {
+        Config {
+            policy,
+            weight_limit: 2,
+            capacity: 2,
+            unit: None,
+            small_queue_percent: None,
+        }
+    }
+
+    #[test]
+    fn test_reference_policies() {
+        let requests = [get(1, 10), get(2, 10), get(1, 10), get(3, 30), get(1, 10)];
+        let lru = replay(&requests, &config(Policy::Lru));
+        assert_eq!((lru.requests, lru.hits), (5, 2));
+        assert_eq!((lru.bytes, lru.hit_bytes), (70, 20));
+        let fifo = replay(&requests, &config(Policy::Fifo));
+        assert_eq!(fifo.hits, 1);
+
+        let deleted = [
+            get(1, 10),
+            Request {
+                key: 1,
+                size: 10,
+                op: Op::Delete,
+            },
+            get(1, 10),
+        ];
+        assert_eq!(replay(&deleted, &config(Policy::Lru)).hits, 0);
+    }
+
+    #[test]
+    fn test_tinyufo() {
+        let requests: Vec<_> = (0..1000).map(|i| get(i % 10, 100)).collect();
+        let config = Config {
+            weight_limit: 100,
+            capacity: 100,
+            unit: Some(10),
+            ..config(Policy::TinyUfo)
+        };
+        let report = replay(&requests, &config);
+        assert_eq!(report.requests, 1000);
+        assert!(report.hit_ratio() > 0.9, "{}", report.hit_ratio());
+        assert_eq!(report.hit_ratio(), report.byte_hit_ratio());
+    }
+}
@@ -0,0 +1,44 @@
+//! Cache slow lookups of an axum service.
+//!
+//! `cargo run --example axum --features axum`, then `curl localhost:3000/users/1` twice.
+
+use axum::extract::{FromRef, Path};
+use axum::routing::get;
+use axum::Router;
+use cachez::integrations::axum::Cache;
+use std::time::Duration;
+
+#[derive(Clone, FromRef)]
+struct AppState {
+    users: Cache<u64, String>,
+}
+
+async fn load_user(id: u64) -> String {
+    // stands in for a database query
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    format!("user {id}")
+}
+
+async fn user(Path(id): Path<u64>, users: Cache<u64, String>) -> String {
+    users.get_with(id, || load_user(id)).await
+}
+
+async fn stats(users: Cache<u64, String>) -> String {
+    format!("{:?}", users.stats())
+}
+
+#[tokio::main]
+async fn main() {
+    let state = AppState {
+        users: Cache::new(10_000, 10_000),
+    };
+    let app = Router::new()
+        .route("/users/{id}", get(user))
+        .route("/stats", get(stats))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
+        .await
+        .unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
@@ -0,0 +1,62 @@
+//! Cache query results of a SQLite database.
+//!
+//! `cargo run --example sqlx --features sqlx`
+
+use cachez::integrations::sqlx::QueryCache;
+use sqlx::SqlitePool;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, sqlx::FromRow)]
+struct Product {
+    id: i64,
+    name: String,
+    description: String,
+}
+
+const BY_ID: &str = "SELECT id, name, description FROM products WHERE id = ?";
+
+#[tokio::main]
+async fn main() -> Result<(), sqlx::Error> {
+    let pool = SqlitePool::connect("sqlite::memory:").await?;
+    sqlx::query("CREATE TABLE products (id INTEGER PRIMARY KEY, name TEXT, description TEXT)")
+        .execute(&pool)
+        .await?;
+    for id in 0..1000i64 {
+        sqlx::query("INSERT INTO products VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(format!("product {id}"))
+            .bind("lorem ipsum ".repeat(id as usize % 50))
+            .execute(&pool)
+            .await?;
+    }
+
+    // 64 KiB of rows, results live for a minute
+    let cache: QueryCache<Product> = QueryCache::new(64, 1000, Duration::from_secs(60))
+        .with_row_size(|p: &Product| 8 + p.name.len() + p.description.len());
+
+    let start = Instant::now();
+    for i in 0..100_000u64 {
+        // a few popular products and a long tail
+        let id = if i % 4 == 0 {
+            (i * 7919) % 1000
+        } else {
+            i % 20
+        } as i64;
+        let product = cache
+            .fetch_optional(BY_ID, &id, || {
+                sqlx::query_as(BY_ID).bind(id).fetch_optional(&pool)
+            })
+            .await?;
+        assert_eq!(product.map(|p| p.id), Some(id));
+    }
+
+    let stats = cache.stats();
+    println!(
+        "{:?} for 100k lookups, {} queries run, hit ratio {:.2}, {} KiB cached",
+        start.elapsed(),
+        stats.loads,
+        stats.cache.hit_ratio(),
+        stats.cache.weight,
+    );
+    Ok(())
+}
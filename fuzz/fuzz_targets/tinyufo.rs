@@ -0,0 +1,47 @@
+//! Arbitrary operations on a TinyUFO, checking its invariants after each one.
+//!
+//! `cargo +nightly fuzz run tinyufo`
+#![no_main]
+
+use arbitrary::Arbitrary;
+use cachez::tinyufo::TinyUFO;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+    Get(u8),
+    Put(u8, u16),
+    Remove(u8),
+    SetWeightLimit(u16),
+    SetSmallQueuePercent(u8),
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    weight_limit: u16,
+    capacity: u8,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut cache = TinyUFO::new(input.weight_limit as usize, input.capacity as usize);
+    for op in input.ops {
+        match op {
+            Op::Get(key) => {
+                cache.get(&key);
+            }
+            Op::Put(key, weight) => {
+                cache.put(key, weight, key);
+                // only the entry just put is sure to stay
+                assert_eq!(cache.peek(&key), Some(&key));
+            }
+            Op::Remove(key) => {
+                cache.remove(&key);
+                assert_eq!(cache.peek(&key), None);
+            }
+            Op::SetWeightLimit(limit) => cache.set_weight_limit(limit as usize, |_, _| {}),
+            Op::SetSmallQueuePercent(percent) => cache.set_small_queue_percent(percent),
+        }
+        cache.check_invariants();
+    }
+});
@@ -0,0 +1,185 @@
+//! What every cache of the crate offers, to write code generic over them.
+//!
+//! ```
+//! use cachez::prelude::*;
+//!
+//! fn warm(cache: &mut impl Cache<u64, String>, keys: &[u64]) {
+//!     for key in keys {
+//!         cache.put(*key, 1, key.to_string());
+//!     }
+//! }
+//!
+//! let mut cache = TinyUFO::new(100, 100);
+//! warm(&mut cache, &[1, 2, 3]);
+//! assert_eq!(Cache::get(&mut cache, &2), Some("2".to_string()));
+//! assert_eq!((cache.len(), cache.weight()), (3, 3));
+//! ```
+
+use crate::tinyufo::{
+    CacheStats, CheckedTinyUFO, ConcurrentTinyUFO, ExperimentedTinyUFO, FixedTinyUfo,
+    GenerationalTinyUFO, LruTinyUFO, ShadowedTinyUFO, TinyUFO, Weight,
+};
+use std::hash::{BuildHasher, Hash};
+
+/// A weighted cache of `V`s by `K`.
+///
+/// Values are returned as clones so that the caches shared between threads, which can't lend
+/// them out, fit too: keep large values in an `Arc`.
+pub trait Cache<K, V> {
+    /// A clone of the value of `key`, counting as a read
+    fn get(&mut self, key: &K) -> Option<V>;
+
+    /// Cache `value` under `key`, replacing the value of a cached key
+    fn put(&mut self, key: K, weight: Weight, value: V);
+
+    /// Remove `key`, returns its value if it was cached
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    fn stats(&self) -> CacheStats;
+
+    /// Number of cached entries
+    fn len(&self) -> usize {
+        self.stats().entries
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total weight of the cached entries
+    fn weight(&self) -> usize {
+        self.stats().weight
+    }
+}
+
+impl<K: Hash, V: Clone, S: BuildHasher> Cache<K, V> for TinyUFO<K, V, (), S> {
+    fn get(&mut self, key: &K) -> Option<V> {
+        TinyUFO::get(self, key).cloned()
+    }
+
+    fn put(&mut self, key: K, weight: Weight, value: V) {
+        TinyUFO::put(self, key, weight, value);
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        TinyUFO::remove(self, key)
+    }
+
+    fn stats(&self) -> CacheStats {
+        TinyUFO::stats(self)
+    }
+}
+
+impl<K: Hash, V: Clone> Cache<K, V> for LruTinyUFO<K, V> {
+    fn get(&mut self, key: &K) -> Option<V> {
+        LruTinyUFO::get(self, key).cloned()
+    }
+
+    fn put(&mut self, key: K, weight: Weight, value: V) {
+        LruTinyUFO::put(self, key, weight, value);
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        LruTinyUFO::remove(self, key)
+    }
+
+    fn stats(&self) -> CacheStats {
+        LruTinyUFO::stats(self)
+    }
+}
+
+impl<K: Hash + Eq, V: Clone, S: BuildHasher> Cache<K, V> for CheckedTinyUFO<K, V, S> {
+    fn get(&mut self, key: &K) -> Option<V> {
+        CheckedTinyUFO::get(self, key).cloned()
+    }
+
+    fn put(&mut self, key: K, weight: Weight, value: V) {
+        CheckedTinyUFO::put(self, key, weight, value);
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        CheckedTinyUFO::remove(self, key)
+    }
+
+    fn stats(&self) -> CacheStats {
+        CheckedTinyUFO::stats(self)
+    }
+}
+
+impl<K: Hash, V: Clone, const N: usize> Cache<K, V> for FixedTinyUfo<K, V, N> {
+    fn get(&mut self, key: &K) -> Option<V> {
+        FixedTinyUfo::get(self, key).cloned()
+    }
+
+    fn put(&mut self, key: K, weight: Weight, value: V) {
+        FixedTinyUfo::put(self, key, weight, value);
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        FixedTinyUfo::remove(self, key)
+    }
+
+    fn stats(&self) -> CacheStats {
+        FixedTinyUfo::stats(self)
+    }
+}
+
+/// The caches shared through `&self`, usable through `&mut` as well
+macro_rules! impl_shared_cache {
+    ($($cache:ident),*) => {$(
+        impl<K: Hash, V: Clone> Cache<K, V> for $cache<K, V> {
+            fn get(&mut self, key: &K) -> Option<V> {
+                $cache::get(self, key)
+            }
+
+            fn put(&mut self, key: K, weight: Weight, value: V) {
+                $cache::put(self, key, weight, value);
+            }
+
+            fn remove(&mut self, key: &K) -> Option<V> {
+                $cache::remove(self, key)
+            }
+
+            fn stats(&self) -> CacheStats {
+                $cache::stats(self)
+            }
+        }
+    )*};
+}
+
+impl_shared_cache!(
+    ConcurrentTinyUFO,
+    GenerationalTinyUFO,
+    ShadowedTinyUFO,
+    ExperimentedTinyUFO
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tinyufo::{CacheConfig, ShadowConfig};
+
+    fn exercise(mut cache: impl Cache<u64, u64>) {
+        assert!(cache.is_empty());
+        for key in 0..4 {
+            cache.put(key, 2, key * 10);
+        }
+        assert_eq!(cache.get(&3), Some(30));
+        assert_eq!(cache.remove(&3), Some(30));
+        assert_eq!(cache.get(&3), None);
+        assert_eq!((cache.len(), cache.weight()), (3, 6));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.removals), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_every_cache() {
+        let config = CacheConfig::new(100, 100);
+        exercise(TinyUFO::new(100, 100));
+        exercise(FixedTinyUfo::<_, _, 16>::new(100));
+        exercise(ConcurrentTinyUFO::with_shards(100, 100, 1));
+        exercise(GenerationalTinyUFO::new(100, 100));
+        exercise(ShadowedTinyUFO::new(100, 100, &ShadowConfig::default()));
+        exercise(ExperimentedTinyUFO::new(&config, &config, 1));
+    }
+}
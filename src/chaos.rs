@@ -0,0 +1,364 @@
+//! Fault injection into the loaders and writers of the caches, for tests and soak runs of what
+//! happens when the backend misbehaves: serving stale values, timing loads out, sharing a load
+//! that hangs.
+//!
+//! A [`Chaos`] rolls a seeded die on every call to pick at most one [`Fault`], so a run replays
+//! the same faults from the same seed. [`Chaotic`] wraps a [`CacheLoader`], an
+//! [`AsyncCacheLoader`] or a [`CacheWriter`] to go through it first.
+//!
+//! ```
+//! use cachez::chaos::{Chaos, Chaotic, InjectedFault};
+//! use cachez::compat::moka::{sync::Cache, LoadError};
+//! use std::sync::Arc;
+//!
+//! let chaos = Arc::new(Chaos::new(42).error(1.0));
+//! let loader = |key: &u64| -> Result<u64, LoadError> { Ok(*key) };
+//! let cache = Cache::builder()
+//!     .max_capacity(16)
+//!     .loader(Chaotic::new(loader, chaos.clone()))
+//!     .build();
+//! let error = cache.get(&1).unwrap_err();
+//! assert!(error.downcast_ref::<InjectedFault>().is_some());
+//! assert_eq!(chaos.stats().errors, 1);
+//! ```
+
+use crate::compat::moka::future::Task;
+use crate::compat::moka::{
+    AsyncCacheLoader, CacheLoader, CacheWriter, LoadError, LoadFuture, WriteError,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// What a call is made to suffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Answer this much later
+    Latency(Duration),
+    /// Fail with [`InjectedFault`] without making the call
+    Error,
+    /// Never answer: a blocking call parks its thread for good, an async one stays pending
+    Hang,
+}
+
+/// Error of a call failed by [`Fault::Error`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectedFault;
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("injected fault")
+    }
+}
+
+impl Error for InjectedFault {}
+
+/// Faults injected so far by a [`Chaos`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    /// Calls rolled, faulty or not
+    pub calls: u64,
+    pub latencies: u64,
+    pub errors: u64,
+    pub hangs: u64,
+}
+
+/// Faults and their probabilities, see the [module](self) documentation
+pub struct Chaos {
+    // exclusive: one draw per call picks at most one of them
+    faults: Vec<(f64, Fault)>,
+    rng: Mutex<fastrand::Rng>,
+    calls: AtomicU64,
+    latencies: AtomicU64,
+    errors: AtomicU64,
+    hangs: AtomicU64,
+}
+
+impl Chaos {
+    /// No fault until some are added, drawn from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            faults: Vec::new(),
+            rng: Mutex::new(fastrand::Rng::with_seed(seed)),
+            calls: AtomicU64::new(0),
+            latencies: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            hangs: AtomicU64::new(0),
+        }
+    }
+
+    /// Delay a share `probability` of the calls by `latency`
+    pub fn latency(self, probability: f64, latency: Duration) -> Self {
+        self.fault(probability, Fault::Latency(latency))
+    }
+
+    /// Fail a share `probability` of the calls
+    pub fn error(self, probability: f64) -> Self {
+        self.fault(probability, Fault::Error)
+    }
+
+    /// Hang a share `probability` of the calls
+    pub fn hang(self, probability: f64) -> Self {
+        self.fault(probability, Fault::Hang)
+    }
+
+    /// Inject `fault` in a share `probability` of the calls. The faults exclude each other, their
+    /// probabilities add up and what goes over 1 is never injected
+    pub fn fault(mut self, probability: f64, fault: Fault) -> Self {
+        self.faults.push((probability.clamp(0.0, 1.0), fault));
+        self
+    }
+
+    /// The fault of the next call, if any
+    pub fn roll(&self) -> Option<Fault> {
+        self.calls.fetch_add(1, Relaxed);
+        let draw = self
+            .rng
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .f64();
+        let mut threshold = 0.0;
+        let (_, fault) = self.faults.iter().find(|(probability, _)| {
+            threshold += probability;
+            draw < threshold
+        })?;
+        let counter = match fault {
+            Fault::Latency(_) => &self.latencies,
+            Fault::Error => &self.errors,
+            Fault::Hang => &self.hangs,
+        };
+        counter.fetch_add(1, Relaxed);
+        Some(*fault)
+    }
+
+    /// Roll the fault of a blocking call and suffer it, an error is to be returned in place of
+    /// making the call
+    pub fn inject(&self) -> Result<(), InjectedFault> {
+        match self.roll() {
+            None => Ok(()),
+            Some(Fault::Latency(latency)) => {
+                std::thread::sleep(latency);
+                Ok(())
+            }
+            Some(Fault::Error) => Err(InjectedFault),
+            Some(Fault::Hang) => loop {
+                std::thread::park();
+            },
+        }
+    }
+
+    /// Like [`Self::inject`] for an async call, waiting with `sleep`, the runtime's, e.g.
+    /// `tokio::time::sleep`
+    pub async fn inject_async<S, F>(&self, sleep: S) -> Result<(), InjectedFault>
+    where
+        S: FnOnce(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        match self.roll() {
+            None => Ok(()),
+            Some(Fault::Latency(latency)) => {
+                sleep(latency).await;
+                Ok(())
+            }
+            Some(Fault::Error) => Err(InjectedFault),
+            Some(Fault::Hang) => std::future::pending().await,
+        }
+    }
+
+    pub fn stats(&self) -> ChaosStats {
+        ChaosStats {
+            calls: self.calls.load(Relaxed),
+            latencies: self.latencies.load(Relaxed),
+            errors: self.errors.load(Relaxed),
+            hangs: self.hangs.load(Relaxed),
+        }
+    }
+}
+
+impl fmt::Debug for Chaos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chaos")
+            .field("faults", &self.faults)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A loader or writer whose calls go through a [`Chaos`] first. Every call of a bulk load rolls
+/// once
+pub struct Chaotic<L> {
+    inner: L,
+    chaos: Arc<Chaos>,
+    // the runtime's timer, for the latency of async loads
+    sleep: Option<Arc<dyn Fn(Duration) -> Task + Send + Sync>>,
+}
+
+impl<L> Chaotic<L> {
+    /// Wrap a blocking loader or writer
+    pub fn new(inner: L, chaos: Arc<Chaos>) -> Self {
+        Self {
+            inner,
+            chaos,
+            sleep: None,
+        }
+    }
+
+    /// Wrap an async loader, injecting latency with `sleep`, the runtime's, e.g.
+    /// `tokio::time::sleep`
+    pub fn new_async<S>(
+        inner: L,
+        chaos: Arc<Chaos>,
+        sleep: impl Fn(Duration) -> S + Send + Sync + 'static,
+    ) -> Self
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            inner,
+            chaos,
+            sleep: Some(Arc::new(move |after| Box::pin(sleep(after)) as Task)),
+        }
+    }
+
+    /// [`Chaos::inject_async`] with the timer given, blocking the thread without one
+    async fn inject_async(&self) -> Result<(), InjectedFault> {
+        match &self.sleep {
+            Some(sleep) => self.chaos.inject_async(|after| sleep(after)).await,
+            None => {
+                self.chaos
+                    .inject_async(|after| async move { std::thread::sleep(after) })
+                    .await
+            }
+        }
+    }
+}
+
+impl<K, V, L: CacheLoader<K, V>> CacheLoader<K, V> for Chaotic<L> {
+    fn load(&self, key: &K) -> Result<V, LoadError> {
+        self.chaos.inject()?;
+        self.inner.load(key)
+    }
+
+    fn load_all(&self, keys: &[K]) -> Result<HashMap<K, V>, LoadError>
+    where
+        K: Clone + Eq + Hash,
+    {
+        self.chaos.inject()?;
+        self.inner.load_all(keys)
+    }
+}
+
+impl<K: Sync, V, L: AsyncCacheLoader<K, V>> AsyncCacheLoader<K, V> for Chaotic<L> {
+    fn load<'a>(&'a self, key: &'a K) -> LoadFuture<'a, V> {
+        Box::pin(async move {
+            self.inject_async().await?;
+            self.inner.load(key).await
+        })
+    }
+
+    fn load_all<'a>(&'a self, keys: &'a [K]) -> LoadFuture<'a, HashMap<K, V>>
+    where
+        K: Clone + Eq + Hash + Send + Sync,
+        V: Send + 'a,
+    {
+        Box::pin(async move {
+            self.inject_async().await?;
+            self.inner.load_all(keys).await
+        })
+    }
+}
+
+impl<K, V, W: CacheWriter<K, V>> CacheWriter<K, V> for Chaotic<W> {
+    fn write(&self, key: &K, value: &V) -> Result<(), WriteError> {
+        self.chaos.inject()?;
+        self.inner.write(key, value)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::compat::moka::future::LoadTimedOut;
+    use crate::compat::moka::{future, sync, ErrorPolicy, WriteMode};
+
+    #[test]
+    fn test_roll() {
+        let chaos = || {
+            Chaos::new(7)
+                .latency(0.2, Duration::from_millis(1))
+                .error(0.3)
+                .hang(0.1)
+        };
+        let (a, b) = (chaos(), chaos());
+        let rolls: Vec<_> = (0..1000).map(|_| a.roll()).collect();
+        assert_eq!(rolls, (0..1000).map(|_| b.roll()).collect::<Vec<_>>());
+
+        let stats = a.stats();
+        assert_eq!(stats.calls, 1000);
+        assert!((150..250).contains(&stats.latencies), "{stats:?}");
+        assert!((250..350).contains(&stats.errors), "{stats:?}");
+        assert!((50..150).contains(&stats.hangs), "{stats:?}");
+        assert_eq!(Chaos::new(7).roll(), None);
+    }
+
+    #[test]
+    fn test_serve_stale() {
+        let clock = Arc::new(ManualClock::new());
+        let chaos = Arc::new(Chaos::new(1).error(0.5));
+        let loader = |key: &u64| -> Result<u64, LoadError> { Ok(*key) };
+        let cache = sync::Cache::builder()
+            .max_capacity(100)
+            .time_to_live(Duration::from_secs(1))
+            .clock(clock.clone())
+            .on_load_error(ErrorPolicy::ServeStale(Duration::MAX))
+            .loader(Chaotic::new(loader, chaos.clone()))
+            .build();
+        let loaded: Vec<_> = (0..100).filter(|key| cache.get(key).is_ok()).collect();
+        clock.advance(Duration::from_secs(1));
+        for key in loaded {
+            let fetched = cache.fetch(&key).unwrap();
+            assert_eq!(fetched.value, key);
+        }
+        let stats = cache.load_stats();
+        assert_eq!(stats.failures, chaos.stats().errors);
+        assert!(stats.stale > 0);
+    }
+
+    #[test]
+    fn test_writer() {
+        let chaos = Arc::new(Chaos::new(1).error(1.0));
+        let writer = |_: &u64, _: &u64| -> Result<(), WriteError> { Ok(()) };
+        let cache = sync::Cache::builder()
+            .max_capacity(100)
+            .writer(Chaotic::new(writer, chaos), WriteMode::Through)
+            .build();
+        assert!(cache.try_insert(1, 1).is_err());
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[tokio::test]
+    async fn test_hang() {
+        let chaos = Arc::new(Chaos::new(1).hang(1.0));
+        let loader = |key: &u64| {
+            let key = *key;
+            async move { Ok::<_, LoadError>(key) }
+        };
+        let cache = future::Cache::builder()
+            .max_capacity(100)
+            .async_loader(Chaotic::new_async(loader, chaos, tokio::time::sleep))
+            .load_timeout(Duration::from_millis(5), tokio::time::sleep)
+            .build();
+        // every caller shares the hanging load and times out with it
+        let (a, b) = tokio::join!(cache.get(&1), cache.get(&1));
+        for error in [a.unwrap_err(), b.unwrap_err()] {
+            assert!(error.downcast_ref::<LoadTimedOut>().is_some());
+        }
+        assert_eq!(cache.load_stats().waiters, 1);
+    }
+}
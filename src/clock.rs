@@ -0,0 +1,115 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Source of monotonic time for everything time based in the cache.
+///
+/// Nothing in the crate reads `std::time::Instant` directly: it panics on
+/// wasm32-unknown-unknown, and tests want to drive time by hand.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since an arbitrary, fixed origin
+    fn now(&self) -> Duration;
+}
+
+/// [`Clock`] backed by `std::time::Instant`
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[derive(Debug, Clone, Copy)]
+pub struct StdClock {
+    origin: std::time::Instant,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl StdClock {
+    pub fn new() -> Self {
+        Self {
+            origin: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// [`Clock`] that only moves when told to, for tests and for hosts that bring their own time
+/// (e.g. `performance.now()` in the browser).
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `by`
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Relaxed);
+    }
+
+    /// Set the clock to `now`, callers are responsible for keeping it monotonic
+    pub fn set(&self, now: Duration) {
+        self.nanos.store(now.as_nanos() as u64, Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Relaxed))
+    }
+}
+
+/// [`StdClock`] where there is one, the thread's [`deterministic::clock`] when enabled
+///
+/// [`deterministic::clock`]: crate::deterministic::clock
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) fn default_clock() -> Arc<dyn Clock> {
+    match crate::deterministic::clock() {
+        Some(clock) => clock,
+        None => Arc::new(StdClock::new()),
+    }
+}
+
+/// No clock to read, time only moves with a clock given by the host
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn default_clock() -> Arc<dyn Clock> {
+    match crate::deterministic::clock() {
+        Some(clock) => clock,
+        None => Arc::new(ManualClock::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        assert_eq!(clock.now(), Duration::ZERO);
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now(), Duration::from_millis(1500));
+        clock.set(Duration::from_secs(10));
+        assert_eq!(clock.now(), Duration::from_secs(10));
+    }
+
+    #[test]
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn test_std_clock_is_monotonic() {
+        let clock = StdClock::new();
+        let first = clock.now();
+        assert!(clock.now() >= first);
+    }
+}
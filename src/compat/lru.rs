@@ -0,0 +1,141 @@
+//! Drop-in for [lru](https://docs.rs/lru)'s `LruCache`.
+//!
+//! ```
+//! use std::num::NonZeroUsize;
+//! // use lru::LruCache;
+//! use cachez::compat::lru::LruCache;
+//!
+//! let mut cache = LruCache::new(NonZeroUsize::new(2).unwrap());
+//! cache.put("apple", 3);
+//! cache.put("banana", 2);
+//! assert_eq!(cache.get(&"apple"), Some(&3));
+//! assert_eq!(cache.len(), 2);
+//! ```
+//!
+//! The capacity is a number of entries like in lru, but which entry makes room for a new one is
+//! decided by TinyUFO rather than recency. Keys are identified by their hash like in
+//! [`TinyUFO`].
+
+use crate::tinyufo::TinyUFO;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+
+/// Cache of at most `cap` entries with the API of `lru::LruCache`
+pub struct LruCache<K, V> {
+    cache: TinyUFO<K, V>,
+    cap: NonZeroUsize,
+}
+
+impl<K: Hash, V: Clone> LruCache<K, V> {
+    /// Insert a key-value pair, returns the value previously cached for the key
+    pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        let old = self.cache.peek(&k).cloned();
+        self.cache.put(k, 1, v);
+        old
+    }
+}
+
+impl<K: Hash, V> LruCache<K, V> {
+    pub fn new(cap: NonZeroUsize) -> Self {
+        Self {
+            cache: TinyUFO::new(cap.get(), cap.get()),
+            cap,
+        }
+    }
+
+    pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.get(k)
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.get_mut(k)
+    }
+
+    /// Get a value without updating its usage
+    pub fn peek<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.peek(k)
+    }
+
+    pub fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.peek(k).is_some()
+    }
+
+    /// Remove a key, returns its value if it was cached
+    pub fn pop<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.remove(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.stats().entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn cap(&self) -> NonZeroUsize {
+        self.cap
+    }
+
+    /// Drop every entry, statistics included
+    pub fn clear(&mut self) {
+        self.cache = TinyUFO::new(self.cap.get(), self.cap.get());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_api() {
+        let mut cache = LruCache::new(NonZeroUsize::new(2).unwrap());
+        assert!(cache.is_empty());
+        assert_eq!(cache.put("a", 1), None);
+        assert_eq!(cache.put("a", 2), Some(1));
+        assert_eq!(cache.peek("a"), Some(&2));
+        *cache.get_mut("a").unwrap() = 3;
+        assert_eq!(cache.get("a"), Some(&3));
+        assert!(cache.contains("a"));
+        assert_eq!(cache.pop("a"), Some(3));
+        assert!(!cache.contains("a"));
+
+        for i in 0..10 {
+            cache.put(["x", "y", "z"][i % 3], i);
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.cap().get(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+
+        // a put always stores, however hot the entries it evicts
+        cache.put("a", 1);
+        cache.put("b", 2);
+        cache.get("a");
+        cache.get("b");
+        cache.put("c", 3);
+        assert_eq!(cache.get("c"), Some(&3));
+        assert_eq!(cache.len(), 2);
+    }
+}
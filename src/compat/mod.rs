@@ -0,0 +1,3 @@
+//! API compatible facades over the caches, to trial TinyUFO by swapping an import
+pub mod lru;
+pub mod moka;
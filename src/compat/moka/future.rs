@@ -0,0 +1,844 @@
+//! Counterpart of `moka::future`, usable from any async runtime
+use super::{
+    lock, AsyncCacheLoader, CacheBuilder, Fetched, Inner, LoadCounters, LoadError, LoadFuture,
+    LoadStats, RetryPolicy, WriteError, WriteStats,
+};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use t1ha::T1haBuildHasher;
+
+/// An `init` in flight, awaited by the other callers missing the same key
+struct Flight<V> {
+    // None while running, then the value or None when it failed or was dropped
+    outcome: Option<Option<V>>,
+    wakers: Vec<Waker>,
+}
+
+type Flights<V> = Mutex<HashMap<u64, Arc<Mutex<Flight<V>>>>>;
+
+/// A future handed to the runtime, see [`CacheBuilder::continue_cancelled_loads`]
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The runtime's timer, a future ready after the duration
+pub(super) type Sleep = dyn Fn(Duration) -> Task + Send + Sync;
+
+/// Times the loads out, see [`CacheBuilder::load_timeout`]
+pub(super) struct Timeout {
+    after: Duration,
+    sleep: Box<Sleep>,
+}
+
+pub(super) type Spawn = dyn Fn(Task) + Send + Sync;
+
+/// Runs the loads apart from their callers, see [`CacheBuilder::continue_cancelled_loads`]
+struct Detach<K, V: Clone> {
+    spawn: Arc<Spawn>,
+    // `load_detached` for these types, which have the bounds it needs
+    load: for<'a> fn(&'a LoadingCache<K, V>, K) -> LoadFuture<'a, V>,
+}
+
+/// Error of a load taking longer than [`CacheBuilder::load_timeout`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadTimedOut(pub Duration);
+
+impl fmt::Display for LoadTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "load timed out after {:?}", self.0)
+    }
+}
+
+impl Error for LoadTimedOut {}
+
+/// Cache with moka's `future::Cache` API, clones share the same cache.
+///
+/// Nothing in the cache blocks for long, the methods are async only to match moka. Callers
+/// missing a key while another one initializes it wait for its value rather than run their
+/// `init`.
+pub struct Cache<K, V: Clone> {
+    inner: Arc<Inner<K, V>>,
+    flights: Arc<Flights<V>>,
+    // the waiters, shared with the loading cache
+    counters: Arc<LoadCounters>,
+}
+
+impl<K, V: Clone> Clone for Cache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            flights: self.flights.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+impl<K: Hash, V: Clone> Cache<K, V> {
+    /// Cache holding up to `max_capacity` entries
+    pub fn new(max_capacity: u64) -> Self {
+        Self::builder().max_capacity(max_capacity).build()
+    }
+
+    pub fn builder() -> CacheBuilder<K, V, Self> {
+        CacheBuilder::new()
+    }
+
+    pub async fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.get(key).is_some()
+    }
+
+    /// Cache `value`, through the writer if there is one: a failed write-through caches
+    /// nothing, see [`Self::try_insert`] for the error
+    pub async fn insert(&self, key: K, value: V) {
+        let _ = self.inner.insert(key, value);
+    }
+
+    /// [`Self::insert`] returning the writer's error. The writer is synchronous, it runs on
+    /// the calling task
+    pub async fn try_insert(&self, key: K, value: V) -> Result<(), WriteError> {
+        self.inner.insert(key, value)
+    }
+
+    /// Get the cached value or cache the one `init` resolves to, `init` only runs if no other
+    /// caller is initializing the key
+    pub async fn get_with(&self, key: K, init: impl Future<Output = V>) -> V {
+        let loaded = self
+            .load(key, async { Ok::<_, Infallible>(init.await) })
+            .await;
+        match loaded {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`Cache::get_with`], nothing is cached when `init` resolves to `None`
+    pub async fn optionally_get_with(
+        &self,
+        key: K,
+        init: impl Future<Output = Option<V>>,
+    ) -> Option<V> {
+        self.load(key, async { init.await.ok_or(()) }).await.ok()
+    }
+
+    /// Like [`Cache::get_with`], nothing is cached when `init` fails
+    pub async fn try_get_with<E>(
+        &self,
+        key: K,
+        init: impl Future<Output = Result<V, E>>,
+    ) -> Result<V, Arc<E>> {
+        self.load(key, init).await.map_err(Arc::new)
+    }
+
+    /// Get the cached value, or wait for the caller initializing `key`, or initialize it with
+    /// `init`. Waiters whose initializer failed or was dropped start over
+    async fn load<E>(&self, key: K, init: impl Future<Output = Result<V, E>>) -> Result<V, E> {
+        let hash = T1haBuildHasher::default().hash_one(&key);
+        loop {
+            if let Some(value) = self.inner.get(&key) {
+                return Ok(value);
+            }
+            let (flight, leads) = self.board(hash);
+            if !leads {
+                self.counters.waiters.fetch_add(1, Relaxed);
+                if let Some(value) = (Landing { flight }).await {
+                    return Ok(value);
+                }
+                continue;
+            }
+            // lands the flight, even if this future is dropped halfway
+            let mut pilot = Pilot {
+                flights: &self.flights,
+                hash,
+                value: None,
+            };
+            let value = init.await?;
+            self.inner.admit(key, value.clone());
+            pilot.value = Some(value.clone());
+            return Ok(value);
+        }
+    }
+
+    /// The flight of the key hashed to `hash`, and whether the caller starts it and has to land
+    /// it
+    fn board(&self, hash: u64) -> (Arc<Mutex<Flight<V>>>, bool) {
+        let mut flights = lock(&self.flights);
+        if let Some(flight) = flights.get(&hash) {
+            return (flight.clone(), false);
+        }
+        let flight = Arc::new(Mutex::new(Flight {
+            outcome: None,
+            wakers: Vec::new(),
+        }));
+        flights.insert(hash, flight.clone());
+        (flight, true)
+    }
+
+    pub async fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.remove(key);
+    }
+
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.remove(key)
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.inner.entry_count()
+    }
+
+    pub fn weighted_size(&self) -> u64 {
+        self.inner.weighted_size()
+    }
+
+    /// Write back the dirty entries, returns how many were written or the last error
+    pub async fn flush(&self) -> Result<usize, WriteError> {
+        self.inner.flush()
+    }
+
+    pub fn write_stats(&self) -> WriteStats {
+        self.inner.write_stats()
+    }
+
+    /// Write back the dirty entries, the failed ones stay dirty
+    pub async fn run_pending_tasks(&self) {
+        let _ = self.inner.flush();
+    }
+}
+
+impl<K: Hash, V: Clone> CacheBuilder<K, V, Cache<K, V>> {
+    pub fn build(self) -> Cache<K, V> {
+        Cache {
+            inner: Arc::new(self.build_inner()),
+            flights: Arc::default(),
+            counters: Arc::default(),
+        }
+    }
+
+    /// Build a [`LoadingCache`] reading through `loader` instead
+    pub fn async_loader(
+        self,
+        loader: impl AsyncCacheLoader<K, V> + 'static,
+    ) -> CacheBuilder<K, V, LoadingCache<K, V>> {
+        let mut builder = self.convert();
+        builder.async_loader = Some(Arc::new(loader));
+        builder
+    }
+}
+
+/// Fails `load` with [`LoadTimedOut`] once `timer` goes off
+struct Deadline<'a, V> {
+    load: LoadFuture<'a, V>,
+    timer: Option<(Task, Duration)>,
+}
+
+impl<V> Future for Deadline<'_, V> {
+    type Output = Result<V, LoadError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(loaded) = this.load.as_mut().poll(cx) {
+            return Poll::Ready(loaded);
+        }
+        if let Some((sleep, after)) = &mut this.timer {
+            if sleep.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(Box::new(LoadTimedOut(*after))));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Ends the flight of the caller running `init` and wakes its waiters, with `value` once set
+struct Pilot<'a, V> {
+    flights: &'a Flights<V>,
+    hash: u64,
+    value: Option<V>,
+}
+
+impl<V> Drop for Pilot<'_, V> {
+    fn drop(&mut self) {
+        let Some(flight) = lock(self.flights).remove(&self.hash) else {
+            return;
+        };
+        let mut flight = lock(&flight);
+        flight.outcome = Some(self.value.take());
+        for waker in flight.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Waits for the outcome of a flight
+struct Landing<V> {
+    flight: Arc<Mutex<Flight<V>>>,
+}
+
+impl<V: Clone> Future for Landing<V> {
+    type Output = Option<V>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<V>> {
+        let mut flight = lock(&self.flight);
+        match &flight.outcome {
+            Some(outcome) => Poll::Ready(outcome.clone()),
+            None => {
+                if !flight
+                    .wakers
+                    .iter()
+                    .any(|waker| waker.will_wake(cx.waker()))
+                {
+                    flight.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Read-through [`Cache`]: `get` awaits the [`AsyncCacheLoader`] on a miss and caches what it
+/// returns, weighed by the weigher. Concurrent misses of a key share one load. Clones share the
+/// same cache.
+pub struct LoadingCache<K, V: Clone> {
+    cache: Cache<K, V>,
+    loader: Arc<dyn AsyncCacheLoader<K, V>>,
+    counters: Arc<LoadCounters>,
+    timeout: Option<Arc<Timeout>>,
+    retry: Option<(Arc<RetryPolicy>, Arc<Sleep>)>,
+    detach: Option<Arc<Detach<K, V>>>,
+}
+
+impl<K, V: Clone> Clone for LoadingCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            loader: self.loader.clone(),
+            counters: self.counters.clone(),
+            timeout: self.timeout.clone(),
+            retry: self.retry.clone(),
+            detach: self.detach.clone(),
+        }
+    }
+}
+
+impl<K: Hash, V: Clone> LoadingCache<K, V> {
+    /// Get the cached value, or load and cache it
+    pub async fn get(&self, key: &K) -> Result<V, LoadError>
+    where
+        K: Clone,
+    {
+        self.fetch(key).await.map(|fetched| fetched.value)
+    }
+
+    /// Like [`Self::get`], telling whether the value is a stale one served in place of a failed
+    /// load
+    pub async fn fetch(&self, key: &K) -> Result<Fetched<V>, LoadError>
+    where
+        K: Clone,
+    {
+        let inner = &self.cache.inner;
+        let stale = match inner.lookup_refreshing(key) {
+            Ok(value) => return Ok(Fetched::fresh(value)),
+            Err(stale) => stale,
+        };
+        let loaded = match &self.detach {
+            Some(detach) => (detach.load)(self, key.clone()).await,
+            None => self.cache.load(key.clone(), self.load_one(key)).await,
+        };
+        match loaded {
+            Ok(value) => Ok(Fetched::fresh(value)),
+            Err(error) => inner.serve_stale(error, stale, &self.counters),
+        }
+    }
+
+    /// Get the cached values of `keys`, loading all the missing ones with a single
+    /// [`AsyncCacheLoader::load_all`] call. Keys the loader leaves out are left out. The load
+    /// isn't shared with concurrent `get`s of the same keys
+    pub async fn multi_get(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<HashMap<K, V>, LoadError>
+    where
+        K: Clone + Eq + Send + Sync,
+        V: Send,
+    {
+        let inner = &self.cache.inner;
+        let batch = inner.batch(keys)?;
+        if batch.missing.is_empty() {
+            return Ok(batch.values);
+        }
+        let loaded = self.retrying(|| self.loader.load_all(&batch.missing)).await;
+        self.counters.record(&loaded);
+        inner.complete(batch, loaded, &self.counters)
+    }
+
+    /// Load `key` for the callers sharing a flight, see [`Self::fetch`]
+    async fn load_one(&self, key: &K) -> Result<V, LoadError> {
+        let inner = &self.cache.inner;
+        // waiters on a failed load get here too, and find its error if cached
+        if let Some(error) = inner.cached_error(key) {
+            return Err(error);
+        }
+        let loaded = self.retrying(|| self.loader.load(key)).await;
+        self.counters.record(&loaded);
+        loaded.map_err(|error| inner.remember_error(key, error))
+    }
+
+    /// Run `load`, timed out, until it succeeds or the [`RetryPolicy`] gives up
+    async fn retrying<'a, T>(&self, load: impl Fn() -> LoadFuture<'a, T>) -> Result<T, LoadError> {
+        let mut retries = 0;
+        loop {
+            let loaded = self.deadline(load()).await;
+            let (Some((retry, sleep)), Err(error)) = (&self.retry, &loaded) else {
+                return loaded;
+            };
+            let Some(wait) = retry.next_retry(retries, error) else {
+                return loaded;
+            };
+            self.counters.retries.fetch_add(1, Relaxed);
+            sleep(wait).await;
+            retries += 1;
+        }
+    }
+
+    /// `load`, timed out as configured
+    fn deadline<'a, T>(&self, load: LoadFuture<'a, T>) -> Deadline<'a, T> {
+        let timer = self
+            .timeout
+            .as_ref()
+            .map(|timeout| ((timeout.sleep)(timeout.after), timeout.after));
+        Deadline { load, timer }
+    }
+
+    /// Get the cached value without loading it
+    pub async fn get_if_present<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.get(key).await
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        self.cache.insert(key, value).await;
+    }
+
+    pub async fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.invalidate(key).await;
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.cache.entry_count()
+    }
+
+    pub fn weighted_size(&self) -> u64 {
+        self.cache.weighted_size()
+    }
+
+    pub fn load_stats(&self) -> LoadStats {
+        self.counters.snapshot()
+    }
+
+    /// See [`Cache::flush`]
+    pub async fn flush(&self) -> Result<usize, WriteError> {
+        self.cache.flush().await
+    }
+
+    pub fn write_stats(&self) -> WriteStats {
+        self.cache.write_stats()
+    }
+
+    /// Flush the dirty entries and reload the entries due for a refresh, see
+    /// [`CacheBuilder::refresh_ahead`]. A failed refresh leaves the entry to expire
+    pub async fn run_pending_tasks(&self) {
+        self.cache.run_pending_tasks().await;
+        for key in self.cache.inner.take_refreshes() {
+            let loaded = self.retrying(|| self.loader.load(&key)).await;
+            self.counters.record_refresh(&loaded);
+            if let Ok(value) = loaded {
+                self.cache.inner.admit(key, value);
+            }
+        }
+    }
+}
+
+impl<K, V> CacheBuilder<K, V, LoadingCache<K, V>>
+where
+    K: Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn build(mut self) -> LoadingCache<K, V> {
+        let loader = self
+            .async_loader
+            .take()
+            .expect("set by CacheBuilder::async_loader");
+        let timeout = self.load_timeout.take();
+        let retry = self.retry.take().zip(self.sleep.take());
+        let detach = self.spawn.take().map(|spawn| {
+            Arc::new(Detach {
+                spawn,
+                load: load_detached,
+            })
+        });
+        let counters = Arc::<LoadCounters>::default();
+        LoadingCache {
+            cache: Cache {
+                inner: Arc::new(self.build_inner()),
+                flights: Arc::default(),
+                counters: counters.clone(),
+            },
+            loader,
+            counters,
+            timeout,
+            retry,
+            detach,
+        }
+    }
+
+    /// Retry the failed loads as `policy` says, waiting between retries with `sleep`, the
+    /// runtime's, e.g. `tokio::time::sleep`. A load timing out is retried as well
+    pub fn retry<S>(
+        self,
+        policy: RetryPolicy,
+        sleep: impl Fn(Duration) -> S + Send + Sync + 'static,
+    ) -> Self
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            retry: Some(Arc::new(policy)),
+            sleep: Some(Arc::new(move |after| Box::pin(sleep(after)) as Task)),
+            ..self
+        }
+    }
+
+    /// Fail the loads taking longer than `after` with [`LoadTimedOut`], timed by `sleep`, the
+    /// runtime's, e.g. `tokio::time::sleep`
+    pub fn load_timeout<S>(
+        self,
+        after: Duration,
+        sleep: impl Fn(Duration) -> S + Send + Sync + 'static,
+    ) -> Self
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        let timeout = Timeout {
+            after,
+            sleep: Box::new(move |after| Box::pin(sleep(after))),
+        };
+        Self {
+            load_timeout: Some(Arc::new(timeout)),
+            ..self
+        }
+    }
+
+    /// Keep a load going when the caller running it is cancelled, for the callers waiting on
+    /// it, by handing it to `spawn`, the runtime's, e.g. `|load| { tokio::spawn(load); }`. By
+    /// default the load is dropped with its caller, and one of the waiting callers starts over
+    pub fn continue_cancelled_loads(self, spawn: impl Fn(Task) + Send + Sync + 'static) -> Self {
+        Self {
+            spawn: Some(Arc::new(spawn)),
+            ..self
+        }
+    }
+}
+
+/// Like [`Cache::load`], running the load of a flight as a task of its own that no caller can
+/// cancel
+fn load_detached<K, V>(cache: &LoadingCache<K, V>, key: K) -> LoadFuture<'_, V>
+where
+    K: Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    Box::pin(async move {
+        let hash = T1haBuildHasher::default().hash_one(&key);
+        loop {
+            if let Some(value) = cache.cache.inner.get(&key) {
+                return Ok(value);
+            }
+            let (flight, leads) = cache.cache.board(hash);
+            let failure = Arc::new(Mutex::new(None));
+            if !leads {
+                cache.counters.waiters.fetch_add(1, Relaxed);
+            } else {
+                let task = {
+                    let cache = cache.clone();
+                    let failure = failure.clone();
+                    let key = key.clone();
+                    async move {
+                        let mut pilot = Pilot {
+                            flights: &cache.cache.flights,
+                            hash,
+                            value: None,
+                        };
+                        match cache.load_one(&key).await {
+                            Ok(value) => {
+                                cache.cache.inner.admit(key, value.clone());
+                                pilot.value = Some(value);
+                            }
+                            Err(error) => *lock(&failure) = Some(error),
+                        }
+                    }
+                };
+                let detach = cache.detach.as_ref().expect("set to load detached");
+                (detach.spawn)(Box::pin(task));
+            }
+            match (Landing { flight }).await {
+                Some(value) => return Ok(value),
+                // the failure of the load the caller started is its own
+                None if leads => {
+                    return Err(lock(&failure)
+                        .take()
+                        .unwrap_or_else(|| "load dropped by the runtime".into()))
+                }
+                None => {}
+            }
+        }
+    })
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::compat::moka::Shared;
+    use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+    #[tokio::test]
+    async fn test_future_cache() {
+        let cache: Cache<u64, String> = Cache::new(100);
+        cache.insert(1, "a".to_string()).await;
+        assert_eq!(cache.get(&1).await.as_deref(), Some("a"));
+        let value = cache.get_with(2, async { "b".to_string() }).await;
+        assert_eq!(value, "b");
+        assert_eq!(cache.get_with(2, async { unreachable!() }).await, "b");
+        let err = cache.try_get_with(3, async { Err(()) }).await;
+        assert!(err.is_err());
+        assert!(!cache.contains_key(&3));
+
+        cache.run_pending_tasks().await;
+        cache.invalidate(&1).await;
+        assert_eq!(cache.get(&1).await, None);
+        assert_eq!(cache.entry_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_init() {
+        let cache: Cache<u64, u64> = Cache::new(100);
+        let runs = std::sync::atomic::AtomicU64::new(0);
+        let init = || async {
+            runs.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            7
+        };
+        let (a, b, c) = tokio::join!(
+            cache.get_with(1, init()),
+            cache.get_with(1, init()),
+            cache.get_with(1, init()),
+        );
+        assert_eq!((a, b, c), (7, 7, 7));
+        assert_eq!(runs.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        // a failed or dropped init leaves the waiters to run theirs
+        let (failed, waited) = tokio::join!(
+            cache.try_get_with(2, async { Err::<u64, _>("down") }),
+            cache.try_get_with(2, async { Ok::<_, ()>(8) }),
+        );
+        assert!(failed.is_err());
+        assert_eq!(waited.unwrap(), 8);
+        let timeout = std::time::Duration::from_millis(5);
+        let (dropped, waited) = tokio::join!(
+            tokio::time::timeout(timeout, cache.get_with(3, std::future::pending())),
+            cache.get_with(3, async { 9 }),
+        );
+        assert!(dropped.is_err());
+        assert_eq!(waited, 9);
+    }
+
+    #[tokio::test]
+    async fn test_loading_cache() {
+        let runs = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let counted = runs.clone();
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .async_loader(move |key: &u64| {
+                let key = *key;
+                counted.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    match key {
+                        0 => Err(LoadError::from("no user 0")),
+                        _ => Ok(key.to_string()),
+                    }
+                }
+            })
+            .build();
+        let (a, b) = tokio::join!(cache.get(&42), cache.get(&42));
+        assert_eq!(
+            (a.unwrap(), b.unwrap()),
+            ("42".to_string(), "42".to_string())
+        );
+        assert_eq!(runs.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(cache.get_if_present(&42).await.as_deref(), Some("42"));
+
+        assert!(cache.get(&0).await.is_err());
+        assert_eq!(
+            cache.load_stats(),
+            LoadStats {
+                loads: 1,
+                failures: 1,
+                refreshes: 0,
+                stale: 0,
+                retries: 0,
+                waiters: 1,
+            }
+        );
+        cache.invalidate(&42).await;
+        cache.get(&42).await.unwrap();
+        assert_eq!(cache.load_stats().loads, 2);
+
+        // one by one by default
+        let values = cache.multi_get([42, 1, 2]).await.unwrap();
+        assert_eq!(values.len(), 3);
+        assert_eq!(runs.load(std::sync::atomic::Ordering::Relaxed), 5);
+        assert_eq!(cache.load_stats().loads, 3);
+        assert!(cache.multi_get([0, 1]).await.is_err());
+    }
+
+    fn slow_loader(runs: &Arc<AtomicU64>) -> impl AsyncCacheLoader<u64, u64> {
+        let runs = runs.clone();
+        move |key: &u64| {
+            let key = *key;
+            runs.fetch_add(1, Relaxed);
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(key)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_timeout() {
+        let runs = Arc::new(AtomicU64::new(0));
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .async_loader(slow_loader(&runs))
+            .load_timeout(Duration::from_millis(5), tokio::time::sleep)
+            .build();
+        let error = cache.get(&1).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<LoadTimedOut>(),
+            Some(&LoadTimedOut(Duration::from_millis(5)))
+        );
+        assert_eq!(cache.load_stats().failures, 1);
+        assert_eq!(cache.get_if_present(&1).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_shared() {
+        let runs = Arc::new(AtomicU64::new(0));
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .async_loader(Shared(slow_loader(&runs)))
+            .build();
+        let (a, b, c) = tokio::join!(cache.get(&1), cache.get(&1), cache.get(&1));
+        let (a, b, c) = (a.unwrap(), b.unwrap(), c.unwrap());
+        assert!(Arc::ptr_eq(&a, &b) && Arc::ptr_eq(&a, &c));
+        assert!(Arc::ptr_eq(&a, &cache.get_if_present(&1).await.unwrap()));
+        assert_eq!(runs.load(Relaxed), 1);
+        assert_eq!(cache.load_stats().waiters, 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let runs = Arc::new(AtomicU64::new(0));
+        let counted = runs.clone();
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .async_loader(move |key: &u64| {
+                let key = *key;
+                let run = counted.fetch_add(1, Relaxed);
+                async move {
+                    match run {
+                        0 => Err(LoadError::from("flaky")),
+                        _ => Ok(key),
+                    }
+                }
+            })
+            .retry(RetryPolicy::default(), tokio::time::sleep)
+            .build();
+        assert_eq!(cache.get(&1).await.unwrap(), 1);
+        assert_eq!(runs.load(Relaxed), 2);
+        assert_eq!(cache.load_stats().retries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_load() {
+        let cancelled = Duration::from_millis(5);
+        // dropped with its caller, the waiter loads again
+        let runs = Arc::new(AtomicU64::new(0));
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .async_loader(slow_loader(&runs))
+            .build();
+        let (cancel, wait) = tokio::join!(
+            tokio::time::timeout(cancelled, cache.get(&1)),
+            cache.get(&1)
+        );
+        assert!(cancel.is_err());
+        assert_eq!(wait.unwrap(), 1);
+        assert_eq!(runs.load(Relaxed), 2);
+
+        let runs = Arc::new(AtomicU64::new(0));
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .async_loader(slow_loader(&runs))
+            .continue_cancelled_loads(|load| {
+                tokio::spawn(load);
+            })
+            .build();
+        let (cancel, wait) = tokio::join!(
+            tokio::time::timeout(cancelled, cache.get(&1)),
+            cache.get(&1)
+        );
+        assert!(cancel.is_err());
+        assert_eq!(wait.unwrap(), 1);
+        assert_eq!(runs.load(Relaxed), 1);
+
+        // cached without anyone waiting
+        assert!(tokio::time::timeout(cancelled, cache.get(&2))
+            .await
+            .is_err());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cache.get_if_present(&2).await, Some(2));
+        assert_eq!(cache.load_stats().loads, 2);
+    }
+}
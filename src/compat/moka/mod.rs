@@ -0,0 +1,898 @@
+//! Mirrors [moka](https://docs.rs/moka)'s `sync::Cache` and `future::Cache`.
+//!
+//! ```
+//! // use moka::sync::Cache;
+//! use cachez::compat::moka::sync::Cache;
+//!
+//! let cache: Cache<String, u64> = Cache::builder()
+//!     .max_capacity(1024)
+//!     .weigher(|_key, value: &u64| (*value as u32).max(1))
+//!     .build();
+//! cache.insert("a".to_string(), 1);
+//! assert_eq!(cache.get("a"), Some(1));
+//! ```
+//!
+//! Differences with moka:
+//! - `time_to_idle` and eviction listeners are not available, expired entries are dropped when
+//!   next read rather than by `run_pending_tasks`
+//! - `sync::Cache::get_with` and friends don't coalesce concurrent initializations of a key: each
+//!   caller that misses runs its own `init`. The `future::Cache` ones do, but don't share errors:
+//!   the callers waiting on a failed `init` run theirs
+//! - keys are identified by their hash like in [`TinyUFO`](crate::tinyufo::TinyUFO)
+//!
+//! On top of moka, a [`CacheLoader`] or an [`AsyncCacheLoader`] set on the builder makes a
+//! read-through [`sync::LoadingCache`] or [`future::LoadingCache`], whose `get` loads what is
+//! missing, and a [`CacheWriter`] writes what is inserted through or back to its store. With a
+//! loader and a `time_to_live`, [`CacheBuilder::refresh_ahead`] reloads the popular entries
+//! before they expire, and [`ErrorPolicy`] sets what a failed load leaves behind.
+
+pub mod future;
+pub mod sync;
+
+use crate::clock::{default_clock, Clock};
+use crate::deterministic;
+use crate::tinyufo::{ConcurrentTinyUFO, Key, Weight};
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Entries preallocated when `initial_capacity` isn't set and `max_capacity` doesn't count entries
+const DEFAULT_CAPACITY: usize = 1024;
+
+type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> u32 + Send + Sync>;
+
+/// Error of a [`CacheLoader`]
+pub type LoadError = Box<dyn Error + Send + Sync>;
+
+/// What a loading cache does when its loader fails, see [`CacheBuilder::on_load_error`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Return the error, the next read of the key loads again
+    #[default]
+    Propagate,
+    /// Cache the error for this long, the reads of the key meanwhile return it as a
+    /// [`CachedLoadError`] without loading
+    CacheFor(Duration),
+    /// Return the value of the key if it expired less than this long ago, flagged stale by
+    /// `fetch`, like HTTP's `stale-if-error`. Expired values are kept that long rather than
+    /// dropped when read, `Duration::MAX` keeping them until evicted
+    ServeStale(Duration),
+}
+
+/// How a loading cache retries a failed load before handing its error to the [`ErrorPolicy`],
+/// see `CacheBuilder::retry`
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Loads in all, 1 doesn't retry
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for every next one up to `max_backoff`
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Share of the wait taken off at random, from 0 to 1, so that the callers failing together
+    /// don't retry together
+    pub jitter: f64,
+    /// Whether an error is worth retrying, every one is by default
+    pub retryable: Arc<dyn Fn(&LoadError) -> bool + Send + Sync>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.5,
+            retryable: Arc::new(|_| true),
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("attempts", &self.attempts)
+            .field("backoff", &self.backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before retrying a load failing with `error` after `retries` retries, `None` to
+    /// give up
+    fn next_retry(&self, retries: u32, error: &LoadError) -> Option<Duration> {
+        if retries + 1 >= self.attempts || !(self.retryable)(error) {
+            return None;
+        }
+        let backoff = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(retries))
+            .min(self.max_backoff);
+        Some(backoff.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * deterministic::f64()))
+    }
+}
+
+/// A value read through a loading cache, see `LoadingCache::fetch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fetched<V> {
+    pub value: V,
+    /// Whether `value` expired and was returned because its load failed, see
+    /// [`ErrorPolicy::ServeStale`]
+    pub stale: bool,
+}
+
+impl<V> Fetched<V> {
+    fn fresh(value: V) -> Self {
+        Self {
+            value,
+            stale: false,
+        }
+    }
+}
+
+/// A load error cached by [`ErrorPolicy::CacheFor`], shared by the reads of the key until it
+/// expires
+#[derive(Debug, Clone)]
+pub struct CachedLoadError(Arc<LoadError>);
+
+impl fmt::Display for CachedLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for CachedLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&**self.0)
+    }
+}
+
+/// Loads the values missing from a [`sync::LoadingCache`], see [`CacheBuilder::loader`]
+pub trait CacheLoader<K, V>: Send + Sync {
+    /// The value of `key`, an error is handled by the [`ErrorPolicy`]
+    fn load(&self, key: &K) -> Result<V, LoadError>;
+
+    /// The values of `keys` missed by `LoadingCache::multi_get`, those left out of the map are
+    /// not cached. Loads them one by one unless overridden with a bulk query
+    fn load_all(&self, keys: &[K]) -> Result<HashMap<K, V>, LoadError>
+    where
+        K: Clone + Eq + Hash,
+    {
+        keys.iter()
+            .map(|key| Ok((key.clone(), self.load(key)?)))
+            .collect()
+    }
+}
+
+impl<K, V, F> CacheLoader<K, V> for F
+where
+    F: Fn(&K) -> Result<V, LoadError> + Send + Sync,
+{
+    fn load(&self, key: &K) -> Result<V, LoadError> {
+        self(key)
+    }
+}
+
+/// Future of an [`AsyncCacheLoader`]
+pub type LoadFuture<'a, V> = Pin<Box<dyn Future<Output = Result<V, LoadError>> + Send + 'a>>;
+
+/// Loads the values missing from a [`future::LoadingCache`], see [`CacheBuilder::async_loader`]
+pub trait AsyncCacheLoader<K, V>: Send + Sync {
+    /// The value of `key`, an error is handled by the [`ErrorPolicy`]
+    fn load<'a>(&'a self, key: &'a K) -> LoadFuture<'a, V>;
+
+    /// Like [`CacheLoader::load_all`]
+    fn load_all<'a>(&'a self, keys: &'a [K]) -> LoadFuture<'a, HashMap<K, V>>
+    where
+        K: Clone + Eq + Hash + Send + Sync,
+        V: Send + 'a,
+    {
+        Box::pin(async move {
+            let mut values = HashMap::with_capacity(keys.len());
+            for key in keys {
+                values.insert(key.clone(), self.load(key).await?);
+            }
+            Ok(values)
+        })
+    }
+}
+
+impl<K, V, F, Fut> AsyncCacheLoader<K, V> for F
+where
+    F: Fn(&K) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<V, LoadError>> + Send + 'static,
+{
+    fn load<'a>(&'a self, key: &'a K) -> LoadFuture<'a, V> {
+        Box::pin(self(key))
+    }
+}
+
+/// Loader of values shared through an [`Arc`], the one cached being the one every caller gets
+/// rather than a clone of it each. Cheap clones matter with large values, which the callers
+/// sharing a load of the async cache would each clone otherwise
+///
+/// ```
+/// use cachez::compat::moka::{future::Cache, LoadError, Shared};
+/// use std::sync::Arc;
+///
+/// let cache = Cache::<u64, Arc<Vec<u8>>>::builder()
+///     .max_capacity(16)
+///     .async_loader(Shared(|key: &u64| {
+///         let len = *key as usize;
+///         async move { Ok::<_, LoadError>(vec![0; len]) }
+///     }))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Shared<L>(pub L);
+
+impl<K, V, L> CacheLoader<K, Arc<V>> for Shared<L>
+where
+    L: CacheLoader<K, V>,
+{
+    fn load(&self, key: &K) -> Result<Arc<V>, LoadError> {
+        self.0.load(key).map(Arc::new)
+    }
+
+    fn load_all(&self, keys: &[K]) -> Result<HashMap<K, Arc<V>>, LoadError>
+    where
+        K: Clone + Eq + Hash,
+    {
+        let values = self.0.load_all(keys)?;
+        Ok(values.into_iter().map(|(k, v)| (k, Arc::new(v))).collect())
+    }
+}
+
+impl<K, V, L> AsyncCacheLoader<K, Arc<V>> for Shared<L>
+where
+    L: AsyncCacheLoader<K, V>,
+    V: Send + Sync + 'static,
+{
+    fn load<'a>(&'a self, key: &'a K) -> LoadFuture<'a, Arc<V>> {
+        let load = self.0.load(key);
+        Box::pin(async move { load.await.map(Arc::new) })
+    }
+
+    fn load_all<'a>(&'a self, keys: &'a [K]) -> LoadFuture<'a, HashMap<K, Arc<V>>>
+    where
+        K: Clone + Eq + Hash + Send + Sync,
+        Arc<V>: Send + 'a,
+    {
+        Box::pin(async move {
+            let values = self.0.load_all(keys).await?;
+            Ok(values.into_iter().map(|(k, v)| (k, Arc::new(v))).collect())
+        })
+    }
+}
+
+/// Statistics of the loads of a [`sync::LoadingCache`] or a [`future::LoadingCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    /// Loads that returned a value, now cached
+    pub loads: u64,
+    pub failures: u64,
+    /// Loads of entries refreshed ahead of their expiry, counted in `loads` or `failures` too
+    pub refreshes: u64,
+    /// Failed loads answered with a stale value, counted in `failures` too
+    pub stale: u64,
+    /// Loads run again by the [`RetryPolicy`], not counted in `loads` or `failures`
+    pub retries: u64,
+    /// Reads of the async cache that waited on the load of another rather than loading, every
+    /// one getting a clone of its value, see [`Shared`]
+    pub waiters: u64,
+}
+
+#[derive(Default)]
+struct LoadCounters {
+    loads: AtomicU64,
+    failures: AtomicU64,
+    refreshes: AtomicU64,
+    stale: AtomicU64,
+    retries: AtomicU64,
+    waiters: AtomicU64,
+}
+
+impl LoadCounters {
+    fn record<V>(&self, result: &Result<V, LoadError>) {
+        let counter = match result {
+            Ok(_) => &self.loads,
+            Err(_) => &self.failures,
+        };
+        counter.fetch_add(1, Relaxed);
+    }
+
+    fn record_refresh<V>(&self, result: &Result<V, LoadError>) {
+        self.record(result);
+        self.refreshes.fetch_add(1, Relaxed);
+    }
+
+    fn snapshot(&self) -> LoadStats {
+        LoadStats {
+            loads: self.loads.load(Relaxed),
+            failures: self.failures.load(Relaxed),
+            refreshes: self.refreshes.load(Relaxed),
+            stale: self.stale.load(Relaxed),
+            retries: self.retries.load(Relaxed),
+            waiters: self.waiters.load(Relaxed),
+        }
+    }
+}
+
+/// Error of a [`CacheWriter`]
+pub type WriteError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Writes the entries inserted in a cache to their backing store, see [`CacheBuilder::writer`]
+pub trait CacheWriter<K, V>: Send + Sync {
+    fn write(&self, key: &K, value: &V) -> Result<(), WriteError>;
+}
+
+impl<K, V, F> CacheWriter<K, V> for F
+where
+    F: Fn(&K, &V) -> Result<(), WriteError> + Send + Sync,
+{
+    fn write(&self, key: &K, value: &V) -> Result<(), WriteError> {
+        self(key, value)
+    }
+}
+
+/// When a [`CacheWriter`] writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// `insert` writes before caching, nothing is cached when the write fails
+    Through,
+    /// `insert` caches and marks the entry dirty, it is written by `flush` and
+    /// `run_pending_tasks`, or before being evicted. A failed write stays dirty
+    Back,
+}
+
+/// Statistics of the writes of a cache with a [`CacheWriter`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    pub writes: u64,
+    pub failures: u64,
+    /// Entries waiting to be written back
+    pub dirty: usize,
+}
+
+type WriterConfig<K, V> = (Arc<dyn CacheWriter<K, V>>, WriteMode, fn(&K) -> K);
+
+/// A [`CacheWriter`] and what it has left to write back
+struct Writer<K, V> {
+    writer: Arc<dyn CacheWriter<K, V>>,
+    mode: WriteMode,
+    // write-back keeps its own copy of the key
+    clone_key: fn(&K) -> K,
+    // by the hash the cache hands to eviction callbacks
+    dirty: Mutex<HashMap<Key, (K, V)>>,
+    writes: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl<K, V> Writer<K, V> {
+    fn write(&self, key: &K, value: &V) -> Result<(), WriteError> {
+        let written = self.writer.write(key, value);
+        let counter = match written {
+            Ok(_) => &self.writes,
+            Err(_) => &self.failures,
+        };
+        counter.fetch_add(1, Relaxed);
+        written
+    }
+
+    /// Write back the dirty entries of `hashes`, or all of them, returns how many were written
+    /// or the last error. Failed entries stay dirty unless put again meanwhile
+    fn flush(&self, hashes: Option<Vec<Key>>) -> Result<usize, WriteError> {
+        let mut batch: Vec<_> = {
+            let mut dirty = lock(&self.dirty);
+            match hashes {
+                Some(hashes) => hashes
+                    .into_iter()
+                    .filter_map(|hash| Some((hash, dirty.remove(&hash)?)))
+                    .collect(),
+                None => dirty.drain().collect(),
+            }
+        };
+        deterministic::order(&mut batch, |(hash, _)| *hash);
+        let mut written = 0;
+        let mut error = None;
+        for (hash, (key, value)) in batch {
+            match self.write(&key, &value) {
+                Ok(()) => written += 1,
+                Err(e) => {
+                    lock(&self.dirty).entry(hash).or_insert((key, value));
+                    error = Some(e);
+                }
+            }
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(written),
+        }
+    }
+}
+
+/// A cached value and when it expires on the cache's clock
+#[derive(Clone)]
+struct Stamped<V> {
+    value: V,
+    expires_at: Option<Duration>,
+}
+
+/// Keys of a `LoadingCache::multi_get`, looked up in the cache
+struct Batch<K, V> {
+    values: HashMap<K, V>,
+    missing: Vec<K>,
+    // expired values of missing keys, kept by ErrorPolicy::ServeStale
+    stale: HashMap<K, V>,
+}
+
+/// Which entries a loading cache reloads before they expire, see [`CacheBuilder::refresh_ahead`]
+#[derive(Debug, Clone, Copy)]
+struct RefreshAhead {
+    window: Duration,
+    min_uses: u8,
+}
+
+/// Builder of [`sync::Cache`] and [`future::Cache`], see `Cache::builder`
+pub struct CacheBuilder<K, V, C> {
+    max_capacity: Option<u64>,
+    initial_capacity: Option<usize>,
+    weigher: Option<Weigher<K, V>>,
+    time_to_live: Option<Duration>,
+    clock: Option<Arc<dyn Clock>>,
+    refresh_ahead: Option<RefreshAhead>,
+    error_policy: ErrorPolicy,
+    // set on the way to a `LoadingCache`
+    loader: Option<Arc<dyn CacheLoader<K, V>>>,
+    async_loader: Option<Arc<dyn AsyncCacheLoader<K, V>>>,
+    load_timeout: Option<Arc<future::Timeout>>,
+    retry: Option<Arc<RetryPolicy>>,
+    // the async runtime's, to wait between retries
+    sleep: Option<Arc<future::Sleep>>,
+    spawn: Option<Arc<future::Spawn>>,
+    writer: Option<WriterConfig<K, V>>,
+    _cache: PhantomData<C>,
+}
+
+impl<K, V, C> CacheBuilder<K, V, C> {
+    fn new() -> Self {
+        Self {
+            max_capacity: None,
+            initial_capacity: None,
+            weigher: None,
+            time_to_live: None,
+            clock: None,
+            refresh_ahead: None,
+            error_policy: ErrorPolicy::default(),
+            loader: None,
+            async_loader: None,
+            load_timeout: None,
+            retry: None,
+            sleep: None,
+            spawn: None,
+            writer: None,
+            _cache: PhantomData,
+        }
+    }
+
+    /// The same settings, building another kind of cache
+    fn convert<D>(self) -> CacheBuilder<K, V, D> {
+        CacheBuilder {
+            max_capacity: self.max_capacity,
+            initial_capacity: self.initial_capacity,
+            weigher: self.weigher,
+            time_to_live: self.time_to_live,
+            clock: self.clock,
+            refresh_ahead: self.refresh_ahead,
+            error_policy: self.error_policy,
+            loader: self.loader,
+            async_loader: self.async_loader,
+            load_timeout: self.load_timeout,
+            retry: self.retry,
+            sleep: self.sleep,
+            spawn: self.spawn,
+            writer: self.writer,
+            _cache: PhantomData,
+        }
+    }
+
+    /// Maximum number of entries, or total weight when a weigher is set
+    pub fn max_capacity(self, max_capacity: u64) -> Self {
+        Self {
+            max_capacity: Some(max_capacity),
+            ..self
+        }
+    }
+
+    pub fn initial_capacity(self, initial_capacity: usize) -> Self {
+        Self {
+            initial_capacity: Some(initial_capacity),
+            ..self
+        }
+    }
+
+    pub fn weigher(self, weigher: impl Fn(&K, &V) -> u32 + Send + Sync + 'static) -> Self {
+        Self {
+            weigher: Some(Arc::new(weigher)),
+            ..self
+        }
+    }
+
+    /// Expire entries `ttl` after they were inserted or loaded
+    pub fn time_to_live(self, ttl: Duration) -> Self {
+        Self {
+            time_to_live: Some(ttl),
+            ..self
+        }
+    }
+
+    /// Measure the time to live with `clock`
+    pub fn clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock: Some(clock),
+            ..self
+        }
+    }
+
+    /// Have a loading cache reload the entries read within `window` of their expiry that are
+    /// popular, read at least `min_uses` times (1 to 3) lately, so that their readers never
+    /// miss. Due entries are reloaded by `run_pending_tasks`, to be run periodically, while
+    /// readers keep getting the current value
+    pub fn refresh_ahead(self, window: Duration, min_uses: u8) -> Self {
+        Self {
+            refresh_ahead: Some(RefreshAhead { window, min_uses }),
+            ..self
+        }
+    }
+
+    /// What a loading cache does when its loader fails, [`ErrorPolicy::Propagate`] by default
+    pub fn on_load_error(self, error_policy: ErrorPolicy) -> Self {
+        Self {
+            error_policy,
+            ..self
+        }
+    }
+
+    /// Write the inserted entries to their store with `writer`, see [`WriteMode`]
+    pub fn writer(self, writer: impl CacheWriter<K, V> + 'static, mode: WriteMode) -> Self
+    where
+        K: Clone,
+    {
+        Self {
+            writer: Some((Arc::new(writer), mode, K::clone)),
+            ..self
+        }
+    }
+
+    fn build_inner(self) -> Inner<K, V>
+    where
+        K: Hash,
+        V: Clone,
+    {
+        let limit = self.max_capacity.map_or(usize::MAX, |max| max as usize);
+        let capacity = match (self.initial_capacity, &self.weigher) {
+            (Some(capacity), _) => capacity,
+            // without a weigher the limit is the number of entries
+            (None, None) if limit != usize::MAX => limit,
+            _ => DEFAULT_CAPACITY,
+        };
+        Inner {
+            cache: ConcurrentTinyUFO::new(limit, capacity),
+            weigher: self.weigher,
+            time_to_live: self.time_to_live,
+            clock: self.clock.unwrap_or_else(default_clock),
+            refresh_ahead: self.refresh_ahead,
+            refreshes: Mutex::default(),
+            error_policy: self.error_policy,
+            errors: Mutex::default(),
+            writer: self.writer.map(|(writer, mode, clone_key)| Writer {
+                writer,
+                mode,
+                clone_key,
+                dirty: Mutex::default(),
+                writes: AtomicU64::new(0),
+                failures: AtomicU64::new(0),
+            }),
+        }
+    }
+}
+
+/// State shared by the clones of a cache
+struct Inner<K, V: Clone> {
+    cache: ConcurrentTinyUFO<K, Stamped<V>>,
+    weigher: Option<Weigher<K, V>>,
+    time_to_live: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    refresh_ahead: Option<RefreshAhead>,
+    // keys due for a refresh, by hash
+    refreshes: Mutex<HashMap<Key, K>>,
+    error_policy: ErrorPolicy,
+    // load errors cached by ErrorPolicy::CacheFor, by hash with their expiry
+    errors: Mutex<HashMap<Key, (Duration, CachedLoadError)>>,
+    writer: Option<Writer<K, V>>,
+}
+
+impl<K: Hash, V: Clone> Inner<K, V> {
+    fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        Some(self.get_stamped(key)?.value)
+    }
+
+    /// Get the cached value and its expiry, dropping it once expired
+    fn get_stamped<Q>(&self, key: &Q) -> Option<Stamped<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.lookup(key).ok()
+    }
+
+    /// The cached value and its expiry, or the expired value if [`ErrorPolicy::ServeStale`]
+    /// still allows it
+    fn lookup<Q>(&self, key: &Q) -> Result<Stamped<V>, Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let stamped = self.cache.get(key).ok_or(None)?;
+        if !self.is_expired(&stamped) {
+            return Ok(stamped);
+        }
+        if let (ErrorPolicy::ServeStale(allowance), Some(expires_at)) =
+            (self.error_policy, stamped.expires_at)
+        {
+            if self.clock.now() - expires_at < allowance {
+                return Err(Some(stamped.value));
+            }
+        }
+        self.cache.remove(key);
+        Err(None)
+    }
+
+    fn is_expired(&self, stamped: &Stamped<V>) -> bool {
+        stamped.expires_at.is_some_and(|at| self.clock.now() >= at)
+    }
+
+    /// Like [`Self::lookup`], queueing the value for a refresh if it is due
+    fn lookup_refreshing(&self, key: &K) -> Result<V, Option<V>>
+    where
+        K: Clone,
+    {
+        let stamped = self.lookup(key)?;
+        self.schedule_refresh(key, &stamped);
+        Ok(stamped.value)
+    }
+
+    /// The error cached for `key`, if any
+    fn cached_error(&self, key: &K) -> Option<LoadError> {
+        let hash = self.cache.key_hash(key);
+        let mut errors = lock(&self.errors);
+        let (expires_at, error) = errors.get(&hash)?;
+        if self.clock.now() >= *expires_at {
+            errors.remove(&hash);
+            return None;
+        }
+        Some(Box::new(error.clone()))
+    }
+
+    /// Cache the `error` of loading `key` as the [`ErrorPolicy`] says, returns what to hand the
+    /// caller
+    fn remember_error(&self, key: &K, error: LoadError) -> LoadError {
+        self.remember_errors(std::slice::from_ref(key), error)
+    }
+
+    /// Like [`Self::remember_error`], for a load of several `keys`
+    fn remember_errors(&self, keys: &[K], error: LoadError) -> LoadError {
+        let ErrorPolicy::CacheFor(ttl) = self.error_policy else {
+            return error;
+        };
+        let now = self.clock.now();
+        let error = CachedLoadError(Arc::new(error));
+        let mut errors = lock(&self.errors);
+        errors.retain(|_, (expires_at, _)| *expires_at > now);
+        for key in keys {
+            errors.insert(self.cache.key_hash(key), (now + ttl, error.clone()));
+        }
+        Box::new(error)
+    }
+
+    /// Split `keys` into the cached values and the keys to load, the first cached error of one
+    /// of them failing the whole batch
+    fn batch(&self, keys: impl IntoIterator<Item = K>) -> Result<Batch<K, V>, LoadError>
+    where
+        K: Clone + Eq,
+    {
+        let mut batch = Batch {
+            values: HashMap::new(),
+            missing: Vec::new(),
+            stale: HashMap::new(),
+        };
+        for key in keys.into_iter().collect::<HashSet<_>>() {
+            match self.lookup_refreshing(&key) {
+                Ok(value) => {
+                    batch.values.insert(key, value);
+                }
+                Err(stale) => {
+                    if let Some(error) = self.cached_error(&key) {
+                        return Err(error);
+                    }
+                    if let Some(value) = stale {
+                        batch.stale.insert(key.clone(), value);
+                    }
+                    batch.missing.push(key);
+                }
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Cache what the load of the `batch` missing keys returned, all together, and add it to
+    /// the values. A failed load is served stale only if every missing key has a stale value
+    fn complete(
+        &self,
+        mut batch: Batch<K, V>,
+        loaded: Result<HashMap<K, V>, LoadError>,
+        counters: &LoadCounters,
+    ) -> Result<HashMap<K, V>, LoadError>
+    where
+        K: Clone + Eq,
+    {
+        match loaded {
+            Ok(loaded) => {
+                for (key, value) in loaded {
+                    self.admit(key.clone(), value.clone());
+                    batch.values.insert(key, value);
+                }
+            }
+            Err(error) => {
+                let error = self.remember_errors(&batch.missing, error);
+                if batch.stale.len() < batch.missing.len() {
+                    return Err(error);
+                }
+                counters.stale.fetch_add(batch.stale.len() as u64, Relaxed);
+                batch.values.extend(batch.stale);
+            }
+        }
+        Ok(batch.values)
+    }
+
+    /// The `stale` value, which [`Self::lookup`] only returns under
+    /// [`ErrorPolicy::ServeStale`], in place of a failed load
+    fn serve_stale(
+        &self,
+        error: LoadError,
+        stale: Option<V>,
+        counters: &LoadCounters,
+    ) -> Result<Fetched<V>, LoadError> {
+        let value = stale.ok_or(error)?;
+        counters.stale.fetch_add(1, Relaxed);
+        Ok(Fetched { value, stale: true })
+    }
+
+    /// Queue `key` for a refresh if it is popular and about to expire
+    fn schedule_refresh(&self, key: &K, stamped: &Stamped<V>)
+    where
+        K: Clone,
+    {
+        let (Some(refresh), Some(expires_at)) = (self.refresh_ahead, stamped.expires_at) else {
+            return;
+        };
+        if expires_at.saturating_sub(self.clock.now()) > refresh.window {
+            return;
+        }
+        if self.cache.uses(key).unwrap_or(0) < refresh.min_uses {
+            return;
+        }
+        let hash = self.cache.key_hash(key);
+        lock(&self.refreshes)
+            .entry(hash)
+            .or_insert_with(|| key.clone());
+    }
+
+    /// The keys queued for a refresh
+    fn take_refreshes(&self) -> Vec<K> {
+        let mut refreshes: Vec<_> = lock(&self.refreshes).drain().collect();
+        deterministic::order(&mut refreshes, |(hash, _)| *hash);
+        refreshes.into_iter().map(|(_, key)| key).collect()
+    }
+
+    fn stamp(&self, value: V) -> Stamped<V> {
+        Stamped {
+            value,
+            expires_at: self.time_to_live.map(|ttl| self.clock.now() + ttl),
+        }
+    }
+
+    /// Cache `value` and write it with the writer if there is one
+    fn insert(&self, key: K, value: V) -> Result<(), WriteError> {
+        let Some(writer) = &self.writer else {
+            self.admit(key, value);
+            return Ok(());
+        };
+        if writer.mode == WriteMode::Through {
+            writer.write(&key, &value)?;
+            self.admit(key, value);
+            return Ok(());
+        }
+        let hash = self.cache.key_hash(&key);
+        lock(&writer.dirty).insert(hash, ((writer.clone_key)(&key), value.clone()));
+        let weight = self.weight(&key, &value);
+        let mut evicted = Vec::new();
+        self.cache
+            .put_evicting(key, weight, self.stamp(value), |hash, _| evicted.push(hash));
+        // written outside of the cache's lock
+        if !evicted.is_empty() {
+            writer.flush(Some(evicted)).map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Cache `value`, loaded from the store so not to be written
+    fn admit(&self, key: K, value: V) {
+        let weight = self.weight(&key, &value);
+        self.cache.put(key, weight, self.stamp(value));
+    }
+
+    fn weight(&self, key: &K, value: &V) -> Weight {
+        self.weigher
+            .as_ref()
+            .map_or(1, |weigher| Weight::from(weigher(key, value)))
+    }
+
+    /// Write back every dirty entry
+    fn flush(&self) -> Result<usize, WriteError> {
+        match &self.writer {
+            Some(writer) => writer.flush(None),
+            None => Ok(0),
+        }
+    }
+
+    fn write_stats(&self) -> WriteStats {
+        self.writer
+            .as_ref()
+            .map_or_else(WriteStats::default, |writer| WriteStats {
+                writes: writer.writes.load(Relaxed),
+                failures: writer.failures.load(Relaxed),
+                dirty: lock(&writer.dirty).len(),
+            })
+    }
+
+    fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        if self.error_policy != ErrorPolicy::Propagate {
+            lock(&self.errors).remove(&self.cache.key_hash(key));
+        }
+        let stamped = self.cache.remove(key)?;
+        (!self.is_expired(&stamped)).then_some(stamped.value)
+    }
+
+    fn entry_count(&self) -> u64 {
+        self.cache.stats().entries as u64
+    }
+
+    fn weighted_size(&self) -> u64 {
+        self.cache.stats().weight as u64
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
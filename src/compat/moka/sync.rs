@@ -0,0 +1,666 @@
+//! Counterpart of `moka::sync`
+use super::{
+    CacheBuilder, CacheLoader, Fetched, Inner, LoadCounters, LoadError, LoadStats, RetryPolicy,
+    WriteError, WriteStats,
+};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::thread;
+
+/// Thread safe cache with moka's `sync::Cache` API, clones share the same cache.
+pub struct Cache<K, V: Clone> {
+    inner: Arc<Inner<K, V>>,
+}
+
+impl<K, V: Clone> Clone for Cache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K: Hash, V: Clone> Cache<K, V> {
+    /// Cache holding up to `max_capacity` entries
+    pub fn new(max_capacity: u64) -> Self {
+        Self::builder().max_capacity(max_capacity).build()
+    }
+
+    pub fn builder() -> CacheBuilder<K, V, Self> {
+        CacheBuilder::new()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.get(key).is_some()
+    }
+
+    /// Cache `value`, through the writer if there is one: a failed write-through caches
+    /// nothing, see [`Self::try_insert`] for the error
+    pub fn insert(&self, key: K, value: V) {
+        let _ = self.inner.insert(key, value);
+    }
+
+    /// [`Self::insert`] returning the writer's error
+    pub fn try_insert(&self, key: K, value: V) -> Result<(), WriteError> {
+        self.inner.insert(key, value)
+    }
+
+    /// Get the cached value or cache the one `init` returns
+    pub fn get_with(&self, key: K, init: impl FnOnce() -> V) -> V {
+        if let Some(value) = self.inner.get(&key) {
+            return value;
+        }
+        let value = init();
+        self.inner.admit(key, value.clone());
+        value
+    }
+
+    /// Like [`Cache::get_with`], nothing is cached when `init` returns `None`
+    pub fn optionally_get_with(&self, key: K, init: impl FnOnce() -> Option<V>) -> Option<V> {
+        if let Some(value) = self.inner.get(&key) {
+            return Some(value);
+        }
+        let value = init()?;
+        self.inner.admit(key, value.clone());
+        Some(value)
+    }
+
+    /// Like [`Cache::get_with`], nothing is cached when `init` fails
+    pub fn try_get_with<E>(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<V, E>,
+    ) -> Result<V, Arc<E>> {
+        if let Some(value) = self.inner.get(&key) {
+            return Ok(value);
+        }
+        let value = init().map_err(Arc::new)?;
+        self.inner.admit(key, value.clone());
+        Ok(value)
+    }
+
+    pub fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.remove(key);
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.remove(key)
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.inner.entry_count()
+    }
+
+    pub fn weighted_size(&self) -> u64 {
+        self.inner.weighted_size()
+    }
+
+    /// Write back the dirty entries, returns how many were written or the last error
+    pub fn flush(&self) -> Result<usize, WriteError> {
+        self.inner.flush()
+    }
+
+    pub fn write_stats(&self) -> WriteStats {
+        self.inner.write_stats()
+    }
+
+    /// Write back the dirty entries, the failed ones stay dirty
+    pub fn run_pending_tasks(&self) {
+        let _ = self.inner.flush();
+    }
+}
+
+impl<K: Hash, V: Clone> CacheBuilder<K, V, Cache<K, V>> {
+    pub fn build(self) -> Cache<K, V> {
+        Cache {
+            inner: Arc::new(self.build_inner()),
+        }
+    }
+
+    /// Build a [`LoadingCache`] reading through `loader` instead
+    pub fn loader(
+        self,
+        loader: impl CacheLoader<K, V> + 'static,
+    ) -> CacheBuilder<K, V, LoadingCache<K, V>> {
+        let mut builder = self.convert();
+        builder.loader = Some(Arc::new(loader));
+        builder
+    }
+}
+
+/// Read-through [`Cache`]: `get` runs the [`CacheLoader`] on a miss and caches what it returns,
+/// weighed by the weigher. Clones share the same cache.
+pub struct LoadingCache<K, V: Clone> {
+    cache: Cache<K, V>,
+    loader: Arc<dyn CacheLoader<K, V>>,
+    counters: Arc<LoadCounters>,
+    retry: Option<Arc<RetryPolicy>>,
+}
+
+impl<K, V: Clone> Clone for LoadingCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            loader: self.loader.clone(),
+            counters: self.counters.clone(),
+            retry: self.retry.clone(),
+        }
+    }
+}
+
+impl<K: Hash, V: Clone> LoadingCache<K, V> {
+    /// Get the cached value, or load and cache it. Concurrent misses of a key each run a load
+    pub fn get(&self, key: &K) -> Result<V, LoadError>
+    where
+        K: Clone,
+    {
+        self.fetch(key).map(|fetched| fetched.value)
+    }
+
+    /// Like [`Self::get`], telling whether the value is a stale one served in place of a failed
+    /// load
+    pub fn fetch(&self, key: &K) -> Result<Fetched<V>, LoadError>
+    where
+        K: Clone,
+    {
+        let inner = &self.cache.inner;
+        let stale = match inner.lookup_refreshing(key) {
+            Ok(value) => return Ok(Fetched::fresh(value)),
+            Err(stale) => stale,
+        };
+        if let Some(error) = inner.cached_error(key) {
+            return Err(error);
+        }
+        let loaded = self.retrying(|| self.loader.load(key));
+        self.counters.record(&loaded);
+        match loaded {
+            Ok(value) => {
+                inner.admit(key.clone(), value.clone());
+                Ok(Fetched::fresh(value))
+            }
+            Err(error) => {
+                let error = inner.remember_error(key, error);
+                inner.serve_stale(error, stale, &self.counters)
+            }
+        }
+    }
+
+    /// Get the cached values of `keys`, loading all the missing ones with a single
+    /// [`CacheLoader::load_all`] call. Keys the loader leaves out are left out
+    pub fn multi_get(&self, keys: impl IntoIterator<Item = K>) -> Result<HashMap<K, V>, LoadError>
+    where
+        K: Clone + Eq,
+    {
+        let inner = &self.cache.inner;
+        let batch = inner.batch(keys)?;
+        if batch.missing.is_empty() {
+            return Ok(batch.values);
+        }
+        let loaded = self.retrying(|| self.loader.load_all(&batch.missing));
+        self.counters.record(&loaded);
+        inner.complete(batch, loaded, &self.counters)
+    }
+
+    /// Run `load` until it succeeds or the [`RetryPolicy`] gives up, blocking between retries
+    fn retrying<T>(&self, load: impl Fn() -> Result<T, LoadError>) -> Result<T, LoadError> {
+        let mut retries = 0;
+        loop {
+            let loaded = load();
+            let (Some(retry), Err(error)) = (&self.retry, &loaded) else {
+                return loaded;
+            };
+            let Some(wait) = retry.next_retry(retries, error) else {
+                return loaded;
+            };
+            self.counters.retries.fetch_add(1, Relaxed);
+            thread::sleep(wait);
+            retries += 1;
+        }
+    }
+
+    /// Get the cached value without loading it
+    pub fn get_if_present<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.get(key)
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.cache.insert(key, value);
+    }
+
+    pub fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.invalidate(key);
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.cache.entry_count()
+    }
+
+    pub fn weighted_size(&self) -> u64 {
+        self.cache.weighted_size()
+    }
+
+    pub fn load_stats(&self) -> LoadStats {
+        self.counters.snapshot()
+    }
+
+    /// See [`Cache::flush`]
+    pub fn flush(&self) -> Result<usize, WriteError> {
+        self.cache.flush()
+    }
+
+    pub fn write_stats(&self) -> WriteStats {
+        self.cache.write_stats()
+    }
+
+    /// Flush the dirty entries and reload the entries due for a refresh, see
+    /// [`CacheBuilder::refresh_ahead`]. A failed refresh leaves the entry to expire
+    pub fn run_pending_tasks(&self) {
+        self.cache.run_pending_tasks();
+        for key in self.cache.inner.take_refreshes() {
+            let loaded = self.retrying(|| self.loader.load(&key));
+            self.counters.record_refresh(&loaded);
+            if let Ok(value) = loaded {
+                self.cache.inner.admit(key, value);
+            }
+        }
+    }
+}
+
+impl<K: Hash, V: Clone> CacheBuilder<K, V, LoadingCache<K, V>> {
+    pub fn build(mut self) -> LoadingCache<K, V> {
+        let loader = self.loader.take().expect("set by CacheBuilder::loader");
+        let retry = self.retry.take();
+        LoadingCache {
+            cache: Cache {
+                inner: Arc::new(self.build_inner()),
+            },
+            loader,
+            counters: Arc::default(),
+            retry,
+        }
+    }
+
+    /// Retry the failed loads as `policy` says
+    pub fn retry(self, policy: RetryPolicy) -> Self {
+        Self {
+            retry: Some(Arc::new(policy)),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::compat::moka::{CachedLoadError, ErrorPolicy, WriteMode};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+    use std::time::Duration;
+
+    #[test]
+    fn test_sync_cache() {
+        let cache: Cache<String, u32> = Cache::new(100);
+        cache.insert("a".to_string(), 1);
+        assert_eq!(cache.get("a"), Some(1));
+        assert!(cache.contains_key("a"));
+        assert_eq!(cache.get_with("a".to_string(), || unreachable!()), 1);
+        assert_eq!(cache.get_with("b".to_string(), || 2), 2);
+        assert_eq!(cache.optionally_get_with("c".to_string(), || None), None);
+        let err = cache.try_get_with("d".to_string(), || Err("boom"));
+        assert_eq!(*err.unwrap_err(), "boom");
+        cache.run_pending_tasks();
+        assert_eq!(cache.entry_count(), 2);
+
+        cache.invalidate("a");
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.remove("b"), Some(2));
+    }
+
+    #[test]
+    fn test_weigher() {
+        let cache: Cache<u32, Vec<u8>> = Cache::builder()
+            .max_capacity(1000)
+            .weigher(|_, value: &Vec<u8>| value.len() as u32)
+            .build();
+        cache.insert(1, vec![0; 300]);
+        cache.insert(2, vec![0; 200]);
+        assert_eq!(cache.weighted_size(), 500);
+        for i in 3..100 {
+            cache.insert(i, vec![0; 100]);
+        }
+        assert!(cache.weighted_size() <= 1000);
+    }
+
+    #[test]
+    fn test_loading_cache() {
+        let cache = Cache::builder()
+            .max_capacity(1000)
+            .weigher(|_, value: &String| value.len() as u32)
+            .loader(|key: &u64| -> Result<String, LoadError> {
+                match key {
+                    0 => Err("no user 0".into()),
+                    _ => Ok("x".repeat(*key as usize)),
+                }
+            })
+            .build();
+        assert_eq!(cache.get(&3).unwrap(), "xxx");
+        assert_eq!(cache.get_if_present(&3).as_deref(), Some("xxx"));
+        assert_eq!(cache.weighted_size(), 3);
+        // cached, not loaded again
+        cache.get(&3).unwrap();
+        assert_eq!(cache.get(&0).unwrap_err().to_string(), "no user 0");
+        assert_eq!(cache.get_if_present(&0), None);
+        assert_eq!(
+            cache.load_stats(),
+            LoadStats {
+                loads: 1,
+                failures: 1,
+                refreshes: 0,
+                stale: 0,
+                retries: 0,
+                waiters: 0,
+            }
+        );
+
+        cache.invalidate(&3);
+        cache.clone().get(&3).unwrap();
+        assert_eq!(cache.load_stats().loads, 2);
+        assert_eq!(cache.entry_count(), 1);
+    }
+
+    #[test]
+    fn test_refresh_ahead() {
+        let clock = Arc::new(ManualClock::new());
+        let versions = AtomicU64::new(0);
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .time_to_live(Duration::from_secs(10))
+            .clock(clock.clone())
+            .refresh_ahead(Duration::from_secs(2), 3)
+            .loader(move |_: &u64| -> Result<u64, LoadError> {
+                Ok(versions.fetch_add(1, Relaxed) + 1)
+            })
+            .build();
+        assert_eq!(cache.get(&1).unwrap(), 1);
+        assert_eq!(cache.get(&2).unwrap(), 2);
+        for _ in 0..3 {
+            cache.get(&1).unwrap();
+        }
+        // not due yet
+        cache.run_pending_tasks();
+        assert_eq!(cache.load_stats().refreshes, 0);
+
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(cache.get(&1).unwrap(), 1);
+        assert_eq!(cache.get(&2).unwrap(), 2);
+        cache.run_pending_tasks();
+        clock.advance(Duration::from_secs(2));
+        // the hot key was reloaded before expiring, the cold one expired
+        assert_eq!(cache.get_if_present(&1), Some(3));
+        assert_eq!(cache.get_if_present(&2), None);
+        assert_eq!(cache.get(&2).unwrap(), 4);
+        assert_eq!(
+            cache.load_stats(),
+            LoadStats {
+                loads: 4,
+                failures: 0,
+                refreshes: 1,
+                stale: 0,
+                retries: 0,
+                waiters: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_error_policy() {
+        let clock = Arc::new(ManualClock::new());
+        let up = Arc::new(AtomicBool::new(false));
+        let loader = {
+            let up = up.clone();
+            move |key: &u64| -> Result<u64, LoadError> {
+                match up.load(Relaxed) {
+                    true => Ok(*key),
+                    false => Err("backend down".into()),
+                }
+            }
+        };
+        let builder = || {
+            Cache::builder()
+                .max_capacity(100)
+                .time_to_live(Duration::from_secs(10))
+                .clock(clock.clone())
+        };
+
+        let cache = builder().loader(loader.clone()).build();
+        cache.get(&1).unwrap_err();
+        cache.get(&1).unwrap_err();
+        assert_eq!(cache.load_stats().failures, 2);
+
+        let cache = builder()
+            .on_load_error(ErrorPolicy::CacheFor(Duration::from_secs(1)))
+            .loader(loader.clone())
+            .build();
+        let error = cache.get(&1).unwrap_err();
+        assert!(error.downcast_ref::<CachedLoadError>().is_some());
+        assert_eq!(error.to_string(), "backend down");
+        up.store(true, Relaxed);
+        // served from the cache until it expires
+        cache.get(&1).unwrap_err();
+        assert_eq!(cache.load_stats().failures, 1);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&1).unwrap(), 1);
+
+        let cache = builder()
+            .on_load_error(ErrorPolicy::ServeStale(Duration::from_secs(5)))
+            .loader(loader)
+            .build();
+        assert_eq!(cache.get(&3).unwrap(), 3);
+        assert!(!cache.fetch(&3).unwrap().stale);
+        clock.advance(Duration::from_secs(10));
+        up.store(false, Relaxed);
+        assert_eq!(
+            cache.fetch(&3).unwrap(),
+            Fetched {
+                value: 3,
+                stale: true
+            }
+        );
+        assert_eq!(cache.get_if_present(&3), None);
+        cache.get(&4).unwrap_err();
+        // past the allowance
+        clock.advance(Duration::from_secs(5));
+        cache.get(&3).unwrap_err();
+        assert_eq!(
+            cache.load_stats(),
+            LoadStats {
+                loads: 1,
+                failures: 3,
+                refreshes: 0,
+                stale: 1,
+                retries: 0,
+                waiters: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_retry() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(3),
+            jitter: 0.0,
+            retryable: Arc::new(|error| error.to_string() != "not found"),
+        };
+        let flaky: LoadError = "flaky".into();
+        assert_eq!(policy.next_retry(0, &flaky), Some(Duration::from_millis(1)));
+        assert_eq!(policy.next_retry(1, &flaky), Some(Duration::from_millis(2)));
+        assert_eq!(policy.next_retry(2, &flaky), None);
+
+        let runs = Arc::new(AtomicU64::new(0));
+        let counted = runs.clone();
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .loader(move |key: &u64| -> Result<u64, LoadError> {
+                let run = counted.fetch_add(1, Relaxed);
+                match key {
+                    0 => Err("not found".into()),
+                    1 if run < 2 => Err("flaky".into()),
+                    1 => Ok(1),
+                    _ => Err("down".into()),
+                }
+            })
+            .retry(policy)
+            .build();
+        assert_eq!(cache.get(&1).unwrap(), 1);
+        assert_eq!(cache.get(&0).unwrap_err().to_string(), "not found");
+        assert_eq!(cache.get(&2).unwrap_err().to_string(), "down");
+        assert_eq!(runs.load(Relaxed), 7);
+        assert_eq!(
+            cache.load_stats(),
+            LoadStats {
+                loads: 1,
+                failures: 2,
+                refreshes: 0,
+                stale: 0,
+                retries: 4,
+                waiters: 0,
+            }
+        );
+    }
+
+    struct Database {
+        queries: Arc<AtomicU64>,
+    }
+
+    impl CacheLoader<u64, u64> for Database {
+        fn load(&self, _: &u64) -> Result<u64, LoadError> {
+            unreachable!("loaded in bulk")
+        }
+
+        fn load_all(&self, keys: &[u64]) -> Result<HashMap<u64, u64>, LoadError> {
+            self.queries.fetch_add(1, Relaxed);
+            // no row for 0
+            Ok(keys
+                .iter()
+                .filter(|&&key| key != 0)
+                .map(|&key| (key, key * 2))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_multi_get() {
+        let queries = Arc::new(AtomicU64::new(0));
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .loader(Database {
+                queries: queries.clone(),
+            })
+            .build();
+        cache.insert(1, 10);
+        let values = cache.multi_get([1, 2, 3, 3, 0]).unwrap();
+        assert_eq!(values, HashMap::from([(1, 10), (2, 4), (3, 6)]));
+        assert_eq!(queries.load(Relaxed), 1);
+        assert_eq!(cache.get_if_present(&3), Some(6));
+
+        // all cached
+        cache.multi_get([2, 3]).unwrap();
+        assert_eq!(queries.load(Relaxed), 1);
+        assert_eq!(cache.load_stats().loads, 1);
+    }
+
+    type Store = Arc<std::sync::Mutex<std::collections::HashMap<u64, u64>>>;
+
+    fn writer(store: &Store) -> impl Fn(&u64, &u64) -> Result<(), WriteError> {
+        let store = store.clone();
+        move |key, value| match key {
+            0 => Err("read only".into()),
+            _ => {
+                store.lock().unwrap().insert(*key, *value);
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_through() {
+        let store = Store::default();
+        let cache = Cache::builder()
+            .max_capacity(100)
+            .writer(writer(&store), WriteMode::Through)
+            .build();
+        cache.insert(1, 10);
+        assert_eq!(store.lock().unwrap().get(&1), Some(&10));
+        assert_eq!(cache.get(&1), Some(10));
+        assert!(cache.try_insert(0, 1).is_err());
+        assert_eq!(cache.get(&0), None);
+        // loaded from the store, not written back to it
+        cache.get_with(2, || 20);
+        assert_eq!(store.lock().unwrap().get(&2), None);
+        let stats = cache.write_stats();
+        assert_eq!((stats.writes, stats.failures, stats.dirty), (1, 1, 0));
+    }
+
+    #[test]
+    fn test_write_back() {
+        let store = Store::default();
+        let cache = Cache::builder()
+            .max_capacity(10)
+            .writer(writer(&store), WriteMode::Back)
+            .build();
+        cache.insert(1, 10);
+        cache.insert(1, 11);
+        cache.insert(0, 1);
+        assert!(store.lock().unwrap().is_empty());
+        assert_eq!(cache.write_stats().dirty, 2);
+        // the failed one stays dirty
+        assert!(cache.flush().is_err());
+        assert_eq!(store.lock().unwrap().get(&1), Some(&11));
+        assert_eq!(cache.write_stats().dirty, 1);
+        cache.invalidate(&0);
+        cache.run_pending_tasks();
+        assert_eq!(cache.write_stats().dirty, 1);
+
+        // evicted entries are written before they go
+        for key in 100..200 {
+            cache.insert(key, key);
+        }
+        let store = store.lock().unwrap();
+        let dirty = cache.write_stats().dirty;
+        assert!(dirty <= 11);
+        assert_eq!(store.len() + dirty - 1, 101);
+    }
+}
@@ -0,0 +1,124 @@
+//! Switch making the caches of a thread reproducible from a seed, to replay a failing
+//! simulation or bug report bit for bit.
+//!
+//! Once [`enable`]d on a thread, caches created there:
+//! - seed their frequency sketches from the seed instead of randomly
+//! - read time from the thread's [`clock`], a [`ManualClock`] only moving when advanced
+//! - use a single shard whatever the number of cores
+//!
+//! and the randomness of the caches used there (retry jitter) as well as the order of their
+//! maintenance batches (refreshes, write-backs) derive from the seed too. The map hasher is
+//! already seeded with a constant.
+//!
+//! The switch is per thread so that tests running side by side don't affect each other.
+//!
+//! ```
+//! use cachez::deterministic;
+//! use cachez::tinyufo::TinyUFO;
+//!
+//! let run = |seed| {
+//!     deterministic::enable(seed);
+//!     let mut cache = TinyUFO::new(10, 10);
+//!     let mut evicted = Vec::new();
+//!     for key in 0..100u64 {
+//!         cache.put_evicting(key % 30, 1, key, |_, data| evicted.push(data));
+//!     }
+//!     deterministic::disable();
+//!     evicted
+//! };
+//! assert_eq!(run(42), run(42));
+//! ```
+
+use crate::clock::ManualClock;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+struct State {
+    rng: fastrand::Rng,
+    clock: Arc<ManualClock>,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+/// Make what this thread does from now on reproducible from `seed`, restarting the clock at 0
+pub fn enable(seed: u64) {
+    STATE.with(|state| {
+        *state.borrow_mut() = Some(State {
+            rng: fastrand::Rng::with_seed(seed),
+            clock: Arc::new(ManualClock::new()),
+        })
+    });
+}
+
+/// Back to random seeds and the system clock, caches created meanwhile stay deterministic
+pub fn disable() {
+    STATE.with(|state| *state.borrow_mut() = None);
+}
+
+/// Whether [`enable`] was called on this thread and not [`disable`]d since
+pub fn is_enabled() -> bool {
+    STATE.with(|state| state.borrow().is_some())
+}
+
+/// The clock of the caches created on this thread while enabled, to advance by hand
+pub fn clock() -> Option<Arc<ManualClock>> {
+    STATE.with(|state| state.borrow().as_ref().map(|state| state.clock.clone()))
+}
+
+/// A random `u64`, the next of the seeded sequence when enabled
+pub(crate) fn u64() -> u64 {
+    STATE.with(|state| match state.borrow_mut().as_mut() {
+        Some(state) => state.rng.u64(..),
+        None => fastrand::u64(..),
+    })
+}
+
+/// A random `f64` in `0..1`, the next of the seeded sequence when enabled
+pub(crate) fn f64() -> f64 {
+    STATE.with(|state| match state.borrow_mut().as_mut() {
+        Some(state) => state.rng.f64(),
+        None => fastrand::f64(),
+    })
+}
+
+/// Sort a batch of maintenance work by hashed key when enabled, it comes out of hash maps in
+/// an arbitrary order otherwise
+pub(crate) fn order<T, K: Ord>(batch: &mut [T], key: impl FnMut(&T) -> K) {
+    if is_enabled() {
+        batch.sort_by_key(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use std::time::Duration;
+
+    #[test]
+    fn test_deterministic() {
+        assert!(!is_enabled() && clock().is_none());
+        enable(7);
+        let first = (u64(), f64());
+        let clock = clock().unwrap();
+        clock.advance(Duration::from_secs(1));
+        enable(7);
+        assert_eq!((u64(), f64()), first);
+        // restarted
+        assert_eq!(super::clock().unwrap().now(), Duration::ZERO);
+
+        let mut batch = vec![3, 1, 2];
+        order(&mut batch, |key| *key);
+        assert_eq!(batch, vec![1, 2, 3]);
+        std::thread::spawn(|| assert!(!is_enabled()))
+            .join()
+            .unwrap();
+
+        disable();
+        let mut batch = vec![3, 1, 2];
+        order(&mut batch, |key| *key);
+        assert_eq!(batch, vec![3, 1, 2]);
+    }
+}
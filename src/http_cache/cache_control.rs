@@ -0,0 +1,141 @@
+use http::header::{HeaderMap, CACHE_CONTROL, PRAGMA};
+use std::time::Duration;
+
+/// The Cache-Control directives the cache acts on (RFC 9111 section 5.2)
+///
+/// Directives are matched case-insensitively, unknown ones are ignored. The field-name lists of
+/// `no-cache="..."` and `private="..."` are not honored: they are treated as their unqualified
+/// form, which is always safe for a shared cache.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub max_age: Option<Duration>,
+    pub s_maxage: Option<Duration>,
+    pub max_stale: Option<Duration>,
+    pub min_fresh: Option<Duration>,
+    /// RFC 5861
+    pub stale_while_revalidate: Option<Duration>,
+    pub no_cache: bool,
+    pub no_store: bool,
+    pub private: bool,
+    pub public: bool,
+    pub must_revalidate: bool,
+    pub proxy_revalidate: bool,
+    pub only_if_cached: bool,
+}
+
+impl CacheControl {
+    /// Parse all the Cache-Control fields of `headers`
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut cc = Self::default();
+        let mut seen = false;
+        for value in headers.get_all(CACHE_CONTROL) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            seen = true;
+            for directive in split_directives(value) {
+                cc.apply(directive);
+            }
+        }
+        // HTTP/1.0 caches only know Pragma, it is only looked at without Cache-Control
+        if !seen {
+            cc.no_cache = headers
+                .get_all(PRAGMA)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .any(|v| {
+                    v.split(',')
+                        .any(|d| d.trim().eq_ignore_ascii_case("no-cache"))
+                });
+        }
+        cc
+    }
+
+    fn apply(&mut self, directive: &str) {
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (directive.trim(), None),
+        };
+        let seconds = || value.and_then(parse_delta_seconds);
+        match name.to_ascii_lowercase().as_str() {
+            "max-age" => self.max_age = seconds(),
+            "s-maxage" => self.s_maxage = seconds(),
+            // a bare max-stale accepts any staleness
+            "max-stale" => self.max_stale = Some(seconds().unwrap_or(Duration::MAX)),
+            "min-fresh" => self.min_fresh = seconds(),
+            "stale-while-revalidate" => self.stale_while_revalidate = seconds(),
+            "no-cache" => self.no_cache = true,
+            "no-store" => self.no_store = true,
+            "private" => self.private = true,
+            "public" => self.public = true,
+            "must-revalidate" => self.must_revalidate = true,
+            "proxy-revalidate" => self.proxy_revalidate = true,
+            "only-if-cached" => self.only_if_cached = true,
+            _ => {}
+        }
+    }
+}
+
+/// Split on commas outside of quoted strings
+fn split_directives(value: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    value
+        .split(move |c| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c == ',' && !quoted
+        })
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+}
+
+/// delta-seconds, values too large to represent saturate (RFC 9111 section 1.2.2)
+fn parse_delta_seconds(value: &str) -> Option<Duration> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(Duration::from_secs(value.parse().unwrap_or(u64::MAX)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn parse(values: &[&'static str]) -> CacheControl {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(CACHE_CONTROL, HeaderValue::from_static(value));
+        }
+        CacheControl::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_parse() {
+        let cc = parse(&[
+            "public, Max-Age=60",
+            "s-maxage=\"120\", no-cache=\"Set-Cookie, X-Foo\"",
+        ]);
+        assert_eq!(cc.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(cc.s_maxage, Some(Duration::from_secs(120)));
+        assert!(cc.public && cc.no_cache && !cc.private);
+
+        let cc = parse(&["max-age=abc, max-stale, private"]);
+        assert_eq!(cc.max_age, None);
+        assert_eq!(cc.max_stale, Some(Duration::MAX));
+        assert!(cc.private);
+
+        let cc = parse(&["max-age=99999999999999999999999"]);
+        assert_eq!(cc.max_age, Some(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn test_pragma() {
+        let mut headers = HeaderMap::new();
+        headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
+        assert!(CacheControl::from_headers(&headers).no_cache);
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("max-age=5"));
+        assert!(!CacheControl::from_headers(&headers).no_cache);
+    }
+}
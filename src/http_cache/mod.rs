@@ -0,0 +1,651 @@
+//! Shared HTTP cache for reverse proxies, following RFC 9111.
+//!
+//! [`HttpCache`] decides on its own which responses may be stored and for how long, from
+//! Cache-Control, Expires, Age, Date and Last-Modified, and keeps one variant per set of
+//! request headers named by Vary. Lookups tell the proxy whether the stored response can be
+//! served, or has to be revalidated with the origin using the validators (ETag, Last-Modified)
+//! it carries:
+//!
+//! ```
+//! # use cachez::http_cache::{HttpCache, Lookup};
+//! # use http::{Request, Response};
+//! # use bytes::Bytes;
+//! # fn origin(_: &Request<()>) -> Response<Bytes> { Response::new(Bytes::new()) }
+//! let cache = HttpCache::new(64 * 1024, 10_000);
+//! let request = Request::get("http://example.com/").body(()).unwrap();
+//! let response = match cache.lookup(&request) {
+//!     Lookup::Fresh(response) => response,
+//!     Lookup::Stale(stale) => {
+//!         let mut conditional = Request::get(request.uri()).body(()).unwrap();
+//!         conditional.headers_mut().extend(stale.validators.clone());
+//!         let response = origin(&conditional);
+//!         if response.status() == http::StatusCode::NOT_MODIFIED {
+//!             cache.refresh(&request, response.headers()).unwrap_or(stale.response)
+//!         } else {
+//!             cache.store(&request, &response);
+//!             response
+//!         }
+//!     }
+//!     Lookup::Miss => {
+//!         let response = origin(&request);
+//!         cache.store(&request, &response);
+//!         response
+//!     }
+//! };
+//! ```
+//!
+//! Entries are weighted by their size in KiB, so the weight limit is the memory budget in KiB.
+
+mod cache_control;
+
+pub use cache_control::CacheControl;
+
+use crate::clock::Clock;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::clock::StdClock;
+use crate::tinyufo::{ConcurrentTinyUFO, Weight};
+use bytes::Bytes;
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, AGE, AUTHORIZATION, CONTENT_LENGTH, DATE, ETAG, EXPIRES,
+    HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY,
+};
+use http::{Method, Request, Response, StatusCode, Version};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Variants kept per URL, the oldest one is dropped beyond that
+const MAX_VARIANTS: usize = 8;
+/// Heuristic freshness is this fraction of the time since Last-Modified (RFC 9111 4.2.2)
+const HEURISTIC_DIVISOR: u32 = 10;
+
+/// Result of [`HttpCache::lookup`]
+pub enum Lookup {
+    /// The stored response can be served as is. It is a 304 when the request was conditional
+    /// and the stored response matches it.
+    Fresh(Response<Bytes>),
+    /// A response is stored but must be revalidated with the origin before being served
+    Stale(Stale),
+    Miss,
+}
+
+/// A stored response that needs revalidation
+pub struct Stale {
+    /// The stored response, what a proxy may serve if the origin is unreachable and
+    /// `must_revalidate` is false
+    pub response: Response<Bytes>,
+    /// If-None-Match / If-Modified-Since to send to the origin
+    pub validators: HeaderMap,
+    pub must_revalidate: bool,
+    /// Still within the response's stale-while-revalidate window (RFC 5861): the stored
+    /// response may be served right away while it is revalidated in the background
+    pub stale_while_revalidate: bool,
+}
+
+#[derive(Clone)]
+struct Variant {
+    // request header values the response was selected with
+    vary: Vec<(HeaderName, Vec<HeaderValue>)>,
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+    // clock time the response was received
+    stored_at: Duration,
+    // age of the response when received
+    initial_age: Duration,
+    freshness_lifetime: Duration,
+    no_cache: bool,
+    must_revalidate: bool,
+    stale_while_revalidate: Duration,
+}
+
+impl Variant {
+    fn matches(&self, request: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, values)| request.get_all(name).iter().eq(values.iter()))
+    }
+
+    fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        self.body.len() + headers
+    }
+
+    fn to_response(&self, status: StatusCode, age: Duration, body: Bytes) -> Response<Bytes> {
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        *response.version_mut() = self.version;
+        *response.headers_mut() = self.headers.clone();
+        response
+            .headers_mut()
+            .insert(AGE, HeaderValue::from(age.as_secs()));
+        response
+    }
+}
+
+/// Shared cache of HTTP responses, see the [module docs](self).
+pub struct HttpCache {
+    cache: ConcurrentTinyUFO<String, Arc<[Variant]>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl HttpCache {
+    /// `weight_limit_kib` is the total size of the stored responses, in KiB
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn new(weight_limit_kib: usize, capacity: usize) -> Self {
+        Self::with_clock(weight_limit_kib, capacity, Arc::new(StdClock::new()))
+    }
+
+    /// Measure response ages with `clock`
+    pub fn with_clock(weight_limit_kib: usize, capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::new(weight_limit_kib, capacity),
+            clock,
+        }
+    }
+
+    /// Find a stored response for `request`
+    pub fn lookup<B>(&self, request: &Request<B>) -> Lookup {
+        let head = request.method() == Method::HEAD;
+        if request.method() != Method::GET && !head {
+            return Lookup::Miss;
+        }
+        let Some(variant) = self.variant(request) else {
+            return Lookup::Miss;
+        };
+
+        let cc = CacheControl::from_headers(request.headers());
+        let age = self.current_age(&variant);
+        let lifetime = match cc.max_age {
+            Some(max_age) => variant.freshness_lifetime.min(max_age),
+            None => variant.freshness_lifetime,
+        };
+        let fresh = age.saturating_add(cc.min_fresh.unwrap_or_default()) < lifetime;
+        let staleness = age.saturating_sub(variant.freshness_lifetime);
+        let stale_allowed = !variant.must_revalidate
+            && cc.max_age.is_none()
+            && cc.max_stale.is_some_and(|max_stale| staleness <= max_stale);
+        let body = if head {
+            Bytes::new()
+        } else {
+            variant.body.clone()
+        };
+
+        if cc.no_cache || variant.no_cache || !(fresh || stale_allowed) {
+            let mut validators = HeaderMap::new();
+            if let Some(etag) = variant.headers.get(ETAG) {
+                validators.insert(IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(last_modified) = variant.headers.get(LAST_MODIFIED) {
+                validators.insert(IF_MODIFIED_SINCE, last_modified.clone());
+            }
+            return Lookup::Stale(Stale {
+                response: variant.to_response(variant.status, age, body),
+                validators,
+                must_revalidate: variant.must_revalidate || variant.no_cache,
+                stale_while_revalidate: !(cc.no_cache
+                    || variant.no_cache
+                    || variant.must_revalidate)
+                    && cc.max_age.is_none()
+                    && staleness <= variant.stale_while_revalidate,
+            });
+        }
+
+        if not_modified(request.headers(), &variant.headers) {
+            let mut response = variant.to_response(StatusCode::NOT_MODIFIED, age, Bytes::new());
+            response.headers_mut().remove(CONTENT_LENGTH);
+            return Lookup::Fresh(response);
+        }
+        Lookup::Fresh(variant.to_response(variant.status, age, body))
+    }
+
+    /// Store the origin's `response` to `request` if RFC 9111 allows it, returns whether it was.
+    ///
+    /// A successful response to an unsafe method (POST, PUT, DELETE...) invalidates the stored
+    /// responses of its URL instead.
+    pub fn store<B>(&self, request: &Request<B>, response: &Response<Bytes>) -> bool {
+        if !request.method().is_safe() {
+            if response.status().is_success() || response.status().is_redirection() {
+                self.invalidate(request);
+            }
+            return false;
+        }
+        if request.method() != Method::GET {
+            return false;
+        }
+        let Some(variant) = self.new_variant(request, response) else {
+            return false;
+        };
+
+        let key = primary_key(request);
+        let mut variants: Vec<Variant> = self
+            .cache
+            .get(&key)
+            .map(|variants| variants.to_vec())
+            .unwrap_or_default();
+        variants.retain(|v| !v.matches(request.headers()));
+        variants.push(variant);
+        if variants.len() > MAX_VARIANTS {
+            variants.remove(0);
+        }
+        self.put(key, variants);
+        true
+    }
+
+    /// Update the stored response with the headers of a 304 the origin answered a
+    /// revalidation with, returns the refreshed response
+    pub fn refresh<B>(
+        &self,
+        request: &Request<B>,
+        not_modified: &HeaderMap,
+    ) -> Option<Response<Bytes>> {
+        let key = primary_key(request);
+        let mut variants = self.cache.get(&key)?.to_vec();
+        let index = variants.iter().position(|v| v.matches(request.headers()))?;
+
+        let stored = &variants[index];
+        let mut response = stored.to_response(stored.status, Duration::ZERO, stored.body.clone());
+        response.headers_mut().remove(AGE);
+        for (name, value) in not_modified {
+            if name != CONTENT_LENGTH {
+                response.headers_mut().insert(name, value.clone());
+            }
+        }
+        let Some(variant) = self.new_variant(request, &response) else {
+            // the origin made the response uncacheable
+            variants.remove(index);
+            self.put(key, variants);
+            return Some(response);
+        };
+        let age = self.current_age(&variant);
+        let response = variant.to_response(variant.status, age, variant.body.clone());
+        variants[index] = variant;
+        self.put(key, variants);
+        Some(response)
+    }
+
+    /// Drop every stored response of the URL of `request`
+    pub fn invalidate<B>(&self, request: &Request<B>) {
+        self.cache.remove(primary_key(request).as_str());
+    }
+
+    fn variant<B>(&self, request: &Request<B>) -> Option<Variant> {
+        let variants = self.cache.get(primary_key(request).as_str())?;
+        variants
+            .iter()
+            .rev()
+            .find(|v| v.matches(request.headers()))
+            .cloned()
+    }
+
+    fn put(&self, key: String, variants: Vec<Variant>) {
+        if variants.is_empty() {
+            self.cache.remove(key.as_str());
+            return;
+        }
+        let size: usize = variants.iter().map(Variant::size).sum();
+        let weight = size.div_ceil(1024).max(1) as Weight;
+        self.cache.put(key, weight, variants.into());
+    }
+
+    fn current_age(&self, variant: &Variant) -> Duration {
+        variant.initial_age + self.clock.now().saturating_sub(variant.stored_at)
+    }
+
+    /// The stored form of `response`, `None` if it may not be stored (RFC 9111 section 3)
+    fn new_variant<B>(&self, request: &Request<B>, response: &Response<Bytes>) -> Option<Variant> {
+        let request_cc = CacheControl::from_headers(request.headers());
+        let cc = CacheControl::from_headers(response.headers());
+        if request_cc.no_store || cc.no_store || cc.private {
+            return None;
+        }
+        if request.headers().contains_key(AUTHORIZATION)
+            && !(cc.public || cc.must_revalidate || cc.s_maxage.is_some())
+        {
+            return None;
+        }
+        let status = response.status();
+        if status.is_informational() || status == StatusCode::PARTIAL_CONTENT {
+            return None;
+        }
+
+        let headers = response.headers();
+        let now = SystemTime::now();
+        let date = http_date(headers, DATE).filter(|date| *date <= now);
+        let explicit = cc.s_maxage.or(cc.max_age).or_else(|| {
+            let expires = http_date(headers, EXPIRES)?;
+            Some(
+                expires
+                    .duration_since(date.unwrap_or(now))
+                    .unwrap_or_default(),
+            )
+        });
+        let heuristic = || {
+            let last_modified = http_date(headers, LAST_MODIFIED)?;
+            let since = date.unwrap_or(now).duration_since(last_modified).ok()?;
+            Some(since / HEURISTIC_DIVISOR)
+        };
+        let freshness_lifetime = match explicit {
+            Some(lifetime) => lifetime,
+            None if heuristically_cacheable(status) => heuristic().unwrap_or_default(),
+            None if cc.public => Duration::ZERO,
+            None => return None,
+        };
+
+        let mut vary = Vec::new();
+        for value in headers.get_all(VARY) {
+            for name in value.to_str().ok()?.split(',').map(str::trim) {
+                if name == "*" {
+                    return None;
+                }
+                let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+                    continue;
+                };
+                let values = request.headers().get_all(&name).iter().cloned().collect();
+                vary.push((name, values));
+            }
+        }
+
+        let age_value = headers
+            .get(AGE)
+            .and_then(|age| age.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        let apparent_age = date
+            .and_then(|date| now.duration_since(date).ok())
+            .unwrap_or_default();
+
+        Some(Variant {
+            vary,
+            status,
+            version: response.version(),
+            headers: headers.clone(),
+            body: response.body().clone(),
+            stored_at: self.clock.now(),
+            initial_age: age_value.max(apparent_age),
+            freshness_lifetime,
+            no_cache: cc.no_cache,
+            must_revalidate: cc.must_revalidate || cc.proxy_revalidate || cc.s_maxage.is_some(),
+            stale_while_revalidate: cc.stale_while_revalidate.unwrap_or_default(),
+        })
+    }
+}
+
+/// The URL of the request, taking the authority from Host for origin-form targets
+fn primary_key<B>(request: &Request<B>) -> String {
+    let uri = request.uri();
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    match uri.authority() {
+        Some(authority) => format!("{authority}{path}"),
+        None => {
+            let host = request
+                .headers()
+                .get(HOST)
+                .and_then(|host| host.to_str().ok())
+                .unwrap_or_default();
+            format!("{host}{path}")
+        }
+    }
+}
+
+/// Status codes cacheable without explicit freshness (RFC 9110 section 15.1)
+fn heuristically_cacheable(status: StatusCode) -> bool {
+    matches!(
+        status.as_u16(),
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+fn http_date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    httpdate::parse_http_date(headers.get(name)?.to_str().ok()?).ok()
+}
+
+/// Whether the conditional `request` is satisfied by the stored response (RFC 9110 13.1)
+fn not_modified(request: &HeaderMap, stored: &HeaderMap) -> bool {
+    if let Some(if_none_match) = request.get(IF_NONE_MATCH) {
+        let Some(etag) = stored.get(ETAG).and_then(|etag| etag.to_str().ok()) else {
+            return false;
+        };
+        let Ok(if_none_match) = if_none_match.to_str() else {
+            return false;
+        };
+        // If-None-Match uses the weak comparison
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+        return if_none_match.trim() == "*"
+            || if_none_match
+                .split(',')
+                .any(|tag| opaque(tag) == opaque(etag));
+    }
+    match (
+        http_date(request, IF_MODIFIED_SINCE),
+        http_date(stored, LAST_MODIFIED),
+    ) {
+        (Some(since), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn setup() -> (HttpCache, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        (HttpCache::with_clock(1024, 100, clock.clone()), clock)
+    }
+
+    fn get(uri: &str) -> Request<()> {
+        Request::get(uri).body(()).unwrap()
+    }
+
+    fn response(headers: &[(&str, &str)], body: &'static str) -> Response<Bytes> {
+        let mut response =
+            Response::builder().header(DATE, httpdate::fmt_http_date(SystemTime::now()));
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        response.body(Bytes::from_static(body.as_bytes())).unwrap()
+    }
+
+    fn fresh(lookup: Lookup) -> Response<Bytes> {
+        match lookup {
+            Lookup::Fresh(response) => response,
+            _ => panic!("not fresh"),
+        }
+    }
+
+    fn stale(lookup: Lookup) -> Stale {
+        match lookup {
+            Lookup::Stale(stale) => stale,
+            _ => panic!("not stale"),
+        }
+    }
+
+    #[test]
+    fn test_max_age() {
+        let (cache, clock) = setup();
+        let request = get("http://a.com/x");
+        assert!(matches!(cache.lookup(&request), Lookup::Miss));
+        assert!(cache.store(
+            &request,
+            &response(&[("cache-control", "max-age=60")], "hello")
+        ));
+
+        clock.advance(Duration::from_secs(10));
+        let hit = fresh(cache.lookup(&request));
+        assert_eq!(hit.body(), "hello");
+        assert_eq!(hit.headers()[AGE], "10");
+        // origin-form requests share the entry through Host
+        let origin_form = Request::get("/x").header(HOST, "a.com").body(()).unwrap();
+        assert_eq!(fresh(cache.lookup(&origin_form)).body(), "hello");
+
+        clock.advance(Duration::from_secs(60));
+        assert!(matches!(cache.lookup(&request), Lookup::Stale(_)));
+    }
+
+    #[test]
+    fn test_not_storable() {
+        let (cache, _) = setup();
+        let request = get("http://a.com/");
+        assert!(!cache.store(&request, &response(&[("cache-control", "no-store")], "")));
+        assert!(!cache.store(
+            &request,
+            &response(&[("cache-control", "private, max-age=5")], "")
+        ));
+        assert!(!cache.store(
+            &request,
+            &response(&[("vary", "*"), ("cache-control", "max-age=5")], "")
+        ));
+        let mut created = response(&[], "");
+        *created.status_mut() = StatusCode::CREATED;
+        assert!(!cache.store(&request, &created));
+
+        let authorized = Request::get("http://a.com/")
+            .header(AUTHORIZATION, "x")
+            .body(())
+            .unwrap();
+        assert!(!cache.store(
+            &authorized,
+            &response(&[("cache-control", "max-age=5")], "")
+        ));
+        assert!(cache.store(
+            &authorized,
+            &response(&[("cache-control", "public, max-age=5")], "")
+        ));
+    }
+
+    #[test]
+    fn test_expires_and_heuristic() {
+        let (cache, clock) = setup();
+        let now = SystemTime::now();
+        let expires = httpdate::fmt_http_date(now + Duration::from_secs(100));
+        let request = get("http://a.com/expires");
+        cache.store(&request, &response(&[("expires", &expires)], "e"));
+        clock.advance(Duration::from_secs(50));
+        fresh(cache.lookup(&request));
+
+        // 10% of the 1000s since the last modification
+        let last_modified = httpdate::fmt_http_date(now - Duration::from_secs(1000));
+        let request = get("http://a.com/heuristic");
+        cache.store(
+            &request,
+            &response(&[("last-modified", &last_modified)], "h"),
+        );
+        clock.advance(Duration::from_secs(50));
+        fresh(cache.lookup(&request));
+        clock.advance(Duration::from_secs(60));
+        let stale = stale(cache.lookup(&request));
+        assert_eq!(stale.validators[IF_MODIFIED_SINCE], last_modified.as_str());
+    }
+
+    #[test]
+    fn test_etag_revalidation() {
+        let (cache, clock) = setup();
+        let request = get("http://a.com/");
+        let headers = [("cache-control", "max-age=10"), ("etag", "\"v1\"")];
+        cache.store(&request, &response(&headers, "body"));
+
+        let conditional = Request::get("http://a.com/")
+            .header(IF_NONE_MATCH, "W/\"v0\", \"v1\"")
+            .body(())
+            .unwrap();
+        let not_modified = fresh(cache.lookup(&conditional));
+        assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+        assert!(not_modified.body().is_empty());
+
+        clock.advance(Duration::from_secs(20));
+        let stale = stale(cache.lookup(&request));
+        assert_eq!(stale.validators[IF_NONE_MATCH], "\"v1\"");
+        assert_eq!(stale.response.body(), "body");
+
+        let mut origin = HeaderMap::new();
+        origin.insert("cache-control", HeaderValue::from_static("max-age=30"));
+        let refreshed = cache.refresh(&request, &origin).unwrap();
+        assert_eq!(refreshed.body(), "body");
+        clock.advance(Duration::from_secs(20));
+        let hit = fresh(cache.lookup(&request));
+        assert_eq!(hit.headers()["cache-control"], "max-age=30");
+    }
+
+    #[test]
+    fn test_request_directives() {
+        let (cache, clock) = setup();
+        let request = get("http://a.com/");
+        cache.store(&request, &response(&[("cache-control", "max-age=10")], ""));
+        clock.advance(Duration::from_secs(5));
+
+        let no_cache = Request::get("http://a.com/")
+            .header("cache-control", "no-cache")
+            .body(())
+            .unwrap();
+        assert!(matches!(cache.lookup(&no_cache), Lookup::Stale(_)));
+        let max_age = Request::get("http://a.com/")
+            .header("cache-control", "max-age=2")
+            .body(())
+            .unwrap();
+        assert!(matches!(cache.lookup(&max_age), Lookup::Stale(_)));
+
+        clock.advance(Duration::from_secs(10));
+        let max_stale = Request::get("http://a.com/")
+            .header("cache-control", "max-stale=60")
+            .body(())
+            .unwrap();
+        fresh(cache.lookup(&max_stale));
+    }
+
+    #[test]
+    fn test_stale_while_revalidate() {
+        let (cache, clock) = setup();
+        let request = get("http://a.com/");
+        let headers = [("cache-control", "max-age=10, stale-while-revalidate=20")];
+        cache.store(&request, &response(&headers, ""));
+
+        clock.advance(Duration::from_secs(25));
+        assert!(stale(cache.lookup(&request)).stale_while_revalidate);
+        clock.advance(Duration::from_secs(10));
+        assert!(!stale(cache.lookup(&request)).stale_while_revalidate);
+    }
+
+    #[test]
+    fn test_vary() {
+        let (cache, _) = setup();
+        let gzip = Request::get("http://a.com/")
+            .header("accept-encoding", "gzip")
+            .body(())
+            .unwrap();
+        let plain = get("http://a.com/");
+        let headers = [("cache-control", "max-age=60"), ("vary", "Accept-Encoding")];
+        cache.store(&gzip, &response(&headers, "zipped"));
+        assert!(matches!(cache.lookup(&plain), Lookup::Miss));
+        cache.store(&plain, &response(&headers, "plain"));
+
+        assert_eq!(fresh(cache.lookup(&gzip)).body(), "zipped");
+        assert_eq!(fresh(cache.lookup(&plain)).body(), "plain");
+    }
+
+    #[test]
+    fn test_unsafe_methods_invalidate() {
+        let (cache, _) = setup();
+        let request = get("http://a.com/");
+        cache.store(&request, &response(&[("cache-control", "max-age=60")], ""));
+        let post = Request::post("http://a.com/").body(()).unwrap();
+        assert!(!cache.store(&post, &response(&[], "")));
+        assert!(matches!(cache.lookup(&request), Lookup::Miss));
+    }
+
+    #[test]
+    fn test_head() {
+        let (cache, _) = setup();
+        cache.store(
+            &get("http://a.com/"),
+            &response(&[("cache-control", "max-age=60")], "body"),
+        );
+        let head = Request::head("http://a.com/").body(()).unwrap();
+        assert!(fresh(cache.lookup(&head)).body().is_empty());
+    }
+}
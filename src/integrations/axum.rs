@@ -0,0 +1,198 @@
+//! [axum](https://docs.rs/axum) integration.
+//!
+//! [`Cache`] is a cheap to clone handle that can be the router state itself or a field of it.
+//! Handlers then take it as an argument like any other extractor:
+//!
+//! ```no_run
+//! use axum::{extract::FromRef, extract::Path, routing::get, Router};
+//! use cachez::integrations::axum::Cache;
+//!
+//! #[derive(Clone, FromRef)]
+//! struct AppState {
+//!     users: Cache<u64, String>,
+//! }
+//!
+//! async fn user(Path(id): Path<u64>, users: Cache<u64, String>) -> String {
+//!     users.get_with(id, || async move { format!("user {id}") }).await
+//! }
+//!
+//! let app: Router = Router::new()
+//!     .route("/users/{id}", get(user))
+//!     .with_state(AppState { users: Cache::new(10_000, 10_000) });
+//! ```
+
+use crate::tinyufo::{CacheStats, ConcurrentTinyUFO, Weight};
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use std::borrow::Borrow;
+use std::convert::Infallible;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Shared, typed cache handle, clones point to the same cache.
+pub struct Cache<K, V: Clone> {
+    inner: Arc<ConcurrentTinyUFO<K, V>>,
+}
+
+impl<K, V: Clone> Clone for Cache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K: Hash, V: Clone> Cache<K, V> {
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::from_cache(ConcurrentTinyUFO::new(total_weight_limit, capacity))
+    }
+
+    /// Share an already configured cache
+    pub fn from_cache(cache: ConcurrentTinyUFO<K, V>) -> Self {
+        Self {
+            inner: Arc::new(cache),
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.get(key)
+    }
+
+    /// Cache `value` with a weight of 1
+    pub fn insert(&self, key: K, value: V) {
+        self.inner.put(key, 1, value);
+    }
+
+    pub fn insert_weighted(&self, key: K, weight: Weight, value: V) {
+        self.inner.put(key, weight, value);
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.inner.remove(key)
+    }
+
+    /// Get the cached value or compute it with `init`, caching the result with a weight of 1.
+    ///
+    /// Concurrent misses on the same key each run `init`, the last one to finish wins.
+    pub async fn get_with<F, Fut>(&self, key: K, init: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        if let Some(value) = self.inner.get(&key) {
+            return value;
+        }
+        let value = init().await;
+        self.inner.put(key, 1, value.clone());
+        value
+    }
+
+    /// Like [`Cache::get_with`], errors are returned and not cached.
+    pub async fn try_get_with<F, Fut, E>(&self, key: K, init: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        if let Some(value) = self.inner.get(&key) {
+            return Ok(value);
+        }
+        let value = init().await?;
+        self.inner.put(key, 1, value.clone());
+        Ok(value)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.inner.stats()
+    }
+
+    /// The cache behind the handle
+    pub fn inner(&self) -> &ConcurrentTinyUFO<K, V> {
+        &self.inner
+    }
+}
+
+impl<S, K, V> FromRequestParts<S> for Cache<K, V>
+where
+    Cache<K, V>: FromRef<S>,
+    S: Send + Sync,
+    K: Send + Sync,
+    V: Clone + Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_ref(state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::extract::Path;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    #[derive(Clone, FromRef)]
+    struct AppState {
+        squares: Cache<u64, u64>,
+        loads: Arc<AtomicUsize>,
+    }
+
+    async fn square(
+        Path(n): Path<u64>,
+        squares: Cache<u64, u64>,
+        axum::extract::State(state): axum::extract::State<AppState>,
+    ) -> String {
+        squares
+            .get_with(n, || async move {
+                state.loads.fetch_add(1, Ordering::Relaxed);
+                n * n
+            })
+            .await
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_extractor() {
+        let state = AppState {
+            squares: Cache::new(100, 100),
+            loads: Arc::default(),
+        };
+        let app = Router::new()
+            .route("/square/{n}", get(square))
+            .with_state(state.clone());
+
+        for _ in 0..3 {
+            let request = Request::get("/square/7").body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(state.loads.load(Ordering::Relaxed), 1);
+        assert_eq!(state.squares.get(&7), Some(49));
+        assert_eq!(state.squares.stats().hits, 3);
+    }
+
+    #[tokio::test]
+    async fn test_try_get_with_does_not_cache_errors() {
+        let cache: Cache<&str, u64> = Cache::new(10, 10);
+        let err = cache.try_get_with("a", || async { Err("down") }).await;
+        assert_eq!(err, Err("down"));
+        assert_eq!(cache.get("a"), None);
+        let ok = cache.try_get_with("a", || async { Ok::<_, &str>(1) }).await;
+        assert_eq!(ok, Ok(1));
+        assert_eq!(cache.get("a"), Some(1));
+    }
+}
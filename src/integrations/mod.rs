@@ -0,0 +1,8 @@
+//! Glue between the caches and third party frameworks, each behind its own feature
+
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "reqwest")]
+pub mod reqwest;
+#[cfg(feature = "sqlx")]
+pub mod sqlx;
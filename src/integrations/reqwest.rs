@@ -0,0 +1,241 @@
+//! Client side HTTP caching for [reqwest](https://docs.rs/reqwest) through
+//! [reqwest-middleware](https://docs.rs/reqwest-middleware).
+//!
+//! [`CacheMiddleware`] answers GET and HEAD requests from an [`HttpCache`] when it can,
+//! revalidates stale responses with conditional requests and, within a response's
+//! stale-while-revalidate window, serves the stale response right away while revalidating it in
+//! the background.
+//!
+//! ```no_run
+//! # use cachez::http_cache::HttpCache;
+//! # use cachez::integrations::reqwest::CacheMiddleware;
+//! # use std::sync::Arc;
+//! let cache = Arc::new(HttpCache::new(16 * 1024, 1000));
+//! let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+//!     .with(CacheMiddleware::new(cache, reqwest::Client::new()))
+//!     .build();
+//! ```
+
+use crate::http_cache::{HttpCache, Lookup};
+use bytes::Bytes;
+use http::{Extensions, HeaderMap, Method, StatusCode};
+use reqwest::{Client, Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// reqwest-middleware caching GET responses in an [`HttpCache`]
+#[derive(Clone)]
+pub struct CacheMiddleware {
+    cache: Arc<HttpCache>,
+    // background revalidations run outside of the middleware stack, `Next` can't outlive the
+    // request it was handed for
+    client: Client,
+    revalidating: Arc<Mutex<HashSet<String>>>,
+}
+
+impl CacheMiddleware {
+    /// `client` sends the background revalidations of stale-while-revalidate responses
+    pub fn new(cache: Arc<HttpCache>, client: Client) -> Self {
+        Self {
+            cache,
+            client,
+            revalidating: Arc::default(),
+        }
+    }
+
+    pub fn cache(&self) -> &HttpCache {
+        &self.cache
+    }
+
+    fn revalidate_in_background(&self, request: http::Request<()>, validators: HeaderMap) {
+        let key = request.uri().to_string();
+        if !self.revalidating.lock().unwrap().insert(key.clone()) {
+            // already on its way
+            return;
+        }
+        let mut headers = request.headers().clone();
+        headers.extend(validators);
+        let send = self.client.get(key.as_str()).headers(headers).send();
+        let cache = self.cache.clone();
+        let revalidating = self.revalidating.clone();
+        tokio::spawn(async move {
+            if let Ok(response) = send.await {
+                if response.status() == StatusCode::NOT_MODIFIED {
+                    cache.refresh(&request, response.headers());
+                } else if let Ok(response) = into_http(response).await {
+                    cache.store(&request, &response);
+                }
+            }
+            revalidating.lock().unwrap().remove(&key);
+        });
+    }
+
+    async fn fetch(
+        &self,
+        request: &http::Request<()>,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let response = into_http(next.run(req, extensions).await?).await?;
+        self.cache.store(request, &response);
+        Ok(response.into())
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for CacheMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let request = to_http(&req);
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            let response = next.run(req, extensions).await?;
+            if !request.method().is_safe()
+                && (response.status().is_success() || response.status().is_redirection())
+            {
+                self.cache.invalidate(&request);
+            }
+            return Ok(response);
+        }
+
+        match self.cache.lookup(&request) {
+            Lookup::Fresh(response) => Ok(response.into()),
+            Lookup::Stale(stale) if stale.stale_while_revalidate => {
+                self.revalidate_in_background(request, stale.validators);
+                Ok(stale.response.into())
+            }
+            Lookup::Stale(stale) => {
+                req.headers_mut().extend(stale.validators);
+                let response = next.run(req, extensions).await?;
+                if response.status() != StatusCode::NOT_MODIFIED {
+                    let response = into_http(response).await?;
+                    self.cache.store(&request, &response);
+                    return Ok(response.into());
+                }
+                let refreshed = self.cache.refresh(&request, response.headers());
+                Ok(refreshed.unwrap_or(stale.response).into())
+            }
+            Lookup::Miss => self.fetch(&request, req, extensions, next).await,
+        }
+    }
+}
+
+/// The head of `req`, which is all the cache looks at
+fn to_http(req: &Request) -> http::Request<()> {
+    let mut request = http::Request::new(());
+    *request.method_mut() = req.method().clone();
+    *request.uri_mut() = req.url().as_str().parse().unwrap_or_default();
+    *request.version_mut() = req.version();
+    *request.headers_mut() = req.headers().clone();
+    request
+}
+
+/// Read the whole body of `response`
+async fn into_http(response: Response) -> reqwest::Result<http::Response<Bytes>> {
+    let mut http = http::Response::new(Bytes::new());
+    *http.status_mut() = response.status();
+    *http.version_mut() = response.version();
+    *http.headers_mut() = response.headers().clone();
+    *http.body_mut() = response.bytes().await?;
+    Ok(http)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Origin answering every request with `cache_control` and an ETag, 304 when it matches
+    async fn origin(cache_control: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = socket.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                let response = if request.contains("if-none-match: \"v\"") {
+                    format!("HTTP/1.1 304 Not Modified\r\ncache-control: {cache_control}\r\netag: \"v\"\r\nconnection: close\r\n\r\n")
+                } else {
+                    let body = format!("response {n}");
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncache-control: {cache_control}\r\netag: \"v\"\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                };
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn client(clock: Arc<ManualClock>) -> reqwest_middleware::ClientWithMiddleware {
+        let cache = Arc::new(HttpCache::with_clock(1024, 100, clock));
+        reqwest_middleware::ClientBuilder::new(Client::new())
+            .with(CacheMiddleware::new(cache, Client::new()))
+            .build()
+    }
+
+    async fn get(client: &reqwest_middleware::ClientWithMiddleware, url: &str) -> String {
+        client.get(url).send().await.unwrap().text().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_revalidation() {
+        let (url, requests) = origin("max-age=10").await;
+        let clock = Arc::new(ManualClock::new());
+        let client = client(clock.clone());
+
+        assert_eq!(get(&client, &url).await, "response 1");
+        assert_eq!(get(&client, &url).await, "response 1");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // conditional request, answered with a 304
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(get(&client, &url).await, "response 1");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(get(&client, &url).await, "response 1");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stale_while_revalidate() {
+        let (url, requests) = origin("max-age=10, stale-while-revalidate=60").await;
+        let clock = Arc::new(ManualClock::new());
+        let client = client(clock.clone());
+
+        get(&client, &url).await;
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(get(&client, &url).await, "response 1");
+        while requests.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // refreshed by the background revalidation
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(get(&client, &url).await, "response 1");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unsafe_method_invalidates() {
+        let (url, requests) = origin("max-age=10").await;
+        let client = client(Arc::new(ManualClock::new()));
+        get(&client, &url).await;
+        client.post(&url).send().await.unwrap();
+        assert_eq!(get(&client, &url).await, "response 3");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,310 @@
+//! Query result cache for [sqlx](https://docs.rs/sqlx).
+//!
+//! [`QueryCache`] caches the rows a query returns, keyed by its SQL and bind parameters, for a
+//! fixed TTL. Each result is weighted by the byte size of its rows, so the weight limit is a
+//! memory budget in KiB. sqlx already reuses prepared statements per connection, this saves
+//! the round trip to the database altogether.
+//!
+//! ```no_run
+//! # async fn run(pool: sqlx::SqlitePool) -> Result<(), sqlx::Error> {
+//! use cachez::integrations::sqlx::QueryCache;
+//! use std::time::Duration;
+//!
+//! #[derive(Clone, sqlx::FromRow)]
+//! struct User {
+//!     id: i64,
+//!     name: String,
+//! }
+//!
+//! let cache: QueryCache<User> = QueryCache::new(4 * 1024, 1000, Duration::from_secs(30))
+//!     .with_row_size(|user: &User| user.name.len() + 8);
+//! let sql = "SELECT id, name FROM users WHERE id = ?";
+//! let users = cache
+//!     .fetch_all(sql, &42, || sqlx::query_as(sql).bind(42).fetch_all(&pool))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Writes are not tracked, call [`QueryCache::invalidate`] after modifying rows that cached
+//! queries return, or rely on the TTL.
+
+use crate::clock::Clock;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::clock::StdClock;
+use crate::tinyufo::{CacheStats, ConcurrentTinyUFO, Weight};
+use std::future::Future;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Duration;
+use t1ha::{T1haBuildHasher, T1haHasher};
+
+// the parameters are only hashed, a second hash of them from another seed tells apart the
+// queries whose key collides
+const PARAMS_SEED: u64 = 0x5041_5241_4d53_5351;
+
+/// Rows returned by a cached query
+pub type Rows<T> = Arc<[T]>;
+
+struct CachedRows<T> {
+    sql: Arc<str>,
+    params_hash: u64,
+    rows: Rows<T>,
+    expires_at: Duration,
+}
+
+// derive would require T: Clone, the rows are shared
+impl<T> Clone for CachedRows<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sql: self.sql.clone(),
+            params_hash: self.params_hash,
+            rows: self.rows.clone(),
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+/// Statistics of a [`QueryCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryCacheStats {
+    /// Hits and misses count fresh results only, an expired result is a miss
+    pub cache: CacheStats,
+    /// Results dropped because their TTL passed
+    pub expirations: u64,
+    /// Queries run by the loaders
+    pub loads: u64,
+}
+
+/// Cache of query results of type `T`, see the [module docs](self).
+pub struct QueryCache<T> {
+    cache: ConcurrentTinyUFO<u64, CachedRows<T>>,
+    ttl: Duration,
+    row_size: Box<dyn Fn(&T) -> usize + Send + Sync>,
+    clock: Arc<dyn Clock>,
+    hasher: T1haBuildHasher,
+    expirations: AtomicU64,
+    loads: AtomicU64,
+}
+
+impl<T: Send + Sync + 'static> QueryCache<T> {
+    /// `weight_limit_kib` bounds the total byte size of the cached rows, in KiB
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn new(weight_limit_kib: usize, capacity: usize, ttl: Duration) -> Self {
+        Self::with_clock(weight_limit_kib, capacity, ttl, Arc::new(StdClock::new()))
+    }
+
+    /// Measure the TTLs with `clock`
+    pub fn with_clock(
+        weight_limit_kib: usize,
+        capacity: usize,
+        ttl: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::new(weight_limit_kib, capacity),
+            ttl,
+            row_size: Box::new(|_| std::mem::size_of::<T>()),
+            clock,
+            hasher: T1haBuildHasher::default(),
+            expirations: AtomicU64::new(0),
+            loads: AtomicU64::new(0),
+        }
+    }
+
+    /// Size in bytes of a row, `size_of::<T>()` by default which ignores heap data such as
+    /// strings
+    pub fn with_row_size(self, row_size: impl Fn(&T) -> usize + Send + Sync + 'static) -> Self {
+        Self {
+            row_size: Box::new(row_size),
+            ..self
+        }
+    }
+
+    /// Get the rows of `sql` bound to `params`, running `load` and caching what it returns on
+    /// a miss. Errors are returned and not cached.
+    pub async fn fetch_all<P, F, Fut>(
+        &self,
+        sql: &str,
+        params: &P,
+        load: F,
+    ) -> Result<Rows<T>, sqlx::Error>
+    where
+        P: Hash + ?Sized,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<T>, sqlx::Error>>,
+    {
+        let key = self.key(sql, params);
+        let params_hash = params_hash(params);
+        if let Some(rows) = self.get(key, sql, params_hash) {
+            return Ok(rows);
+        }
+
+        self.loads.fetch_add(1, Relaxed);
+        let rows: Rows<T> = load().await?.into();
+        let bytes: usize = rows.iter().map(|row| (self.row_size)(row)).sum();
+        let weight = bytes.div_ceil(1024).max(1) as Weight;
+        let cached = CachedRows {
+            sql: sql.into(),
+            params_hash,
+            rows: rows.clone(),
+            expires_at: self.clock.now() + self.ttl,
+        };
+        self.cache.put(key, weight, cached);
+        Ok(rows)
+    }
+
+    /// Like [`Self::fetch_all`] for queries returning at most one row
+    pub async fn fetch_optional<P, F, Fut>(
+        &self,
+        sql: &str,
+        params: &P,
+        load: F,
+    ) -> Result<Option<T>, sqlx::Error>
+    where
+        P: Hash + ?Sized,
+        T: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<T>, sqlx::Error>>,
+    {
+        let rows = self
+            .fetch_all(sql, params, || async {
+                Ok(load().await?.into_iter().collect())
+            })
+            .await?;
+        Ok(rows.first().cloned())
+    }
+
+    /// Drop the cached result of `sql` bound to `params`
+    pub fn invalidate<P: Hash + ?Sized>(&self, sql: &str, params: &P) {
+        self.cache.remove(&self.key(sql, params));
+    }
+
+    pub fn stats(&self) -> QueryCacheStats {
+        let mut cache = self.cache.stats();
+        let expirations = self.expirations.load(Relaxed);
+        // an expired result was read as a hit then removed, account it as a miss
+        cache.hits = cache.hits.saturating_sub(expirations);
+        cache.misses += expirations;
+        cache.removals = cache.removals.saturating_sub(expirations);
+        QueryCacheStats {
+            cache,
+            expirations,
+            loads: self.loads.load(Relaxed),
+        }
+    }
+
+    fn key<P: Hash + ?Sized>(&self, sql: &str, params: &P) -> u64 {
+        self.hasher.hash_one((sql, params))
+    }
+
+    /// The fresh rows cached under `key`, None if they are another query's
+    fn get(&self, key: u64, sql: &str, params_hash: u64) -> Option<Rows<T>> {
+        let cached = self.cache.get(&key)?;
+        if *cached.sql != *sql || cached.params_hash != params_hash {
+            return None;
+        }
+        if cached.expires_at > self.clock.now() {
+            return Some(cached.rows);
+        }
+        if self.cache.remove(&key).is_some() {
+            self.expirations.fetch_add(1, Relaxed);
+        }
+        None
+    }
+}
+
+fn params_hash<P: Hash + ?Sized>(params: &P) -> u64 {
+    let mut hasher = T1haHasher::with_seed(PARAMS_SEED);
+    params.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use sqlx::SqlitePool;
+
+    #[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+    struct User {
+        id: i64,
+        name: String,
+    }
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users (id, name) VALUES (1, 'ada'), (2, 'grace')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    const BY_ID: &str = "SELECT id, name FROM users WHERE id = ?";
+
+    #[tokio::test]
+    async fn test_query_cache() {
+        let pool = pool().await;
+        let clock = Arc::new(ManualClock::new());
+        let cache: QueryCache<User> =
+            QueryCache::with_clock(1024, 100, Duration::from_secs(10), clock.clone())
+                .with_row_size(|user: &User| 8 + user.name.len());
+        let by_id = |id: i64| {
+            let pool = pool.clone();
+            move || async move { sqlx::query_as(BY_ID).bind(id).fetch_optional(&pool).await }
+        };
+
+        let user = cache.fetch_optional(BY_ID, &1, by_id(1)).await.unwrap();
+        assert_eq!(user.unwrap().name, "ada");
+        let user = cache.fetch_optional(BY_ID, &1, by_id(1)).await.unwrap();
+        assert_eq!(user.unwrap().name, "ada");
+        // same SQL, other parameters
+        let user = cache.fetch_optional(BY_ID, &2, by_id(2)).await.unwrap();
+        assert_eq!(user.unwrap().name, "grace");
+        assert_eq!(cache.stats().loads, 2);
+
+        sqlx::query("UPDATE users SET name = 'lovelace' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let user = cache.fetch_optional(BY_ID, &1, by_id(1)).await.unwrap();
+        assert_eq!(user.unwrap().name, "ada");
+        clock.advance(Duration::from_secs(10));
+        let user = cache.fetch_optional(BY_ID, &1, by_id(1)).await.unwrap();
+        assert_eq!(user.unwrap().name, "lovelace");
+
+        let stats = cache.stats();
+        assert_eq!((stats.loads, stats.expirations), (3, 1));
+        assert_eq!((stats.cache.hits, stats.cache.misses), (2, 3));
+        assert_eq!(stats.cache.removals, 0);
+    }
+
+    #[tokio::test]
+    async fn test_errors_and_invalidate() {
+        let pool = pool().await;
+        let cache: QueryCache<User> = QueryCache::new(1024, 100, Duration::from_secs(60));
+        let broken = "SELECT nope FROM users";
+        let load = || sqlx::query_as(broken).fetch_all(&pool);
+        assert!(cache.fetch_all(broken, &(), load).await.is_err());
+        assert_eq!(cache.stats().cache.inserts, 0);
+
+        let all = "SELECT id, name FROM users ORDER BY id";
+        let load = || sqlx::query_as(all).fetch_all(&pool);
+        assert_eq!(cache.fetch_all(all, &(), load).await.unwrap().len(), 2);
+        // another query whose key collides misses
+        let key = cache.key(all, &());
+        assert!(cache.get(key, all, params_hash(&())).is_some());
+        assert!(cache.get(key, "SELECT 1", params_hash(&())).is_none());
+        assert!(cache.get(key, all, params_hash(&1)).is_none());
+        cache.invalidate(all, &());
+        let load = || sqlx::query_as(all).fetch_all(&pool);
+        cache.fetch_all(all, &(), load).await.unwrap();
+        assert_eq!(cache.stats().loads, 3);
+    }
+}
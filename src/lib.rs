@@ -1 +1,16 @@
-mod tinyufo;
+pub mod cache;
+pub mod chaos;
+pub mod clock;
+pub mod compat;
+pub mod deterministic;
+#[cfg(feature = "http-cache")]
+pub mod http_cache;
+pub mod integrations;
+pub mod prelude;
+pub mod tinyufo;
+pub mod workload;
+
+pub use cache::Cache;
+pub use tinyufo::{
+    CacheConfig, CacheStats, ConcurrentTinyUFO, Estimator, Key, TinyLFU, TinyUFO, Weight,
+};
@@ -0,0 +1,4 @@
+//! The traits and types most users of the crate need, `use cachez::prelude::*;`
+
+pub use crate::cache::Cache;
+pub use crate::tinyufo::{CacheConfig, CacheStats, ConcurrentTinyUFO, Key, TinyUFO, Weight};
@@ -0,0 +1,134 @@
+use crate::tinyufo::estimator::TinyLFU;
+use crate::tinyufo::types::{Key, Weight};
+
+/// Decides whether a new key is worth the entries its put evicts.
+///
+/// Asked by a full cache only, see
+/// [`TinyUFO::with_admission_policy`](crate::tinyufo::TinyUFO::with_admission_policy). A
+/// rejected put leaves the cache as it was: counted in
+/// [`CacheStats::rejections`](crate::tinyufo::CacheStats::rejections) rather than as an insert,
+/// its value handed back to the `on_evict` of the put but to no listener.
+pub trait AdmissionPolicy: Send + Sync {
+    /// `key` was read or put
+    fn record(&mut self, _key: Key) {}
+
+    /// Whether to cache `candidate`, weighing `weight`, at the cost of `victim`, the entry
+    /// eviction takes first. `lfu` is the cache's frequency sketch, which counted both
+    fn admit(&mut self, candidate: Key, weight: Weight, victim: Key, lfu: &TinyLFU) -> bool;
+}
+
+/// Admit every key, S3-FIFO's small queue sorting them out. The default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlwaysAdmit;
+
+impl AdmissionPolicy for AlwaysAdmit {
+    fn admit(&mut self, _: Key, _: Weight, _: Key, _: &TinyLFU) -> bool {
+        true
+    }
+}
+
+/// Admit a key at least as frequent as the victim, as W-TinyLFU does, by the cache's sketch
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TinyLfuAdmission;
+
+impl AdmissionPolicy for TinyLfuAdmission {
+    fn admit(&mut self, candidate: Key, _: Weight, victim: Key, lfu: &TinyLFU) -> bool {
+        lfu.get(candidate) >= lfu.get(victim)
+    }
+}
+
+/// Admit a key with a fixed probability, in `0..=1`: keys must be put several times to get in
+#[derive(Debug, Clone, Copy)]
+pub struct ProbabilisticAdmission {
+    pub probability: f64,
+}
+
+impl AdmissionPolicy for ProbabilisticAdmission {
+    fn admit(&mut self, _: Key, _: Weight, _: Key, _: &TinyLFU) -> bool {
+        crate::deterministic::f64() < self.probability
+    }
+}
+
+/// Admit a key with a probability of `e^(-weight / scale)`, as AdaptSize does: heavy keys
+/// must be put more often to get in, keeping the room for many light ones
+#[derive(Debug, Clone, Copy)]
+pub struct SizeAwareAdmission {
+    pub scale: Weight,
+}
+
+impl AdmissionPolicy for SizeAwareAdmission {
+    fn admit(&mut self, _: Key, weight: Weight, _: Key, _: &TinyLFU) -> bool {
+        let probability = (-(weight as f64) / self.scale.max(1) as f64).exp();
+        crate::deterministic::f64() < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tinyufo::TinyUFO;
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_admission() {
+        let mut lfu = TinyLFU::new(16);
+        for _ in 0..3 {
+            lfu.incr(1);
+        }
+        assert!(TinyLfuAdmission.admit(1, 1, 2, &lfu));
+        assert!(!TinyLfuAdmission.admit(2, 1, 1, &lfu));
+
+        let mut policy = SizeAwareAdmission { scale: 1 };
+        assert!(!(0..100).any(|_| policy.admit(1, 100, 2, &lfu)));
+
+        // 1 and 2 are hot, read through get_mut as well, one-off keys don't get in
+        let notified = Arc::new(AtomicUsize::new(0));
+        let listener = notified.clone();
+        let mut cache = TinyUFO::new(2, 2)
+            .with_admission_policy(TinyLfuAdmission)
+            .with_eviction_listener(move |_, _, _, _| {
+                listener.fetch_add(1, Relaxed);
+            });
+        cache.put(1, 1, 1);
+        cache.put(2, 1, 2);
+        for _ in 0..3 {
+            cache.get(&1);
+            cache.get_mut(&2);
+        }
+        let mut rejected = vec![];
+        cache.put_evicting(3, 1, 3, |_, data| rejected.push(data));
+        assert_eq!(rejected, [3]);
+        assert_eq!((cache.peek(&1), cache.peek(&2)), (Some(&1), Some(&2)));
+        // neither inserted nor evicted
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.inserts, stats.rejections, stats.evictions),
+            (2, 1, 0)
+        );
+        assert_eq!(notified.load(Relaxed), 0);
+        // still returned
+        assert_eq!(*cache.get_or_insert_with(4, 1, || 4), 4);
+
+        // twice as many keys as fit, put over and over: a key seen once is colder than the
+        // entry it would evict
+        let mut cache = TinyUFO::new(4, 64).with_admission_policy(TinyLfuAdmission);
+        for _ in 0..4 {
+            for i in 1..=8 {
+                cache.put(i, 1, i);
+            }
+        }
+        rejected.clear();
+        cache.put_evicting(100, 1, 100, |_, data| rejected.push(data));
+        assert_eq!(rejected, [100]);
+        assert_eq!(cache.len(), 4);
+
+        // all are by default
+        let mut cache = TinyUFO::new(2, 2);
+        cache.put(1, 1, 1);
+        cache.put(2, 1, 2);
+        cache.get(&1);
+        cache.put_evicting(3, 1, 3, |_, data| rejected.push(data));
+        assert_eq!(cache.peek(&3), Some(&3));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::clock::Clock;
+use crate::tinyufo::admission::AdmissionPolicy;
+use crate::tinyufo::concurrent::ConcurrentTinyUFO;
+use crate::tinyufo::config::{CacheConfig, EstimatorConfig};
+use crate::tinyufo::events::{CacheEventHandler, EvictionListener, RemovalCause};
+use crate::tinyufo::eviction::EvictionPolicy;
+use crate::tinyufo::tinyufo::{TinyUFO, USES_CAP};
+use crate::tinyufo::types::{Key, Weigher, Weight};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Estimated items of a builder given none, when the weight limit is larger: with weights in
+/// bytes, the limit would size the map and the sketch for far more entries than fit
+const DEFAULT_ESTIMATED_ITEMS: usize = 1 << 16;
+
+/// Most estimated items a builder accepts, each costs a map slot and sketch counters up front
+pub const MAX_ESTIMATED_ITEMS: usize = 1 << 28;
+
+/// Error of a [`TinyUfoBuilder`] given settings no cache can work with
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// No weight limit was set, or it is 0
+    NoWeightLimit,
+    /// The small queue fraction isn't in (0, 1)
+    SmallQueueFraction(f64),
+    ZeroShards,
+    /// The estimator was given no rows or no counters
+    EmptyEstimator,
+    ZeroTimeToIdle,
+    /// The uses cap isn't in 1..=3
+    UsesCap(u8),
+    /// The promotion threshold isn't under the uses cap, nothing would be promoted
+    PromotionThreshold(u8),
+    /// More estimated items than [`MAX_ESTIMATED_ITEMS`]
+    TooManyItems(usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoWeightLimit => f.write_str("the weight limit must be set and positive"),
+            Self::SmallQueueFraction(fraction) => {
+                write!(f, "small queue fraction {fraction} is not in (0, 1)")
+            }
+            Self::ZeroShards => f.write_str("a concurrent cache needs one shard at least"),
+            Self::EmptyEstimator => {
+                f.write_str("the estimator needs one row and one counter at least")
+            }
+            Self::ZeroTimeToIdle => f.write_str("the time to idle must be positive"),
+            Self::UsesCap(cap) => write!(f, "uses cap {cap} is not in 1..={USES_CAP}"),
+            Self::PromotionThreshold(threshold) => {
+                write!(
+                    f,
+                    "promotion threshold {threshold} is not under the uses cap"
+                )
+            }
+            Self::TooManyItems(items) => {
+                write!(f, "{items} estimated items is over {MAX_ESTIMATED_ITEMS}")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Builder of a [`TinyUFO`] or a [`ConcurrentTinyUFO`], see [`TinyUFO::builder`].
+///
+/// Only the weight limit is required, the settings are checked by `build`.
+pub struct TinyUfoBuilder<K, T> {
+    config: CacheConfig,
+    small_queue_fraction: Option<f64>,
+    clock: Option<Arc<dyn Clock>>,
+    events: Option<Arc<dyn CacheEventHandler>>,
+    listener: Option<EvictionListener<T>>,
+    weigher: Option<Weigher<K, T>>,
+    admission: Option<Box<dyn Fn() -> Box<dyn AdmissionPolicy>>>,
+    eviction: Option<Box<dyn Fn() -> Box<dyn EvictionPolicy>>>,
+    _k: PhantomData<K>,
+}
+
+impl<K: Hash, T> TinyUfoBuilder<K, T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: CacheConfig::new(0, 0),
+            small_queue_fraction: None,
+            clock: None,
+            events: None,
+            listener: None,
+            weigher: None,
+            admission: None,
+            eviction: None,
+            _k: PhantomData,
+        }
+    }
+
+    /// Start from `config`, as [`TinyUFO::from_config`] would, the other settings applied over
+    /// it
+    pub fn config(mut self, config: CacheConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Total weight of the cached entries
+    pub fn weight_limit(mut self, weight_limit: usize) -> Self {
+        self.config.weight_limit = weight_limit;
+        self
+    }
+
+    /// Expected number of cached entries, sizes the map and the estimator, up to
+    /// [`MAX_ESTIMATED_ITEMS`]. When unset, the weight limit as if every entry weighed 1, at
+    /// most 65536
+    pub fn estimated_items(mut self, items: usize) -> Self {
+        self.config.capacity = items;
+        self
+    }
+
+    /// Share of the weight limit given to the small queue, in (0, 1). See
+    /// [`TinyUFO::set_small_queue_percent`]
+    pub fn small_queue_fraction(mut self, fraction: f64) -> Self {
+        self.small_queue_fraction = Some(fraction);
+        self
+    }
+
+    /// See [`TinyUFO::set_uses_cap`]
+    pub fn uses_cap(mut self, cap: u8) -> Self {
+        self.config.uses_cap = Some(cap);
+        self
+    }
+
+    /// See [`TinyUFO::set_promotion_threshold`], under the uses cap
+    pub fn promotion_threshold(mut self, threshold: u8) -> Self {
+        self.config.promotion_threshold = Some(threshold);
+        self
+    }
+
+    /// See [`TinyUFO::set_ghost_queue`]
+    pub fn ghost_queue(mut self, enabled: bool) -> Self {
+        self.config.ghost_queue = Some(enabled);
+        self
+    }
+
+    /// Shards of a concurrent cache, 4 per core when unset. Ignored by [`Self::build`]
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.config.shards = Some(shards);
+        self
+    }
+
+    pub fn estimator(mut self, estimator: EstimatorConfig) -> Self {
+        self.config.estimator = estimator;
+        self
+    }
+
+    /// Seed of the frequency sketches, for runs that estimate alike. See
+    /// [`TinyLFU::with_seed`](crate::tinyufo::TinyLFU::with_seed)
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.estimator.seed = Some(seed);
+        self
+    }
+
+    /// Track the `n` hottest keys of each sketch, see [`TinyUFO::hot_keys`]
+    pub fn hot_keys(mut self, n: usize) -> Self {
+        self.config.estimator.hot_keys = Some(n);
+        self
+    }
+
+    /// See [`TinyUFO::expire_after_access`], rounded up to a millisecond
+    pub fn time_to_idle(mut self, tti: Duration) -> Self {
+        let ms = tti.as_millis() + u128::from(!tti.subsec_nanos().is_multiple_of(1_000_000));
+        self.config.time_to_idle_ms = Some(ms.try_into().unwrap_or(u64::MAX));
+        self
+    }
+
+    /// See [`TinyUFO::with_clock`]
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// See [`TinyUFO::with_event_handler`]
+    pub fn event_handler(mut self, handler: Arc<dyn CacheEventHandler>) -> Self {
+        self.events = Some(handler);
+        self
+    }
+
+    /// See [`TinyUFO::with_eviction_listener`]
+    pub fn eviction_listener(
+        mut self,
+        listener: impl Fn(Key, &T, Weight, RemovalCause) + Send + Sync + 'static,
+    ) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// See [`TinyUFO::with_weigher`]
+    pub fn weigher(mut self, weigher: impl Fn(&K, &T) -> Weight + Send + Sync + 'static) -> Self {
+        self.weigher = Some(Arc::new(weigher));
+        self
+    }
+
+    /// See [`TinyUFO::with_admission_policy`], `make` called once per shard
+    pub fn admission_policy<P: AdmissionPolicy + 'static>(
+        mut self,
+        make: impl Fn() -> P + 'static,
+    ) -> Self {
+        self.admission = Some(Box::new(move || Box::new(make())));
+        self
+    }
+
+    /// See [`TinyUFO::with_eviction_policy`], `make` called once per shard
+    pub fn eviction_policy<P: EvictionPolicy + 'static>(
+        mut self,
+        make: impl Fn() -> P + 'static,
+    ) -> Self {
+        self.eviction = Some(Box::new(move || Box::new(make())));
+        self
+    }
+
+    /// The configuration, checked, the small queue fraction set in it. The shards are only
+    /// checked for a `concurrent` cache
+    fn validate(&self, concurrent: bool) -> Result<CacheConfig, ConfigError> {
+        let mut config = self.config.clone();
+        if config.weight_limit == 0 {
+            return Err(ConfigError::NoWeightLimit);
+        }
+        if config.capacity == 0 {
+            config.capacity = config.weight_limit.min(DEFAULT_ESTIMATED_ITEMS);
+        }
+        if config.capacity > MAX_ESTIMATED_ITEMS {
+            return Err(ConfigError::TooManyItems(config.capacity));
+        }
+        if concurrent && config.shards == Some(0) {
+            return Err(ConfigError::ZeroShards);
+        }
+        if config.estimator.hashes == Some(0) || config.estimator.slots == Some(0) {
+            return Err(ConfigError::EmptyEstimator);
+        }
+        if config.time_to_idle_ms == Some(0) {
+            return Err(ConfigError::ZeroTimeToIdle);
+        }
+        let uses_cap = config.uses_cap.unwrap_or(USES_CAP);
+        if !(1..=USES_CAP).contains(&uses_cap) {
+            return Err(ConfigError::UsesCap(uses_cap));
+        }
+        if let Some(threshold) = config.promotion_threshold.filter(|&t| t >= uses_cap) {
+            return Err(ConfigError::PromotionThreshold(threshold));
+        }
+        if let Some(fraction) = self.small_queue_fraction {
+            config.small_queue_percent = Some(small_queue_percent(fraction)?);
+        }
+        if let Some(percent) = config.small_queue_percent.filter(|p| !(1..=99).contains(p)) {
+            return Err(ConfigError::SmallQueueFraction(f64::from(percent) / 100.0));
+        }
+        Ok(config)
+    }
+
+    pub fn build(self) -> Result<TinyUFO<K, T>, ConfigError> {
+        let config = self.validate(false)?;
+        let mut cache = TinyUFO::from_config(&config);
+        if let Some(clock) = self.clock {
+            cache.set_clock(clock);
+        }
+        if let Some(events) = self.events {
+            cache.set_event_handler(Some(events));
+        }
+        if let Some(listener) = self.listener {
+            cache.set_eviction_listener(listener);
+        }
+        if let Some(weigher) = self.weigher {
+            cache.set_weigher(weigher);
+        }
+        if let Some(make) = self.admission {
+            cache.set_admission_policy(make());
+        }
+        if let Some(make) = self.eviction {
+            cache.set_eviction_policy(make());
+        }
+        Ok(cache)
+    }
+
+    /// Build a [`ConcurrentTinyUFO`], the weight limit and estimated items split over the
+    /// shards
+    pub fn build_concurrent(self) -> Result<ConcurrentTinyUFO<K, T>, ConfigError>
+    where
+        T: Clone,
+    {
+        let config = self.validate(true)?;
+        let mut cache = ConcurrentTinyUFO::from_config(&config);
+        if let Some(clock) = self.clock {
+            cache = cache.with_clock(clock);
+        }
+        if let Some(events) = self.events {
+            cache = cache.with_event_handler(events);
+        }
+        if let Some(listener) = self.listener {
+            cache = cache.with_listener(listener);
+        }
+        if let Some(weigher) = self.weigher {
+            cache = cache.with_shared_weigher(weigher);
+        }
+        if let Some(make) = self.admission {
+            cache = cache.with_boxed_admission_policy(make);
+        }
+        if let Some(make) = self.eviction {
+            cache = cache.with_boxed_eviction_policy(make);
+        }
+        Ok(cache)
+    }
+}
+
+/// `fraction` of a small queue as a percent, if in (0, 1): there is no main queue to promote to
+/// otherwise
+pub(crate) fn small_queue_percent(fraction: f64) -> Result<u8, ConfigError> {
+    if fraction > 0.0 && fraction < 1.0 {
+        Ok((fraction * 100.0).round().clamp(1.0, 99.0) as u8)
+    } else {
+        Err(ConfigError::SmallQueueFraction(fraction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_builder() {
+        let clock = Arc::new(ManualClock::new());
+        let mut cache: TinyUFO<u64, u64> = TinyUFO::builder()
+            .weight_limit(100)
+            .small_queue_fraction(0.2)
+            .time_to_idle(Duration::from_secs(1))
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        assert_eq!((cache.weight_limit(), cache.capacity()), (100, 100));
+        assert_eq!(cache.small_queue_percent(), 20);
+        cache.put(1, 1, 1);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&1), None);
+
+        let cache: ConcurrentTinyUFO<u64, u64> = TinyUFO::builder()
+            .weight_limit(100)
+            .estimated_items(50)
+            .shards(2)
+            .build_concurrent()
+            .unwrap();
+        assert_eq!(cache.shards(), 2);
+
+        // a limit in bytes doesn't size the cache for as many entries
+        let cache: TinyUFO<u64, u64> = TinyUFO::builder().weight_limit(1 << 30).build().unwrap();
+        assert_eq!(cache.capacity(), DEFAULT_ESTIMATED_ITEMS);
+
+        // the setters apply over a config
+        let config = CacheConfig {
+            ghost_queue: Some(false),
+            uses_cap: Some(3),
+            ..CacheConfig::new(100, 10)
+        };
+        let cache: TinyUFO<u64, u64> = TinyUFO::builder()
+            .config(config)
+            .uses_cap(2)
+            .time_to_idle(Duration::from_micros(1))
+            .build()
+            .unwrap();
+        assert!(!cache.has_ghost_queue());
+        assert_eq!((cache.capacity(), cache.uses_cap()), (10, 2));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let builder = || TinyUFO::<u64, u64>::builder().weight_limit(10);
+        let error = |builder: TinyUfoBuilder<u64, u64>| builder.build().err();
+        assert_eq!(
+            error(TinyUFO::builder().estimated_items(10)),
+            Some(ConfigError::NoWeightLimit)
+        );
+        assert_eq!(
+            error(builder().small_queue_fraction(1.5)),
+            Some(ConfigError::SmallQueueFraction(1.5))
+        );
+        assert_eq!(
+            error(builder().small_queue_fraction(1.0)),
+            Some(ConfigError::SmallQueueFraction(1.0))
+        );
+        assert_eq!(
+            error(builder().small_queue_fraction(f64::NAN)).map(|e| e.to_string()),
+            Some("small queue fraction NaN is not in (0, 1)".to_string())
+        );
+        assert_eq!(
+            builder().shards(0).build_concurrent().err(),
+            Some(ConfigError::ZeroShards)
+        );
+        assert!(builder().shards(0).build().is_ok());
+        let estimator = EstimatorConfig {
+            slots: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            error(builder().estimator(estimator)),
+            Some(ConfigError::EmptyEstimator)
+        );
+        assert_eq!(
+            error(builder().time_to_idle(Duration::ZERO)),
+            Some(ConfigError::ZeroTimeToIdle)
+        );
+        let config = CacheConfig {
+            small_queue_percent: Some(100),
+            ..CacheConfig::new(10, 10)
+        };
+        assert_eq!(
+            error(TinyUFO::builder().config(config)),
+            Some(ConfigError::SmallQueueFraction(1.0))
+        );
+        assert_eq!(error(builder().uses_cap(4)), Some(ConfigError::UsesCap(4)));
+        assert_eq!(
+            error(builder().uses_cap(2).promotion_threshold(2)),
+            Some(ConfigError::PromotionThreshold(2))
+        );
+        assert_eq!(
+            error(builder().estimated_items(usize::MAX)),
+            Some(ConfigError::TooManyItems(usize::MAX))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::tinyufo::{TinyUFO, USES_CAP};
+use crate::tinyufo::types::Weight;
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use t1ha::T1haBuildHasher;
+
+/// TinyUFO keeping the full keys, to tell colliding ones apart.
+///
+/// A [`TinyUFO`] only keeps the 64 bit hash of a key: two keys hashing alike share an entry and
+/// read each other's value, rare with t1ha but within reach of whoever picks the keys. This one
+/// keeps every key next to its value and checks it on reads, at the cost of the key's memory.
+/// The put of a colliding key still replaces the entry of the other one.
+pub struct CheckedTinyUFO<K, T, S = T1haBuildHasher> {
+    cache: TinyUFO<K, T, K, S>,
+}
+
+impl<K: Hash + Eq, T> CheckedTinyUFO<K, T> {
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::from_config(&CacheConfig::new(total_weight_limit, capacity))
+    }
+
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self::with_hasher(config, T1haBuildHasher::default())
+    }
+}
+
+impl<K: Hash + Eq, T, S: BuildHasher> CheckedTinyUFO<K, T, S> {
+    /// See [`TinyUFO::with_hasher`]
+    pub fn with_hasher(config: &CacheConfig, hasher: S) -> Self {
+        Self {
+            cache: TinyUFO::with_hasher(config, hasher),
+        }
+    }
+
+    /// Get a value from the cache, a colliding key is a miss
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hashed_key = self.cache.key_hash(key);
+        self.cache
+            .get_hashed(hashed_key, |cached| cached.borrow() == key)
+            .map(|(data, _)| data)
+    }
+
+    /// See [`TinyUFO::peek`]
+    pub fn peek<Q>(&self, key: &Q) -> Option<&T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (data, cached) = self.cache.peek_with_meta(key)?;
+        (cached.borrow() == key).then_some(data)
+    }
+
+    pub fn put(&mut self, key: K, weight: Weight, data: T) {
+        self.put_evicting(key, weight, data, |_, _| {});
+    }
+
+    /// See [`TinyUFO::put_evicting`], `on_evict` gets the evicted keys themselves
+    pub fn put_evicting(
+        &mut self,
+        key: K,
+        weight: Weight,
+        data: T,
+        mut on_evict: impl FnMut(K, T),
+    ) {
+        let hashed_key = self.cache.key_hash(&key);
+        self.cache
+            .put_hashed(hashed_key, weight, data, key, USES_CAP, |_, data, key| {
+                on_evict(key, data)
+            });
+    }
+
+    /// Remove a key from the cache, returns its data if it was cached. A colliding key is left
+    /// alone
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.peek(key)?;
+        self.cache.remove(key)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    /// Every key collides
+    #[derive(Default)]
+    struct Colliding;
+
+    impl Hasher for Colliding {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    #[test]
+    fn test_collisions() {
+        let config = CacheConfig::new(10, 10);
+        let hasher = BuildHasherDefault::<Colliding>::default();
+        let mut unchecked: TinyUFO<&str, u64, (), _> =
+            TinyUFO::with_hasher(&config, hasher.clone());
+        unchecked.put("a", 1, 1);
+        assert_eq!(unchecked.get("b"), Some(&1));
+
+        let mut cache = CheckedTinyUFO::with_hasher(&config, hasher);
+        cache.put("a", 1, 1);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.peek("b"), None);
+        assert_eq!(cache.remove("b"), None);
+        assert_eq!(cache.get("a"), Some(&1));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        cache.put("b", 1, 2);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.remove("b"), Some(2));
+
+        // evictions hand the keys back
+        let mut cache = CheckedTinyUFO::new(1, 1);
+        let mut evicted = vec![];
+        cache.put("a".to_string(), 1, 1);
+        cache.put_evicting("b".to_string(), 1, 2, |key, _| evicted.push(key));
+        assert_eq!(evicted, ["a"]);
+    }
+}
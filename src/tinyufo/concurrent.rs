@@ -0,0 +1,464 @@
+use crate::clock::Clock;
+use crate::tinyufo::admission::AdmissionPolicy;
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::events::{CacheEventHandler, EvictionListener, RemovalCause};
+use crate::tinyufo::eviction::EvictionPolicy;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::tinyufo::TinyUFO;
+use crate::tinyufo::types::{Key, Weigher, Weight};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use t1ha::T1haHasher;
+
+// shard selection must not reuse the map's hash, otherwise every key of a shard shares the
+// bits the map probes with
+const SHARD_SEED: u64 = 0x5348_4152_4453_4545;
+
+#[derive(Clone, Copy, Default)]
+struct ShardHasher;
+
+impl BuildHasher for ShardHasher {
+    type Hasher = T1haHasher;
+
+    fn build_hasher(&self) -> T1haHasher {
+        T1haHasher::with_seed(SHARD_SEED)
+    }
+}
+
+/// 4 shards per available core, 1 in deterministic mode so that runs don't depend on the machine
+fn default_shards() -> usize {
+    if crate::deterministic::is_enabled() {
+        return 1;
+    }
+    std::thread::available_parallelism().map_or(1, |n| n.get()) * 4
+}
+
+/// TinyUFO usable from many threads through `&self`.
+///
+/// Keys are spread over independently locked shards, each one a [`TinyUFO`] holding an equal
+/// part of the weight limit. Values are cloned out on `get`, wrap large values in an `Arc`.
+pub struct ConcurrentTinyUFO<K, T: Clone> {
+    shards: Box<[Mutex<TinyUFO<K, T>>]>,
+    total_weight_limit: AtomicUsize,
+}
+
+impl<K: Hash, T: Clone> ConcurrentTinyUFO<K, T> {
+    /// Create a cache with 4 shards per available core
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::from_config(&CacheConfig::new(total_weight_limit, capacity))
+    }
+
+    /// Create a cache with `shards` shards, fewer if the weight limit can't feed them all
+    pub fn with_shards(total_weight_limit: usize, capacity: usize, shards: usize) -> Self {
+        let mut config = CacheConfig::new(total_weight_limit, capacity);
+        config.shards = Some(shards);
+        Self::from_config(&config)
+    }
+
+    /// Create a cache tuned by `config`, its weight limit and capacity are split over the shards
+    pub fn from_config(config: &CacheConfig) -> Self {
+        let shards = config.shards.unwrap_or_else(default_shards);
+        let shards = shards.clamp(1, config.weight_limit.max(1));
+        let shard_config = CacheConfig {
+            weight_limit: config.weight_limit / shards,
+            capacity: config.capacity.div_ceil(shards),
+            shards: None,
+            ..config.clone()
+        };
+        let shards = (0..shards)
+            .map(|_| Mutex::new(TinyUFO::from_config(&shard_config)))
+            .collect();
+        Self {
+            shards,
+            total_weight_limit: AtomicUsize::new(config.weight_limit),
+        }
+    }
+
+    /// See [`TinyUFO::with_event_handler`], the shards share `handler`
+    pub fn with_event_handler(mut self, handler: Arc<dyn CacheEventHandler>) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_event_handler(Some(handler.clone()));
+        }
+        self
+    }
+
+    /// See [`TinyUFO::with_eviction_listener`], the shards share `listener` and call it while
+    /// locked
+    pub fn with_eviction_listener(
+        self,
+        listener: impl Fn(Key, &T, Weight, RemovalCause) + Send + Sync + 'static,
+    ) -> Self {
+        self.with_listener(Arc::new(listener))
+    }
+
+    pub(crate) fn with_listener(mut self, listener: EvictionListener<T>) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_eviction_listener(listener.clone());
+        }
+        self
+    }
+
+    /// See [`TinyUFO::with_admission_policy`], each shard with its own policy made by `make`
+    pub fn with_admission_policy<P: AdmissionPolicy + 'static>(self, make: impl Fn() -> P) -> Self {
+        self.with_boxed_admission_policy(|| Box::new(make()))
+    }
+
+    pub(crate) fn with_boxed_admission_policy(
+        mut self,
+        make: impl Fn() -> Box<dyn AdmissionPolicy>,
+    ) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_admission_policy(make());
+        }
+        self
+    }
+
+    /// See [`TinyUFO::with_eviction_policy`], each shard with its own policy made by `make`
+    pub fn with_eviction_policy<P: EvictionPolicy + 'static>(self, make: impl Fn() -> P) -> Self {
+        self.with_boxed_eviction_policy(|| Box::new(make()))
+    }
+
+    pub(crate) fn with_boxed_eviction_policy(
+        mut self,
+        make: impl Fn() -> Box<dyn EvictionPolicy>,
+    ) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_eviction_policy(make());
+        }
+        self
+    }
+
+    /// See [`TinyUFO::with_weigher`], the shards share `weigher`
+    pub fn with_weigher(self, weigher: impl Fn(&K, &T) -> Weight + Send + Sync + 'static) -> Self {
+        self.with_shared_weigher(Arc::new(weigher))
+    }
+
+    pub(crate) fn with_shared_weigher(mut self, weigher: Weigher<K, T>) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_weigher(weigher.clone());
+        }
+        self
+    }
+
+    /// See [`TinyUFO::with_clock`], the shards share `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_clock(clock.clone());
+        }
+        self
+    }
+
+    /// See [`TinyUFO::expire_after_access`]
+    pub fn expire_after_access(mut self, tti: Duration) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_time_to_idle(tti);
+        }
+        self
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> MutexGuard<'_, TinyUFO<K, T>> {
+        let index = ShardHasher.hash_one(key) as usize % self.shards.len();
+        // a panic while holding a shard can't leave it half updated in a way that matters to
+        // a cache, keep serving it
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Get a clone of the cached value.
+    pub fn get<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).get(key).cloned()
+    }
+
+    /// Get a clone of the cached value without it counting as an access
+    pub fn peek<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).peek(key).cloned()
+    }
+
+    /// Set a key-value pair in the cache, replacing the data if the key is already cached.
+    pub fn put(&self, key: K, weight: Weight, data: T) {
+        self.shard(&key).put(key, weight, data);
+    }
+
+    /// See [`TinyUFO::insert`]
+    pub fn insert(&self, key: K, data: T) {
+        self.shard(&key).insert(key, data);
+    }
+
+    /// See [`TinyUFO::get_or_insert_with`], `f` runs while the shard is locked so that
+    /// concurrent misses on a key compute it once
+    pub fn get_or_insert_with(&self, key: K, weight: Weight, f: impl FnOnce() -> T) -> T {
+        self.shard(&key).get_or_insert_with(key, weight, f).clone()
+    }
+
+    /// See [`TinyUFO::put_with_ttl`]
+    pub fn put_with_ttl(&self, key: K, weight: Weight, data: T, ttl: Duration) {
+        self.shard(&key).put_with_ttl(key, weight, data, ttl);
+    }
+
+    /// See [`TinyUFO::purge_expired`], the shards are purged one after the other
+    pub fn purge_expired(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .lock()
+                    .unwrap_or_else(|p| p.into_inner())
+                    .purge_expired()
+            })
+            .sum()
+    }
+
+    /// See [`TinyUFO::put_evicting`], `on_evict` runs while the shard is locked.
+    pub fn put_evicting(&self, key: K, weight: Weight, data: T, on_evict: impl FnMut(Key, T)) {
+        self.shard(&key).put_evicting(key, weight, data, on_evict);
+    }
+
+    /// See [`TinyUFO::put_returning_evicted`]
+    pub fn put_returning_evicted(&self, key: K, weight: Weight, data: T) -> Vec<(Key, T)> {
+        self.shard(&key).put_returning_evicted(key, weight, data)
+    }
+
+    /// See [`TinyUFO::uses`]
+    pub(crate) fn uses<Q>(&self, key: &Q) -> Option<u8>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).uses(key)
+    }
+
+    /// See [`TinyUFO::key_hash`]
+    pub(crate) fn key_hash<Q: Hash + ?Sized>(&self, key: &Q) -> Key {
+        self.shard(key).key_hash(key)
+    }
+
+    /// See [`TinyUFO::put_capped`]
+    pub(crate) fn put_capped(
+        &self,
+        key: K,
+        weight: Weight,
+        data: T,
+        uses_cap: u8,
+        mut on_evict: impl FnMut(Key, T),
+    ) {
+        self.shard(&key)
+            .put_capped(key, weight, data, (), uses_cap, |key, data, _| {
+                on_evict(key, data)
+            });
+    }
+
+    /// Remove a key from the cache, returns its data if it was cached.
+    pub fn remove<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shard(key).remove(key)
+    }
+
+    /// Statistics summed over all shards
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats::default();
+        for shard in self.shards.iter() {
+            stats += shard.lock().unwrap_or_else(|p| p.into_inner()).stats();
+        }
+        stats
+    }
+
+    /// The hottest keys of all shards, hottest first, see [`TinyUFO::hot_keys`]
+    pub fn hot_keys(&self) -> Vec<(Key, u64)> {
+        let mut counts: HashMap<Key, u64> = HashMap::new();
+        for shard in self.shards.iter() {
+            let hot_keys = shard.lock().unwrap_or_else(|p| p.into_inner()).hot_keys();
+            for (key, count) in hot_keys {
+                *counts.entry(key).or_default() += count;
+            }
+        }
+        let mut hot_keys: Vec<_> = counts.into_iter().collect();
+        hot_keys.sort_unstable_by_key(|&(_, count)| std::cmp::Reverse(count));
+        hot_keys
+    }
+
+    pub fn weight_limit(&self) -> usize {
+        self.total_weight_limit.load(Relaxed)
+    }
+
+    /// Change the weight limit without dropping the cache, see [`TinyUFO::set_weight_limit`].
+    ///
+    /// The shards are resized one after the other, `on_evict` runs while the shard is locked.
+    pub fn set_weight_limit(&self, total_weight_limit: usize, mut on_evict: impl FnMut(Key, T)) {
+        self.total_weight_limit.store(total_weight_limit, Relaxed);
+        let shard_limit = (total_weight_limit / self.shards.len()).max(1);
+        for shard in self.shards.iter() {
+            shard
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_weight_limit(shard_limit, &mut on_evict);
+        }
+    }
+
+    /// See [`TinyUFO::set_small_queue_percent`]
+    pub fn set_small_queue_percent(&self, percent: u8) {
+        for shard in self.shards.iter() {
+            shard
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_small_queue_percent(percent);
+        }
+    }
+
+    /// See [`TinyUFO::set_uses_cap`]
+    pub fn set_uses_cap(&self, cap: u8) {
+        for shard in self.shards.iter() {
+            shard
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_uses_cap(cap);
+        }
+    }
+
+    /// See [`TinyUFO::set_promotion_threshold`]
+    pub fn set_promotion_threshold(&self, threshold: u8) {
+        for shard in self.shards.iter() {
+            shard
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_promotion_threshold(threshold);
+        }
+    }
+
+    /// See [`TinyUFO::set_ghost_queue`]
+    pub fn set_ghost_queue(&self, enabled: bool) {
+        for shard in self.shards.iter() {
+            shard
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .set_ghost_queue(enabled);
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_sanity() {
+        let cache = ConcurrentTinyUFO::with_shards(100, 100, 4);
+        cache.put("a".to_string(), 1, 1);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.remove("a"), Some(1));
+        assert_eq!(cache.get("a"), None);
+        assert!(cache
+            .put_returning_evicted("b".to_string(), 1, 2)
+            .is_empty());
+        assert_eq!(cache.shards(), 4);
+    }
+
+    #[test]
+    fn test_threads() {
+        let cache = Arc::new(ConcurrentTinyUFO::with_shards(1000, 1000, 8));
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        cache.put(t * 1000 + i, 1, i);
+                        cache.get(&(t * 1000 + i / 2));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats.inserts, 4000);
+        assert!(stats.weight <= 1000);
+        assert_eq!(stats.entries as u64, 4000 - stats.evictions);
+    }
+
+    #[test]
+    fn test_tiny_limit() {
+        let cache: ConcurrentTinyUFO<u64, ()> = ConcurrentTinyUFO::with_shards(2, 10, 16);
+        assert_eq!(cache.shards(), 2);
+    }
+
+    #[test]
+    fn test_set_weight_limit() {
+        let cache = ConcurrentTinyUFO::with_shards(1000, 1000, 4);
+        for i in 0..1000u64 {
+            cache.put(i, 1, i);
+        }
+        let before = cache.stats();
+        let mut evicted = 0;
+        cache.set_weight_limit(400, |_, _| evicted += 1);
+        let stats = cache.stats();
+        assert_eq!(cache.weight_limit(), 400);
+        assert!(stats.weight <= 400);
+        assert_eq!(stats.entries + evicted, before.entries);
+        assert_eq!(stats.evictions, before.evictions + evicted as u64);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let cache = ConcurrentTinyUFO::with_shards(100, 100, 4).with_clock(clock.clone());
+        for i in 0..10u64 {
+            cache.put_with_ttl(i, 1, i, Duration::from_secs(1 + i % 2));
+        }
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&0), None);
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.purge_expired(), 4);
+        assert_eq!(cache.stats().expirations, 5);
+
+        let cache = ConcurrentTinyUFO::with_shards(100, 100, 4)
+            .with_clock(clock.clone())
+            .expire_after_access(Duration::from_secs(1));
+        cache.put(1, 1, 1);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&1), None);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::tinyufo::admission::{
+    AdmissionPolicy, AlwaysAdmit, ProbabilisticAdmission, SizeAwareAdmission, TinyLfuAdmission,
+};
+use crate::tinyufo::estimator::{Estimator, TinyLFU, WINDOW_PER_ENTRY};
+use crate::tinyufo::eviction::EvictionPolicy;
+use crate::tinyufo::lru::LruPolicy;
+use crate::tinyufo::types::Weight;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Tuning of a [`TinyUFO`](crate::tinyufo::TinyUFO) or
+/// [`ConcurrentTinyUFO`](crate::tinyufo::ConcurrentTinyUFO).
+///
+/// Plain data, with the `serde` feature it can be read straight from a service's config file:
+///
+/// ```toml
+/// weight_limit = 100_000
+/// capacity = 10_000
+/// shards = 16
+/// small_queue_percent = 20
+/// time_to_idle_ms = 300000
+/// admission = { policy = "tiny_lfu" }
+///
+/// [estimator]
+/// hashes = 4
+/// ```
+///
+/// The settings left unset keep the cache's defaults.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct CacheConfig {
+    /// Total weight of the cached entries
+    pub weight_limit: usize,
+    /// Expected number of cached entries, sizes the map and the estimator
+    pub capacity: usize,
+    /// Shards of a `ConcurrentTinyUFO`, 4 per core when unset. Ignored by `TinyUFO`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shards: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub estimator: EstimatorConfig,
+    /// See [`TinyUFO::set_small_queue_percent`](crate::tinyufo::TinyUFO::set_small_queue_percent)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub small_queue_percent: Option<u8>,
+    /// See [`TinyUFO::set_uses_cap`](crate::tinyufo::TinyUFO::set_uses_cap)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub uses_cap: Option<u8>,
+    /// See [`TinyUFO::set_promotion_threshold`](crate::tinyufo::TinyUFO::set_promotion_threshold)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub promotion_threshold: Option<u8>,
+    /// See [`TinyUFO::set_ghost_queue`](crate::tinyufo::TinyUFO::set_ghost_queue)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ghost_queue: Option<bool>,
+    /// Milliseconds an entry stays cached unread, see
+    /// [`TinyUFO::expire_after_access`](crate::tinyufo::TinyUFO::expire_after_access)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub time_to_idle_ms: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub admission: Option<AdmissionConfig>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub eviction: Option<EvictionConfig>,
+}
+
+impl CacheConfig {
+    pub fn new(weight_limit: usize, capacity: usize) -> Self {
+        Self {
+            weight_limit,
+            capacity,
+            shards: None,
+            estimator: EstimatorConfig::default(),
+            small_queue_percent: None,
+            uses_cap: None,
+            promotion_threshold: None,
+            ghost_queue: None,
+            time_to_idle_ms: None,
+            admission: None,
+            eviction: None,
+        }
+    }
+}
+
+/// One of the built-in [`AdmissionPolicy`]s, [`AlwaysAdmit`] when unset
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "policy", rename_all = "snake_case"))]
+pub enum AdmissionConfig {
+    Always,
+    TinyLfu,
+    Probabilistic { probability: f64 },
+    SizeAware { scale: Weight },
+}
+
+impl AdmissionConfig {
+    pub(crate) fn build(&self) -> Box<dyn AdmissionPolicy> {
+        match *self {
+            Self::Always => Box::new(AlwaysAdmit),
+            Self::TinyLfu => Box::new(TinyLfuAdmission),
+            Self::Probabilistic { probability } => Box::new(ProbabilisticAdmission { probability }),
+            Self::SizeAware { scale } => Box::new(SizeAwareAdmission { scale }),
+        }
+    }
+}
+
+/// One of the built-in eviction policies, S3-FIFO's queues when unset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "policy", rename_all = "snake_case"))]
+pub enum EvictionConfig {
+    S3Fifo,
+    Lru,
+}
+
+impl EvictionConfig {
+    /// None for the queues built into the cache
+    pub(crate) fn build(&self) -> Option<Box<dyn EvictionPolicy>> {
+        match self {
+            Self::S3Fifo => None,
+            Self::Lru => Some(Box::new(LruPolicy::default())),
+        }
+    }
+}
+
+/// Parameters of the frequency sketch, unset ones are derived from the capacity.
+///
+/// A `ConcurrentTinyUFO` gives each shard its own sketch with these parameters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct EstimatorConfig {
+    /// Rows (hash functions) of the Count-Min sketch
+    pub hashes: Option<usize>,
+    /// Counters per row, rounded up to a power of two
+    pub slots: Option<usize>,
+    /// Increments between two agings of the counters
+    pub window: Option<usize>,
+    /// Keep the first occurrence of a key in a window out of the counters, on when unset
+    pub doorkeeper: Option<bool>,
+    /// Seed of the hashes, random when unset. See [`TinyLFU::with_seed`]
+    pub seed: Option<u64>,
+    /// Track the hottest keys, this many per sketch, none when unset. See
+    /// [`TinyLFU::with_hot_keys`]
+    pub hot_keys: Option<usize>,
+}
+
+impl EstimatorConfig {
+    pub(crate) fn build(&self, capacity: usize) -> TinyLFU {
+        let (slots, hashes) = Estimator::optimal_params(capacity);
+        let estimator = Estimator::new(
+            self.hashes.unwrap_or(hashes).max(1),
+            self.slots.unwrap_or(slots).max(1),
+        );
+        let window = self.window.unwrap_or(capacity * WINDOW_PER_ENTRY);
+        let lfu = TinyLFU::with_estimator(estimator, window);
+        let lfu = if self.doorkeeper.unwrap_or(true) {
+            lfu.with_doorkeeper(window)
+        } else {
+            lfu
+        };
+        let lfu = match self.hot_keys {
+            Some(n) => lfu.with_hot_keys(n),
+            None => lfu,
+        };
+        match self.seed {
+            Some(seed) => lfu.with_seed(seed),
+            None => lfu,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::tinyufo::{ConcurrentTinyUFO, Key, TinyUFO};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_from_config() {
+        let mut config = CacheConfig::new(10, 10);
+        config.shards = Some(2);
+        config.estimator.hashes = Some(3);
+
+        let mut cache = TinyUFO::from_config(&config);
+        for i in 0..100 {
+            cache.put(i, 1, i);
+        }
+        assert!(cache.stats().weight <= 10);
+        let cache: ConcurrentTinyUFO<u64, u64> = ConcurrentTinyUFO::from_config(&config);
+        assert_eq!(cache.shards(), 2);
+
+        let evicted = |eviction| {
+            let config = CacheConfig {
+                eviction,
+                ..CacheConfig::new(3, 3)
+            };
+            let mut cache = TinyUFO::from_config(&config);
+            for i in 1..=3 {
+                cache.put(i, 1, i);
+            }
+            for i in (1..=3).rev() {
+                cache.get(&i);
+            }
+            let mut evicted = vec![];
+            cache.put_evicting(4, 1, 4, |_, data| evicted.push(data));
+            evicted
+        };
+        // the queues evict the first put, LRU the least recently read
+        assert_eq!(evicted(None), [1]);
+        assert_eq!(evicted(Some(EvictionConfig::Lru)), [3]);
+
+        let clock = Arc::new(ManualClock::new());
+        let config = CacheConfig {
+            small_queue_percent: Some(20),
+            uses_cap: Some(2),
+            promotion_threshold: Some(1),
+            ghost_queue: Some(false),
+            time_to_idle_ms: Some(1000),
+            admission: Some(AdmissionConfig::Probabilistic { probability: 0.0 }),
+            ..CacheConfig::new(2, 2)
+        };
+        let mut cache = TinyUFO::from_config(&config).with_clock(clock.clone());
+        assert_eq!(cache.small_queue_percent(), 20);
+        assert_eq!((cache.uses_cap(), cache.promotion_threshold()), (2, 1));
+        assert!(!cache.has_ghost_queue());
+        cache.put(1, 1, 1);
+        cache.put(2, 1, 2);
+        cache.put(3, 1, 3);
+        assert_eq!((cache.peek(&3), cache.stats().rejections), (None, 1));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn test_hot_keys() {
+        let mut config = CacheConfig::new(100, 100);
+        assert!(TinyUFO::<u64, u64>::from_config(&config)
+            .hot_keys()
+            .is_empty());
+
+        config.estimator.hot_keys = Some(4);
+        config.shards = Some(2);
+        let mut cache = TinyUFO::from_config(&config);
+        let concurrent = ConcurrentTinyUFO::from_config(&config);
+        // 1 and 2 are a third of the keys each, read whether cached or not
+        cache.put(1, 1, 1);
+        concurrent.put(1, 1, 1);
+        for i in 0..20 {
+            cache.get(&1);
+            cache.get(&2);
+            cache.put(100 + i, 1, i);
+            concurrent.get(&1);
+            concurrent.get(&2);
+            concurrent.put(100 + i, 1, i);
+        }
+        let hottest = |hot_keys: Vec<(Key, u64)>, mut expected: [Key; 2]| {
+            assert!(hot_keys.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+            let mut keys: Vec<Key> = hot_keys[..2].iter().map(|&(key, _)| key).collect();
+            keys.sort();
+            expected.sort();
+            assert_eq!(keys, expected);
+        };
+        assert_eq!(cache.hot_keys().len(), 4);
+        hottest(cache.hot_keys(), [1, 2].map(|key| cache.key_hash(&key)));
+        // the shards' keys merged
+        assert!(concurrent.hot_keys().len() <= 8);
+        hottest(
+            concurrent.hot_keys(),
+            [1, 2].map(|key| concurrent.key_hash(&key)),
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let config: CacheConfig = serde_json::from_str(
+            r#"{"weight_limit": 100, "capacity": 10, "estimator": {"slots": 64}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.shards, None);
+        assert_eq!(config.estimator.slots, Some(64));
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<CacheConfig>(&json).unwrap(), config);
+
+        let config: CacheConfig = serde_json::from_str(
+            r#"{"weight_limit": 100, "capacity": 10, "small_queue_percent": 20,
+                "uses_cap": 2, "promotion_threshold": 1, "ghost_queue": false,
+                "time_to_idle_ms": 5000, "admission": {"policy": "size_aware", "scale": 64},
+                "eviction": {"policy": "lru"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            (config.small_queue_percent, config.time_to_idle_ms),
+            (Some(20), Some(5000))
+        );
+        assert_eq!(
+            config.admission,
+            Some(AdmissionConfig::SizeAware { scale: 64 })
+        );
+        assert_eq!(config.eviction, Some(EvictionConfig::Lru));
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<CacheConfig>(&json).unwrap(), config);
+
+        assert!(serde_json::from_str::<CacheConfig>(
+            r#"{"weight_limit": 1, "capacity": 1, "admission": {"policy": "sometimes"}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<CacheConfig>(
+            r#"{"weight_limit": 1, "capacity": 1, "typo": 1}"#
+        )
+        .is_err());
+    }
+}
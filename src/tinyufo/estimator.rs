@@ -1,63 +1,144 @@
+use bit_vec::BitVec;
 use std::cmp;
 use std::cmp::max;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::sync::atomic::Ordering::{Acquire, Relaxed};
+use std::ops::Range;
+use std::sync::atomic::Ordering::Relaxed;
 use std::sync::atomic::{AtomicU8, AtomicUsize};
-use t1ha::T1haHasher;
+use t1ha::{T1ha2Hasher, T1haHasher};
 
 use crate::tinyufo::types::Key;
 
 /// Stores estimated frequency of items in the cache.
 ///
 /// Inner algorithm: Count-Min Sketch
-/// we limit frequency with 8 bit
+/// we limit frequency with 4 bit, two counters packed in each byte
+///
+/// The key is hashed once whatever the depth, to 128 bits split into `h1` and `h2`: row `i`
+/// takes the counter at `h1 + i * h2` (Kirsch–Mitzenmacher), as good as a hash per row for a
+/// Count-Min sketch.
 #[derive(Debug)]
 pub struct Estimator {
-    inner: Vec<(Vec<AtomicU8>, u64)>,
+    // the rows one after the other, `stride` bytes each
+    inner: Box<[AtomicU8]>,
+    depth: usize,
+    stride: usize,
+    // counters per row, a power of two, half as many bytes
+    slots: usize,
+    seeds: [u64; 2],
+}
+
+/// Highest frequency a counter of an [`Estimator`] reaches
+pub const COUNTER_MAX: u8 = 0x0F;
+
+/// Error of [`Estimator::merge`] given a sketch counting differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    /// The sketches don't have as many rows and counters per row
+    Dimensions,
+    /// The sketches hash keys from other seeds, see [`Estimator::with_seed`]
+    Seeds,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dimensions => f.write_str("the estimators have different dimensions"),
+            Self::Seeds => f.write_str("the estimators have different seeds"),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+/// Feeds what a key hashes to a [`T1ha2Hasher`], read back with its 128 bit `finish128`
+struct Hasher128(T1ha2Hasher);
+
+impl Hasher for Hasher128 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    // std's `finish` can't consume the state as t1ha2 needs to, only `finish128` is used
+    fn finish(&self) -> u64 {
+        unreachable!("a 128 bit hash is read with finish128")
+    }
 }
 
+// version of the `to_bytes` format, then depth, slots and both seeds as little endian u64s.
+// 2 since the keys are hashed once to 128 bits, the counters of version 1 are elsewhere
+const FORMAT: u8 = 2;
+const HEADER: usize = 1 + 4 * 8;
+
 impl Estimator {
     /// Create a new Count-Min Sketch with optimal parameters
     pub fn new_optimal(items: usize) -> Self {
         let (w, d) = Self::optimal_params(items);
-        Self::new(w, d)
+        Self::new(d, w)
     }
 
     /// Find optimal parameters for Count-Min Sketch
-    fn optimal_params(items: usize) -> (usize, usize) {
+    pub(crate) fn optimal_params(items: usize) -> (usize, usize) {
         // From https://en.wikipedia.org/wiki/Count%E2%80%93min_sketch
-        // w = ⌈e/ε⌉ and d = ⌈ln 1/δ⌉
+        // w = ⌈e/ε⌉ and d = ⌈ln 1/δ⌉, w rounded up to the power of two `new` makes it: a wider
+        // row only lowers the error
         let error_rate = 1.0 / (items as f64);
         let failure_rate = 1.0 / (items as f64);
-        let w = max(16, (std::f64::consts::E / error_rate).ceil() as usize);
+        let w = max(16, (std::f64::consts::E / error_rate).ceil() as usize).next_power_of_two();
         let d = max(2, (failure_rate.ln() / 0.5f64.ln()).ceil() as usize);
         (w, d)
     }
 
-    /// Create a new Count-Min Sketch with `hashes` hash functions and `slots` slots
+    /// Create a new Count-Min Sketch with `hashes` hash functions and `slots` slots, rounded up
+    /// to a power of two so that a hash is masked rather than divided into an index
     pub fn new(hashes: usize, slots: usize) -> Self {
-        let mut inner = Vec::with_capacity(hashes);
-        for _ in 0..hashes {
-            let mut slot = Vec::with_capacity(slots);
-            for _ in 0..slots {
-                slot.push(AtomicU8::new(0));
-            }
-            let seed = fastrand::u64(..);
-            inner.push((slot, seed))
+        let slots = slots.next_power_of_two();
+        let stride = slots.div_ceil(2);
+        let inner = (0..hashes * stride).map(|_| AtomicU8::new(0)).collect();
+        let seeds = [crate::deterministic::u64(), crate::deterministic::u64()];
+        Self {
+            inner,
+            depth: hashes,
+            stride,
+            slots,
+            seeds,
         }
+    }
+
+    /// Hash the keys from `seed` rather than from random seeds, for reproducible estimates.
+    /// See also [`crate::deterministic`], seeding every cache of a thread
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        self.seeds = [rng.u64(..), rng.u64(..)];
+        self
+    }
 
-        Self { inner }
+    /// The counters of `key`, one per row: the byte holding it and its shift in the byte
+    fn counters<H: Hash>(&self, key: &H) -> impl Iterator<Item = (&AtomicU8, u8)> {
+        let mut hasher = Hasher128(T1ha2Hasher::with_seeds(self.seeds[0], self.seeds[1]));
+        key.hash(&mut hasher);
+        let hash = hasher.0.finish128();
+        let (h1, h2) = (hash as u64, (hash >> 64) as u64);
+        // odd, so that the rows of a key differ: it is coprime with the width
+        let h2 = h2 | 1;
+        let mask = self.slots as u64 - 1;
+        (0..self.depth).map(move |row| {
+            let index = (h1.wrapping_add((row as u64).wrapping_mul(h2)) & mask) as usize;
+            (
+                &self.inner[row * self.stride + index / 2],
+                (index % 2) as u8 * 4,
+            )
+        })
     }
 
     /// Get the estimated frequency of the `key`
     pub fn get<H: Hash>(&self, key: H) -> u8 {
-        let mut min = u8::MAX;
-        for (slot, seed) in &self.inner {
-            let mut hasher = T1haHasher::with_seed(*seed);
-            key.hash(&mut hasher);
-            let hash = hasher.finish() as usize % slot.len();
-            let current = &slot[hash];
-            let value = current.load(Relaxed);
+        let mut min = COUNTER_MAX;
+        for (counter, shift) in self.counters(&key) {
+            let value = (counter.load(Relaxed) >> shift) & COUNTER_MAX;
             min = cmp::min(min, value);
         }
         min
@@ -65,74 +146,395 @@ impl Estimator {
 
     /// Increment the frequency of the `key`
     ///
-    /// Returns the min of all the frequencies of different hash seeds
+    /// Returns the min of the counters of the key, one per row
     pub fn incr<H: Hash>(&mut self, key: H) -> u8 {
-        let mut min = u8::MAX;
-        for (slot, seed) in &self.inner {
-            let mut hasher = T1haHasher::with_seed(*seed);
-            key.hash(&mut hasher);
-            let hash = hasher.finish() as usize % slot.len();
-            let current = &slot[hash];
-            let new = Self::incr_no_overflow(current);
-            min = cmp::min(u8::MAX, new);
+        let mut min = COUNTER_MAX;
+        for (counter, shift) in self.counters(&key) {
+            let new = Self::incr_no_overflow(counter, shift);
+            min = cmp::min(min, new);
         }
         min
     }
 
     /// Age, shift right all counters by `shift` bits
     pub fn age(&mut self, shift: u8) {
-        for (slot, _) in &self.inner {
-            for counter in slot {
-                let value = counter.load(Relaxed);
-                counter.store(value >> shift, Relaxed);
+        for row in 0..self.depth() {
+            self.age_row(row, shift);
+        }
+    }
+
+    /// Age a single row (hash function) of the sketch, see [`Self::age`]
+    pub fn age_row(&mut self, row: usize, shift: u8) {
+        self.age_bytes(row * self.stride..(row + 1) * self.stride, shift);
+    }
+
+    /// Age the counters in `bytes` of the rows laid one after the other
+    fn age_bytes(&mut self, bytes: Range<usize>, shift: u8) {
+        let shift = shift.min(4);
+        // both counters at once, the bits shifted out of the high one cleared from the low one
+        let mask = 0x11 * (COUNTER_MAX >> shift);
+        let counters = &mut self.inner[bytes];
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is available
+            unsafe { avx2::age(counters, shift, mask) };
+            return;
+        }
+        age_scalar(counters, shift, mask);
+    }
+
+    /// Number of rows (hash functions) of the sketch
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Add the counters of `other` to these ones, saturating, for the frequencies over both
+    /// streams of keys. Both sketches must have the same dimensions and seeds
+    pub fn merge(&mut self, other: &Estimator) -> Result<(), MergeError> {
+        if (self.depth, self.slots) != (other.depth, other.slots) {
+            return Err(MergeError::Dimensions);
+        }
+        if self.seeds != other.seeds {
+            return Err(MergeError::Seeds);
+        }
+        for (counter, other) in self.inner.iter().zip(other.inner.iter()) {
+            let (a, b) = (counter.load(Relaxed), other.load(Relaxed));
+            let low = ((a & COUNTER_MAX) + (b & COUNTER_MAX)).min(COUNTER_MAX);
+            let high = ((a >> 4) + (b >> 4)).min(COUNTER_MAX);
+            counter.store(high << 4 | low, Relaxed);
+        }
+        Ok(())
+    }
+
+    /// The dimensions, seeds and counters of the sketch, to restore it with [`Self::from_bytes`]
+    /// after a restart instead of learning the frequencies again
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER + self.inner.len());
+        bytes.push(FORMAT);
+        for field in [
+            self.depth as u64,
+            self.slots as u64,
+            self.seeds[0],
+            self.seeds[1],
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend(self.inner.iter().map(|counter| counter.load(Relaxed)));
+        bytes
+    }
+
+    /// The sketch saved by [`Self::to_bytes`], None if `bytes` aren't one
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&format, header) = bytes.get(..HEADER)?.split_first()?;
+        let mut fields = header
+            .chunks_exact(8)
+            .map(|field| u64::from_le_bytes(field.try_into().unwrap()));
+        let mut next = || fields.next().unwrap();
+        let (depth, slots) = (usize::try_from(next()).ok()?, usize::try_from(next()).ok()?);
+        let seeds = [next(), next()];
+        let stride = slots.div_ceil(2);
+        let counters = &bytes[HEADER..];
+        // a sketch without counters would estimate every key at the max
+        if format != FORMAT
+            || depth == 0
+            || slots == 0
+            || !slots.is_power_of_two()
+            || depth.checked_mul(stride) != Some(counters.len())
+        {
+            return None;
+        }
+        Some(Self {
+            inner: counters
+                .iter()
+                .map(|&counter| AtomicU8::new(counter))
+                .collect(),
+            depth,
+            stride,
+            slots,
+            seeds,
+        })
+    }
+
+    #[cfg(test)]
+    fn row(&self, row: usize) -> &[AtomicU8] {
+        &self.inner[row * self.stride..(row + 1) * self.stride]
+    }
+
+    /// Increment the counter at `shift` in `byte` without overflowing, returns its new value
+    ///
+    /// Counters are independent approximate values that guard no other memory, Relaxed is enough.
+    /// The CAS is on the whole byte, a racing increment of the other counter retries it.
+    fn incr_no_overflow(byte: &AtomicU8, shift: u8) -> u8 {
+        let previous = byte
+            .fetch_update(Relaxed, Relaxed, |value| {
+                ((value >> shift) & COUNTER_MAX < COUNTER_MAX).then(|| value + (1 << shift))
+            })
+            .unwrap_or_else(|value| value);
+        (((previous >> shift) & COUNTER_MAX) + 1).min(COUNTER_MAX)
+    }
+}
+
+/// Shift the counters in `bytes` right by `shift`, keeping the bits of `mask`. Plain loads and
+/// stores through `&mut`, which the compiler vectorizes
+fn age_scalar(bytes: &mut [AtomicU8], shift: u8, mask: u8) {
+    for counter in bytes {
+        let value = counter.get_mut();
+        *value = (*value >> shift) & mask;
+    }
+}
+
+/// [`age_scalar`] 32 bytes at a time
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod avx2 {
+    use std::arch::x86_64::*;
+    use std::sync::atomic::AtomicU8;
+
+    /// # Safety
+    ///
+    /// The CPU must support AVX2
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn age(bytes: &mut [AtomicU8], shift: u8, mask: u8) {
+        let mut chunks = bytes.chunks_exact_mut(32);
+        let count = _mm_cvtsi32_si128(shift as i32);
+        let mask_bytes = _mm256_set1_epi8(mask as i8);
+        for chunk in &mut chunks {
+            // AtomicU8 has the layout of a u8, and `&mut` rules out concurrent accesses
+            let chunk = chunk.as_mut_ptr().cast::<__m256i>();
+            // SAFETY: 32 bytes, unaligned loads and stores
+            unsafe {
+                // 16 bit lanes: the bits a low byte gets from the high one are masked out
+                let value = _mm256_srl_epi16(_mm256_loadu_si256(chunk), count);
+                _mm256_storeu_si256(chunk, _mm256_and_si256(value, mask_bytes));
             }
         }
+        super::age_scalar(chunks.into_remainder(), shift, mask);
+    }
+}
+
+/// Increments between two agings per cached entry (heuristic)
+pub(crate) const WINDOW_PER_ENTRY: usize = 8;
+
+/// Bytes of the sketch aged per `incr` while an aging pass is in progress, a cache line. More
+/// if the pass wouldn't end within a window otherwise
+const AGE_BYTES_PER_OP: usize = 64;
+
+// A bit more than 2% false positives for a window of distinct keys
+const DOORKEEPER_BITS_PER_KEY: usize = 8;
+const DOORKEEPER_HASHES: usize = 4;
+
+/// Bloom filter of the keys seen once in the current window
+struct Doorkeeper {
+    bits: BitVec,
+    seed: u64,
+}
+
+impl Doorkeeper {
+    fn new(keys: usize) -> Self {
+        Self {
+            bits: BitVec::from_elem((keys * DOORKEEPER_BITS_PER_KEY).max(64), false),
+            seed: crate::deterministic::u64(),
+        }
+    }
+
+    /// Bits of `key`, derived from the two halves of one hash
+    fn positions(&self, key: Key) -> [usize; DOORKEEPER_HASHES] {
+        let mut hasher = T1haHasher::with_seed(self.seed);
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (low, high) = (hash & u32::MAX as u64, hash >> 32);
+        let len = self.bits.len() as u64;
+        std::array::from_fn(|i| (low.wrapping_add(high.wrapping_mul(i as u64)) % len) as usize)
+    }
+
+    fn contains(&self, key: Key) -> bool {
+        self.positions(key).iter().all(|&bit| self.bits[bit])
+    }
+
+    /// Add `key`, returns whether it was already there
+    fn insert(&mut self, key: Key) -> bool {
+        let mut present = true;
+        for bit in self.positions(key) {
+            present &= self.bits[bit];
+            self.bits.set(bit, true);
+        }
+        present
+    }
+}
+
+/// The most frequent keys, Space-Saving style: `capacity` candidates counted, a new key taking
+/// the place of the least frequent one and its count plus one. A count overestimates by the
+/// count inherited at most, a key more frequent than that is always kept.
+struct HotKeys {
+    capacity: usize,
+    counts: BTreeMap<Key, u64>,
+    // the candidates by count, least frequent first
+    by_count: BTreeSet<(u64, Key)>,
+}
+
+impl HotKeys {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counts: BTreeMap::new(),
+            by_count: BTreeSet::new(),
+        }
     }
 
-    /// Increment the frequency of the key without overflowing
-    fn incr_no_overflow(counter: &AtomicU8) -> u8 {
-        let mut value = counter.load(Relaxed);
-        loop {
-            if value == u8::MAX {
-                return value;
+    fn record(&mut self, key: Key) {
+        let count = match self.counts.get(&key) {
+            Some(&count) => {
+                self.by_count.remove(&(count, key));
+                count + 1
             }
-            match counter.compare_exchange_weak(value, value + 1, Acquire, Relaxed) {
-                Ok(_) => return value,
-                Err(val) => value = val,
+            None if self.counts.len() < self.capacity => 1,
+            None => {
+                let Some((min, evicted)) = self.by_count.pop_first() else {
+                    return;
+                };
+                self.counts.remove(&evicted);
+                min + 1
             }
+        };
+        self.counts.insert(key, count);
+        self.by_count.insert((count, key));
+    }
+
+    /// Halve the counts with the sketch, so that keys cooling down make room
+    fn age(&mut self) {
+        for count in self.counts.values_mut() {
+            *count /= 2;
         }
+        self.by_count = self
+            .counts
+            .iter()
+            .map(|(&key, &count)| (count, key))
+            .collect();
     }
 }
 
-/// No doorkeeper LFU
+/// TinyLFU: a Count-Min sketch behind a doorkeeper
+///
+/// The doorkeeper, a Bloom filter, takes the first occurrence of each key in a window so that
+/// one-hit wonders never reach the counters, it is cleared as the window ends.
+///
+/// Aging is incremental: once the window is full, each following `incr` ages the next
+/// `AGE_BYTES_PER_OP` bytes of counters instead of sweeping the whole sketch at once, so that no
+/// operation pays for more than a slice of it however large the sketch.
 pub struct TinyLFU {
     estimator: Estimator,
+    doorkeeper: Option<Doorkeeper>,
+    hot_keys: Option<HotKeys>,
     window_counter: AtomicUsize,
     window_limit: usize,
+    // next byte to age, None when no aging pass is in progress
+    aging_at: Option<usize>,
+    age_bytes_per_op: usize,
 }
 
 impl TinyLFU {
     pub fn new(cache_size: usize) -> Self {
-        let estimator = Estimator::new_optimal(cache_size);
+        let window_limit = cache_size * WINDOW_PER_ENTRY;
+        Self::with_estimator(Estimator::new_optimal(cache_size), window_limit)
+            .with_doorkeeper(window_limit)
+    }
+
+    /// Count frequencies in `estimator`, aging it every `window_limit` increments. Without a
+    /// doorkeeper
+    pub fn with_estimator(estimator: Estimator, window_limit: usize) -> Self {
+        let age_bytes_per_op = estimator
+            .inner
+            .len()
+            .div_ceil(window_limit.max(1))
+            .max(AGE_BYTES_PER_OP);
         Self {
             window_counter: Default::default(),
-            window_limit: cache_size * 8, // heuristic
+            window_limit,
             estimator,
+            doorkeeper: None,
+            hot_keys: None,
+            aging_at: None,
+            age_bytes_per_op,
+        }
+    }
+
+    /// Put a doorkeeper sized for `keys` distinct keys a window in front of the counters
+    pub fn with_doorkeeper(mut self, keys: usize) -> Self {
+        self.doorkeeper = Some(Doorkeeper::new(keys));
+        self
+    }
+
+    /// Keep track of the `n` keys most incremented, see [`Self::hot_keys`]
+    pub fn with_hot_keys(mut self, n: usize) -> Self {
+        self.hot_keys = Some(HotKeys::new(n));
+        self
+    }
+
+    /// The hottest keys and their approximate increments, hottest first, aged as the counters.
+    /// Empty unless tracked with [`Self::with_hot_keys`]
+    pub fn hot_keys(&self) -> Vec<(Key, u64)> {
+        self.hot_keys.as_ref().map_or_else(Vec::new, |hot_keys| {
+            let by_count = hot_keys.by_count.iter().rev();
+            by_count.map(|&(count, key)| (key, count)).collect()
+        })
+    }
+
+    /// Seed the estimator, see [`Estimator::with_seed`], and the doorkeeper if it has one
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.estimator = self.estimator.with_seed(seed);
+        if let Some(doorkeeper) = &mut self.doorkeeper {
+            doorkeeper.seed = fastrand::Rng::with_seed(!seed).u64(..);
         }
+        self
     }
 
-    pub fn get(&mut self, key: Key) -> u8 {
-        self.estimator.get(key)
+    pub fn get(&self, key: Key) -> u8 {
+        let seen = self
+            .doorkeeper
+            .as_ref()
+            .is_some_and(|doorkeeper| doorkeeper.contains(key));
+        self.estimator.get(key).saturating_add(seen as u8)
     }
 
     pub fn incr(&mut self, key: Key) -> u8 {
         let current_window_counter = self.window_counter.fetch_add(1, Relaxed);
         if current_window_counter >= self.window_limit {
-            // reset the counter and age the estimator
+            // reset the counter and start aging the estimator, a pass still
+            // running from the previous window is finished first
             self.window_counter.store(0, Relaxed);
-            self.estimator.age(1);
+            self.age_bytes(usize::MAX);
+            self.aging_at = Some(0);
+            if let Some(doorkeeper) = &mut self.doorkeeper {
+                doorkeeper.bits.clear();
+            }
+            if let Some(hot_keys) = &mut self.hot_keys {
+                hot_keys.age();
+            }
+        }
+        self.age_bytes(self.age_bytes_per_op);
+        if let Some(hot_keys) = &mut self.hot_keys {
+            hot_keys.record(key);
         }
-        self.estimator.incr(key)
+        match self
+            .doorkeeper
+            .as_mut()
+            .map(|doorkeeper| doorkeeper.insert(key))
+        {
+            // first time in the window, only the doorkeeper counts it
+            Some(false) => self.estimator.get(key).saturating_add(1),
+            Some(true) => self.estimator.incr(key).saturating_add(1),
+            None => self.estimator.incr(key),
+        }
+    }
+
+    /// Continue the aging pass in progress for at most `bytes` bytes of counters
+    fn age_bytes(&mut self, bytes: usize) {
+        let Some(start) = self.aging_at else {
+            return;
+        };
+        let len = self.estimator.inner.len();
+        let end = start.saturating_add(bytes).min(len);
+        self.estimator.age_bytes(start..end, 1);
+        self.aging_at = (end < len).then_some(end);
     }
 }
 
@@ -144,7 +546,7 @@ mod tests {
     fn test_optimal_params() {
         let (slots, hashes) = Estimator::optimal_params(1_000_000);
         // just smoke check some standard input
-        assert_eq!(slots, 2718282);
+        assert_eq!(slots, 1 << 22);
         assert_eq!(hashes, 20);
     }
 
@@ -156,6 +558,120 @@ mod tests {
         assert_eq!(estimator.get(1), 1);
     }
 
+    #[test]
+    fn test_nibbles() {
+        // both counters in one byte
+        let mut estimator = Estimator::new(1, 2);
+        assert_eq!(estimator.row(0).len(), 1);
+        for _ in 0..20 {
+            estimator.incr(1);
+        }
+        assert_eq!(estimator.get(1), COUNTER_MAX);
+        let other = (2..).find(|key| estimator.get(key) == 0).unwrap();
+        assert_eq!(estimator.incr(other), 1);
+        assert_eq!(estimator.get(1), COUNTER_MAX);
+
+        estimator.age(1);
+        assert_eq!((estimator.get(1), estimator.get(other)), (7, 0));
+    }
+
+    #[test]
+    fn test_seed() {
+        let run = |seed| {
+            let mut lfu = TinyLFU::new(8).with_seed(seed);
+            let keys: Vec<Key> = (0..100).map(|key| key % 30).collect();
+            keys.iter().map(|&key| lfu.incr(key)).collect::<Vec<_>>()
+        };
+        assert_eq!(run(7), run(7));
+
+        let seeds = |seed| Estimator::new(2, 16).with_seed(seed).seeds;
+        assert_eq!(seeds(7), seeds(7));
+        assert_ne!(seeds(7), seeds(8));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut estimator = Estimator::new(3, 64);
+        for key in 0..100 {
+            for _ in 0..key % 5 {
+                estimator.incr(key);
+            }
+        }
+        let bytes = estimator.to_bytes();
+        let restored = Estimator::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.depth(), 3);
+        assert!((0..100).all(|key| restored.get(key) == estimator.get(key)));
+
+        assert!(Estimator::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Estimator::from_bytes(&bytes[..HEADER - 1]).is_none());
+        let mut other_format = bytes.clone();
+        other_format[0] += 1;
+        assert!(Estimator::from_bytes(&other_format).is_none());
+        // no rows, or rows without counters
+        for (depth, slots) in [(0u64, 64u64), (3, 0), (0, 0)] {
+            let mut empty = bytes[..HEADER].to_vec();
+            empty[1..9].copy_from_slice(&depth.to_le_bytes());
+            empty[9..17].copy_from_slice(&slots.to_le_bytes());
+            assert!(Estimator::from_bytes(&empty).is_none(), "{depth}x{slots}");
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let estimator = || Estimator::new(2, 16).with_seed(7);
+        let (mut a, mut b) = (estimator(), estimator());
+        for _ in 0..10 {
+            a.incr(1);
+            b.incr(1);
+        }
+        b.incr(2);
+        a.merge(&b).unwrap();
+        assert_eq!((a.get(1), a.get(2)), (COUNTER_MAX, 1));
+
+        assert_eq!(
+            a.merge(&Estimator::new(2, 32).with_seed(7)),
+            Err(MergeError::Dimensions)
+        );
+        assert_eq!(
+            a.merge(&Estimator::new(2, 16).with_seed(8)),
+            Err(MergeError::Seeds)
+        );
+    }
+
+    #[test]
+    fn test_hot_keys() {
+        assert!(TinyLFU::new(64).hot_keys().is_empty());
+
+        let mut lfu = TinyLFU::new(1000).with_hot_keys(4);
+        // 1 and 2 are a third of the keys each, more than one in 4: always kept
+        for i in 0..600 {
+            lfu.incr(1);
+            lfu.incr(2);
+            lfu.incr(100 + i);
+        }
+        let hot_keys = lfu.hot_keys();
+        assert_eq!(hot_keys.len(), 4);
+        let mut hottest: Vec<Key> = hot_keys[..2].iter().map(|&(key, _)| key).collect();
+        hottest.sort();
+        assert_eq!(hottest, [1, 2]);
+        assert!(hot_keys.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    }
+
+    #[test]
+    fn test_age() {
+        // more than a vector of counters, and a remainder
+        let mut estimator = Estimator::new(3, 32);
+        let bytes: Vec<u8> = (0..estimator.inner.len()).map(|i| (i * 37) as u8).collect();
+        for (counter, &byte) in estimator.inner.iter().zip(&bytes) {
+            counter.store(byte, Relaxed);
+        }
+        estimator.age(2);
+        for (counter, &byte) in estimator.inner.iter().zip(&bytes) {
+            let (high, low) = (byte >> 4, byte & COUNTER_MAX);
+            assert_eq!(counter.load(Relaxed), (high >> 2) << 4 | low >> 2);
+        }
+    }
+
     #[test]
     fn test_sanity_tinylfu() {
         let mut lfu = TinyLFU::new(64);
@@ -163,4 +679,54 @@ mod tests {
         lfu.incr(1);
         assert_eq!(lfu.get(1), 1);
     }
+
+    #[test]
+    fn test_tinylfu_amortized_aging() {
+        let row_sum = |lfu: &TinyLFU, row: usize| -> u32 {
+            lfu.estimator
+                .row(row)
+                .iter()
+                .map(|c| c.load(Relaxed))
+                .map(|c| (c >> 4) as u32 + (c & COUNTER_MAX) as u32)
+                .sum()
+        };
+
+        // no doorkeeper to take the first incr, rows of `AGE_BYTES_PER_OP` bytes
+        let mut lfu = TinyLFU::with_estimator(Estimator::new(2, 128), WINDOW_PER_ENTRY);
+        assert_eq!(lfu.age_bytes_per_op, AGE_BYTES_PER_OP);
+        for _ in 0..lfu.window_limit {
+            lfu.incr(1);
+        }
+        assert_eq!((row_sum(&lfu, 0), row_sum(&lfu, 1)), (8, 8));
+
+        // the window is full: this incr starts a pass and only ages the first row
+        lfu.incr(1);
+        assert_eq!((row_sum(&lfu, 0), row_sum(&lfu, 1)), (5, 9));
+        assert_eq!(lfu.aging_at, Some(AGE_BYTES_PER_OP));
+
+        lfu.incr(1);
+        assert_eq!((row_sum(&lfu, 0), row_sum(&lfu, 1)), (6, 5));
+        assert_eq!(lfu.aging_at, None);
+
+        // a pass still ends within a short window
+        let lfu = TinyLFU::with_estimator(Estimator::new(2, 1024), 4);
+        assert_eq!(lfu.age_bytes_per_op, 256);
+    }
+
+    #[test]
+    fn test_doorkeeper() {
+        let mut lfu = TinyLFU::new(4);
+        let key: Key = 1;
+        // a one-hit wonder stays out of the counters
+        assert_eq!(lfu.incr(key), 1);
+        assert_eq!((lfu.get(key), lfu.estimator.get(key)), (1, 0));
+        lfu.incr(key);
+        assert_eq!((lfu.get(key), lfu.estimator.get(key)), (2, 1));
+
+        // the window ends, the doorkeeper forgets
+        for other in 100..100 + lfu.window_limit as Key {
+            lfu.incr(other);
+        }
+        assert_eq!(lfu.get(key), lfu.estimator.get(key));
+    }
 }
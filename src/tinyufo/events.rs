@@ -0,0 +1,134 @@
+use crate::tinyufo::types::{Key, Weight};
+use std::sync::Arc;
+
+/// Why an entry left the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemovalCause {
+    /// Evicted to make room for a put
+    Size,
+    /// Evicted by a smaller weight limit
+    Resized,
+    /// Removed by the caller
+    Explicit,
+    /// Past its time to live
+    Expired,
+}
+
+/// Called with every entry that left the cache on its own, see
+/// [`TinyUFO::with_eviction_listener`](crate::tinyufo::TinyUFO::with_eviction_listener)
+pub type EvictionListener<T> = Arc<dyn Fn(Key, &T, Weight, RemovalCause) + Send + Sync>;
+
+/// Hooks on the lifecycle of the entries, for audit logs, secondary indexes or external
+/// refcounts that would otherwise wrap every call site.
+///
+/// Set with `with_event_handler`. Keys are the hashes the cache stores, see
+/// [`Key`]. The hooks run inline, while a [`ConcurrentTinyUFO`](crate::tinyufo::ConcurrentTinyUFO)
+/// shard is locked: keep them short and don't call back into the cache.
+pub trait CacheEventHandler: Send + Sync {
+    /// A key that wasn't cached was put
+    fn on_insert(&self, _key: Key, _weight: Weight) {}
+
+    /// A cached key was put again, `weight` is its new weight
+    fn on_update(&self, _key: Key, _weight: Weight) {}
+
+    fn on_hit(&self, _key: Key, _weight: Weight) {}
+
+    fn on_miss(&self, _key: Key) {}
+
+    /// An entry left the cache
+    fn on_evict(&self, _key: Key, _weight: Weight, _cause: RemovalCause) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tinyufo::{ConcurrentTinyUFO, TinyUFO};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl CacheEventHandler for Recorder {
+        fn on_insert(&self, _: Key, weight: Weight) {
+            self.0.lock().unwrap().push(format!("insert {weight}"));
+        }
+
+        fn on_update(&self, _: Key, weight: Weight) {
+            self.0.lock().unwrap().push(format!("update {weight}"));
+        }
+
+        fn on_hit(&self, _: Key, weight: Weight) {
+            self.0.lock().unwrap().push(format!("hit {weight}"));
+        }
+
+        fn on_miss(&self, _: Key) {
+            self.0.lock().unwrap().push("miss".to_string());
+        }
+
+        fn on_evict(&self, _: Key, weight: Weight, cause: RemovalCause) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("evict {weight} {cause:?}"));
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let recorder = Arc::new(Recorder::default());
+        let mut cache = TinyUFO::new(2, 2).with_event_handler(recorder.clone());
+        cache.put(1, 1, 1);
+        cache.put(1, 2, 1);
+        cache.get(&1);
+        cache.get(&2);
+        cache.put(2, 1, 2);
+        cache.remove(&2);
+        cache.set_weight_limit(0, |_, _| {});
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "insert 1",
+                "update 2",
+                "hit 2",
+                "miss",
+                "insert 1",
+                "evict 2 Size",
+                "evict 1 Explicit",
+            ]
+        );
+
+        let recorder = Arc::new(Recorder::default());
+        let cache =
+            ConcurrentTinyUFO::with_shards(100, 100, 4).with_event_handler(recorder.clone());
+        for i in 0..10 {
+            cache.put(i, 1, i);
+        }
+        cache.set_weight_limit(4, |_, _| {});
+        let events = recorder.0.lock().unwrap();
+        let resized = events.iter().filter(|e| *e == "evict 1 Resized").count();
+        assert_eq!(events.iter().filter(|e| *e == "insert 1").count(), 10);
+        assert_eq!(resized, 10 - cache.stats().entries);
+    }
+
+    #[test]
+    fn test_eviction_listener() {
+        let flushed = Arc::new(Mutex::new(vec![]));
+        let sink = flushed.clone();
+        let mut cache =
+            TinyUFO::new(2, 2).with_eviction_listener(move |_, data: &u64, weight, cause| {
+                sink.lock().unwrap().push((*data, weight, cause));
+            });
+        cache.put(1, 1, 1);
+        cache.put(2, 1, 2);
+        let mut evicted = vec![];
+        cache.put_evicting(3, 1, 3, |_, data| evicted.push(data));
+        cache.remove(&3);
+        cache.set_weight_limit(0, |_, _| {});
+
+        // the value still reaches the call's `on_evict`, removes aren't evictions
+        let flushed = flushed.lock().unwrap();
+        assert_eq!(flushed[0], (evicted[0], 1, RemovalCause::Size));
+        assert_eq!(flushed[1].2, RemovalCause::Resized);
+        assert_eq!(flushed.len(), 2);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::tinyufo::types::{Key, Weight};
+
+/// Decides which entry makes room for the others, in place of the S3-FIFO queues.
+///
+/// Set with [`TinyUFO::with_eviction_policy`](crate::tinyufo::TinyUFO::with_eviction_policy):
+/// the cache keeps its map, weight limit, stats and API, and only asks the policy for the keys
+/// to evict until the entries fit. S3-FIFO, built into the entries' state, is the default.
+pub trait EvictionPolicy: Send + Sync {
+    /// `key`, weighing `weight`, was cached. A key put again is removed and inserted again
+    fn insert(&mut self, key: Key, weight: Weight);
+
+    /// A cached `key` was read
+    fn access(&mut self, key: Key);
+
+    /// `key` left the cache other than through [`Self::evict`]: removed or expired
+    fn remove(&mut self, key: Key);
+
+    /// Forget the next key to evict and return it, None once empty. A key no longer cached is
+    /// skipped
+    fn evict(&mut self) -> Option<Key>;
+
+    /// The key [`Self::evict`] would return next, left in place. The admission policy weighs
+    /// new keys against it on every put that evicts, so it should be cheap
+    fn victim(&self) -> Option<Key>;
+
+    /// The keys in the order [`Self::evict`] would return them
+    fn eviction_order(&self) -> Vec<Key>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tinyufo::{ConcurrentTinyUFO, LruPolicy, TinyLfuAdmission, TinyUFO};
+    use std::collections::VecDeque;
+
+    /// Evict in put order, reads or not
+    #[derive(Default)]
+    struct Fifo(VecDeque<Key>);
+
+    impl EvictionPolicy for Fifo {
+        fn insert(&mut self, key: Key, _: Weight) {
+            self.0.push_back(key);
+        }
+
+        fn access(&mut self, _: Key) {}
+
+        fn remove(&mut self, key: Key) {
+            self.0.retain(|&queued| queued != key);
+        }
+
+        fn evict(&mut self) -> Option<Key> {
+            self.0.pop_front()
+        }
+
+        fn victim(&self) -> Option<Key> {
+            self.0.front().copied()
+        }
+
+        fn eviction_order(&self) -> Vec<Key> {
+            self.0.iter().copied().collect()
+        }
+    }
+
+    #[test]
+    fn test_eviction_policy() {
+        let mut cache = TinyUFO::new(3, 3);
+        cache.put(1, 1, 1);
+        // the entries already cached are handed over
+        let mut cache = cache.with_eviction_policy(Fifo::default());
+        for i in 2..=3 {
+            cache.put(i, 1, i);
+        }
+        // S3-FIFO would keep 1, read twice
+        cache.get(&1);
+        cache.get(&1);
+        let mut evicted = vec![];
+        cache.put_evicting(4, 2, 4, |_, data| evicted.push(data));
+        assert_eq!(evicted, [1, 2]);
+        cache.remove(&3);
+        assert_eq!((cache.len(), cache.total_weight()), (1, 2));
+        let order: Vec<_> = cache.iter_eviction_order().map(|(_, data)| *data).collect();
+        assert_eq!(order, [4]);
+        cache.check_invariants();
+
+        let cache = ConcurrentTinyUFO::with_shards(4, 4, 2).with_eviction_policy(Fifo::default);
+        for i in 0..10 {
+            cache.put(i, 1, i);
+        }
+        assert!(cache.stats().weight <= 4);
+    }
+
+    #[test]
+    fn test_admission_with_eviction_policy() {
+        // TinyLFU admission weighs new keys against the policy's victim
+        let mut fifo = TinyUFO::new(2, 2)
+            .with_admission_policy(TinyLfuAdmission)
+            .with_eviction_policy(Fifo::default());
+        let mut lru = TinyUFO::new(2, 2)
+            .with_admission_policy(TinyLfuAdmission)
+            .with_eviction_policy(LruPolicy::default());
+        for cache in [&mut fifo, &mut lru] {
+            cache.put(1, 1, 1);
+            cache.put(2, 1, 2);
+            for _ in 0..3 {
+                cache.get(&1);
+                cache.get(&2);
+            }
+            let mut rejected = vec![];
+            cache.put_evicting(3, 1, 3, |_, data| rejected.push(data));
+            assert_eq!(rejected, [3]);
+            assert_eq!((cache.peek(&1), cache.peek(&2)), (Some(&1), Some(&2)));
+
+            // admitted once as frequent as the victim, which is then evicted
+            for _ in 0..4 {
+                cache.get(&3);
+            }
+            let mut evicted = vec![];
+            cache.put_evicting(3, 1, 3, |_, data| evicted.push(data));
+            assert_eq!(evicted, [1]);
+            assert_eq!(cache.peek(&3), Some(&3));
+            cache.check_invariants();
+        }
+    }
+}
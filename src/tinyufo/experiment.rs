@@ -0,0 +1,279 @@
+use crate::tinyufo::concurrent::ConcurrentTinyUFO;
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::shadow::SampleHasher;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::tinyufo::TinyUFO;
+use crate::tinyufo::types::{hash_key, Key, Weight};
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Mutex, MutexGuard};
+
+/// A cache policy simulated on the hashes of sampled keys, without values
+pub trait Simulated: Send {
+    /// Whether `key` is cached, counting as a read
+    fn get(&mut self, key: Key) -> bool;
+    fn put(&mut self, key: Key, weight: Weight);
+    fn remove(&mut self, key: Key);
+}
+
+impl Simulated for TinyUFO<Key, ()> {
+    fn get(&mut self, key: Key) -> bool {
+        TinyUFO::get(self, &key).is_some()
+    }
+
+    fn put(&mut self, key: Key, weight: Weight) {
+        TinyUFO::put(self, key, weight, ());
+    }
+
+    fn remove(&mut self, key: Key) {
+        TinyUFO::remove(self, &key);
+    }
+}
+
+/// Hits of the two arms of an [`Experiment`] on the same sampled reads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExperimentStats {
+    /// Sampled reads
+    pub requests: u64,
+    pub control_hits: u64,
+    pub candidate_hits: u64,
+}
+
+impl ExperimentStats {
+    pub fn control_hit_ratio(&self) -> f64 {
+        ratio(self.control_hits, self.requests)
+    }
+
+    pub fn candidate_hit_ratio(&self) -> f64 {
+        ratio(self.candidate_hits, self.requests)
+    }
+
+    /// Hit ratio the candidate gains over the control, negative when it loses
+    pub fn lift(&self) -> f64 {
+        self.candidate_hit_ratio() - self.control_hit_ratio()
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+/// A/B test of a cache policy or configuration on a sample of the live keys.
+///
+/// The sampled reads and writes of a cache are mirrored into two shadows: the control, set up as
+/// the real cache, and the candidate. Both see exactly the same keys at the same scale, so their
+/// hit ratios compare without the bias sampling has against the real cache's.
+pub struct Experiment {
+    control: Mutex<Box<dyn Simulated>>,
+    candidate: Mutex<Box<dyn Simulated>>,
+    sampling: Key,
+    requests: AtomicU64,
+    control_hits: AtomicU64,
+    candidate_hits: AtomicU64,
+}
+
+impl Experiment {
+    /// Feed `control` and `candidate` 1 key in `sampling`, sized for that share of the traffic
+    pub fn new(control: Box<dyn Simulated>, candidate: Box<dyn Simulated>, sampling: u32) -> Self {
+        Self {
+            control: Mutex::new(control),
+            candidate: Mutex::new(candidate),
+            sampling: sampling.max(1) as Key,
+            requests: AtomicU64::new(0),
+            control_hits: AtomicU64::new(0),
+            candidate_hits: AtomicU64::new(0),
+        }
+    }
+
+    /// TinyUFO against TinyUFO, each arm tuned by its config scaled down to the sample
+    pub fn tinyufo(control: &CacheConfig, candidate: &CacheConfig, sampling: u32) -> Self {
+        let arm = |config: &CacheConfig| -> Box<dyn Simulated> {
+            let sampling = sampling.max(1) as usize;
+            let scaled = CacheConfig {
+                weight_limit: config.weight_limit.div_ceil(sampling),
+                capacity: config.capacity.div_ceil(sampling).max(1),
+                ..config.clone()
+            };
+            Box::new(TinyUFO::<Key, ()>::from_config(&scaled))
+        };
+        Self::new(arm(control), arm(candidate), sampling)
+    }
+
+    fn sampled<Q: Hash + ?Sized>(&self, key: &Q) -> Option<Key> {
+        let hash = hash_key(&SampleHasher, key);
+        hash.is_multiple_of(self.sampling).then_some(hash)
+    }
+
+    /// Count a read of `key` in both arms
+    pub fn get<Q: Hash + ?Sized>(&self, key: &Q) {
+        let Some(key) = self.sampled(key) else {
+            return;
+        };
+        self.requests.fetch_add(1, Relaxed);
+        if lock(&self.control).get(key) {
+            self.control_hits.fetch_add(1, Relaxed);
+        }
+        if lock(&self.candidate).get(key) {
+            self.candidate_hits.fetch_add(1, Relaxed);
+        }
+    }
+
+    pub fn put<Q: Hash + ?Sized>(&self, key: &Q, weight: Weight) {
+        if let Some(key) = self.sampled(key) {
+            lock(&self.control).put(key, weight);
+            lock(&self.candidate).put(key, weight);
+        }
+    }
+
+    pub fn remove<Q: Hash + ?Sized>(&self, key: &Q) {
+        if let Some(key) = self.sampled(key) {
+            lock(&self.control).remove(key);
+            lock(&self.candidate).remove(key);
+        }
+    }
+
+    pub fn stats(&self) -> ExperimentStats {
+        ExperimentStats {
+            requests: self.requests.load(Relaxed),
+            control_hits: self.control_hits.load(Relaxed),
+            candidate_hits: self.candidate_hits.load(Relaxed),
+        }
+    }
+
+    /// Start counting over, keeping the content of the arms
+    pub fn reset(&self) {
+        self.requests.store(0, Relaxed);
+        self.control_hits.store(0, Relaxed);
+        self.candidate_hits.store(0, Relaxed);
+    }
+}
+
+fn lock(arm: &Mutex<Box<dyn Simulated>>) -> MutexGuard<'_, Box<dyn Simulated>> {
+    arm.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// [`ConcurrentTinyUFO`] running an [`Experiment`] of another configuration on its traffic, to
+/// validate a change before switching to it.
+pub struct ExperimentedTinyUFO<K, T: Clone> {
+    cache: ConcurrentTinyUFO<K, T>,
+    experiment: Experiment,
+}
+
+impl<K: Hash, T: Clone> ExperimentedTinyUFO<K, T> {
+    /// A cache tuned by `config`, tried against `candidate` on 1 key in `sampling`
+    pub fn new(config: &CacheConfig, candidate: &CacheConfig, sampling: u32) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::from_config(config),
+            experiment: Experiment::tinyufo(config, candidate, sampling),
+        }
+    }
+
+    /// A cache tuned by `config`, with an experiment of any kind
+    pub fn with_experiment(config: &CacheConfig, experiment: Experiment) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::from_config(config),
+            experiment,
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.experiment.get(key);
+        self.cache.get(key)
+    }
+
+    pub fn put(&self, key: K, weight: Weight, data: T) {
+        self.experiment.put(&key, weight);
+        self.cache.put(key, weight, data);
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.experiment.remove(key);
+        self.cache.remove(key)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    pub fn experiment(&self) -> &Experiment {
+        &self.experiment
+    }
+
+    pub fn cache(&self) -> &ConcurrentTinyUFO<K, T> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps nothing
+    struct NoCache;
+
+    impl Simulated for NoCache {
+        fn get(&mut self, _: Key) -> bool {
+            false
+        }
+
+        fn put(&mut self, _: Key, _: Weight) {}
+
+        fn remove(&mut self, _: Key) {}
+    }
+
+    #[test]
+    fn test_experiment() {
+        let config = CacheConfig::new(500, 500);
+        let candidate = CacheConfig::new(2000, 2000);
+        let cache = ExperimentedTinyUFO::new(&config, &candidate, 4);
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..100_000 {
+            let key = rng.u64(0..2000);
+            if cache.get(&key).is_none() {
+                cache.put(key, 1, ());
+            }
+        }
+        let stats = cache.experiment().stats();
+        assert!((20_000..30_000).contains(&stats.requests), "{stats:?}");
+        // the bigger candidate holds the whole working set
+        assert!(stats.candidate_hit_ratio() > 0.95, "{stats:?}");
+        assert!(stats.lift() > 0.5, "{stats:?}");
+        let real = cache.stats().hit_ratio();
+        assert!(
+            (real - stats.control_hit_ratio()).abs() < 0.1,
+            "{real} {stats:?}"
+        );
+
+        cache.remove(&1);
+        cache.experiment().reset();
+        assert_eq!(cache.experiment().stats(), ExperimentStats::default());
+
+        let control = Box::new(TinyUFO::<Key, ()>::new(10, 10));
+        let experiment = Experiment::new(control, Box::new(NoCache), 1);
+        for _ in 0..10 {
+            experiment.put(&1, 1);
+            experiment.get(&1);
+        }
+        assert_eq!(
+            experiment.stats(),
+            ExperimentStats {
+                requests: 10,
+                control_hits: 10,
+                candidate_hits: 0
+            }
+        );
+    }
+}
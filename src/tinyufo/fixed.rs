@@ -0,0 +1,518 @@
+use crate::tinyufo::builder::{small_queue_percent, ConfigError};
+use crate::tinyufo::stats::{CacheStats, Stats};
+use crate::tinyufo::types::{fold_key, hash_key, units, Key, Weight};
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use t1ha::T1haBuildHasher;
+
+const USES_CAP: u8 = 3;
+const DEFAULT_SMALL_QUEUE_PERCENT: usize = 10;
+
+// Rows of the sketch, each one is N counters
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_SEEDS: [u64; SKETCH_DEPTH] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0x27d4_eb2f_1656_67c5,
+];
+// Increments between two agings per cached entry, same heuristic as TinyLFU
+const WINDOW_PER_ENTRY: usize = 8;
+
+struct Slot<V> {
+    key: Key,
+    data: V,
+    weight: Weight,
+    uses: u8,
+    main: bool,
+}
+
+/// FIFO of hashed keys backed by an array
+struct Ring<const N: usize> {
+    keys: [Key; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Self {
+            keys: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push_back(&mut self, key: Key) {
+        debug_assert!(self.len < N, "ring overflow");
+        self.keys[(self.head + self.len) % N] = key;
+        self.len += 1;
+    }
+
+    fn pop_front(&mut self) -> Option<Key> {
+        if self.len == 0 {
+            return None;
+        }
+        let key = self.keys[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(key)
+    }
+
+    /// Remove `key` wherever it is, O(N)
+    fn remove(&mut self, key: Key) {
+        let Some(position) = (0..self.len).position(|i| self.keys[(self.head + i) % N] == key)
+        else {
+            return;
+        };
+        for i in position..self.len - 1 {
+            self.keys[(self.head + i) % N] = self.keys[(self.head + i + 1) % N];
+        }
+        self.len -= 1;
+    }
+}
+
+/// TinyUFO with a capacity fixed at compile time, for embedded and real-time use.
+///
+/// Entries, queues and the frequency sketch are arrays stored inline: nothing is allocated after
+/// construction and every operation is bounded by `N`. Place it in a `Box` or a `static` if it is
+/// too large for the stack.
+///
+/// Entries live in an open addressing table of `N` slots which is kept at most 7/8 full, so up to
+/// `N - N / 8` entries are cached, fewer if the weight limit is reached first. The sketch is
+/// 4 rows of `N` counters, much smaller than the one [`TinyUFO`](crate::tinyufo::TinyUFO) sizes
+/// for the same capacity. It counts reads and the puts of new keys, a new key it counted more
+/// than once before going straight to the main queue, as a `TinyUFO` without ghost queue does.
+pub struct FixedTinyUfo<K, V, const N: usize> {
+    table: [Option<Slot<V>>; N],
+    len: usize,
+    small: Ring<N>,
+    main: Ring<N>,
+    small_weight: usize,
+    main_weight: usize,
+    small_weight_limit: usize,
+    small_queue_percent: usize,
+    total_weight_limit: usize,
+
+    sketch: [[u8; N]; SKETCH_DEPTH],
+    window_counter: usize,
+    // next row to age, None when no aging pass is in progress
+    aging_row: Option<usize>,
+
+    hasher: T1haBuildHasher,
+    stats: Stats,
+    _k: PhantomData<K>,
+}
+
+impl<K: Hash, V, const N: usize> FixedTinyUfo<K, V, N> {
+    const EMPTY: Option<Slot<V>> = None;
+
+    pub fn new(total_weight_limit: usize) -> Self {
+        assert!(N > 0, "FixedTinyUfo needs at least one slot");
+        Self {
+            table: [Self::EMPTY; N],
+            len: 0,
+            small: Ring::new(),
+            main: Ring::new(),
+            small_weight: 0,
+            main_weight: 0,
+            small_weight_limit: small_weight_limit(total_weight_limit, DEFAULT_SMALL_QUEUE_PERCENT),
+            small_queue_percent: DEFAULT_SMALL_QUEUE_PERCENT,
+            total_weight_limit,
+            sketch: [[0; N]; SKETCH_DEPTH],
+            window_counter: 0,
+            aging_row: None,
+            hasher: T1haBuildHasher::default(),
+            stats: Stats::default(),
+            _k: PhantomData,
+        }
+    }
+
+    /// Give `fraction` of the weight and the slots to the small queue, in (0, 1). 10% by
+    /// default, more lets bursts of one-hit wonders pass without flushing the main queue
+    pub fn with_small_queue_fraction(mut self, fraction: f64) -> Result<Self, ConfigError> {
+        self.small_queue_percent = small_queue_percent(fraction)?.into();
+        self.small_weight_limit =
+            small_weight_limit(self.total_weight_limit, self.small_queue_percent);
+        Ok(self)
+    }
+
+    /// Maximum number of entries
+    pub const fn capacity() -> usize {
+        if N - N / 8 == 0 {
+            1
+        } else {
+            N - N / 8
+        }
+    }
+
+    /// Get a value from the cache.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hashed_key = hash_key(&self.hasher, key);
+        self.incr_frequency(hashed_key);
+        match self.find(hashed_key) {
+            Some(index) => {
+                self.stats.record_hit();
+                let slot = self.table[index].as_mut()?;
+                slot.uses = (slot.uses + 1).min(USES_CAP);
+                Some(&slot.data)
+            }
+            None => {
+                self.stats.record_miss();
+                None
+            }
+        }
+    }
+
+    /// Look at a value without it counting as an access
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let index = self.find(hash_key(&self.hasher, key))?;
+        self.table[index].as_ref().map(|slot| &slot.data)
+    }
+
+    /// Set a key-value pair in the cache, replacing the data if the key is already cached.
+    pub fn put(&mut self, key: K, weight: Weight, data: V) {
+        self.put_evicting(key, weight, data, |_, _| {});
+    }
+
+    /// Same as [`Self::put`] but hands the hashed key and data of every entry evicted to make
+    /// room to `on_evict`.
+    pub fn put_evicting(
+        &mut self,
+        key: K,
+        weight: Weight,
+        data: V,
+        mut on_evict: impl FnMut(Key, V),
+    ) {
+        let hashed_key = hash_key(&self.hasher, &key);
+        let evictions;
+        if let Some(index) = self.find(hashed_key) {
+            let Some(slot) = self.table[index].as_mut() else {
+                unreachable!();
+            };
+            let queue_weight = if slot.main {
+                &mut self.main_weight
+            } else {
+                &mut self.small_weight
+            };
+            *queue_weight = *queue_weight - units(slot.weight) + units(weight);
+            slot.weight = weight;
+            slot.data = data;
+            slot.uses = (slot.uses + 1).min(USES_CAP);
+            self.stats.record_update();
+            evictions = self.evict_until(0, &mut on_evict);
+        } else {
+            evictions = self.evict_until(units(weight), &mut on_evict);
+            // put or read often enough before, no need to prove itself in the small queue
+            let main = self.estimate(hashed_key) > 1;
+            self.incr_frequency(hashed_key);
+            self.insert_slot(Slot {
+                key: hashed_key,
+                data,
+                weight,
+                uses: 1,
+                main,
+            });
+            if main {
+                self.main.push_back(hashed_key);
+                self.main_weight += units(weight);
+            } else {
+                self.small.push_back(hashed_key);
+                self.small_weight += units(weight);
+            }
+            self.stats.record_insert();
+        }
+        self.stats.record_evictions(evictions);
+    }
+
+    /// Remove a key from the cache, returns its data if it was cached.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hashed_key = hash_key(&self.hasher, key);
+        let index = self.find(hashed_key)?;
+        let slot = self.remove_slot(index);
+        if slot.main {
+            self.main.remove(hashed_key);
+            self.main_weight -= units(slot.weight);
+        } else {
+            self.small.remove(hashed_key);
+            self.small_weight -= units(slot.weight);
+        }
+        self.stats.record_removal();
+        Some(slot.data)
+    }
+
+    /// Estimated frequency of `key`
+    pub fn frequency<Q>(&self, key: &Q) -> u8
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.estimate(hash_key(&self.hasher, key))
+    }
+
+    fn estimate(&self, key: Key) -> u8 {
+        (0..SKETCH_DEPTH)
+            .map(|row| self.sketch[row][sketch_index::<N>(key, row)])
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Snapshot of the cache statistics
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+            .snapshot(self.len, self.small_weight + self.main_weight)
+    }
+
+    fn find(&self, key: Key) -> Option<usize> {
+        let mut index = key as usize % N;
+        for _ in 0..N {
+            match &self.table[index] {
+                None => return None,
+                Some(slot) if slot.key == key => return Some(index),
+                Some(_) => index = (index + 1) % N,
+            }
+        }
+        None
+    }
+
+    fn insert_slot(&mut self, slot: Slot<V>) {
+        let mut index = slot.key as usize % N;
+        while self.table[index].is_some() {
+            index = (index + 1) % N;
+        }
+        self.table[index] = Some(slot);
+        self.len += 1;
+    }
+
+    /// Take the slot at `index` out, shifting back the probe chain behind it
+    fn remove_slot(&mut self, mut index: usize) -> Slot<V> {
+        let Some(slot) = self.table[index].take() else {
+            unreachable!("removing an empty slot");
+        };
+        self.len -= 1;
+        let mut next = index;
+        loop {
+            next = (next + 1) % N;
+            let Some(home) = self.table[next].as_ref().map(|s| s.key as usize % N) else {
+                break;
+            };
+            // the entry at `next` can fill the hole if its home isn't cyclically in (index, next]
+            let stays = if index <= next {
+                index < home && home <= next
+            } else {
+                index < home || home <= next
+            };
+            if !stays {
+                self.table[index] = self.table[next].take();
+                index = next;
+            }
+        }
+        slot
+    }
+
+    /// Evict until an entry of `weight` fits, returns the number of entries evicted
+    fn evict_until(&mut self, weight: usize, on_evict: &mut impl FnMut(Key, V)) -> u64 {
+        let mut evictions = 0;
+        while self.len >= Self::capacity()
+            || self.total_weight_limit
+                < (self.small_weight + self.main_weight).saturating_add(weight)
+        {
+            let Some(slot) = self.evict_one() else {
+                break;
+            };
+            on_evict(slot.key, slot.data);
+            evictions += 1;
+        }
+        evictions
+    }
+
+    /// Evict from small first then main
+    fn evict_one(&mut self) -> Option<Slot<V>> {
+        // the cache can be bounded by its slots rather than its weight, size small on both
+        let small_full = self.small_weight > self.small_weight_limit
+            || self.small.len > Self::capacity() * self.small_queue_percent / 100;
+        if small_full {
+            if let Some(slot) = self.evict_small() {
+                return Some(slot);
+            }
+        }
+        self.evict_main().or_else(|| self.evict_small())
+    }
+
+    fn evict_small(&mut self) -> Option<Slot<V>> {
+        loop {
+            let key = self.small.pop_front()?;
+            let index = self.find(key)?;
+            let Some(slot) = self.table[index].as_mut() else {
+                unreachable!();
+            };
+            if slot.uses > 1 {
+                slot.main = true;
+                self.small_weight -= units(slot.weight);
+                self.main_weight += units(slot.weight);
+                self.main.push_back(key);
+                continue;
+            }
+            let slot = self.remove_slot(index);
+            self.small_weight -= units(slot.weight);
+            return Some(slot);
+        }
+    }
+
+    fn evict_main(&mut self) -> Option<Slot<V>> {
+        loop {
+            let key = self.main.pop_front()?;
+            let index = self.find(key)?;
+            let Some(slot) = self.table[index].as_mut() else {
+                unreachable!();
+            };
+            if slot.uses > 0 {
+                slot.uses -= 1;
+                self.main.push_back(key);
+                continue;
+            }
+            let slot = self.remove_slot(index);
+            self.main_weight -= units(slot.weight);
+            return Some(slot);
+        }
+    }
+
+    fn incr_frequency(&mut self, key: Key) {
+        self.window_counter += 1;
+        if self.window_counter >= Self::capacity() * WINDOW_PER_ENTRY {
+            self.window_counter = 0;
+            // finish the previous pass before starting a new one
+            while self.aging_row.is_some() {
+                self.age_row();
+            }
+            self.aging_row = Some(0);
+        }
+        // amortized aging: one row per increment while a pass is in progress
+        self.age_row();
+        for row in 0..SKETCH_DEPTH {
+            let counter = &mut self.sketch[row][sketch_index::<N>(key, row)];
+            *counter = counter.saturating_add(1);
+        }
+    }
+
+    fn age_row(&mut self) {
+        let Some(row) = self.aging_row else {
+            return;
+        };
+        for counter in self.sketch[row].iter_mut() {
+            *counter >>= 1;
+        }
+        self.aging_row = (row + 1 < SKETCH_DEPTH).then_some(row + 1);
+    }
+}
+
+/// The share of `total_weight_limit` given to the small queue, in u128 so that no limit overflows
+fn small_weight_limit(total_weight_limit: usize, small_queue_percent: usize) -> usize {
+    (total_weight_limit as u128 * small_queue_percent as u128 / 100) as usize + 1
+}
+
+fn sketch_index<const N: usize>(key: Key, row: usize) -> usize {
+    let mixed = (fold_key(key) ^ SKETCH_SEEDS[row]).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (mixed >> 32) as usize % N
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanity() {
+        let mut cache: FixedTinyUfo<u64, u64, 16> = FixedTinyUfo::new(100);
+        assert_eq!(FixedTinyUfo::<u64, u64, 16>::capacity(), 14);
+        cache.put(1, 1, 10);
+        cache.put(1, 2, 11);
+        assert_eq!(cache.get(&1), Some(&11));
+        assert_eq!(cache.peek(&2), None);
+        assert_eq!(cache.stats().weight, 2);
+        assert_eq!(cache.remove(&1), Some(11));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut cache: FixedTinyUfo<u64, u64, 32> = FixedTinyUfo::new(1000);
+        let mut evicted = 0;
+        for i in 0..10_000u64 {
+            cache.put_evicting(i % 100, 1, i, |_, _| evicted += 1);
+            cache.get(&(i % 10));
+            if i % 7 == 0 {
+                cache.remove(&(i % 100));
+            }
+        }
+        let stats = cache.stats();
+        assert!(stats.entries <= 28);
+        assert_eq!(
+            stats.entries as u64,
+            stats.inserts - evicted - stats.removals
+        );
+        // the hot keys survive the churn
+        assert!((0..10).filter(|i| cache.peek(i).is_some()).count() >= 8);
+        assert!(cache.frequency(&50) > 0);
+    }
+
+    #[test]
+    fn test_weight_limit() {
+        let mut cache: FixedTinyUfo<u64, (), 64> = FixedTinyUfo::new(10);
+        for i in 0..100 {
+            cache.put(i, 3, ());
+        }
+        assert!(cache.stats().weight <= 10);
+    }
+
+    #[test]
+    fn test_small_queue_fraction() {
+        let cache = FixedTinyUfo::<u64, u64, 16>::new(100);
+        assert!(matches!(
+            cache.with_small_queue_fraction(1.0),
+            Err(ConfigError::SmallQueueFraction(_))
+        ));
+        let cache = FixedTinyUfo::<u64, u64, 16>::new(100)
+            .with_small_queue_fraction(0.5)
+            .unwrap();
+        assert_eq!(cache.small_weight_limit, 51);
+
+        let cache = FixedTinyUfo::<u64, u64, 16>::new(usize::MAX);
+        assert_eq!(cache.small_weight_limit, usize::MAX / 10 + 1);
+    }
+
+    #[test]
+    fn test_frequent_keys_to_main() {
+        let mut cache: FixedTinyUfo<u64, u64, 16> = FixedTinyUfo::new(100);
+        cache.put(1, 1, 1);
+        assert!(!cache.table.iter().flatten().any(|slot| slot.main));
+        // counted twice before: put, then read while missing
+        cache.remove(&1);
+        cache.get(&1);
+        cache.put(1, 1, 1);
+        assert!(cache.table.iter().flatten().any(|slot| slot.main));
+        assert_eq!((cache.main.len, cache.stats().weight), (1, 1));
+    }
+
+    #[test]
+    fn test_single_slot() {
+        let mut cache: FixedTinyUfo<&str, u8, 1> = FixedTinyUfo::new(10);
+        cache.put("a", 1, 1);
+        cache.put("b", 1, 2);
+        assert_eq!(cache.get("b"), Some(&2));
+        assert_eq!(cache.get("a"), None);
+    }
+}
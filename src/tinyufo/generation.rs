@@ -0,0 +1,168 @@
+use crate::tinyufo::concurrent::ConcurrentTinyUFO;
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::types::Weight;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, RwLock};
+
+#[derive(Clone)]
+struct Stamped<T> {
+    // the generations the entry belongs to, with their value at put time
+    stamps: Arc<[(Arc<AtomicU64>, u64)]>,
+    data: T,
+}
+
+/// [`ConcurrentTinyUFO`] whose entries can belong to named generations, e.g. `"prices"`: bumping
+/// a generation turns every entry put in an older one into a miss.
+///
+/// Unlike tags, nothing indexes the entries of a generation, a bump is O(1) and an entry only
+/// carries a counter and its value per generation. Stale entries are dropped when next read or
+/// evicted as usual. Generations are never forgotten, which suits a bounded set of names.
+pub struct GenerationalTinyUFO<K, T: Clone> {
+    cache: ConcurrentTinyUFO<K, Stamped<T>>,
+    generations: RwLock<HashMap<Box<str>, Arc<AtomicU64>>>,
+    stale: AtomicU64,
+}
+
+impl<K: Hash, T: Clone> GenerationalTinyUFO<K, T> {
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::from_config(&CacheConfig::new(total_weight_limit, capacity))
+    }
+
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::from_config(config),
+            generations: RwLock::default(),
+            stale: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a clone of the cached value, unless one of its generations was bumped since its put
+    pub fn get<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let stamped = self.cache.get(key)?;
+        if is_stale(&stamped.stamps) {
+            if self.cache.remove(key).is_some() {
+                self.stale.fetch_add(1, Relaxed);
+            }
+            return None;
+        }
+        Some(stamped.data)
+    }
+
+    /// Set a key-value pair outside of any generation
+    pub fn put(&self, key: K, weight: Weight, data: T) {
+        self.put_in(key, weight, data, &[]);
+    }
+
+    /// Set a key-value pair in the current `generations`, those not bumped yet are created
+    pub fn put_in(&self, key: K, weight: Weight, data: T, generations: &[&str]) {
+        let stamps = generations
+            .iter()
+            .map(|name| {
+                let counter = self.counter(name);
+                let at = counter.load(Relaxed);
+                (counter, at)
+            })
+            .collect();
+        self.cache.put(key, weight, Stamped { stamps, data });
+    }
+
+    /// Remove a key, returns its data if it was cached and fresh
+    pub fn remove<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let stamped = self.cache.remove(key)?;
+        (!is_stale(&stamped.stamps)).then_some(stamped.data)
+    }
+
+    /// Invalidate every entry put in generation `name` so far, returns the new generation
+    pub fn bump_generation(&self, name: &str) -> u64 {
+        self.counter(name).fetch_add(1, Relaxed) + 1
+    }
+
+    /// Current value of generation `name`, 0 until first bumped
+    pub fn generation(&self, name: &str) -> u64 {
+        self.generations
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+            .map_or(0, |counter| counter.load(Relaxed))
+    }
+
+    /// Statistics of the cache, a read finding a stale value counts as a miss
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = self.cache.stats();
+        let stale = self.stale.load(Relaxed);
+        stats.hits = stats.hits.saturating_sub(stale);
+        stats.misses += stale;
+        stats.removals = stats.removals.saturating_sub(stale);
+        stats
+    }
+
+    fn counter(&self, name: &str) -> Arc<AtomicU64> {
+        if let Some(counter) = self
+            .generations
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+        {
+            return counter.clone();
+        }
+        self.generations
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(name.into())
+            .or_default()
+            .clone()
+    }
+}
+
+fn is_stale(stamps: &[(Arc<AtomicU64>, u64)]) -> bool {
+    stamps
+        .iter()
+        .any(|(counter, at)| counter.load(Relaxed) != *at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump_generation() {
+        let cache = GenerationalTinyUFO::new(100, 100);
+        cache.put_in("eur", 1, 1, &["prices"]);
+        cache.put_in("usd", 1, 2, &["prices", "rates"]);
+        cache.put_in("fee", 1, 3, &["rates"]);
+        cache.put("name", 1, 4);
+        assert_eq!(cache.get("eur"), Some(1));
+        assert_eq!(cache.generation("prices"), 0);
+
+        assert_eq!(cache.bump_generation("prices"), 1);
+        assert_eq!(cache.get("eur"), None);
+        assert_eq!(cache.remove("usd"), None);
+        assert_eq!(cache.get("fee"), Some(3));
+        assert_eq!(cache.get("name"), Some(4));
+        // put again in the new generation
+        cache.put_in("eur", 1, 5, &["prices"]);
+        assert_eq!(cache.get("eur"), Some(5));
+
+        cache.bump_generation("rates");
+        assert_eq!(cache.get("fee"), None);
+        assert_eq!(cache.get("eur"), Some(5));
+        assert_eq!(cache.generation("rates"), 1);
+        assert_eq!(cache.generation("nope"), 0);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (5, 2));
+        assert_eq!(stats.entries, 2);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::tinyufo::concurrent::ConcurrentTinyUFO;
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::types::Weight;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{RwLock, RwLockReadGuard};
+use t1ha::T1haHasher;
+
+const PATH_SEED: u64 = 0x5041_5448_5345_4544;
+
+#[derive(Clone)]
+struct Stamped<T> {
+    // when the entry was put, older than an invalidation of one of its ancestors means stale
+    epoch: u64,
+    data: T,
+}
+
+/// [`ConcurrentTinyUFO`] of structured keys, paths of segments such as
+/// `["catalog", region, sku]`, which can be invalidated at any ancestor level at once.
+///
+/// Invalidating a prefix is O(1): it only records the prefix's generation, and entries put
+/// before it are dropped when next read. A read checks every ancestor of its key, O(depth).
+/// Every invalidated prefix is remembered, which suits a bounded set of ancestors like regions
+/// or tenants rather than one per key.
+pub struct HierarchicalTinyUFO<T: Clone> {
+    cache: ConcurrentTinyUFO<u64, Stamped<T>>,
+    // hash of an invalidated prefix to the epoch it was invalidated at
+    generations: RwLock<HashMap<u64, u64>>,
+    epoch: AtomicU64,
+    stale: AtomicU64,
+}
+
+impl<T: Clone> HierarchicalTinyUFO<T> {
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::from_config(&CacheConfig::new(total_weight_limit, capacity))
+    }
+
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::from_config(config),
+            generations: RwLock::default(),
+            epoch: AtomicU64::new(0),
+            stale: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a clone of the value at `path`, unless one of its ancestors was invalidated since
+    pub fn get<S: Hash>(&self, path: &[S]) -> Option<T> {
+        let prefixes = prefixes(path);
+        let key = *prefixes.last()?;
+        let stamped = self.cache.get(&key)?;
+        if self.is_stale(&prefixes, stamped.epoch) {
+            if self.cache.remove(&key).is_some() {
+                self.stale.fetch_add(1, Relaxed);
+            }
+            return None;
+        }
+        Some(stamped.data)
+    }
+
+    /// Set the value at `path`, replacing any older one. An empty path is ignored
+    pub fn put<S: Hash>(&self, path: &[S], weight: Weight, data: T) {
+        let Some(key) = prefixes(path).pop() else {
+            return;
+        };
+        let epoch = self.epoch.fetch_add(1, Relaxed);
+        self.cache.put(key, weight, Stamped { epoch, data });
+    }
+
+    /// Remove the value at `path`, returns it if it was cached and fresh
+    pub fn remove<S: Hash>(&self, path: &[S]) -> Option<T> {
+        let prefixes = prefixes(path);
+        let stamped = self.cache.remove(prefixes.last()?)?;
+        (!self.is_stale(&prefixes, stamped.epoch)).then_some(stamped.data)
+    }
+
+    /// Drop every value at `prefix` or under it, e.g. `["catalog", region]`. The empty prefix
+    /// drops everything
+    pub fn invalidate<S: Hash>(&self, prefix: &[S]) {
+        let hash = prefixes(prefix).pop().unwrap_or(PATH_SEED);
+        let epoch = self.epoch.fetch_add(1, Relaxed);
+        self.generations
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(hash, epoch);
+    }
+
+    /// Statistics of the cache, a read finding a stale value counts as a miss
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = self.cache.stats();
+        let stale = self.stale.load(Relaxed);
+        stats.hits = stats.hits.saturating_sub(stale);
+        stats.misses += stale;
+        stats.removals = stats.removals.saturating_sub(stale);
+        stats
+    }
+
+    fn is_stale(&self, prefixes: &[u64], epoch: u64) -> bool {
+        let generations = self.generations();
+        if generations.is_empty() {
+            return false;
+        }
+        std::iter::once(&PATH_SEED)
+            .chain(prefixes)
+            .any(|prefix| generations.get(prefix).is_some_and(|&at| at > epoch))
+    }
+
+    fn generations(&self) -> RwLockReadGuard<'_, HashMap<u64, u64>> {
+        self.generations
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Hashes of the non-empty prefixes of `path`, each chained from its parent's
+fn prefixes<S: Hash>(path: &[S]) -> Vec<u64> {
+    let mut hash = PATH_SEED;
+    path.iter()
+        .map(|segment| {
+            let mut hasher = T1haHasher::with_seed(hash);
+            segment.hash(&mut hasher);
+            hash = hasher.finish();
+            hash
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidate_prefix() {
+        let cache = HierarchicalTinyUFO::new(100, 100);
+        cache.put(&["catalog", "eu", "1"], 1, 1);
+        cache.put(&["catalog", "eu", "2"], 1, 2);
+        cache.put(&["catalog", "us", "1"], 1, 3);
+        assert_eq!(cache.get(&["catalog", "eu", "1"]), Some(1));
+        // a prefix of another segment isn't an ancestor
+        cache.invalidate(&["catalog", "e"]);
+        assert_eq!(cache.get(&["catalog", "eu", "2"]), Some(2));
+
+        cache.invalidate(&["catalog", "eu"]);
+        assert_eq!(cache.get(&["catalog", "eu", "1"]), None);
+        assert_eq!(cache.remove(&["catalog", "eu", "2"]), None);
+        assert_eq!(cache.get(&["catalog", "us", "1"]), Some(3));
+        // put again after the invalidation
+        cache.put(&["catalog", "eu", "1"], 1, 4);
+        assert_eq!(cache.get(&["catalog", "eu", "1"]), Some(4));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (4, 1));
+        // owned segments hash like borrowed ones
+        let owned = ["catalog", "eu", "1"].map(String::from);
+        assert_eq!(cache.get(&owned), Some(4));
+
+        cache.invalidate::<&str>(&[]);
+        assert_eq!(cache.get(&["catalog", "us", "1"]), None);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::tinyufo::tinyufo::TinyUFO;
+use crate::tinyufo::types::Weight;
+use std::sync::Arc;
+use t1ha::T1haHashMap;
+
+/// Compact handle to a string stored in an [`Interner`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KeyId(u32);
+
+impl KeyId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Stores each distinct string once and hands out [`KeyId`]s for it.
+///
+/// Ids of removed strings are recycled, so an id is only meaningful while its string is interned.
+#[derive(Debug, Default)]
+pub struct Interner {
+    ids: T1haHashMap<Arc<str>, KeyId>,
+    strings: Vec<Option<Arc<str>>>,
+    free: Vec<KeyId>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Intern `key`, returns the existing id if it is already interned
+    pub fn intern(&mut self, key: &str) -> KeyId {
+        if let Some(id) = self.ids.get(key) {
+            return *id;
+        }
+
+        let key: Arc<str> = Arc::from(key);
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.strings[id.index()] = Some(key.clone());
+                id
+            }
+            None => {
+                let id = KeyId(u32::try_from(self.strings.len()).expect("interner id overflow"));
+                self.strings.push(Some(key.clone()));
+                id
+            }
+        };
+        self.ids.insert(key, id);
+        id
+    }
+
+    /// Look up the id of `key` without interning it
+    pub fn get(&self, key: &str) -> Option<KeyId> {
+        self.ids.get(key).copied()
+    }
+
+    /// Get the string behind `id`
+    pub fn resolve(&self, id: KeyId) -> Option<&str> {
+        self.strings.get(id.index())?.as_deref()
+    }
+
+    /// Remove the string behind `id`, its id can be handed out again afterwards
+    pub fn remove(&mut self, id: KeyId) -> Option<Arc<str>> {
+        let key = self.strings.get_mut(id.index())?.take()?;
+        self.ids.remove(&key);
+        self.free.push(id);
+        Some(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// TinyUFO keyed by strings, for long keys such as URLs or paths.
+///
+/// Every cached key is interned once, entries and queues only carry the 4 bytes [`KeyId`].
+/// An id lives exactly as long as its entry: it is released when the entry is evicted.
+pub struct InternedTinyUFO<T> {
+    interner: Interner,
+    cache: TinyUFO<KeyId, (KeyId, T)>,
+}
+
+impl<T> InternedTinyUFO<T> {
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self {
+            interner: Interner::new(),
+            cache: TinyUFO::new(total_weight_limit, capacity),
+        }
+    }
+
+    /// Get a value from the cache.
+    pub fn get(&mut self, key: &str) -> Option<&T> {
+        let id = self.interner.get(key)?;
+        self.cache.get(&id).map(|(_, data)| data)
+    }
+
+    /// Set a key-value pair in the cache.
+    pub fn put(&mut self, key: &str, weight: Weight, data: T) {
+        let id = self.interner.intern(key);
+        let interner = &mut self.interner;
+        self.cache
+            .put_evicting(id, weight, (id, data), |_, (evicted_id, _)| {
+                interner.remove(evicted_id);
+            });
+    }
+
+    /// The interner backing the cached keys
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interner() {
+        let mut interner = Interner::new();
+        let a = interner.intern("/a/very/long/path");
+        let b = interner.intern("/another/long/path");
+        assert_ne!(a, b);
+        assert_eq!(interner.intern("/a/very/long/path"), a);
+        assert_eq!(interner.resolve(b), Some("/another/long/path"));
+        assert_eq!(interner.len(), 2);
+
+        interner.remove(a);
+        assert_eq!(interner.get("/a/very/long/path"), None);
+        assert_eq!(interner.resolve(a), None);
+        // freed ids are recycled
+        assert_eq!(interner.intern("/third"), a);
+    }
+
+    #[test]
+    fn test_interned_cache_releases_evicted_keys() {
+        let mut cache = InternedTinyUFO::new(5, 5);
+        for i in 0..100 {
+            cache.put(&format!("https://example.com/{i}"), 1, i);
+        }
+        assert!(cache.interner().len() <= 6);
+
+        cache.put("https://example.com/hit", 1, 42);
+        assert_eq!(cache.get("https://example.com/hit"), Some(&42));
+        assert_eq!(cache.get("https://example.com/miss"), None);
+
+        // values are never cloned
+        let mut cache = InternedTinyUFO::new(5, 5);
+        cache.put("/lock", 1, std::sync::Mutex::new(1));
+        assert!(cache.get("/lock").is_some());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::eviction::EvictionPolicy;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::tinyufo::TinyUFO;
+use crate::tinyufo::types::{Key, Weight};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Evict the least recently used key, a read or put making a key the most recent
+#[derive(Debug, Default)]
+pub struct LruPolicy {
+    // last use of each key, and the keys by last use
+    ticks: HashMap<Key, u64>,
+    order: BTreeMap<u64, Key>,
+    tick: u64,
+}
+
+impl LruPolicy {
+    fn touch(&mut self, key: Key) {
+        self.tick += 1;
+        if let Some(previous) = self.ticks.insert(key, self.tick) {
+            self.order.remove(&previous);
+        }
+        self.order.insert(self.tick, key);
+    }
+}
+
+impl EvictionPolicy for LruPolicy {
+    fn insert(&mut self, key: Key, _: Weight) {
+        self.touch(key);
+    }
+
+    fn access(&mut self, key: Key) {
+        if self.ticks.contains_key(&key) {
+            self.touch(key);
+        }
+    }
+
+    fn remove(&mut self, key: Key) {
+        if let Some(tick) = self.ticks.remove(&key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn evict(&mut self) -> Option<Key> {
+        let (_, key) = self.order.pop_first()?;
+        self.ticks.remove(&key);
+        Some(key)
+    }
+
+    fn victim(&self) -> Option<Key> {
+        self.order.first_key_value().map(|(_, &key)| key)
+    }
+
+    fn eviction_order(&self) -> Vec<Key> {
+        self.order.values().copied().collect()
+    }
+}
+
+/// Cache with strict recency semantics: a [`TinyUFO`] evicting the least recently used entries
+/// with [`LruPolicy`], for workloads that need them or as a baseline. Same map, weights and stats.
+pub struct LruTinyUFO<K, T> {
+    cache: TinyUFO<K, T>,
+}
+
+impl<K: Hash, T> LruTinyUFO<K, T> {
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::from_config(&CacheConfig::new(total_weight_limit, capacity))
+    }
+
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self {
+            cache: TinyUFO::from_config(config).with_eviction_policy(LruPolicy::default()),
+        }
+    }
+
+    /// Get a value, making it the most recently used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.get(key)
+    }
+
+    /// See [`TinyUFO::get_mut`]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.get_mut(key)
+    }
+
+    /// Get a value without making it more recent
+    pub fn peek<Q>(&self, key: &Q) -> Option<&T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.peek(key)
+    }
+
+    pub fn put(&mut self, key: K, weight: Weight, data: T) {
+        self.cache.put(key, weight, data);
+    }
+
+    /// See [`TinyUFO::put_evicting`]
+    pub fn put_evicting(&mut self, key: K, weight: Weight, data: T, on_evict: impl FnMut(Key, T)) {
+        self.cache.put_evicting(key, weight, data, on_evict);
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.cache.remove(key)
+    }
+
+    /// See [`TinyUFO::set_weight_limit`]
+    pub fn set_weight_limit(&mut self, total_weight_limit: usize, on_evict: impl FnMut(Key, T)) {
+        self.cache.set_weight_limit(total_weight_limit, on_evict);
+    }
+
+    pub fn weight_limit(&self) -> usize {
+        self.cache.weight_limit()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// See [`TinyUFO::total_weight`]
+    pub fn total_weight(&self) -> usize {
+        self.cache.total_weight()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru() {
+        let mut cache = LruTinyUFO::new(3, 3);
+        for i in 1..=3 {
+            cache.put(i, 1, i);
+        }
+        // 1 is the most recent now, peeks don't count
+        cache.get(&1);
+        cache.peek(&2);
+        let mut evicted = vec![];
+        cache.put_evicting(4, 1, 4, |_, data| evicted.push(data));
+        assert_eq!(evicted, [2]);
+        // put again, 3 is the most recent
+        cache.put(3, 1, 3);
+        cache.put_evicting(5, 2, 5, |_, data| evicted.push(data));
+        assert_eq!(evicted, [2, 1, 4]);
+        assert_eq!((cache.len(), cache.total_weight()), (2, 3));
+
+        assert_eq!(cache.remove(&3), Some(3));
+        cache.set_weight_limit(1, |_, data| evicted.push(data));
+        assert_eq!(evicted.last(), Some(&5));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.entries), (1, 0));
+    }
+}
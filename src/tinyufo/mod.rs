@@ -1,3 +1,48 @@
+mod admission;
+mod builder;
+mod checked;
+mod concurrent;
+mod config;
 mod estimator;
+mod events;
+mod eviction;
+mod experiment;
+mod fixed;
+mod generation;
+mod hierarchy;
+mod intern;
+mod lru;
+#[cfg(test)]
+mod model;
+mod namespace;
+mod pool;
+mod registry;
+mod shadow;
+mod stats;
+#[allow(clippy::module_inception)]
 mod tinyufo;
 mod types;
+mod wheel;
+
+pub use admission::{
+    AdmissionPolicy, AlwaysAdmit, ProbabilisticAdmission, SizeAwareAdmission, TinyLfuAdmission,
+};
+pub use builder::{ConfigError, TinyUfoBuilder, MAX_ESTIMATED_ITEMS};
+pub use checked::CheckedTinyUFO;
+pub use concurrent::ConcurrentTinyUFO;
+pub use config::{AdmissionConfig, CacheConfig, EstimatorConfig, EvictionConfig};
+pub use estimator::{Estimator, MergeError, TinyLFU, COUNTER_MAX};
+pub use events::{CacheEventHandler, EvictionListener, RemovalCause};
+pub use eviction::EvictionPolicy;
+pub use experiment::{Experiment, ExperimentStats, ExperimentedTinyUFO, Simulated};
+pub use fixed::FixedTinyUfo;
+pub use generation::GenerationalTinyUFO;
+pub use hierarchy::HierarchicalTinyUFO;
+pub use intern::{InternedTinyUFO, Interner, KeyId};
+pub use lru::{LruPolicy, LruTinyUFO};
+pub use namespace::{Namespace, NamespacePolicy, NamespaceStats, NamespacedTinyUFO};
+pub use registry::CacheRegistry;
+pub use shadow::{MissRatio, ShadowCaches, ShadowConfig, ShadowedTinyUFO};
+pub use stats::CacheStats;
+pub use tinyufo::{TinyUFO, DEFAULT_PROMOTION_THRESHOLD, DEFAULT_SMALL_QUEUE_PERCENT};
+pub use types::{Key, Weigher, Weight};
@@ -0,0 +1,131 @@
+//! Differential tests of [`TinyUFO`] against a reference model: an unbounded map of what was
+//! put, minus what was removed or reported evicted. Random operation sequences run through both
+//! and their content, entry count and weight must agree after every step.
+
+use crate::tinyufo::tinyufo::TinyUFO;
+use crate::tinyufo::types::{units, Key, Weight};
+use std::collections::HashMap;
+
+const KEYS: u64 = 32;
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Get(u64),
+    Put(u64, Weight),
+    Remove(u64),
+    SetWeightLimit(usize),
+}
+
+fn random_op(rng: &mut fastrand::Rng, weight_limit: usize) -> Op {
+    let key = rng.u64(0..KEYS);
+    match rng.u8(0..20) {
+        0..=7 => Op::Get(key),
+        8..=15 => {
+            // the edges of the weight arithmetic along with ordinary weights
+            let weight = match rng.u8(0..10) {
+                0 => 0,
+                1 => weight_limit as Weight,
+                2 => Weight::MAX,
+                _ => rng.u64(1..=(weight_limit as Weight / 4).max(1)),
+            };
+            Op::Put(key, weight)
+        }
+        16..=18 => Op::Remove(key),
+        _ => Op::SetWeightLimit(rng.usize(0..=weight_limit * 2)),
+    }
+}
+
+/// The entries the cache must hold, by key: their weight and the value last put
+struct Model {
+    entries: HashMap<u64, (Weight, u64)>,
+    // hashed key to key, to apply the evictions the cache reports
+    keys: HashMap<Key, u64>,
+    weight_limit: usize,
+}
+
+impl Model {
+    fn new(cache: &TinyUFO<u64, u64>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            keys: (0..KEYS).map(|key| (cache.key_hash(&key), key)).collect(),
+            weight_limit: cache.weight_limit(),
+        }
+    }
+
+    fn evict(&mut self, hashed_key: Key, data: u64) {
+        let key = self.keys[&hashed_key];
+        let evicted = self.entries.remove(&key);
+        assert_eq!(evicted.map(|(_, value)| value), Some(data), "evicted {key}");
+    }
+
+    fn weight(&self) -> usize {
+        self.entries
+            .values()
+            .map(|(weight, _)| units(*weight))
+            .sum()
+    }
+}
+
+/// Run `op` on both, `value` being what a put stores
+fn step(cache: &mut TinyUFO<u64, u64>, model: &mut Model, op: Op, value: u64) {
+    let mut evicted = vec![];
+    match op {
+        Op::Get(key) => {
+            let expected = model.entries.get(&key).map(|(_, value)| *value);
+            assert_eq!(cache.get(&key).copied(), expected, "get {key}");
+        }
+        Op::Put(key, weight) => {
+            cache.put_evicting(key, weight, value, |hashed_key, data| {
+                evicted.push((hashed_key, data))
+            });
+            model.entries.insert(key, (weight, value));
+        }
+        Op::Remove(key) => {
+            let expected = model.entries.remove(&key).map(|(_, value)| value);
+            assert_eq!(cache.remove(&key), expected, "remove {key}");
+        }
+        Op::SetWeightLimit(limit) => {
+            cache.set_weight_limit(limit, |hashed_key, data| evicted.push((hashed_key, data)));
+            model.weight_limit = limit;
+        }
+    }
+    for (hashed_key, data) in evicted {
+        model.evict(hashed_key, data);
+    }
+}
+
+fn check(cache: &TinyUFO<u64, u64>, model: &Model, op: Op) {
+    for key in 0..KEYS {
+        let expected = model.entries.get(&key).map(|(_, value)| value);
+        assert_eq!(cache.peek(&key), expected, "key {key} after {op:?}");
+    }
+    let stats = cache.stats();
+    assert_eq!(stats.entries, model.entries.len(), "entries after {op:?}");
+    assert_eq!(stats.weight, model.weight(), "weight after {op:?}");
+    assert_eq!(cache.weight_limit(), model.weight_limit);
+    cache.check_invariants();
+    if let Op::Put(key, _) = op {
+        assert!(model.entries.contains_key(&key), "{op:?} not cached");
+    }
+}
+
+/// Run `len` random operations from `seed` on a cache of `weight_limit`
+fn run(seed: u64, weight_limit: usize, len: usize) {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let mut cache = TinyUFO::new(weight_limit, KEYS as usize);
+    let mut model = Model::new(&cache);
+    for value in 0..len as u64 {
+        let op = random_op(&mut rng, weight_limit);
+        step(&mut cache, &mut model, op, value);
+        check(&cache, &model, op);
+    }
+}
+
+#[test]
+fn test_against_model() {
+    for seed in 0..50 {
+        for weight_limit in [1, 10, 100, 1000, 100_000] {
+            run(seed, weight_limit, 500);
+        }
+    }
+}
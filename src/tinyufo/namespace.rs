@@ -0,0 +1,856 @@
+use crate::clock::{default_clock, Clock};
+use crate::tinyufo::concurrent::ConcurrentTinyUFO;
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::tinyufo::USES_CAP;
+use crate::tinyufo::types::{units, Weight};
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use t1ha::T1haHasher;
+
+// keys are hashed with their namespace, so that namespaces can't see each other's entries
+const SCOPE_SEED: u64 = 0x4e41_4d45_5350_4143;
+
+#[derive(Clone)]
+struct Slot<T> {
+    namespace: usize,
+    // the scoped hash, the cache only hands back its own hash of it on eviction
+    key: u64,
+    // tells a put apart from a later put of the same key
+    id: u64,
+    // on the cache's clock
+    expires_at: Option<Duration>,
+    // time to idle, and when the entry was last read in nanos on the cache's clock
+    idle: Option<(Duration, Arc<AtomicU64>)>,
+    data: T,
+}
+
+/// Defaults of the puts through a [`Namespace`], see [`Namespace::set_policy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamespacePolicy {
+    /// Time to live: entries expire this long after their put
+    pub ttl: Option<Duration>,
+    /// Time to idle: entries expire once not read for this long
+    pub tti: Option<Duration>,
+    /// Reads an entry is credited with at most, 1 to 3. A lower cap lets entries fall out of
+    /// the main queue sooner, 1 keeps them in the small queue, for one-off data that shouldn't
+    /// push out the rest
+    pub uses_cap: u8,
+}
+
+impl Default for NamespacePolicy {
+    fn default() -> Self {
+        Self {
+            ttl: None,
+            tti: None,
+            uses_cap: USES_CAP,
+        }
+    }
+}
+
+/// Statistics of a [`Namespace`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamespaceStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Puts of a key that wasn't cached
+    pub inserts: u64,
+    /// Entries evicted for the namespace's budget, for fairness or by TinyUFO
+    pub evictions: u64,
+    /// Entries found past their TTL or TTI, the reads count as misses
+    pub expirations: u64,
+    pub entries: usize,
+    pub weight: usize,
+}
+
+/// What a namespace holds, in the order it was put
+struct Budget {
+    limit: usize,
+    quota: usize,
+    weight: usize,
+    // of every namespace together
+    total: Arc<AtomicUsize>,
+    entries: HashMap<u64, (Weight, u64)>,
+    // may hold keys removed since or put twice, skipped when evicting
+    order: VecDeque<u64>,
+    policy: NamespacePolicy,
+    priority: f64,
+    inserts: u64,
+    evictions: u64,
+    expirations: u64,
+}
+
+impl Budget {
+    // saturating: entries may weigh up to Weight::MAX, the sums must not wrap
+    fn add(&mut self, weight: Weight) {
+        let units = units(weight);
+        self.weight = self.weight.saturating_add(units);
+        let _ = self
+            .total
+            .fetch_update(Relaxed, Relaxed, |total| Some(total.saturating_add(units)));
+    }
+
+    fn sub(&mut self, weight: Weight) {
+        let units = units(weight);
+        self.weight = self.weight.saturating_sub(units);
+        let _ = self
+            .total
+            .fetch_update(Relaxed, Relaxed, |total| Some(total.saturating_sub(units)));
+    }
+
+    /// Take out the oldest entries until the namespace weighs at most `target`, sparing `keep`
+    fn evict_down_to(&mut self, target: usize, keep: Option<u64>) -> Vec<u64> {
+        let mut victims = Vec::new();
+        while self.weight > target {
+            // alone over the target, it stays
+            if self.entries.len() == 1 && keep.is_some_and(|key| self.entries.contains_key(&key)) {
+                break;
+            }
+            let Some(key) = self.order.pop_front() else {
+                break;
+            };
+            if Some(key) == keep {
+                self.order.push_back(key);
+                continue;
+            }
+            if let Some((weight, _)) = self.entries.remove(&key) {
+                self.sub(weight);
+                victims.push(key);
+            }
+        }
+        if self.order.len() > 2 * self.entries.len() + 16 {
+            let entries = &self.entries;
+            self.order.retain(|key| entries.contains_key(key));
+        }
+        self.evictions += victims.len() as u64;
+        victims
+    }
+
+    /// Forget the entry of `key` if it is still the put `id`, returns whether it was
+    fn forget(&mut self, key: u64, id: u64) -> bool {
+        match self.entries.get(&key) {
+            Some(&(weight, current)) if current == id => {
+                self.entries.remove(&key);
+                self.sub(weight);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A namespace's accounting, and its counters updated without its lock
+struct Shared {
+    budget: Mutex<Budget>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Names and accounting of the namespaces, indexed by [`Slot::namespace`]
+type Registry = Vec<(Arc<str>, Arc<Shared>)>;
+
+/// [`ConcurrentTinyUFO`] shared by named namespaces, e.g. the tenants of a platform cache, each
+/// with a weight budget of its own.
+///
+/// Entries count against both their namespace's budget and the global limit: a namespace over
+/// its budget evicts its own oldest entries, so that one kind of data can't starve the others,
+/// while the global limit is enforced by TinyUFO across namespaces. Reads only lock a shard,
+/// writes to a namespace are serialized by its lock.
+///
+/// With [fair eviction](Self::set_fair_eviction), a full cache makes room by evicting from the
+/// namespaces over their soft [quota](Namespace::set_quota) in proportion to how far over they
+/// are, rather than from whichever entries TinyUFO picks. [Priorities](Namespace::set_priority)
+/// weigh on the same choice, low priority namespaces giving up room first.
+///
+/// Each namespace has its own [policy](Namespace::set_policy) for the entries put through it:
+/// TTL, TTI and how much reads protect them from eviction.
+pub struct NamespacedTinyUFO<K, T: Clone> {
+    cache: ConcurrentTinyUFO<u64, Slot<T>>,
+    namespaces: Mutex<Registry>,
+    next_id: AtomicU64,
+    weight: Arc<AtomicUsize>,
+    fair: AtomicBool,
+    // whether a namespace has a priority other than 1, and the victims are picked here
+    prioritized: AtomicBool,
+    clock: Arc<dyn Clock>,
+    _k: PhantomData<fn(&K)>,
+}
+
+impl<K: Hash, T: Clone> NamespacedTinyUFO<K, T> {
+    pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::from_config(&CacheConfig::new(total_weight_limit, capacity))
+    }
+
+    /// Create a cache with `shards` shards, see [`ConcurrentTinyUFO::with_shards`]
+    pub fn with_shards(total_weight_limit: usize, capacity: usize, shards: usize) -> Self {
+        let mut config = CacheConfig::new(total_weight_limit, capacity);
+        config.shards = Some(shards);
+        Self::from_config(&config)
+    }
+
+    /// Create a cache tuned by `config`, shared by every namespace
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::from_config(config),
+            namespaces: Mutex::default(),
+            next_id: AtomicU64::new(0),
+            weight: Arc::default(),
+            fair: AtomicBool::new(false),
+            prioritized: AtomicBool::new(false),
+            clock: default_clock(),
+            _k: PhantomData,
+        }
+    }
+
+    /// Measure the TTLs and TTIs with `clock`
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// The namespace called `name`, created on first use with the whole weight limit as budget
+    /// and quota
+    pub fn namespace(&self, name: &str) -> Namespace<'_, K, T> {
+        let mut namespaces = lock(&self.namespaces);
+        let index = match namespaces.iter().position(|(known, _)| &**known == name) {
+            Some(index) => index,
+            None => {
+                let budget = Budget {
+                    limit: self.cache.weight_limit(),
+                    quota: self.cache.weight_limit(),
+                    weight: 0,
+                    total: self.weight.clone(),
+                    entries: HashMap::new(),
+                    order: VecDeque::new(),
+                    policy: NamespacePolicy::default(),
+                    priority: 1.0,
+                    inserts: 0,
+                    evictions: 0,
+                    expirations: 0,
+                };
+                let shared = Shared {
+                    budget: Mutex::new(budget),
+                    hits: AtomicU64::new(0),
+                    misses: AtomicU64::new(0),
+                };
+                namespaces.push((name.into(), Arc::new(shared)));
+                namespaces.len() - 1
+            }
+        };
+        Namespace {
+            cache: self,
+            index,
+            name: namespaces[index].0.clone(),
+            shared: namespaces[index].1.clone(),
+        }
+    }
+
+    /// Names of the namespaces created so far
+    pub fn namespaces(&self) -> Vec<Arc<str>> {
+        lock(&self.namespaces)
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Make room in a full cache from the namespaces over their quota, see
+    /// [`Namespace::set_quota`]. Off at first
+    pub fn set_fair_eviction(&self, fair: bool) {
+        self.fair.store(fair, Relaxed);
+    }
+
+    /// See [`ConcurrentTinyUFO::set_small_queue_percent`]. The namespaces share the queues,
+    /// [`NamespacePolicy::uses_cap`] tunes how their entries go through them
+    pub fn set_small_queue_percent(&self, percent: u8) {
+        self.cache.set_small_queue_percent(percent);
+    }
+
+    /// Statistics of the whole cache, over every namespace
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    pub fn weight_limit(&self) -> usize {
+        self.cache.weight_limit()
+    }
+
+    /// Account the entries TinyUFO evicted to their namespaces
+    fn evicted(&self, evicted: Vec<(usize, u64, u64)>) {
+        for (namespace, key, id) in evicted {
+            let shared = lock(&self.namespaces)[namespace].1.clone();
+            let mut budget = lock(&shared.budget);
+            if budget.forget(key, id) {
+                budget.evictions += 1;
+            }
+        }
+    }
+
+    /// Evict `needed` weight from the namespaces, each giving its share of their total weight,
+    /// or of their total excess over quota with fair eviction, scaled down by its priority
+    fn make_room(&self, needed: usize) {
+        let fair = self.fair.load(Relaxed);
+        let namespaces: Vec<_> = lock(&self.namespaces)
+            .iter()
+            .map(|(_, shared)| shared.clone())
+            .collect();
+        let evictable: Vec<_> = namespaces
+            .iter()
+            .map(|shared| {
+                let budget = lock(&shared.budget);
+                let weight = if fair {
+                    budget.weight.saturating_sub(budget.quota)
+                } else {
+                    budget.weight
+                };
+                (weight, weight as f64 / budget.priority)
+            })
+            .collect();
+        let total_pressure: f64 = evictable.iter().map(|(_, pressure)| pressure).sum();
+        if total_pressure == 0.0 {
+            // nothing to take from, TinyUFO picks
+            return;
+        }
+        let mut shares: Vec<_> = evictable
+            .iter()
+            .map(|&(weight, pressure)| {
+                ((needed as f64 * pressure / total_pressure) as usize).min(weight)
+            })
+            .collect();
+        // what rounding down left, from the namespace under the most pressure
+        let rest = needed.saturating_sub(shares.iter().sum());
+        if let Some((most, (weight, _))) = evictable
+            .iter()
+            .enumerate()
+            .max_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b))
+        {
+            shares[most] = (shares[most] + rest).min(*weight);
+        }
+        for (shared, share) in namespaces.iter().zip(shares) {
+            if share == 0 {
+                continue;
+            }
+            let mut budget = lock(&shared.budget);
+            let target = budget.weight.saturating_sub(share);
+            for victim in budget.evict_down_to(target, None) {
+                self.cache.remove(&victim);
+            }
+        }
+    }
+}
+
+/// Handle to a namespace of a [`NamespacedTinyUFO`], see [`NamespacedTinyUFO::namespace`]
+pub struct Namespace<'a, K, T: Clone> {
+    cache: &'a NamespacedTinyUFO<K, T>,
+    index: usize,
+    name: Arc<str>,
+    shared: Arc<Shared>,
+}
+
+impl<K: Hash, T: Clone> Namespace<'_, K, T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get a clone of the cached value
+    pub fn get<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let slot = self
+            .cache
+            .cache
+            .get(&self.scoped(key))
+            .filter(|slot| slot.namespace == self.index);
+        let data = match slot {
+            Some(slot) if self.is_expired(&slot) => {
+                let mut budget = lock(&self.shared.budget);
+                // unless put again since
+                if budget.forget(slot.key, slot.id) {
+                    budget.expirations += 1;
+                    self.cache.cache.remove(&slot.key);
+                }
+                None
+            }
+            Some(slot) => {
+                if let Some((_, read_at)) = &slot.idle {
+                    read_at.store(self.cache.clock.now().as_nanos() as u64, Relaxed);
+                }
+                Some(slot.data)
+            }
+            None => None,
+        };
+        let counter = match data {
+            Some(_) => &self.shared.hits,
+            None => &self.shared.misses,
+        };
+        counter.fetch_add(1, Relaxed);
+        data
+    }
+
+    /// Get a clone of the cached value without it counting as an access
+    pub fn peek<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let slot = self.cache.cache.peek(&self.scoped(key))?;
+        (slot.namespace == self.index && !self.is_expired(&slot)).then_some(slot.data)
+    }
+
+    /// Set a key-value pair with the namespace's policy, evicting its oldest entries if it
+    /// goes over budget
+    pub fn put(&self, key: K, weight: Weight, data: T) {
+        let key = self.scoped(&key);
+        self.admit(std::iter::once((key, weight, data)), units(weight));
+    }
+
+    /// Get the values of `keys`, loading the missing ones with a single call to `loader`.
+    ///
+    /// `loader` gets exactly the keys that missed, once each, and returns the values it found
+    /// with their weights, which are put in one batch. Keys it didn't return are left out of
+    /// the result, its error is returned as is.
+    pub fn get_or_load_many<I, F, E>(&self, keys: I, loader: F) -> Result<HashMap<K, T>, E>
+    where
+        K: Eq + Clone,
+        I: IntoIterator<Item = K>,
+        F: FnOnce(&[K]) -> Result<HashMap<K, (Weight, T)>, E>,
+    {
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        let mut seen = HashSet::new();
+        for key in keys {
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            match self.get(&key) {
+                Some(data) => {
+                    found.insert(key, data);
+                }
+                None => missing.push(key),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(found);
+        }
+
+        let loaded = loader(&missing)?;
+        let weight = loaded.values().fold(0, |sum: usize, (weight, _)| {
+            sum.saturating_add(units(*weight))
+        });
+        let batch: Vec<_> = loaded
+            .into_iter()
+            .map(|(key, (weight, data))| {
+                let scoped = self.scoped(&key);
+                found.insert(key, data.clone());
+                (scoped, weight, data)
+            })
+            .collect();
+        self.admit(batch, weight);
+        Ok(found)
+    }
+
+    /// Remove a key, returns its data if it was cached
+    pub fn remove<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let key = self.scoped(key);
+        let mut budget = lock(&self.shared.budget);
+        let (_, id) = budget.entries.get(&key).copied()?;
+        budget.forget(key, id);
+        self.cache.cache.remove(&key).map(|slot| slot.data)
+    }
+
+    /// Remove every entry of the namespace, returns how many were cached
+    pub fn clear(&self) -> usize {
+        let mut budget = lock(&self.shared.budget);
+        let entries = std::mem::take(&mut budget.entries);
+        budget.order.clear();
+        let weight = budget.weight;
+        budget.weight = 0;
+        budget.total.fetch_sub(weight, Relaxed);
+        entries
+            .into_keys()
+            .filter(|key| self.cache.cache.remove(key).is_some())
+            .count()
+    }
+
+    /// Change the budget, evicting the oldest entries down to a smaller one right away
+    pub fn set_budget(&self, limit: usize) {
+        let mut budget = lock(&self.shared.budget);
+        budget.limit = limit;
+        for victim in budget.evict_down_to(limit, None) {
+            self.cache.cache.remove(&victim);
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        lock(&self.shared.budget).limit
+    }
+
+    /// Change the eviction priority, 1 at first: under global pressure each namespace gives up
+    /// room in proportion to its weight divided by its priority, so prefetched results at 0.25
+    /// lose entries 4 times as fast as sessions at 1. Not positive counts as the smallest one
+    pub fn set_priority(&self, priority: f64) {
+        let priority = priority.max(f64::MIN_POSITIVE);
+        lock(&self.shared.budget).priority = priority;
+        if priority != 1.0 {
+            self.cache.prioritized.store(true, Relaxed);
+        }
+    }
+
+    pub fn priority(&self) -> f64 {
+        lock(&self.shared.budget).priority
+    }
+
+    /// Change the policy of the entries put from now on, those already cached keep theirs
+    pub fn set_policy(&self, policy: NamespacePolicy) {
+        lock(&self.shared.budget).policy = policy;
+    }
+
+    pub fn policy(&self) -> NamespacePolicy {
+        lock(&self.shared.budget).policy
+    }
+
+    /// Change the soft quota: the namespace may go over it while the cache has room, and is
+    /// made to give room back once it is full, with
+    /// [fair eviction](NamespacedTinyUFO::set_fair_eviction)
+    pub fn set_quota(&self, quota: usize) {
+        lock(&self.shared.budget).quota = quota;
+    }
+
+    pub fn quota(&self) -> usize {
+        lock(&self.shared.budget).quota
+    }
+
+    /// Total weight of the namespace's entries
+    pub fn weight(&self) -> usize {
+        lock(&self.shared.budget).weight
+    }
+
+    /// Number of entries in the namespace
+    pub fn len(&self) -> usize {
+        lock(&self.shared.budget).entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> NamespaceStats {
+        let budget = lock(&self.shared.budget);
+        NamespaceStats {
+            hits: self.shared.hits.load(Relaxed),
+            misses: self.shared.misses.load(Relaxed),
+            inserts: budget.inserts,
+            evictions: budget.evictions,
+            expirations: budget.expirations,
+            entries: budget.entries.len(),
+            weight: budget.weight,
+        }
+    }
+
+    /// Put the scoped keys of `batch`, weighing `weight` together, under one lock of the
+    /// namespace
+    fn admit(&self, batch: impl IntoIterator<Item = (u64, Weight, T)>, weight: usize) {
+        if self.cache.fair.load(Relaxed) || self.cache.prioritized.load(Relaxed) {
+            let after = self.cache.weight.load(Relaxed).saturating_add(weight);
+            let limit = self.cache.weight_limit();
+            if after > limit {
+                self.cache.make_room(after - limit);
+            }
+        }
+        let mut evicted = Vec::new();
+        {
+            let mut budget = lock(&self.shared.budget);
+            let policy = budget.policy;
+            let now = self.cache.clock.now();
+            for (key, weight, data) in batch {
+                let id = self.cache.next_id.fetch_add(1, Relaxed);
+                let slot = Slot {
+                    namespace: self.index,
+                    key,
+                    id,
+                    expires_at: policy.ttl.map(|ttl| now + ttl),
+                    idle: policy
+                        .tti
+                        .map(|tti| (tti, Arc::new(AtomicU64::new(now.as_nanos() as u64)))),
+                    data,
+                };
+                match budget.entries.insert(key, (weight, id)) {
+                    Some((replaced, _)) => budget.sub(replaced),
+                    None => {
+                        budget.order.push_back(key);
+                        budget.inserts += 1;
+                    }
+                }
+                budget.add(weight);
+                let limit = budget.limit;
+                for victim in budget.evict_down_to(limit, Some(key)) {
+                    self.cache.cache.remove(&victim);
+                }
+                // under the namespace's lock, so that a racing remove or clear sees the entry
+                self.cache
+                    .cache
+                    .put_capped(key, weight, slot, policy.uses_cap, |_, slot| {
+                        evicted.push((slot.namespace, slot.key, slot.id));
+                    });
+            }
+        }
+        // other namespaces are locked one at a time, never while holding this one
+        self.cache.evicted(evicted);
+    }
+
+    /// Whether `slot` is past its TTL or TTI, only reads the clock for those that have one
+    fn is_expired(&self, slot: &Slot<T>) -> bool {
+        if slot.expires_at.is_none() && slot.idle.is_none() {
+            return false;
+        }
+        let now = self.cache.clock.now();
+        slot.expires_at.is_some_and(|at| now >= at)
+            || slot.idle.as_ref().is_some_and(|(tti, read_at)| {
+                now.saturating_sub(Duration::from_nanos(read_at.load(Relaxed))) >= *tti
+            })
+    }
+
+    fn scoped<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        let mut hasher = T1haHasher::with_seed(SCOPE_SEED);
+        self.index.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // a panic while holding the lock can't leave the accounting in a way that matters to a
+    // cache, keep serving it
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespaces() {
+        let cache = NamespacedTinyUFO::new(1000, 1000);
+        let images = cache.namespace("images");
+        let users = cache.namespace("users");
+        images.put("a".to_string(), 1, 1);
+        users.put("a".to_string(), 1, 2);
+        assert_eq!(images.get("a"), Some(1));
+        assert_eq!(users.get("a"), Some(2));
+        assert_eq!(cache.namespace("images").peek("a"), Some(1));
+        assert_eq!(cache.namespaces().len(), 2);
+
+        assert_eq!(images.remove("a"), Some(1));
+        assert_eq!(images.remove("a"), None);
+        assert_eq!(users.get("a"), Some(2));
+
+        for i in 0..10 {
+            images.put(i.to_string(), 2, i);
+        }
+        assert_eq!((images.len(), images.weight()), (10, 20));
+        assert_eq!(images.clear(), 10);
+        assert!(images.is_empty());
+        assert_eq!(images.get("3"), None);
+        assert_eq!(users.get("a"), Some(2));
+    }
+
+    #[test]
+    fn test_budget() {
+        let cache = NamespacedTinyUFO::with_shards(1000, 1000, 1);
+        let images = cache.namespace("images");
+        let users = cache.namespace("users");
+        images.set_budget(100);
+        for i in 0..10u64 {
+            users.put(i, 1, i);
+        }
+        for i in 0..1000u64 {
+            images.put(i, 1, i);
+        }
+        // the newest fit, the others went first
+        assert_eq!(images.weight(), 100);
+        assert_eq!(images.get(&999), Some(999));
+        assert_eq!(images.peek(&0), None);
+        assert_eq!(users.len(), 10);
+
+        images.set_budget(10);
+        assert_eq!(images.len(), 10);
+        assert!(cache.stats().weight <= 20);
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let cache = NamespacedTinyUFO::with_shards(100, 100, 1);
+        let a = cache.namespace("a");
+        let b = cache.namespace("b");
+        for i in 0..1000u64 {
+            a.put(i, 1, i);
+            b.put(i, 1, i);
+        }
+        let stats = cache.stats();
+        assert!(stats.weight <= 100);
+        // the evictions across namespaces were accounted to their owners
+        assert_eq!(a.weight() + b.weight(), stats.weight);
+        assert_eq!(a.len() + b.len(), stats.entries);
+    }
+
+    #[test]
+    fn test_fair_eviction() {
+        let cache = NamespacedTinyUFO::with_shards(100, 100, 1);
+        cache.set_fair_eviction(true);
+        let small = cache.namespace("small");
+        let big = cache.namespace("big");
+        small.set_quota(50);
+        big.set_quota(50);
+        for i in 0..40u64 {
+            small.put(i, 1, i);
+        }
+        // over its quota while there is room, then paying for the room it took
+        for i in 0..1000u64 {
+            big.put(i, 1, i);
+        }
+        assert_eq!(small.len(), 40);
+        assert_eq!(small.stats().evictions, 0);
+        assert!(big.weight() >= 50);
+        assert!(cache.stats().weight <= 100);
+
+        small.get(&1);
+        small.get(&1000);
+        let stats = small.stats();
+        assert_eq!((stats.hits, stats.misses, stats.inserts), (1, 1, 40));
+        assert_eq!(big.stats().inserts, 1000);
+        assert!(big.stats().evictions > 0);
+    }
+
+    #[test]
+    fn test_priority() {
+        let cache = NamespacedTinyUFO::with_shards(100, 100, 1);
+        let sessions = cache.namespace("sessions");
+        let prefetch = cache.namespace("prefetch");
+        sessions.set_priority(4.0);
+        prefetch.set_priority(0.5);
+        for i in 0..1000u64 {
+            sessions.put(i, 1, i);
+            prefetch.put(i, 1, i);
+        }
+        // both put as much, the low priority one paid for the room
+        assert!(sessions.weight() > 4 * prefetch.weight());
+        assert!(prefetch.stats().evictions > sessions.stats().evictions);
+        assert!(cache.stats().weight <= 100);
+        assert_eq!(prefetch.priority(), 0.5);
+        prefetch.set_priority(-1.0);
+        assert!(prefetch.priority() > 0.0);
+    }
+
+    #[test]
+    fn test_policy() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let cache = NamespacedTinyUFO::new(1000, 1000).with_clock(clock.clone());
+        let sessions = cache.namespace("sessions");
+        let images = cache.namespace("images");
+        sessions.set_policy(NamespacePolicy {
+            tti: Some(Duration::from_secs(60)),
+            ..Default::default()
+        });
+        images.set_policy(NamespacePolicy {
+            ttl: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        });
+        sessions.put("a", 1, 1);
+        sessions.put("b", 1, 2);
+        images.put("a", 1, 3);
+
+        // reads keep a session alive, not an image
+        for _ in 0..100 {
+            clock.advance(Duration::from_secs(50));
+            assert_eq!(sessions.get("a"), Some(1));
+        }
+        assert_eq!(sessions.peek("b"), None);
+        assert_eq!(sessions.get("b"), None);
+        assert_eq!(images.get("a"), None);
+        assert_eq!(sessions.stats().expirations, 1);
+        assert_eq!(images.stats().misses, 1);
+        assert_eq!((sessions.len(), images.len()), (1, 0));
+
+        // the policy applies from the next put
+        images.set_policy(NamespacePolicy::default());
+        images.put("a", 1, 4);
+        clock.advance(Duration::from_secs(86400));
+        assert_eq!(images.get("a"), Some(4));
+    }
+
+    #[test]
+    fn test_get_or_load_many() {
+        let cache = NamespacedTinyUFO::new(1000, 1000);
+        let users = cache.namespace("users");
+        users.put(1u64, 1, "alice".to_string());
+
+        let mut asked = Vec::new();
+        let found = users
+            .get_or_load_many([1, 2, 3, 2], |missing: &[u64]| {
+                asked = missing.to_vec();
+                // 3 doesn't exist
+                Ok::<_, ()>(HashMap::from([(2, (5, "bob".to_string()))]))
+            })
+            .unwrap();
+        assert_eq!(asked, vec![2, 3]);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[&2], "bob");
+        assert_eq!(users.get(&2), Some("bob".to_string()));
+        assert_eq!(users.weight(), 6);
+
+        // all cached, the loader isn't called
+        let found = users
+            .get_or_load_many([1, 2], |_: &[u64]| -> Result<_, ()> { unreachable!() })
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        let failed = users.get_or_load_many([4], |_: &[u64]| Err::<HashMap<_, _>, _>("down"));
+        assert_eq!(failed, Err("down"));
+        assert_eq!(users.get(&4), None);
+
+        // weights adding up past usize::MAX saturate
+        let heavy = HashMap::from([
+            (5, (Weight::MAX, "carol".to_string())),
+            (6, (Weight::MAX, "dave".to_string())),
+        ]);
+        let found = users
+            .get_or_load_many([5, 6], |_: &[u64]| Ok::<_, ()>(heavy))
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        // one of them, cached alone
+        assert_eq!(cache.stats().weight, usize::MAX);
+    }
+
+    #[test]
+    fn test_uses_cap() {
+        let cache = NamespacedTinyUFO::with_shards(100, 100, 1);
+        let metadata = cache.namespace("metadata");
+        let scans = cache.namespace("scans");
+        scans.set_policy(NamespacePolicy {
+            uses_cap: 1,
+            ..Default::default()
+        });
+        for i in 0..20u64 {
+            metadata.put(i, 1, i);
+            scans.put(i, 1, i);
+            metadata.get(&i);
+            scans.get(&i);
+        }
+        for i in 20..300u64 {
+            scans.put(i, 1, i);
+        }
+        // read as much, but the scans never made it to the main queue
+        assert!((0..20u64).all(|i| metadata.peek(&i).is_some()));
+        assert!((0..20u64).all(|i| scans.peek(&i).is_none()));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::tinyufo::types::Key;
+use t1ha::T1haHashMap;
+
+/// Index of a slot in a [`Pool`]
+pub(crate) type SlotId = u32;
+
+enum Slot<V> {
+    Occupied(V),
+    // next free slot
+    Vacant(Option<SlotId>),
+}
+
+/// Slab of values with an intrusive freelist.
+///
+/// Slots freed by `remove` are handed out again by the next `insert`, so steady state
+/// eviction + admission recycles the same memory instead of going through the allocator.
+pub(crate) struct Pool<V> {
+    slots: Vec<Slot<V>>,
+    free_head: Option<SlotId>,
+}
+
+impl<V> Pool<V> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            free_head: None,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, value: V) -> SlotId {
+        if let Some(id) = self.free_head {
+            let slot = &mut self.slots[id as usize];
+            let Slot::Vacant(next) = *slot else {
+                unreachable!("free list points to an occupied slot");
+            };
+            self.free_head = next;
+            *slot = Slot::Occupied(value);
+            id
+        } else {
+            let id = SlotId::try_from(self.slots.len()).expect("pool slot overflow");
+            self.slots.push(Slot::Occupied(value));
+            id
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: SlotId) -> Option<V> {
+        let slot = self.slots.get_mut(id as usize)?;
+        if let Slot::Vacant(_) = slot {
+            return None;
+        }
+        let Slot::Occupied(value) = std::mem::replace(slot, Slot::Vacant(self.free_head)) else {
+            unreachable!();
+        };
+        self.free_head = Some(id);
+        Some(value)
+    }
+
+    pub(crate) fn get(&self, id: SlotId) -> Option<&V> {
+        match self.slots.get(id as usize)? {
+            Slot::Occupied(value) => Some(value),
+            Slot::Vacant(_) => None,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, id: SlotId) -> Option<&mut V> {
+        match self.slots.get_mut(id as usize)? {
+            Slot::Occupied(value) => Some(value),
+            Slot::Vacant(_) => None,
+        }
+    }
+}
+
+/// Hashed key -> value map whose values live in a [`Pool`].
+///
+/// The hash table only holds 4 byte slot ids, so growing it moves little memory.
+pub(crate) struct PooledMap<V> {
+    index: T1haHashMap<Key, SlotId>,
+    pool: Pool<V>,
+}
+
+impl<V> PooledMap<V> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            index: T1haHashMap::with_capacity_and_hasher(capacity, Default::default()),
+            pool: Pool::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &Key) -> Option<&V> {
+        self.pool.get(*self.index.get(key)?)
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &Key) -> Option<&mut V> {
+        self.pool.get_mut(*self.index.get(key)?)
+    }
+
+    /// Insert `value` under `key`, returns the value it replaced
+    pub(crate) fn insert(&mut self, key: Key, value: V) -> Option<V> {
+        if let Some(current) = self.get_mut(&key) {
+            return Some(std::mem::replace(current, value));
+        }
+        let id = self.pool.insert(value);
+        self.index.insert(key, id);
+        None
+    }
+
+    pub(crate) fn remove(&mut self, key: &Key) -> Option<V> {
+        let id = self.index.remove(key)?;
+        self.pool.remove(id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Key, &V)> {
+        self.index
+            .iter()
+            .filter_map(|(key, id)| Some((*key, self.pool.get(*id)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_recycles_slots() {
+        let mut pool = Pool::with_capacity(2);
+        let a = pool.insert("a");
+        let b = pool.insert("b");
+        assert_eq!(pool.remove(a), Some("a"));
+        assert_eq!(pool.remove(a), None);
+        assert_eq!(pool.insert("c"), a);
+        assert_eq!(pool.get(b), Some(&"b"));
+        assert_eq!(pool.slots.len(), 2);
+    }
+
+    #[test]
+    fn test_pooled_map() {
+        let mut map = PooledMap::with_capacity(4);
+        assert_eq!(map.insert(1, "a"), None);
+        assert_eq!(map.insert(1, "b"), Some("a"));
+        assert_eq!(map.get(&1), Some(&"b"));
+        assert_eq!(map.remove(&1), Some("b"));
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.len(), 0);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::tinyufo::concurrent::ConcurrentTinyUFO;
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::stats::CacheStats;
+use std::any::Any;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What the registry needs of a cache, whatever its key and value types
+trait Managed: Send + Sync {
+    fn stats(&self) -> CacheStats;
+    fn weight_limit(&self) -> usize;
+    fn set_weight_limit(&self, limit: usize);
+    fn shards(&self) -> usize;
+}
+
+impl<K, T> Managed for ConcurrentTinyUFO<K, T>
+where
+    K: Hash + Send + Sync,
+    T: Clone + Send,
+{
+    fn stats(&self) -> CacheStats {
+        ConcurrentTinyUFO::stats(self)
+    }
+
+    fn weight_limit(&self) -> usize {
+        ConcurrentTinyUFO::weight_limit(self)
+    }
+
+    fn set_weight_limit(&self, limit: usize) {
+        ConcurrentTinyUFO::set_weight_limit(self, limit, |_, _| {});
+    }
+
+    fn shards(&self) -> usize {
+        ConcurrentTinyUFO::shards(self)
+    }
+}
+
+struct Registered {
+    name: Arc<str>,
+    share: usize,
+    cache: Arc<dyn Managed>,
+    // the same cache, to hand it back with its types
+    typed: Arc<dyn Any + Send + Sync>,
+}
+
+/// Several [`ConcurrentTinyUFO`]s of different key and value types sharing one weight budget.
+///
+/// Each cache is registered with a share of the budget, which sets its weight limit at first.
+/// [`Self::rebalance`] then moves the budget the caches don't use to the ones that are full,
+/// still in proportion to their shares, so that an application with several caches has one
+/// memory limit to think of rather than one per cache.
+pub struct CacheRegistry {
+    total_weight_limit: usize,
+    caches: Mutex<Vec<Registered>>,
+}
+
+impl CacheRegistry {
+    pub fn new(total_weight_limit: usize) -> Self {
+        Self {
+            total_weight_limit,
+            caches: Mutex::default(),
+        }
+    }
+
+    /// The cache called `name`, created with `share` parts of the budget (the other caches are
+    /// resized to make room) and sized for `capacity` entries if it isn't registered yet.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is registered with other key or value types.
+    pub fn register<K, T>(
+        &self,
+        name: &str,
+        share: usize,
+        capacity: usize,
+    ) -> Arc<ConcurrentTinyUFO<K, T>>
+    where
+        K: Hash + Send + Sync + 'static,
+        T: Clone + Send + 'static,
+    {
+        self.register_with(name, share, CacheConfig::new(0, capacity))
+    }
+
+    /// Like [`Self::register`], tuned by `config` but for its weight limit
+    pub fn register_with<K, T>(
+        &self,
+        name: &str,
+        share: usize,
+        mut config: CacheConfig,
+    ) -> Arc<ConcurrentTinyUFO<K, T>>
+    where
+        K: Hash + Send + Sync + 'static,
+        T: Clone + Send + 'static,
+    {
+        let mut caches = self.caches();
+        if let Some(registered) = caches.iter().find(|cache| &*cache.name == name) {
+            return registered
+                .typed
+                .clone()
+                .downcast()
+                .unwrap_or_else(|_| panic!("cache {name} is registered with other types"));
+        }
+        let share = share.max(1);
+        let shares = caches.iter().map(|cache| cache.share).sum::<usize>() + share;
+        for cache in caches.iter() {
+            cache
+                .cache
+                .set_weight_limit(self.part(cache.share, shares, self.total_weight_limit));
+        }
+        config.weight_limit = self.part(share, shares, self.total_weight_limit);
+        let cache = Arc::new(ConcurrentTinyUFO::from_config(&config));
+        caches.push(Registered {
+            name: name.into(),
+            share,
+            cache: cache.clone(),
+            typed: cache.clone(),
+        });
+        cache
+    }
+
+    /// The cache called `name`, if it is registered with these key and value types
+    pub fn get<K, T>(&self, name: &str) -> Option<Arc<ConcurrentTinyUFO<K, T>>>
+    where
+        K: Hash + Send + Sync + 'static,
+        T: Clone + Send + 'static,
+    {
+        let caches = self.caches();
+        let registered = caches.iter().find(|cache| &*cache.name == name)?;
+        registered.typed.clone().downcast().ok()
+    }
+
+    /// Give every cache what it uses, and split the rest of the budget over the full ones by
+    /// their shares. Meant to run periodically; shrinking a cache evicts right away
+    pub fn rebalance(&self) {
+        let caches = self.caches();
+        let demands: Vec<_> = caches
+            .iter()
+            .map(|registered| {
+                let used = registered.cache.stats().weight;
+                let limit = registered.cache.weight_limit();
+                // within a tenth of its limit, it would take more
+                if used >= limit - limit / 10 {
+                    usize::MAX
+                } else {
+                    // some headroom for what it is growing into and for the shards filling
+                    // unevenly
+                    used + used / 4 + registered.cache.shards()
+                }
+            })
+            .collect();
+        let shares: Vec<_> = caches.iter().map(|cache| cache.share).collect();
+        let limits = self.fill(&demands, &shares);
+        for (registered, limit) in caches.iter().zip(limits) {
+            if limit != registered.cache.weight_limit() {
+                registered.cache.set_weight_limit(limit);
+            }
+        }
+    }
+
+    /// Water-filling: caches demanding less than their part of what remains get their demand,
+    /// the rest split what is left by share
+    fn fill(&self, demands: &[usize], shares: &[usize]) -> Vec<usize> {
+        let mut limits = vec![0; demands.len()];
+        let mut open: Vec<usize> = (0..demands.len()).collect();
+        let mut remaining = self.total_weight_limit;
+        loop {
+            let total_share: usize = open.iter().map(|&i| shares[i]).sum();
+            let satisfied: Vec<usize> = open
+                .iter()
+                .copied()
+                .filter(|&i| demands[i] <= self.part(shares[i], total_share, remaining))
+                .collect();
+            if satisfied.is_empty() {
+                for &i in &open {
+                    limits[i] = self.part(shares[i], total_share, remaining);
+                }
+                return limits;
+            }
+            for &i in &satisfied {
+                limits[i] = demands[i];
+                remaining -= demands[i];
+            }
+            open.retain(|i| !satisfied.contains(i));
+            if open.is_empty() {
+                // everyone is served, what is left keeps room to grow
+                let total_share: usize = shares.iter().sum();
+                for (i, limit) in limits.iter_mut().enumerate() {
+                    *limit += remaining * shares[i] / total_share;
+                }
+                return limits;
+            }
+        }
+    }
+
+    /// `share` parts out of `shares` of `total`, at least 1
+    fn part(&self, share: usize, shares: usize, total: usize) -> usize {
+        ((total as u128 * share as u128 / shares.max(1) as u128) as usize).max(1)
+    }
+
+    /// Statistics of every cache, by name in the order they were registered
+    pub fn stats(&self) -> Vec<(Arc<str>, CacheStats)> {
+        self.caches()
+            .iter()
+            .map(|cache| (cache.name.clone(), cache.cache.stats()))
+            .collect()
+    }
+
+    /// Statistics summed over every cache
+    pub fn total_stats(&self) -> CacheStats {
+        let mut total = CacheStats::default();
+        for (_, stats) in self.stats() {
+            total += stats;
+        }
+        total
+    }
+
+    pub fn weight_limit(&self) -> usize {
+        self.total_weight_limit
+    }
+
+    fn caches(&self) -> MutexGuard<'_, Vec<Registered>> {
+        self.caches
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        let registry = CacheRegistry::new(1000);
+        let users = registry.register::<u64, String>("users", 1, 100);
+        assert_eq!(users.weight_limit(), 1000);
+        let pages = registry.register::<String, Vec<u8>>("pages", 3, 100);
+        assert_eq!((users.weight_limit(), pages.weight_limit()), (250, 750));
+
+        users.put(1, 1, "alice".to_string());
+        let again = registry.get::<u64, String>("users").unwrap();
+        assert_eq!(again.get(&1), Some("alice".to_string()));
+        assert!(registry.get::<u64, u64>("users").is_none());
+        assert!(registry.get::<u64, String>("nope").is_none());
+        assert_eq!(registry.total_stats().inserts, 1);
+        assert_eq!(registry.stats()[1].0.as_ref(), "pages");
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let registry = CacheRegistry::new(1000);
+        let mut config = CacheConfig::new(0, 1000);
+        config.shards = Some(2);
+        let hot = registry.register_with::<u64, u64>("hot", 1, config.clone());
+        let cold = registry.register_with::<u64, u64>("cold", 1, config);
+        for i in 0..10 {
+            cold.put(i, 1, i);
+        }
+        for i in 0..2000 {
+            hot.put(i, 1, i);
+        }
+        registry.rebalance();
+        // what cold doesn't use goes to hot
+        assert!(cold.weight_limit() >= 10 && cold.weight_limit() < 50);
+        assert_eq!(hot.weight_limit() + cold.weight_limit(), 1000);
+        assert_eq!(cold.stats().entries, 10);
+
+        for i in 0..2000 {
+            hot.put(i, 1, i);
+        }
+        assert!(hot.stats().weight > 500);
+    }
+
+    #[test]
+    fn test_fill() {
+        let registry = CacheRegistry::new(100);
+        assert_eq!(registry.fill(&[10, 20], &[1, 1]), vec![45, 55]);
+        assert_eq!(registry.fill(&[10, usize::MAX], &[1, 1]), vec![10, 90]);
+        assert_eq!(
+            registry.fill(&[usize::MAX, usize::MAX], &[1, 3]),
+            vec![25, 75]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use crate::tinyufo::concurrent::ConcurrentTinyUFO;
+use crate::tinyufo::config::CacheConfig;
+use crate::tinyufo::stats::CacheStats;
+use crate::tinyufo::tinyufo::TinyUFO;
+use crate::tinyufo::types::{hash_key, Key, Weight};
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Mutex, MutexGuard};
+use t1ha::T1haHasher;
+
+// sampling must not follow the shards or the map's probing, or a sample would be one shard
+const SAMPLE_SEED: u64 = 0x5348_4144_4f57_5353;
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct SampleHasher;
+
+impl BuildHasher for SampleHasher {
+    type Hasher = T1haHasher;
+
+    fn build_hasher(&self) -> T1haHasher {
+        T1haHasher::with_seed(SAMPLE_SEED)
+    }
+}
+
+/// Sizes and sampling of the shadow caches
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowConfig {
+    /// Weight limits of the shadows as multiples of the real cache's
+    pub scales: Vec<f64>,
+    /// Feed the shadows 1 key in `sampling`, each shadow shrunk as much
+    pub sampling: u32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            scales: vec![0.25, 0.5, 1.0, 2.0, 4.0],
+            sampling: 16,
+        }
+    }
+}
+
+struct Shadow {
+    weight_limit: usize,
+    cache: Mutex<TinyUFO<Key, ()>>,
+    requests: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Metadata only TinyUFOs of several sizes replaying a sample of the keys of a cache, to tell
+/// its miss ratio at these sizes from live traffic.
+///
+/// Keys are sampled by hash so that every access to a sampled key is seen, and each shadow holds
+/// the same share of its weight limit as of the keys, as in SHARDS.
+pub struct ShadowCaches {
+    shadows: Box<[Shadow]>,
+    sampling: Key,
+}
+
+impl ShadowCaches {
+    /// Shadows of the weight limits in `config`, scaled from a cache of `weight_limit` expecting
+    /// `capacity` entries
+    pub fn new(weight_limit: usize, capacity: usize, config: &ShadowConfig) -> Self {
+        let sampling = config.sampling.max(1) as usize;
+        let shadows = config
+            .scales
+            .iter()
+            .map(|scale| {
+                let weight_limit = (weight_limit as f64 * scale) as usize;
+                let capacity = (capacity as f64 * scale) as usize;
+                let config = CacheConfig::new(
+                    weight_limit.div_ceil(sampling),
+                    capacity.div_ceil(sampling).max(1),
+                );
+                Shadow {
+                    weight_limit,
+                    cache: Mutex::new(TinyUFO::from_config(&config)),
+                    requests: AtomicU64::new(0),
+                    misses: AtomicU64::new(0),
+                }
+            })
+            .collect();
+        Self {
+            shadows,
+            sampling: sampling as Key,
+        }
+    }
+
+    fn sampled<Q: Hash + ?Sized>(&self, key: &Q) -> Option<Key> {
+        let hash = hash_key(&SampleHasher, key);
+        hash.is_multiple_of(self.sampling).then_some(hash)
+    }
+
+    /// Count a read of `key` in every shadow
+    pub fn get<Q: Hash + ?Sized>(&self, key: &Q) {
+        let Some(key) = self.sampled(key) else {
+            return;
+        };
+        for shadow in self.shadows.iter() {
+            shadow.requests.fetch_add(1, Relaxed);
+            if lock(&shadow.cache).get(&key).is_none() {
+                shadow.misses.fetch_add(1, Relaxed);
+            }
+        }
+    }
+
+    pub fn put<Q: Hash + ?Sized>(&self, key: &Q, weight: Weight) {
+        if let Some(key) = self.sampled(key) {
+            for shadow in self.shadows.iter() {
+                lock(&shadow.cache).put(key, weight, ());
+            }
+        }
+    }
+
+    pub fn remove<Q: Hash + ?Sized>(&self, key: &Q) {
+        if let Some(key) = self.sampled(key) {
+            for shadow in self.shadows.iter() {
+                lock(&shadow.cache).remove(&key);
+            }
+        }
+    }
+
+    /// Miss ratio of every shadow so far, in the order of the configured scales
+    pub fn miss_ratio_curve(&self) -> Vec<MissRatio> {
+        self.shadows
+            .iter()
+            .map(|shadow| MissRatio {
+                weight_limit: shadow.weight_limit,
+                requests: shadow.requests.load(Relaxed),
+                misses: shadow.misses.load(Relaxed),
+            })
+            .collect()
+    }
+
+    /// Start counting over, keeping the content of the shadows
+    pub fn reset(&self) {
+        for shadow in self.shadows.iter() {
+            shadow.requests.store(0, Relaxed);
+            shadow.misses.store(0, Relaxed);
+        }
+    }
+}
+
+fn lock(shadow: &Mutex<TinyUFO<Key, ()>>) -> MutexGuard<'_, TinyUFO<Key, ()>> {
+    shadow
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A point of a miss ratio curve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissRatio {
+    /// Weight limit of the simulated cache, at full scale
+    pub weight_limit: usize,
+    /// Sampled reads
+    pub requests: u64,
+    pub misses: u64,
+}
+
+impl MissRatio {
+    /// Share of the reads missing, 0 without reads
+    pub fn miss_ratio(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.misses as f64 / self.requests as f64
+        }
+    }
+}
+
+/// [`ConcurrentTinyUFO`] feeding [`ShadowCaches`] of other sizes, answering what resizing it
+/// would change to its miss ratio.
+pub struct ShadowedTinyUFO<K, T: Clone> {
+    cache: ConcurrentTinyUFO<K, T>,
+    shadows: ShadowCaches,
+}
+
+impl<K: Hash, T: Clone> ShadowedTinyUFO<K, T> {
+    pub fn new(total_weight_limit: usize, capacity: usize, shadows: &ShadowConfig) -> Self {
+        Self::from_config(&CacheConfig::new(total_weight_limit, capacity), shadows)
+    }
+
+    pub fn from_config(config: &CacheConfig, shadows: &ShadowConfig) -> Self {
+        Self {
+            cache: ConcurrentTinyUFO::from_config(config),
+            shadows: ShadowCaches::new(config.weight_limit, config.capacity, shadows),
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shadows.get(key);
+        self.cache.get(key)
+    }
+
+    pub fn put(&self, key: K, weight: Weight, data: T) {
+        self.shadows.put(&key, weight);
+        self.cache.put(key, weight, data);
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.shadows.remove(key);
+        self.cache.remove(key)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// See [`ShadowCaches::miss_ratio_curve`]
+    pub fn miss_ratio_curve(&self) -> Vec<MissRatio> {
+        self.shadows.miss_ratio_curve()
+    }
+
+    pub fn shadows(&self) -> &ShadowCaches {
+        &self.shadows
+    }
+
+    pub fn cache(&self) -> &ConcurrentTinyUFO<K, T> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_miss_ratio_curve() {
+        let config = ShadowConfig {
+            scales: vec![0.5, 1.0, 2.0],
+            sampling: 4,
+        };
+        let cache = ShadowedTinyUFO::new(1000, 1000, &config);
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..100_000 {
+            // a working set of 2000 keys, uniformly read
+            let key = rng.u64(0..2000);
+            if cache.get(&key).is_none() {
+                cache.put(key, 1, ());
+            }
+        }
+        let curve = cache.miss_ratio_curve();
+        let limits: Vec<_> = curve.iter().map(|point| point.weight_limit).collect();
+        assert_eq!(limits, vec![500, 1000, 2000]);
+        // sampled 1 in 4
+        assert!((20_000..30_000).contains(&curve[0].requests));
+        assert!(curve[0].miss_ratio() > curve[1].miss_ratio());
+        assert!(curve[1].miss_ratio() > curve[2].miss_ratio());
+        assert!(curve[2].miss_ratio() < 0.05, "{:?}", curve[2]);
+
+        let stats = cache.stats();
+        let real = stats.misses as f64 / (stats.hits + stats.misses) as f64;
+        assert!(
+            (real - curve[1].miss_ratio()).abs() < 0.1,
+            "{real} {curve:?}"
+        );
+
+        cache.remove(&1);
+        cache.shadows().reset();
+        assert_eq!(cache.miss_ratio_curve()[0].requests, 0);
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// Counters the cache bumps as it serves requests
+///
+/// Relaxed atomics: every counter is independent and only read as a statistic.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    updates: AtomicU64,
+    rejections: AtomicU64,
+    evictions: AtomicU64,
+    removals: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn record_hit(&self) {
+        self.hits.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.misses.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_insert(&self) {
+        self.inserts.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_update(&self) {
+        self.updates.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_rejection(&self) {
+        self.rejections.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_evictions(&self, evictions: u64) {
+        self.evictions.fetch_add(evictions, Relaxed);
+    }
+
+    pub(crate) fn record_removal(&self) {
+        self.removals.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_expiration(&self) {
+        self.expirations.fetch_add(1, Relaxed);
+    }
+
+    /// Copy the counters, `entries` and `weight` describe the current content of the cache
+    pub(crate) fn snapshot(&self, entries: usize, weight: usize) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Relaxed),
+            misses: self.misses.load(Relaxed),
+            inserts: self.inserts.load(Relaxed),
+            updates: self.updates.load(Relaxed),
+            rejections: self.rejections.load(Relaxed),
+            evictions: self.evictions.load(Relaxed),
+            removals: self.removals.load(Relaxed),
+            expirations: self.expirations.load(Relaxed),
+            entries,
+            weight,
+        }
+    }
+}
+
+/// Point in time copy of the cache statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Puts of a key that wasn't cached
+    pub inserts: u64,
+    /// Puts that replaced the value of a cached key
+    pub updates: u64,
+    /// Puts of a key that wasn't cached the admission policy turned down
+    pub rejections: u64,
+    pub evictions: u64,
+    pub removals: u64,
+    /// Entries taken out past their time to live
+    pub expirations: u64,
+    /// Number of cached entries
+    pub entries: usize,
+    /// Total weight of the cached entries
+    pub weight: usize,
+}
+
+impl CacheStats {
+    /// Hits over lookups, 0 when nothing was looked up yet
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Sum the statistics of several caches, e.g. the shards of one
+impl std::ops::AddAssign for CacheStats {
+    fn add_assign(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.inserts += other.inserts;
+        self.updates += other.updates;
+        self.rejections += other.rejections;
+        self.evictions += other.evictions;
+        self.removals += other.removals;
+        self.expirations += other.expirations;
+        self.entries += other.entries;
+        self.weight += other.weight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let stats = Stats::default();
+        assert_eq!(stats.snapshot(0, 0).hit_ratio(), 0.0);
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        stats.record_evictions(2);
+
+        let snapshot = stats.snapshot(3, 7);
+        assert_eq!(snapshot.hit_ratio(), 0.75);
+        assert_eq!(snapshot.evictions, 2);
+        assert_eq!((snapshot.entries, snapshot.weight), (3, 7));
+    }
+}
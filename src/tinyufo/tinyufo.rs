@@ -1,191 +1,633 @@
+use crate::clock::{default_clock, Clock};
+use crate::tinyufo::admission::AdmissionPolicy;
+use crate::tinyufo::builder::TinyUfoBuilder;
+use crate::tinyufo::config::{CacheConfig, EvictionConfig};
 use crate::tinyufo::estimator::TinyLFU;
-use crate::tinyufo::types::Key;
-use std::collections::VecDeque;
+use crate::tinyufo::events::{CacheEventHandler, EvictionListener, RemovalCause};
+use crate::tinyufo::eviction::EvictionPolicy;
+use crate::tinyufo::pool::PooledMap;
+use crate::tinyufo::stats::{CacheStats, Stats};
+use crate::tinyufo::types::{hash_key, units, Key, Weigher, Weight};
+use crate::tinyufo::wheel::{ticks, ticks_ceil, TimerWheel};
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::{BuildHasher, Hash};
 use std::marker::PhantomData;
-use std::sync::atomic::Ordering::{Relaxed, SeqCst};
-use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize};
-use t1ha::T1haHashMap;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize};
+use std::sync::Arc;
+use std::time::Duration;
+use t1ha::T1haBuildHasher;
 
+#[cfg(all(feature = "mimalloc", not(target_arch = "wasm32")))]
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-const VEC_GROWTH_CAP: usize = 65536;
-const USES_CAP: u8 = 3;
+pub(crate) const USES_CAP: u8 = 3;
 
-type Weight = u16;
+/// `assert!` built with the `strict-checks` feature only, for the O(1) checks run on every
+/// mutation
+macro_rules! strict_assert {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "strict-checks") {
+            assert!($($arg)*);
+        }
+    };
+}
 
-const SMALL: bool = false;
-const MAIN: bool = true;
+// Entry state layout: 0b00CC_EQUU
+// UU: uses, 0..=the entry's cap
+const USES_MASK: u8 = 0b0000_0011;
+// CC: the entry's own cap on uses, 0 for USES_CAP
+const CAP_MASK: u8 = 0b0011_0000;
+const CAP_SHIFT: u8 = 4;
+// Q: 0: small, 1: main
+const MAIN: u8 = 0b0000_0100;
+// E: the entry is expired and should be treated as a miss
+const EXPIRED: u8 = 0b0000_1000;
 
 /// Cache entry holds its data and metadata
 struct Entry<T> {
-    /// We limit uses to 3, TODO: find better implementation bit vector to fit 3
-    pub uses: AtomicU8,
-    pub queue: AtomicBool,
-    // 0: small, 1: main
+    /// uses, queue and expired flag packed in one byte, every transition is a single CAS
+    pub state: AtomicU8,
     pub weight: Weight,
+    /// tick of the last put or hit, only kept up to date with a time to idle
+    pub accessed: AtomicU32,
     pub data: T,
 }
 
 impl<T> Entry<T> {
     pub(crate) fn new(data: T) -> Self {
         Self {
-            uses: AtomicU8::new(1),
-            queue: AtomicBool::new(SMALL),
+            state: AtomicU8::new(1),
             weight: Default::default(),
+            accessed: AtomicU32::new(0),
             data,
         }
     }
 
+    /// Apply `f` to the state until it sticks, returns the previous state
+    ///
+    /// `f` returns None to leave the state as it is.
+    fn update_state(&self, f: impl FnMut(u8) -> Option<u8>) -> u8 {
+        let state = match self.state.fetch_update(Relaxed, Relaxed, f) {
+            Ok(state) | Err(state) => state,
+        };
+        strict_assert!(
+            state & USES_MASK <= uses_cap(state),
+            "uses over their cap in state {state:#010b}"
+        );
+        state
+    }
+
     // Uses ----------------------------------------
     /// Increment the uses counter, return the new value
     pub(crate) fn incr_uses(&self) -> u8 {
-        loop {
-            let uses = self.uses();
-            if uses >= USES_CAP {
-                return uses;
-            }
+        let state = self.update_state(|state| {
+            let uses = state & USES_MASK;
+            (uses < uses_cap(state)).then_some(state + 1)
+        });
+        ((state & USES_MASK) + 1).min(uses_cap(state))
+    }
 
-            if let Err(new_uses) = self.uses.compare_exchange(uses, uses + 1, Relaxed, Relaxed) {
-                // someone else updated the uses
-                if new_uses >= USES_CAP {
-                    return new_uses;
-                } // else retry
-            } else {
-                return uses + 1;
-            }
-        }
+    /// Cap the uses of this entry at `cap`, 1 to [`USES_CAP`]. An entry capped at 1 is never
+    /// promoted to the main queue
+    pub(crate) fn set_uses_cap(&self, cap: u8) {
+        let cap = cap.clamp(1, USES_CAP);
+        let bits = if cap == USES_CAP { 0 } else { cap << CAP_SHIFT };
+        self.update_state(|state| {
+            let uses = (state & USES_MASK).min(cap);
+            Some(state & !(CAP_MASK | USES_MASK) | bits | uses)
+        });
     }
 
     /// Decrement the uses counter, return the previous value
     pub(crate) fn decr_uses(&self) -> u8 {
-        loop {
-            let uses = self.uses();
-            if uses == 0 {
-                return uses;
-            }
-
-            if let Err(new_uses) = self.uses.compare_exchange(uses, uses - 1, Relaxed, Relaxed) {
-                if new_uses == 0 {
-                    return new_uses;
-                } // else retry
-            } else {
-                return uses;
-            }
-        }
+        let state = self.update_state(|state| (state & USES_MASK > 0).then(|| state - 1));
+        state & USES_MASK
     }
 
     /// Get the uses counter
     pub(crate) fn uses(&self) -> u8 {
-        self.uses.load(Relaxed)
+        self.state.load(Relaxed) & USES_MASK
     }
 
+    // Queue ----------------------------------------
     /// Move the entry to the main queue
     pub(crate) fn move_to_main(&self) {
-        self.queue.store(MAIN, Relaxed);
+        self.state.fetch_or(MAIN, Relaxed);
+    }
+
+    /// Whether the entry lives in the main queue
+    pub(crate) fn is_main(&self) -> bool {
+        self.state.load(Relaxed) & MAIN != 0
+    }
+
+    // Expiration ----------------------------------------
+    /// Flag the entry as expired, it is a miss from now on and the first to go on eviction
+    pub(crate) fn expire(&self) {
+        self.state.fetch_or(EXPIRED, Relaxed);
+    }
+
+    /// Clear the expired flag, for a new value put under the key
+    pub(crate) fn renew(&self) {
+        self.state.fetch_and(!EXPIRED, Relaxed);
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        self.state.load(Relaxed) & EXPIRED != 0
+    }
+}
+
+fn uses_cap(state: u8) -> u8 {
+    match (state & CAP_MASK) >> CAP_SHIFT {
+        0 => USES_CAP,
+        cap => cap,
     }
 }
 
-struct EvictedEntry<T> {
+/// What [`FifoQueues::admit`] did with a put
+pub(crate) enum Admitted<T> {
+    /// A new key was cached
+    Inserted,
+    /// The data of a cached key was replaced
+    Updated,
+    /// The admission policy turned the new key down, the cache left as it was
+    Rejected(T),
+}
+
+pub(crate) struct EvictedEntry<T> {
     pub key: Key,
     // hashed key
-    pub data: T,
     pub weight: Weight,
+    pub data: T,
+}
+
+/// Share of the weight limit given to the small queue by default
+pub const DEFAULT_SMALL_QUEUE_PERCENT: u8 = 10;
+
+/// Uses over which an entry leaving the small queue is promoted to the main one by default:
+/// read once after its put
+pub const DEFAULT_PROMOTION_THRESHOLD: u8 = 1;
+
+/// Take the weight of the entry `key` off the weight of its queue, which must include it
+fn sub_weight(queue_weight: &AtomicUsize, weight: Weight, key: Key) {
+    let previous = queue_weight.fetch_sub(units(weight), Relaxed);
+    strict_assert!(
+        previous >= units(weight),
+        "queue weight {previous} short of the weight {weight} of {key}"
+    );
+}
+
+fn small_weight_limit(total_weight_limit: usize, small_queue_percent: u8) -> usize {
+    (total_weight_limit as f32 * small_queue_percent as f32 / 100.0).floor() as usize + 1
+}
+
+/// Hashes of the keys last evicted from the small queue, `capacity` of them at most. A key put
+/// again while still there was evicted too early: S3-FIFO admits it straight to the main queue
+struct Ghost {
+    queue: VecDeque<(Key, u64)>,
+    // the latest push of each key, its older slots in the queue are stale
+    keys: HashMap<Key, u64>,
+    pushes: u64,
+    capacity: usize,
 }
 
-const SMALL_QUEUE_PERCENTAGE: f32 = 0.1;
+impl Ghost {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            keys: HashMap::new(),
+            pushes: 0,
+            capacity,
+        }
+    }
+
+    fn push(&mut self, key: Key) {
+        self.pushes += 1;
+        self.keys.insert(key, self.pushes);
+        self.queue.push_back((key, self.pushes));
+        while self.queue.len() > self.capacity {
+            let Some((key, push)) = self.queue.pop_front() else {
+                break;
+            };
+            if self.keys.get(&key) == Some(&push) {
+                self.keys.remove(&key);
+            }
+        }
+    }
+
+    /// Take `key` out, returns whether it was there
+    fn take(&mut self, key: Key) -> bool {
+        self.keys.remove(&key).is_some()
+    }
+}
 
 // Experiment: We use S3FiFo https://s3fifo.com/ for admission policy
 // TODO: Double check with your own queue performance with VecDeque
-struct FifoQueues<T: Clone> {
+//
+// Memory ordering: the weight counters are only written under `&mut self`, together with the
+// queues they describe, so they never publish other data and Relaxed is enough. A reader
+// without `&mut` (stats) may see a value that is a few operations stale, never a torn one.
+// Entry state is a standalone flag word, Relaxed as well.
+struct FifoQueues<T> {
     small: VecDeque<Key>,
-    // 10% of the cache
     small_weight: AtomicUsize,
     main: VecDeque<Key>,
     main_weight: AtomicUsize,
-    estimator: TinyLFU, // as ghost queue
+    // the keys evicted from small, None to admit the keys the estimator counted twice before instead
+    ghost: Option<Ghost>,
+    estimator: TinyLFU,
+    // asked about the new keys whose put evicts, all admitted when None
+    admission: Option<Box<dyn AdmissionPolicy>>,
+    // in place of the queues, every entry then weighs on the main one
+    eviction: Option<Box<dyn EvictionPolicy>>,
 
+    small_queue_percent: u8,
     small_weight_limit: usize,
     total_weight_limit: usize,
+    // cap on the uses of the entries admitted, the lower of it and the put's
+    uses_cap: u8,
+    promotion_threshold: u8,
+    // entries last accessed at or before this tick are idle, treated as expired
+    idle_before: Option<u32>,
 
     _t: PhantomData<T>,
 }
 
-impl<T> FifoQueues<T>
-where
-    T: Clone,
-{
+impl<T> FifoQueues<T> {
     pub(crate) fn new(total_weight_limit: usize, capacity: usize) -> Self {
-        let small_weight_limit =
-            (total_weight_limit as f32 * SMALL_QUEUE_PERCENTAGE).floor() as usize + 1;
+        Self::with_estimator(total_weight_limit, capacity, TinyLFU::new(capacity))
+    }
+
+    pub(crate) fn with_estimator(
+        total_weight_limit: usize,
+        capacity: usize,
+        estimator: TinyLFU,
+    ) -> Self {
         Self {
+            ghost: Some(Ghost::new(capacity)),
+            admission: None,
+            eviction: None,
             small: VecDeque::with_capacity(capacity / 10), // 10% of the cache (heuristic
             small_weight: Default::default(),
             main: VecDeque::with_capacity(capacity),
             main_weight: Default::default(),
-            estimator: TinyLFU::new(capacity),
+            estimator,
+            small_queue_percent: DEFAULT_SMALL_QUEUE_PERCENT,
+            small_weight_limit: small_weight_limit(total_weight_limit, DEFAULT_SMALL_QUEUE_PERCENT),
             total_weight_limit,
-            small_weight_limit,
+            uses_cap: USES_CAP,
+            promotion_threshold: DEFAULT_PROMOTION_THRESHOLD,
+            idle_before: None,
             _t: PhantomData,
         }
     }
 
-    /// Admit a key to the fifos
+    /// Admit a key to the fifos, the entries evicted to make room for it are appended to `evicted`
+    ///
+    /// An entry heavier than the whole weight limit evicts every other one and is cached alone.
+    /// A new key the admission policy rejects evicts nothing, its data is handed back.
     pub(crate) fn admit(
         &mut self,
         key: Key,
         weight: Weight,
         data: T,
-        cache: &mut T1haHashMap<Key, Entry<T>>,
-    ) {
-        if let Some(current_entry) = cache.get(&key) {
-            // if the key is already in the cache, we just increment the uses
+        uses_cap: u8,
+        cache: &mut PooledMap<Entry<T>>,
+        evicted: &mut Vec<EvictedEntry<T>>,
+    ) -> Admitted<T> {
+        // taken out while making room so that it can't evict itself
+        if let Some(mut current_entry) = self.take(key, cache) {
+            // if the key is already in the cache, we replace the data and increment the uses,
+            // the new data fresh even if the old one was found expired
+            current_entry.renew();
+            current_entry.set_uses_cap(uses_cap.min(self.uses_cap));
             current_entry.incr_uses();
+            current_entry.weight = weight;
+            current_entry.data = data;
+            self.try_evict(weight, cache, evicted);
+            if let Some(policy) = &mut self.eviction {
+                // it may have been evicted meanwhile, forgotten by the policy
+                policy.remove(key);
+                policy.insert(key, weight);
+                self.main_weight.fetch_add(units(weight), Relaxed);
+            } else if current_entry.is_main() {
+                // its queue slot may have been popped meanwhile, a duplicate one is skipped later
+                self.main.push_back(key);
+                self.main_weight.fetch_add(units(weight), Relaxed);
+            } else {
+                self.small.push_back(key);
+                self.small_weight.fetch_add(units(weight), Relaxed);
+            }
+            let _ = cache.insert(key, current_entry);
+            self.strict_check(cache);
+            Admitted::Updated
         } else {
+            // evicted too early, or counted more than once before when there is no ghost queue,
+            // the sketch counting every new key
+            let to_main = match &mut self.ghost {
+                Some(ghost) => ghost.take(key),
+                None => self.estimator.get(key) > 1,
+            };
+            self.estimator.incr(key);
+            if !self.admits(key, weight, cache) {
+                // still evicted too early the next time it is put
+                if let Some(ghost) = self.ghost.as_mut().filter(|_| to_main) {
+                    ghost.push(key);
+                }
+                return Admitted::Rejected(data);
+            }
             let mut new_entry = Entry::new(data);
+            new_entry.set_uses_cap(uses_cap.min(self.uses_cap));
+            // always the weight added to the queue below, the queues must be able to subtract
+            // exactly what they added when the entry leaves
+            new_entry.weight = weight;
 
-            let evicts = self.try_evict(weight, cache);
-            if evicts.is_empty() {
-                // nothing is evicted, we can insert the new entry
-                new_entry.weight = weight;
+            self.try_evict(weight, cache, evicted);
+            // TODO: multithread checking
+            if let Some(policy) = &mut self.eviction {
+                new_entry.move_to_main();
+                policy.insert(key, weight);
+                self.main_weight.fetch_add(units(weight), Relaxed);
+            } else if to_main {
+                new_entry.move_to_main();
+                self.main.push_back(key);
+                self.main_weight.fetch_add(units(weight), Relaxed);
             } else {
-                // tinylfu: we check evicted entry and new one, if the new one has higher freq,
-                // we insert it, otherwise we put back the evicted entry
-                let new_freq = self.estimator.incr(key);
-                let evicted_first = &evicts[0];
-                let evicted_freq = self.estimator.get(evicted_first.key);
-                if evicted_freq < new_freq {
-                    new_entry.weight = weight;
-                } else {
-                    // new_entry.queue.store(SMALL, Relaxed); // default: insert it back to small, TODO
-                    new_entry.weight = evicted_first.weight;
-                }
+                self.small.push_back(key);
+                self.small_weight.fetch_add(units(weight), Relaxed);
             }
-            // TODO: multithread checking
-            // for all the cases, we insert new_entry to small
             let _ = cache.insert(key, new_entry);
-            self.small.push_back(key);
-            self.small_weight.fetch_add(weight as usize, SeqCst);
+            self.strict_check(cache);
+            Admitted::Inserted
+        }
+    }
+
+    /// Whether the admission policy lets the new `key` in, asked only when its put evicts
+    fn admits(&mut self, key: Key, weight: Weight, cache: &PooledMap<Entry<T>>) -> bool {
+        if self.admission.is_none() {
+            return true;
+        }
+        let fits = self.weight().saturating_add(units(weight)) <= self.total_weight_limit;
+        // only looked up when the put evicts, the eviction policy's may not be free
+        let victim = if fits { None } else { self.victim(cache) };
+        let Some(policy) = &mut self.admission else {
+            return true;
+        };
+        policy.record(key);
+        match victim {
+            Some(victim) => policy.admit(key, weight, victim, &self.estimator),
+            None => true,
+        }
+    }
+
+    /// Count a read of `key`, hit or miss, for the sketch and the admission policy
+    fn record(&mut self, key: Key) {
+        self.estimator.incr(key);
+        if let Some(policy) = &mut self.admission {
+            policy.record(key);
+        }
+    }
+
+    /// The entry eviction would look at first: the eviction policy's next key, else the front of
+    /// the queue it starts from, or of the other one if it has no entry
+    fn victim(&self, cache: &PooledMap<Entry<T>>) -> Option<Key> {
+        if let Some(policy) = &self.eviction {
+            return policy.victim();
+        }
+        let small_first = self.small_weight.load(Relaxed) > self.small_weight_limit;
+        let (first, second) = if small_first {
+            (&self.small, &self.main)
+        } else {
+            (&self.main, &self.small)
+        };
+        first
+            .iter()
+            .chain(second)
+            .find(|victim| cache.get(victim).is_some())
+            .copied()
+    }
+
+    /// Take `key` out of the map and the weight of its queue, its queue slot stays
+    fn take(&mut self, key: Key, cache: &mut PooledMap<Entry<T>>) -> Option<Entry<T>> {
+        let entry = cache.remove(&key)?;
+        let queue_weight = if entry.is_main() {
+            &self.main_weight
+        } else {
+            &self.small_weight
+        };
+        sub_weight(queue_weight, entry.weight, key);
+        Some(entry)
+    }
+
+    /// Remove `key` from the cache, its queue slot is skipped by later eviction passes
+    pub(crate) fn remove(&mut self, key: Key, cache: &mut PooledMap<Entry<T>>) -> Option<Entry<T>> {
+        let entry = self.take(key, cache)?;
+        if let Some(policy) = &mut self.eviction {
+            policy.remove(key);
+        }
+        self.strict_check(cache);
+        Some(entry)
+    }
+
+    /// Change the weight limit, evicting down to it right away
+    pub(crate) fn resize(
+        &mut self,
+        total_weight_limit: usize,
+        cache: &mut PooledMap<Entry<T>>,
+        evicted: &mut Vec<EvictedEntry<T>>,
+    ) {
+        self.total_weight_limit = total_weight_limit;
+        self.small_weight_limit = small_weight_limit(total_weight_limit, self.small_queue_percent);
+        self.try_evict(0, cache, evicted);
+        self.strict_check(cache);
+    }
+
+    /// Change the small queue's share, the queues rebalance through the next evictions
+    pub(crate) fn set_small_queue_percent(&mut self, percent: u8) {
+        self.small_queue_percent = percent.min(100);
+        self.small_weight_limit =
+            small_weight_limit(self.total_weight_limit, self.small_queue_percent);
+    }
+
+    pub(crate) fn weight_limit(&self) -> usize {
+        self.total_weight_limit
+    }
+
+    pub(crate) fn small_queue_percent(&self) -> u8 {
+        self.small_queue_percent
+    }
+
+    pub(crate) fn set_uses_cap(&mut self, cap: u8) {
+        self.uses_cap = cap.clamp(1, USES_CAP);
+    }
+
+    pub(crate) fn set_promotion_threshold(&mut self, threshold: u8) {
+        self.promotion_threshold = threshold.min(USES_CAP);
+    }
+
+    /// Evict with `policy` rather than the queues, handing it the cached entries in the order
+    /// the queues would have evicted them
+    pub(crate) fn set_eviction_policy(
+        &mut self,
+        mut policy: Box<dyn EvictionPolicy>,
+        cache: &PooledMap<Entry<T>>,
+    ) {
+        for key in self.eviction_order(cache) {
+            if let Some(entry) = cache.get(&key) {
+                entry.move_to_main();
+                policy.insert(key, entry.weight);
+            }
+        }
+        self.main_weight.store(self.weight(), Relaxed);
+        self.small_weight.store(0, Relaxed);
+        self.small.clear();
+        self.main.clear();
+        self.ghost = None;
+        self.eviction = Some(policy);
+    }
+
+    /// Keep a ghost queue of `capacity` keys, or none
+    pub(crate) fn set_ghost_queue(&mut self, capacity: Option<usize>) {
+        self.ghost = capacity.map(Ghost::new);
+    }
+
+    /// Current weight of both queues
+    pub(crate) fn weight(&self) -> usize {
+        self.small_weight.load(Relaxed) + self.main_weight.load(Relaxed)
+    }
+
+    /// Whether eviction takes `entry` first and never promotes it: expired or idle
+    fn is_stale(&self, entry: &Entry<T>) -> bool {
+        entry.is_expired()
+            || self
+                .idle_before
+                .is_some_and(|cutoff| entry.accessed.load(Relaxed) <= cutoff)
+    }
+
+    /// Cached keys in the order eviction would take them if nothing else happened: the small
+    /// queue front to back, less the entries used enough to be promoted, then the main queue
+    /// (promoted ones at its back) one pass of the clock after the other, the least used first
+    fn eviction_order(&self, cache: &PooledMap<Entry<T>>) -> Vec<Key> {
+        if let Some(policy) = &self.eviction {
+            let mut order = policy.eviction_order();
+            order.retain(|key| cache.get(key).is_some());
+            return order;
+        }
+        // a key put again while a stale copy of it is queued counts where eviction meets it first
+        let mut seen = HashSet::new();
+        let mut order = Vec::with_capacity(cache.len());
+        let mut promoted = Vec::new();
+        for &key in &self.small {
+            let Some(entry) = cache.get(&key) else {
+                continue;
+            };
+            if entry.is_main() || !seen.insert(key) {
+                continue;
+            }
+            if entry.uses() > self.promotion_threshold && !self.is_stale(entry) {
+                promoted.push(key);
+            } else {
+                order.push(key);
+            }
+        }
+
+        // uses 0 and 1 go on the first pass of the clock, 2 on the second, 3 on the third
+        let pass = |entry: &Entry<T>| {
+            if self.is_stale(entry) {
+                0
+            } else {
+                entry.uses().max(1)
+            }
+        };
+        let mut main = Vec::with_capacity(self.main.len() + promoted.len());
+        for &key in &self.main {
+            match cache.get(&key) {
+                Some(entry) if entry.is_main() && seen.insert(key) => main.push((pass(entry), key)),
+                _ => {}
+            }
+        }
+        for key in promoted {
+            if let Some(entry) = cache.get(&key) {
+                main.push((pass(entry), key));
+            }
         }
+        // stable: the queue order holds within a pass
+        main.sort_by_key(|&(pass, _)| pass);
+        order.extend(main.into_iter().map(|(_, key)| key));
+        order
+    }
+
+    /// Panic unless the queues agree with `cache`, see [`TinyUFO::check_invariants`]
+    fn check(&self, cache: &PooledMap<Entry<T>>) {
+        let small: HashSet<_> = self.small.iter().collect();
+        let main: HashSet<_> = self.main.iter().collect();
+        let (mut small_weight, mut main_weight) = (0, 0);
+        for (key, entry) in cache.iter() {
+            let state = entry.state.load(Relaxed);
+            assert!(
+                state & USES_MASK <= uses_cap(state),
+                "uses of {key} over their cap"
+            );
+            if self.eviction.is_some() {
+                assert!(entry.is_main(), "{key} not weighing on the main queue");
+                main_weight += units(entry.weight);
+            } else if entry.is_main() {
+                assert!(main.contains(&key), "{key} missing from the main queue");
+                main_weight += units(entry.weight);
+            } else {
+                assert!(small.contains(&key), "{key} missing from the small queue");
+                small_weight += units(entry.weight);
+            }
+        }
+        assert_eq!(
+            self.small_weight.load(Relaxed),
+            small_weight,
+            "small queue weight"
+        );
+        assert_eq!(
+            self.main_weight.load(Relaxed),
+            main_weight,
+            "main queue weight"
+        );
+        // an entry heavier than the limit is cached alone
+        assert!(
+            small_weight + main_weight <= self.total_weight_limit || cache.len() == 1,
+            "weight {} over the limit {}",
+            small_weight + main_weight,
+            self.total_weight_limit
+        );
+    }
+
+    /// The O(1) part of [`Self::check`], after every mutation with the `strict-checks` feature
+    fn strict_check(&self, cache: &PooledMap<Entry<T>>) {
+        strict_assert!(
+            self.eviction.is_some() || self.small.len() + self.main.len() >= cache.len(),
+            "{} entries but {} queue slots",
+            cache.len(),
+            self.small.len() + self.main.len()
+        );
+        strict_assert!(
+            self.weight() <= self.total_weight_limit || cache.len() <= 1,
+            "weight {} over the limit {}",
+            self.weight(),
+            self.total_weight_limit
+        );
     }
 
     /// Try to evict as many entries as possible to make room for the new entry.
     fn try_evict(
         &mut self,
         weight: Weight,
-        cache: &mut T1haHashMap<Key, Entry<T>>,
-    ) -> Vec<EvictedEntry<T>> {
-        let mut evicted = if self.total_weight_limit
-            < self.small_weight.load(SeqCst) + self.main_weight.load(SeqCst)
-        {
-            Vec::with_capacity(1)
-        } else {
-            vec![]
-        };
-
+        cache: &mut PooledMap<Entry<T>>,
+        evicted: &mut Vec<EvictedEntry<T>>,
+    ) {
+        // saturating, a weight near `Weight::MAX` still evicts everything and is cached alone
+        let weight = units(weight);
         while self.total_weight_limit
-            < self.small_weight.load(SeqCst) + self.main_weight.load(SeqCst)
+            < (self.small_weight.load(Relaxed) + self.main_weight.load(Relaxed))
+                .saturating_add(weight)
         {
             if let Some(evicted_item) = self.evict_one(cache) {
                 evicted.push(evicted_item);
@@ -193,132 +635,892 @@ where
                 break;
             }
         }
-        evicted
     }
 
     /// Evict one entry from the cache
     ///
-    /// Algorithm: we will try to evict from small first then main.
-    fn evict_one(&mut self, cache: &mut T1haHashMap<Key, Entry<T>>) -> Option<EvictedEntry<T>> {
-        if self.small_weight.load(SeqCst) > self.small_weight_limit {
+    /// Algorithm: we will try to evict from small first then main, and from small anyway when
+    /// main is empty, or a small queue under its limit would never give room.
+    fn evict_one(&mut self, cache: &mut PooledMap<Entry<T>>) -> Option<EvictedEntry<T>> {
+        if let Some(policy) = &mut self.eviction {
+            loop {
+                let key = policy.evict()?;
+                if let Some(entry) = cache.remove(&key) {
+                    sub_weight(&self.main_weight, entry.weight, key);
+                    return Some(EvictedEntry {
+                        key,
+                        weight: entry.weight,
+                        data: entry.data,
+                    });
+                }
+            }
+        }
+        if self.small_weight.load(Relaxed) > self.small_weight_limit {
             if let Some(evicted) = self.evict_small(cache) {
                 return Some(evicted);
             }
         }
 
+        // evicting from small may only have promoted its entries to main
         self.evict_main(cache)
+            .or_else(|| self.evict_small(cache))
+            .or_else(|| self.evict_main(cache))
     }
 
     /// Evict one entry from the small queue
-    fn evict_small(&mut self, cache: &mut T1haHashMap<Key, Entry<T>>) -> Option<EvictedEntry<T>> {
+    fn evict_small(&mut self, cache: &mut PooledMap<Entry<T>>) -> Option<EvictedEntry<T>> {
         loop {
             let to_evict = self.small.pop_front()?;
 
-            if let Some(entry) = cache.get_mut(&to_evict) {
-                if entry.uses() > 1 {
-                    entry.move_to_main();
-                    self.main.push_back(to_evict);
-                    self.main_weight.fetch_add(entry.weight as usize, SeqCst);
-                    continue;
-                }
-                let weight = entry.weight;
-                let data = entry.data.clone();
-                cache.remove(&to_evict);
-                self.small_weight.fetch_sub(weight as usize, SeqCst);
-                return Some(EvictedEntry {
-                    key: to_evict,
-                    data,
-                    weight,
-                });
+            let Some(entry) = cache.get(&to_evict) else {
+                // removed while queued
+                continue;
+            };
+            if entry.is_main() {
+                // stale: removed and put again while queued
+                continue;
             }
-            return None;
+            if entry.uses() > self.promotion_threshold && !self.is_stale(entry) {
+                entry.move_to_main();
+                self.main.push_back(to_evict);
+                sub_weight(&self.small_weight, entry.weight, to_evict);
+                self.main_weight.fetch_add(units(entry.weight), Relaxed);
+                continue;
+            }
+            // expired or idle ones weren't evicted too early
+            let stale = self.is_stale(entry);
+            // the slot goes back to the pool, the data is moved out instead of cloned
+            let entry = cache.remove(&to_evict)?;
+            sub_weight(&self.small_weight, entry.weight, to_evict);
+            if let Some(ghost) = self.ghost.as_mut().filter(|_| !stale) {
+                ghost.push(to_evict);
+            }
+            return Some(EvictedEntry {
+                key: to_evict,
+                weight: entry.weight,
+                data: entry.data,
+            });
         }
     }
 
     /// Evict one entry from the main queue
-    fn evict_main(&mut self, cache: &mut T1haHashMap<Key, Entry<T>>) -> Option<EvictedEntry<T>> {
+    fn evict_main(&mut self, cache: &mut PooledMap<Entry<T>>) -> Option<EvictedEntry<T>> {
         loop {
             let to_evict = self.main.pop_front()?;
 
-            if let Some(entry) = cache.get_mut(&to_evict) {
-                // we decr the use, if it's still in use, we move it back to the main queue
-                if entry.decr_uses() > 0 {
-                    self.main.push_back(to_evict);
-                    continue;
-                }
-                let weight = entry.weight;
-                let data = entry.data.clone();
-                cache.remove(&to_evict);
-                self.main_weight.fetch_sub(weight as usize, SeqCst);
-                return Some(EvictedEntry {
-                    key: to_evict,
-                    data,
-                    weight,
-                });
+            let Some(entry) = cache.get(&to_evict) else {
+                // removed while queued
+                continue;
+            };
+            if !entry.is_main() {
+                // stale: removed and put again while queued
+                continue;
             }
-
-            return None;
+            // we decr the use, if it's still in use, we move it back to the main queue
+            if !self.is_stale(entry) && entry.decr_uses() > 0 {
+                self.main.push_back(to_evict);
+                continue;
+            }
+            let entry = cache.remove(&to_evict)?;
+            sub_weight(&self.main_weight, entry.weight, to_evict);
+            return Some(EvictedEntry {
+                key: to_evict,
+                weight: entry.weight,
+                data: entry.data,
+            });
         }
     }
 }
 
-fn update_weight_atomic(weight: &AtomicUsize, old: u16, new: u16) {
-    let diff = new.abs_diff(old);
-    if diff == 0 {
-        return;
+/// Deadlines of the entries put with a time to live, and the time to idle of them all
+struct Timers {
+    clock: Arc<dyn Clock>,
+    wheel: TimerWheel,
+    deadlines: HashMap<Key, Duration>,
+    // in ticks of the wheel
+    idle: Option<u32>,
+}
+
+impl Timers {
+    fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            wheel: TimerWheel::new(clock.now()),
+            clock,
+            deadlines: HashMap::new(),
+            idle: None,
+        }
+    }
+
+    /// Whether `key` has a deadline and it passed, only reads the clock for those that have one
+    fn is_past(&self, key: Key) -> bool {
+        self.deadlines
+            .get(&key)
+            .is_some_and(|&at| self.clock.now() >= at)
+    }
+
+    /// Whether `entry` wasn't accessed for the time to idle, if there is one
+    fn is_idle<T>(&self, entry: &Entry<T>) -> bool {
+        self.idle
+            .is_some_and(|idle| self.tick().saturating_sub(entry.accessed.load(Relaxed)) >= idle)
     }
 
-    if new > old {
-        weight.fetch_add(diff as usize, SeqCst);
-    } else {
-        weight.fetch_sub(diff as usize, SeqCst);
+    fn tick(&self) -> u32 {
+        ticks(self.clock.now()) as u32
+    }
+
+    /// With a time to idle, the current tick and the last one an idle entry was accessed at
+    fn idle_ticks(&self) -> Option<(u32, Option<u32>)> {
+        let idle = self.idle?;
+        let tick = self.tick();
+        Some((tick, tick.checked_sub(idle)))
     }
 }
 
 /// TinyLFU cache
 /// paper: https://arxiv.org/pdf/1512.00727.pdf
 /// Tuning knobs based on dataset and hardware: evict_window,
-struct TinyUFO<K, T>
-where
-    T: Clone,
-{
-    capacity: usize,
-    cache: T1haHashMap<Key, Entry<T>>,
+///
+/// Every entry carries a metadata `M` besides its value, `()` by default so that it costs
+/// nothing. It is set by [`Self::put_with_meta`], read by [`Self::get_with_meta`] and handed
+/// over with the value of the evicted entries, and doesn't weigh on the entry.
+///
+/// Values are moved in and out, never cloned, so `T` needn't be `Clone`: `get` lends them and
+/// eviction and `remove` hand them over. Store an `Arc<T>` to keep cheap handles past the borrow.
+///
+/// Keys are hashed with `S`, t1ha by default. Only the 64 bit hash is kept, pick a keyed hasher
+/// such as `std`'s `RandomState` when keys come from untrusted input.
+///
+/// Keeping only the hash is this cache's compact layout: two keys hashing alike share an entry,
+/// one reading the other's value. It stays the default rather than an opt-in because the
+/// concurrent, namespaced and loading caches are built on it without requiring `K: Clone + Eq`
+/// or paying for a second copy of every key. Use
+/// [`CheckedTinyUFO`](crate::tinyufo::CheckedTinyUFO), which stores the full keys and checks
+/// them on reads, when a wrong value is worse than the memory, or the `key128` feature to make
+/// collisions out of reach.
+pub struct TinyUFO<K, T, M = (), S = T1haBuildHasher> {
+    cache: PooledMap<Entry<(T, M)>>,
     // storage backend
-    queues: FifoQueues<T>,
+    queues: FifoQueues<(T, M)>,
+    // reused across puts so that evicting doesn't allocate
+    evicted: Vec<EvictedEntry<(T, M)>>,
+    stats: Stats,
+    events: Option<Arc<dyn CacheEventHandler>>,
+    listener: Option<EvictionListener<T>>,
+    weigher: Option<Weigher<K, T>>,
+    // only once an entry is put with a time to live or a clock is given
+    timers: Option<Timers>,
+    capacity: usize,
+    hasher: S,
 
     _k: PhantomData<K>,
 }
 
-impl<K: Hash, T: Clone> TinyUFO<K, T> {
+impl<K: Hash, T> TinyUFO<K, T> {
     /// Create a new TinyLFU cache with a given capacity.
     pub fn new(total_weight_limit: usize, capacity: usize) -> Self {
+        Self::with_queues(
+            capacity,
+            FifoQueues::new(total_weight_limit, capacity),
+            T1haBuildHasher::default(),
+        )
+    }
+
+    /// Create a cache tuned by `config`
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self::with_metadata(config)
+    }
+
+    /// Set up a cache, or a concurrent one, setting by setting
+    pub fn builder() -> TinyUfoBuilder<K, T> {
+        TinyUfoBuilder::new()
+    }
+}
+
+impl<K: Hash, T, M> TinyUFO<K, T, M> {
+    /// Create a cache tuned by `config` whose entries carry an `M`
+    pub fn with_metadata(config: &CacheConfig) -> Self {
+        Self::with_hasher(config, T1haBuildHasher::default())
+    }
+}
+
+impl<K: Hash, T, M, S: BuildHasher> TinyUFO<K, T, M, S> {
+    /// Create a cache tuned by `config` hashing its keys with `hasher`
+    pub fn with_hasher(config: &CacheConfig, hasher: S) -> Self {
+        let estimator = config.estimator.build(config.capacity);
+        let mut cache = Self::with_queues(
+            config.capacity,
+            FifoQueues::with_estimator(config.weight_limit, config.capacity, estimator),
+            hasher,
+        );
+        if let Some(percent) = config.small_queue_percent {
+            cache.set_small_queue_percent(percent);
+        }
+        if let Some(cap) = config.uses_cap {
+            cache.set_uses_cap(cap);
+        }
+        if let Some(threshold) = config.promotion_threshold {
+            cache.set_promotion_threshold(threshold);
+        }
+        if let Some(enabled) = config.ghost_queue {
+            cache.set_ghost_queue(enabled);
+        }
+        if let Some(ms) = config.time_to_idle_ms {
+            cache.set_time_to_idle(Duration::from_millis(ms));
+        }
+        if let Some(admission) = &config.admission {
+            cache.set_admission_policy(admission.build());
+        }
+        if let Some(policy) = config.eviction.as_ref().and_then(EvictionConfig::build) {
+            cache.set_eviction_policy(policy);
+        }
+        cache
+    }
+
+    fn with_queues(capacity: usize, queues: FifoQueues<(T, M)>, hasher: S) -> Self {
         Self {
-            cache: T1haHashMap::with_capacity_and_hasher(capacity, Default::default()),
+            cache: PooledMap::with_capacity(capacity),
+            queues,
+            evicted: Vec::new(),
+            stats: Stats::default(),
+            events: None,
+            listener: None,
+            weigher: None,
+            timers: None,
             capacity,
-            queues: FifoQueues::new(total_weight_limit, capacity),
+            hasher,
 
             _k: PhantomData,
         }
     }
 
+    /// Ask `policy` whether to cache the new keys whose put would evict, see [`AdmissionPolicy`].
+    /// All are by default
+    pub fn with_admission_policy(mut self, policy: impl AdmissionPolicy + 'static) -> Self {
+        self.set_admission_policy(Box::new(policy));
+        self
+    }
+
+    pub(crate) fn set_admission_policy(&mut self, policy: Box<dyn AdmissionPolicy>) {
+        self.queues.admission = Some(policy);
+    }
+
+    /// Evict with `policy` instead of the S3-FIFO queues, see [`EvictionPolicy`]. The entries
+    /// already cached are handed to it
+    pub fn with_eviction_policy(mut self, policy: impl EvictionPolicy + 'static) -> Self {
+        self.set_eviction_policy(Box::new(policy));
+        self
+    }
+
+    pub(crate) fn set_eviction_policy(&mut self, policy: Box<dyn EvictionPolicy>) {
+        self.queues.set_eviction_policy(policy, &self.cache);
+    }
+
+    /// Have `handler` told about the inserts, hits, misses and evictions from now on
+    pub fn with_event_handler(mut self, handler: Arc<dyn CacheEventHandler>) -> Self {
+        self.set_event_handler(Some(handler));
+        self
+    }
+
+    /// Hand every entry evicting, resizing or expiring takes out to `listener`, along with its
+    /// weight and why, before the value moves on to the `on_evict` of the call if there is one
+    pub fn with_eviction_listener(
+        mut self,
+        listener: impl Fn(Key, &T, Weight, RemovalCause) + Send + Sync + 'static,
+    ) -> Self {
+        self.set_eviction_listener(Arc::new(listener));
+        self
+    }
+
+    pub(crate) fn set_eviction_listener(&mut self, listener: EvictionListener<T>) {
+        self.listener = Some(listener);
+    }
+
+    /// Weigh the entries of [`Self::insert`] with `weigher`, rather than each caller
+    pub fn with_weigher(
+        mut self,
+        weigher: impl Fn(&K, &T) -> Weight + Send + Sync + 'static,
+    ) -> Self {
+        self.set_weigher(Arc::new(weigher));
+        self
+    }
+
+    pub(crate) fn set_weigher(&mut self, weigher: Weigher<K, T>) {
+        self.weigher = Some(weigher);
+    }
+
+    /// Measure the times to live with `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        let idle = self.timers.as_ref().and_then(|timers| timers.idle);
+        self.timers = Some(Timers {
+            idle,
+            ..Timers::new(clock)
+        });
+    }
+
+    /// Have the entries expire once not read for `tti`, to the second: a get finds them
+    /// missing and eviction takes them first
+    pub fn expire_after_access(mut self, tti: Duration) -> Self {
+        self.set_time_to_idle(tti);
+        self
+    }
+
+    pub(crate) fn set_time_to_idle(&mut self, tti: Duration) {
+        let timers = self
+            .timers
+            .get_or_insert_with(|| Timers::new(default_clock()));
+        timers.idle = Some(ticks_ceil(tti).max(1) as u32);
+    }
+
+    pub(crate) fn set_event_handler(&mut self, handler: Option<Arc<dyn CacheEventHandler>>) {
+        self.events = handler;
+    }
+
     /// Get a value from the cache.
-    pub fn get(&mut self, key: &K) -> Option<&T> {
-        let hashed_key = self.cache.hasher().hash_one(key);
-        return if let Some(entry) = self.cache.get(&hashed_key) {
-            entry.incr_uses();
-            Some(&entry.data)
-        } else {
-            None
-        };
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.get_with_meta(key).map(|(data, _)| data)
+    }
+
+    /// Same as [`Self::get`], along with the metadata of the entry
+    pub fn get_with_meta<Q>(&mut self, key: &Q) -> Option<(&T, &M)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hashed_key = hash_key(&self.hasher, key);
+        self.get_hashed(hashed_key, |_| true)
+            .map(|(data, meta)| (data, meta))
+    }
+
+    /// Look `hashed_key` up, an entry whose metadata doesn't `matches` is a miss
+    pub(crate) fn get_hashed(
+        &mut self,
+        hashed_key: Key,
+        matches: impl FnOnce(&M) -> bool,
+    ) -> Option<&(T, M)> {
+        let tick = self.check_timers(hashed_key);
+        // misses count too, a key read often gets in once put
+        self.queues.record(hashed_key);
+        match self.cache.get(&hashed_key) {
+            Some(entry) if !entry.is_expired() && matches(&entry.data.1) => {
+                if let Some(tick) = tick {
+                    entry.accessed.store(tick, Relaxed);
+                }
+                entry.incr_uses();
+                if let Some(policy) = &mut self.queues.eviction {
+                    policy.access(hashed_key);
+                }
+                self.stats.record_hit();
+                if let Some(events) = &self.events {
+                    events.on_hit(hashed_key, entry.weight);
+                }
+                Some(&entry.data)
+            }
+            _ => {
+                self.stats.record_miss();
+                if let Some(events) = &self.events {
+                    events.on_miss(hashed_key);
+                }
+                None
+            }
+        }
     }
 
-    /// Set a key-value pair in the cache.
+    /// Same as [`Self::get`] but the value can be modified in place, its weight stays the same
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hashed_key = hash_key(&self.hasher, key);
+        let tick = self.check_timers(hashed_key);
+        self.queues.record(hashed_key);
+        match self.cache.get_mut(&hashed_key) {
+            Some(entry) if !entry.is_expired() => {
+                if let Some(tick) = tick {
+                    entry.accessed.store(tick, Relaxed);
+                }
+                entry.incr_uses();
+                if let Some(policy) = &mut self.queues.eviction {
+                    policy.access(hashed_key);
+                }
+                self.stats.record_hit();
+                if let Some(events) = &self.events {
+                    events.on_hit(hashed_key, entry.weight);
+                }
+                Some(&mut entry.data.0)
+            }
+            _ => {
+                self.stats.record_miss();
+                if let Some(events) = &self.events {
+                    events.on_miss(hashed_key);
+                }
+                None
+            }
+        }
+    }
+
+    /// Look at a value without it counting as an access: neither its uses nor the stats move
+    pub fn peek<Q>(&self, key: &Q) -> Option<&T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.peek_with_meta(key).map(|(data, _)| data)
+    }
+
+    /// Same as [`Self::peek`], along with the metadata of the entry
+    pub fn peek_with_meta<Q>(&self, key: &Q) -> Option<(&T, &M)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hashed_key = hash_key(&self.hasher, key);
+        let entry = self.cache.get(&hashed_key)?;
+        let timed_out = self
+            .timers
+            .as_ref()
+            .is_some_and(|timers| timers.is_past(hashed_key) || timers.is_idle(entry));
+        // can't remove it from here, flag it so that eviction takes it first
+        if timed_out {
+            entry.expire();
+        }
+        let (data, meta) = &entry.data;
+        (!entry.is_expired()).then_some((data, meta))
+    }
+
+    /// Uses of a cached value, 1 when put to the cap of 3, without it counting as an access
+    pub(crate) fn uses<Q>(&self, key: &Q) -> Option<u8>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hashed_key = hash_key(&self.hasher, key);
+        self.cache.get(&hashed_key).map(|entry| entry.uses())
+    }
+
+    /// Set a key-value pair in the cache, replacing the data if the key is already cached.
     ///
     /// Cache is fixed with capacity and it doesn't grow
-    pub fn put(&mut self, key: K, weight: Weight, data: T) {
-        let hashed_key = self.cache.hasher().hash_one(&key);
-        self.queues.admit(hashed_key, weight, data, &mut self.cache);
+    pub fn put(&mut self, key: K, weight: Weight, data: T)
+    where
+        M: Default,
+    {
+        self.put_evicting(key, weight, data, |_, _| {});
+    }
+
+    /// Same as [`Self::put`] weighing the entry with the weigher, 1 without one
+    pub fn insert(&mut self, key: K, data: T)
+    where
+        M: Default,
+    {
+        let weight = self.weigh(&key, &data);
+        self.put(key, weight, data);
+    }
+
+    fn weigh(&self, key: &K, data: &T) -> Weight {
+        self.weigher
+            .as_ref()
+            .map_or(1, |weigher| weigher(key, data))
+    }
+
+    /// Same as [`Self::put`] but hands the hashed key and data of every entry evicted to make
+    /// room to `on_evict`, and those of a new key the admission policy rejected.
+    pub fn put_evicting(
+        &mut self,
+        key: K,
+        weight: Weight,
+        data: T,
+        mut on_evict: impl FnMut(Key, T),
+    ) where
+        M: Default,
+    {
+        self.put_capped(key, weight, data, M::default(), USES_CAP, |key, data, _| {
+            on_evict(key, data)
+        });
+    }
+
+    /// Same as [`Self::put_evicting`], returning the hashed key and data of the entries evicted
+    /// instead, e.g. to hand them to a second-level cache
+    pub fn put_returning_evicted(&mut self, key: K, weight: Weight, data: T) -> Vec<(Key, T)>
+    where
+        M: Default,
+    {
+        let mut evicted = Vec::new();
+        self.put_evicting(key, weight, data, |key, data| evicted.push((key, data)));
+        evicted
+    }
+
+    /// Same as [`Self::put`] with `meta` as the metadata of the entry
+    pub fn put_with_meta(&mut self, key: K, weight: Weight, data: T, meta: M) {
+        self.put_with_meta_evicting(key, weight, data, meta, |_, _, _| {});
+    }
+
+    /// Same as [`Self::put_evicting`], `on_evict` getting the metadata of the entries too
+    pub fn put_with_meta_evicting(
+        &mut self,
+        key: K,
+        weight: Weight,
+        data: T,
+        meta: M,
+        on_evict: impl FnMut(Key, T, M),
+    ) {
+        self.put_capped(key, weight, data, meta, USES_CAP, on_evict);
+    }
+
+    /// Same as [`Self::put_with_meta_evicting`] with the uses of the entry capped at
+    /// `uses_cap`, 1 to 3: a lower cap lets it fall out of the main queue sooner, 1 keeps it out
+    /// of it
+    pub(crate) fn put_capped(
+        &mut self,
+        key: K,
+        weight: Weight,
+        data: T,
+        meta: M,
+        uses_cap: u8,
+        on_evict: impl FnMut(Key, T, M),
+    ) {
+        let hashed_key = hash_key(&self.hasher, &key);
+        self.put_hashed(hashed_key, weight, data, meta, uses_cap, on_evict);
+    }
+
+    pub(crate) fn put_hashed(
+        &mut self,
+        hashed_key: Key,
+        weight: Weight,
+        data: T,
+        meta: M,
+        uses_cap: u8,
+        mut on_evict: impl FnMut(Key, T, M),
+    ) {
+        if let Some(timers) = &mut self.timers {
+            // a put without a time to live keeps the value for good
+            timers.deadlines.remove(&hashed_key);
+        }
+        // what expired makes room before anything is evicted
+        self.purge_expired();
+        let idle = self.timers.as_ref().and_then(Timers::idle_ticks);
+        self.queues.idle_before = idle.and_then(|(_, before)| before);
+        let mut evicted = std::mem::take(&mut self.evicted);
+        let admitted = self.queues.admit(
+            hashed_key,
+            weight,
+            (data, meta),
+            uses_cap,
+            &mut self.cache,
+            &mut evicted,
+        );
+        if let Some((tick, _)) = idle {
+            if let Some(entry) = self.cache.get(&hashed_key) {
+                entry.accessed.store(tick, Relaxed);
+            }
+        }
+        match admitted {
+            Admitted::Inserted => {
+                self.stats.record_insert();
+                if let Some(events) = &self.events {
+                    events.on_insert(hashed_key, weight);
+                }
+            }
+            Admitted::Updated => {
+                self.stats.record_update();
+                if let Some(events) = &self.events {
+                    events.on_update(hashed_key, weight);
+                }
+            }
+            Admitted::Rejected((data, meta)) => {
+                // never cached, so neither inserted nor evicted: only handed back
+                self.stats.record_rejection();
+                on_evict(hashed_key, data, meta);
+            }
+        }
+        self.stats.record_evictions(evicted.len() as u64);
+        for entry in evicted.drain(..) {
+            self.notify_eviction(entry.key, entry.weight, &entry.data.0, RemovalCause::Size);
+            if let Some(timers) = &mut self.timers {
+                timers.deadlines.remove(&entry.key);
+            }
+            let (data, meta) = entry.data;
+            on_evict(entry.key, data, meta);
+        }
+        self.evicted = evicted;
+    }
+
+    /// Same as [`Self::put`], the entry expiring `ttl` from now. It is a miss from then on and
+    /// taken out of the cache by the next put or [`Self::purge_expired`] past its deadline
+    pub fn put_with_ttl(&mut self, key: K, weight: Weight, data: T, ttl: Duration)
+    where
+        M: Default,
+    {
+        let hashed_key = hash_key(&self.hasher, &key);
+        self.put_hashed(
+            hashed_key,
+            weight,
+            data,
+            M::default(),
+            USES_CAP,
+            |_, _, _| {},
+        );
+        if self.cache.get(&hashed_key).is_none() {
+            // rejected by the admission policy
+            return;
+        }
+        let timers = self
+            .timers
+            .get_or_insert_with(|| Timers::new(default_clock()));
+        let deadline = timers.clock.now() + ttl;
+        timers.deadlines.insert(hashed_key, deadline);
+        timers.wheel.schedule(hashed_key, deadline);
+    }
+
+    /// Take the entries past their time to live out of the cache, returns how many. Puts do it
+    /// too, this frees their weight without waiting for one
+    pub fn purge_expired(&mut self) -> usize {
+        let Some(timers) = &mut self.timers else {
+            return 0;
+        };
+        if timers.deadlines.is_empty() {
+            return 0;
+        }
+        let now = timers.clock.now();
+        let mut due = Vec::new();
+        timers.wheel.advance(now, &mut due);
+        // the wheel hands back stale keys too: put again, removed or evicted since
+        due.retain(|key| match timers.deadlines.get(key) {
+            Some(&at) if at <= now => timers.deadlines.remove(key).is_some(),
+            _ => false,
+        });
+        for &key in &due {
+            self.expire(key);
+        }
+        due.len()
+    }
+
+    /// Expire `key` now if it is past its time to live or idle for too long. Returns the
+    /// current tick for a hit to record its access, with a time to idle only
+    fn check_timers(&mut self, hashed_key: Key) -> Option<u32> {
+        let timers = self.timers.as_mut()?;
+        let idle = self
+            .cache
+            .get(&hashed_key)
+            .is_some_and(|entry| timers.is_idle(entry));
+        if idle || timers.is_past(hashed_key) {
+            timers.deadlines.remove(&hashed_key);
+            self.expire(hashed_key);
+            return None;
+        }
+        timers.idle_ticks().map(|(tick, _)| tick)
+    }
+
+    fn expire(&mut self, hashed_key: Key) {
+        if let Some(entry) = self.queues.remove(hashed_key, &mut self.cache) {
+            self.stats.record_expiration();
+            self.notify_eviction(
+                hashed_key,
+                entry.weight,
+                &entry.data.0,
+                RemovalCause::Expired,
+            );
+        }
+    }
+
+    /// Tell the event handler and the eviction listener that an entry left on its own
+    fn notify_eviction(&self, hashed_key: Key, weight: Weight, data: &T, cause: RemovalCause) {
+        if let Some(events) = &self.events {
+            events.on_evict(hashed_key, weight, cause);
+        }
+        if let Some(listener) = &self.listener {
+            listener(hashed_key, data, weight, cause);
+        }
+    }
+
+    /// Get the value of `key`, computing it with `f` and putting it on a miss. The key is hashed
+    /// once for both, `f` only runs on a miss
+    pub fn get_or_insert_with(&mut self, key: K, weight: Weight, f: impl FnOnce() -> T) -> &T
+    where
+        M: Default,
+    {
+        let hashed_key = hash_key(&self.hasher, &key);
+        if self.get_hashed(hashed_key, |_| true).is_none() {
+            // cached whatever the admission policy thinks, to be returned
+            let admission = self.queues.admission.take();
+            self.put_hashed(
+                hashed_key,
+                weight,
+                f(),
+                M::default(),
+                USES_CAP,
+                |_, _, _| {},
+            );
+            self.queues.admission = admission;
+        }
+        // an entry is cached alone rather than evicted when it outweighs the whole limit
+        let entry = self.cache.get(&hashed_key).expect("just put");
+        &entry.data.0
+    }
+
+    /// The hash `key` is cached under, handed to `on_evict` by [`Self::put_evicting`]
+    pub(crate) fn key_hash<Q: Hash + ?Sized>(&self, key: &Q) -> Key {
+        hash_key(&self.hasher, key)
+    }
+
+    /// Remove a key from the cache, returns its data if it was cached.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.remove_with_meta(key).map(|(data, _)| data)
+    }
+
+    /// Same as [`Self::remove`], along with the metadata of the entry
+    pub fn remove_with_meta<Q>(&mut self, key: &Q) -> Option<(T, M)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hashed_key = hash_key(&self.hasher, key);
+        self.check_timers(hashed_key);
+        let entry = self.queues.remove(hashed_key, &mut self.cache)?;
+        if let Some(timers) = &mut self.timers {
+            timers.deadlines.remove(&hashed_key);
+        }
+        self.stats.record_removal();
+        if let Some(events) = &self.events {
+            events.on_evict(hashed_key, entry.weight, RemovalCause::Explicit);
+        }
+        Some(entry.data)
+    }
+
+    /// Change the weight limit without dropping the cache, a smaller one evicts down to it
+    /// right away, handing the evicted entries to `on_evict` like [`Self::put_evicting`]
+    pub fn set_weight_limit(
+        &mut self,
+        total_weight_limit: usize,
+        mut on_evict: impl FnMut(Key, T),
+    ) {
+        let idle = self.timers.as_ref().and_then(Timers::idle_ticks);
+        self.queues.idle_before = idle.and_then(|(_, before)| before);
+        let mut evicted = std::mem::take(&mut self.evicted);
+        self.queues
+            .resize(total_weight_limit, &mut self.cache, &mut evicted);
+        self.stats.record_evictions(evicted.len() as u64);
+        for entry in evicted.drain(..) {
+            self.notify_eviction(
+                entry.key,
+                entry.weight,
+                &entry.data.0,
+                RemovalCause::Resized,
+            );
+            if let Some(timers) = &mut self.timers {
+                timers.deadlines.remove(&entry.key);
+            }
+            on_evict(entry.key, entry.data.0);
+        }
+        self.evicted = evicted;
+    }
+
+    pub fn weight_limit(&self) -> usize {
+        self.queues.weight_limit()
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total weight of the cached entries, at most [`Self::weight_limit`]
+    pub fn total_weight(&self) -> usize {
+        self.queues.weight()
+    }
+
+    /// Expected number of entries the cache was sized for, it holds more if they are light enough
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the share of the weight limit given to the small queue, where new entries wait
+    /// to prove themselves, [`DEFAULT_SMALL_QUEUE_PERCENT`] at first. Capped at 100
+    pub fn set_small_queue_percent(&mut self, percent: u8) {
+        self.queues.set_small_queue_percent(percent);
+    }
+
+    pub fn small_queue_percent(&self) -> u8 {
+        self.queues.small_queue_percent()
+    }
+
+    /// Cap the uses counted for the entries put from now on, 1 to 3 (the default): an entry in
+    /// the main queue survives as many passes of eviction as it has uses
+    pub fn set_uses_cap(&mut self, cap: u8) {
+        self.queues.set_uses_cap(cap);
+    }
+
+    pub fn uses_cap(&self) -> u8 {
+        self.queues.uses_cap
+    }
+
+    /// Promote the entries leaving the small queue with more uses than `threshold` to the main
+    /// one, [`DEFAULT_PROMOTION_THRESHOLD`] at first. Higher demotes more entries quickly, one
+    /// at or over the uses cap promotes none
+    pub fn set_promotion_threshold(&mut self, threshold: u8) {
+        self.queues.set_promotion_threshold(threshold);
+    }
+
+    pub fn promotion_threshold(&self) -> u8 {
+        self.queues.promotion_threshold
+    }
+
+    /// Remember as many keys evicted from the small queue as the capacity, and admit those put
+    /// again straight to the main queue: S3-FIFO's ghost queue, on at first. Off, the keys the
+    /// TinyLFU sketch counted more than once before are admitted there instead
+    pub fn set_ghost_queue(&mut self, enabled: bool) {
+        self.queues
+            .set_ghost_queue(enabled.then_some(self.capacity));
+    }
+
+    pub fn has_ghost_queue(&self) -> bool {
+        self.queues.ghost.is_some()
+    }
+
+    /// The cached entries in the order they would be evicted if nothing else happened, the next
+    /// to go first. A preview of what gets dropped next, or the most retained entries last for
+    /// persistence to save first. O(n log n)
+    pub fn iter_eviction_order(&self) -> impl Iterator<Item = (Key, &T)> + '_ {
+        self.queues
+            .eviction_order(&self.cache)
+            .into_iter()
+            .filter_map(|key| self.cache.get(&key).map(|entry| (key, &entry.data.0)))
+    }
+
+    /// The keys read or put the most and their approximate counts, hottest first: what the cache
+    /// is serving. Empty unless tracked with
+    /// [`EstimatorConfig::hot_keys`](crate::tinyufo::EstimatorConfig::hot_keys)
+    pub fn hot_keys(&self) -> Vec<(Key, u64)> {
+        self.queues.estimator.hot_keys()
+    }
+
+    /// Panic unless the cache is consistent: every entry queued once at least, the weight of
+    /// each queue the sum of its entries' and under the limit. O(n), for tests and fuzzing
+    #[doc(hidden)]
+    pub fn check_invariants(&self) {
+        self.queues.check(&self.cache);
+    }
+
+    /// Snapshot of the cache statistics
+    pub fn stats(&self) -> CacheStats {
+        self.stats.snapshot(self.cache.len(), self.queues.weight())
     }
 }
 
@@ -326,11 +1528,393 @@ impl<K: Hash, T: Clone> TinyUFO<K, T> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_entry_state() {
+        let entry = Entry::new(());
+        assert_eq!(entry.uses(), 1);
+        assert_eq!(entry.incr_uses(), 2);
+        assert_eq!(entry.incr_uses(), 3);
+        assert_eq!(entry.incr_uses(), USES_CAP);
+        assert!(!entry.is_main());
+
+        entry.move_to_main();
+        assert!(entry.is_main());
+        assert_eq!(entry.uses(), USES_CAP);
+        assert_eq!(entry.decr_uses(), 3);
+        assert_eq!(entry.decr_uses(), 2);
+        assert_eq!(entry.decr_uses(), 1);
+        assert_eq!(entry.decr_uses(), 0);
+        assert!(entry.is_main());
+
+        entry.expire();
+        assert!(entry.is_expired());
+        entry.renew();
+        assert!(!entry.is_expired());
+        assert!(entry.is_main());
+        assert_eq!(entry.uses(), 0);
+
+        let capped = Entry::new(());
+        capped.incr_uses();
+        capped.incr_uses();
+        capped.set_uses_cap(2);
+        assert_eq!(capped.uses(), 2);
+        assert_eq!(capped.incr_uses(), 2);
+        capped.set_uses_cap(1);
+        assert_eq!(capped.incr_uses(), 1);
+        capped.set_uses_cap(USES_CAP);
+        assert_eq!(capped.incr_uses(), 2);
+        assert!(!capped.is_main() && !capped.is_expired());
+    }
+
     #[test]
     fn test_sanity() {
-        let items = 100;
         let mut cache = TinyUFO::new(100, 10);
         cache.put(1, 1, 1);
         cache.put(2, 2, 1);
     }
+
+    #[test]
+    fn test_put_replaces() {
+        let mut cache = TinyUFO::new(100, 10);
+        cache.put(1, 1, "a");
+        cache.put(1, 5, "b");
+        assert_eq!(cache.get(&1), Some(&"b"));
+
+        let stats = cache.stats();
+        assert_eq!((stats.inserts, stats.updates), (1, 1));
+        assert_eq!((stats.entries, stats.weight), (1, 5));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut cache = TinyUFO::new(3, 10);
+        cache.put(1, 1, 1);
+        cache.put(2, 1, 2);
+        assert_eq!(cache.remove(&1), Some(1));
+        assert_eq!(cache.remove(&1), None);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.stats().weight, 1);
+
+        // the stale queue slot of 1 must not confuse eviction
+        cache.put(1, 1, 10);
+        let mut evicted = vec![];
+        for i in 3..10 {
+            cache.put_evicting(i, 1, i, |_, data| evicted.push(data));
+        }
+        assert!(cache.stats().weight <= 3);
+        assert_eq!(evicted.len() + cache.stats().entries, 9);
+    }
+
+    #[test]
+    fn test_mixed_weights() {
+        let mut cache = TinyUFO::new(1000, 100);
+        let mut evicted = 0;
+        for i in 0..500u64 {
+            let weight = (i % 7 * 50 + 1) as Weight;
+            for (key, data) in cache.put_returning_evicted(i, weight, i) {
+                assert_eq!(key, cache.key_hash(&data));
+                evicted += 1;
+            }
+        }
+        let stats = cache.stats();
+        assert!(stats.weight <= 1000);
+        assert_eq!(evicted + stats.entries, 500);
+    }
+
+    #[test]
+    fn test_peek_and_get_mut() {
+        let mut cache = TinyUFO::new(10, 10);
+        cache.put(1, 1, 1);
+        assert_eq!(cache.peek(&1), Some(&1));
+        assert_eq!(cache.stats().hits, 0);
+        *cache.get_mut(&1).unwrap() += 1;
+        assert_eq!(cache.get(&1), Some(&2));
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[test]
+    fn test_set_weight_limit() {
+        let mut cache = TinyUFO::new(100, 100);
+        for i in 0..100u64 {
+            cache.put(i, 1, i);
+        }
+        assert_eq!(cache.stats().entries, 100);
+
+        let mut evicted = 0;
+        cache.set_weight_limit(40, |_, _| evicted += 1);
+        let stats = cache.stats();
+        assert_eq!((cache.weight_limit(), stats.weight, evicted), (40, 40, 60));
+        assert_eq!(stats.evictions, 60);
+
+        // growing keeps everything
+        cache.set_weight_limit(200, |_, _| unreachable!());
+        for i in 100..200u64 {
+            cache.put(i, 1, i);
+        }
+        assert_eq!(cache.stats().entries, 140);
+
+        cache.set_small_queue_percent(150);
+        assert_eq!(cache.small_queue_percent(), 100);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut cache: TinyUFO<u64, u64, &str> = TinyUFO::with_metadata(&CacheConfig::new(2, 2));
+        cache.put_with_meta(1, 1, 10, "one");
+        cache.put_with_meta(2, 1, 20, "two");
+        assert_eq!(cache.get_with_meta(&1), Some((&10, &"one")));
+        assert_eq!(cache.get(&2), Some(&20));
+
+        let mut evicted = vec![];
+        for i in 3..6u64 {
+            cache.put_with_meta_evicting(i, 1, i, "new", |key, _, meta| evicted.push((key, meta)));
+        }
+        assert!(evicted.iter().any(|(_, meta)| *meta != "new"));
+        assert_eq!(cache.remove_with_meta(&5), Some((5, "new")));
+    }
+
+    #[test]
+    fn test_iter_eviction_order() {
+        let mut cache = TinyUFO::new(100, 100);
+        for i in 0..100u64 {
+            cache.put(i, 1, i);
+        }
+        // 0..5 used enough to be promoted, then the small queue overflows into main
+        for i in 0..5 {
+            cache.get(&i);
+            cache.get(&i);
+        }
+        cache.remove(&50);
+        cache.put(50, 1, 50);
+
+        let order: Vec<_> = cache.iter_eviction_order().map(|(_, data)| *data).collect();
+        assert_eq!(order.len(), 100);
+        assert_eq!(order[..3], [5, 6, 7]);
+        // the re-put key is back where its stale copy sits
+        assert_eq!(order[45], 50);
+        assert_eq!(order[95..], [0, 1, 2, 3, 4]);
+
+        // and eviction follows it
+        let mut evicted = vec![];
+        cache.set_weight_limit(97, |_, data| evicted.push(data));
+        assert_eq!(evicted, [5, 6, 7]);
+    }
+
+    #[test]
+    fn test_introspection() {
+        let mut cache = TinyUFO::new(10, 5);
+        assert!(cache.is_empty());
+        cache.put(1, 3, 1);
+        cache.put(2, 4, 2);
+        assert_eq!((cache.len(), cache.total_weight()), (2, 7));
+        assert_eq!((cache.weight_limit(), cache.capacity()), (10, 5));
+        cache.remove(&1);
+        assert_eq!((cache.len(), cache.total_weight()), (1, 4));
+        assert!(!cache.is_empty());
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let mut cache = TinyUFO::new(10, 10);
+        assert_eq!(*cache.get_or_insert_with(1, 1, || 1), 1);
+        assert_eq!(*cache.get_or_insert_with(1, 1, || unreachable!()), 1);
+        // heavier than the limit, still returned
+        assert_eq!(*cache.get_or_insert_with(2, 20, || 2), 2);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 1));
+    }
+
+    #[test]
+    fn test_promotion() {
+        // 1 is read once before the small queue overflows
+        let survives = |configure: fn(&mut TinyUFO<u64, u64>)| {
+            let mut cache = TinyUFO::new(5, 5);
+            configure(&mut cache);
+            cache.put(1, 1, 1);
+            cache.get(&1);
+            for i in 2..=6 {
+                cache.put(i, 1, i);
+            }
+            cache.peek(&1).is_some()
+        };
+        assert!(survives(|_| {}));
+        assert!(!survives(|cache| cache.set_promotion_threshold(2)));
+        assert!(!survives(|cache| cache.set_uses_cap(1)));
+        assert!(survives(|cache| {
+            cache.set_uses_cap(2);
+            cache.set_promotion_threshold(1);
+        }));
+    }
+
+    #[test]
+    fn test_ghost_queue() {
+        // 1 is evicted from the small queue unread, then put again
+        let to_main = |ghost_queue| {
+            let mut cache = TinyUFO::new(5, 5);
+            cache.set_ghost_queue(ghost_queue);
+            for i in 1..=6 {
+                cache.put(i, 1, i);
+            }
+            assert_eq!(cache.peek(&1), None);
+            cache.put(1, 1, 1);
+            let key = cache.key_hash(&1);
+            cache.queues.main.contains(&key)
+        };
+        assert!(to_main(true));
+        // the sketch only counted its first put
+        assert!(!to_main(false));
+
+        // as many keys as the capacity
+        let mut cache = TinyUFO::new(5, 5);
+        for i in 0..20 {
+            cache.put(i, 1, i);
+        }
+        let ghost = cache.queues.ghost.as_ref().unwrap();
+        assert_eq!((ghost.queue.len(), ghost.keys.len()), (5, 5));
+    }
+
+    #[test]
+    fn test_weigher() {
+        let mut cache = TinyUFO::new(10, 10).with_weigher(|_, data: &String| data.len() as Weight);
+        cache.insert(1, "abcd".to_string());
+        cache.insert(2, "abcde".to_string());
+        assert_eq!((cache.len(), cache.total_weight()), (2, 9));
+        // replacing reweighs
+        cache.insert(1, "a".to_string());
+        assert_eq!(cache.total_weight(), 6);
+
+        let mut cache = TinyUFO::new(10, 10);
+        cache.insert(1, ());
+        assert_eq!(cache.total_weight(), 1);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let mut cache = TinyUFO::new(10, 10).with_clock(clock.clone());
+        let secs = Duration::from_secs;
+        cache.put_with_ttl(1, 1, 1, secs(5));
+        cache.put_with_ttl(2, 1, 2, secs(5));
+        cache.put_with_ttl(3, 1, 3, secs(2));
+        clock.advance(secs(4));
+        assert_eq!(cache.get(&1), Some(&1));
+        // put again without a time to live, kept for good
+        cache.put(2, 1, 2);
+        assert_eq!(cache.stats().expirations, 1);
+
+        clock.advance(secs(1));
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+        let stats = cache.stats();
+        assert_eq!((stats.expirations, stats.entries, stats.weight), (2, 1, 1));
+
+        cache.put_with_ttl(4, 1, 4, secs(1));
+        clock.advance(secs(2));
+        assert_eq!(cache.purge_expired(), 1);
+        assert_eq!(cache.stats().entries, 1);
+
+        // peeked once expired, the hot entry goes first
+        let mut cache = TinyUFO::new(2, 10).with_clock(clock.clone());
+        cache.put_with_ttl(1, 1, 1, secs(1));
+        cache.get(&1);
+        cache.get(&1);
+        cache.put(2, 1, 2);
+        clock.advance(secs(2));
+        assert_eq!(cache.peek(&1), None);
+        let order: Vec<_> = cache.iter_eviction_order().map(|(_, data)| *data).collect();
+        assert_eq!(order, [1, 2]);
+        // put again, a hit
+        cache.put_with_ttl(1, 1, 10, secs(5));
+        assert_eq!(cache.get(&1), Some(&10));
+        assert_eq!(cache.len(), 2);
+        let mut cache = TinyUFO::new(2, 10).with_clock(clock.clone());
+        cache.put_with_ttl(1, 1, 1, secs(1));
+        clock.advance(secs(2));
+        assert_eq!(cache.peek(&1), None);
+        cache.put(1, 1, 10);
+        assert_eq!(cache.get(&1), Some(&10));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_tti() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let secs = Duration::from_secs;
+        let mut cache = TinyUFO::new(10, 10)
+            .expire_after_access(secs(10))
+            .with_clock(clock.clone());
+        cache.put(1, 1, 1);
+        cache.put(2, 1, 2);
+        clock.advance(secs(6));
+        assert_eq!(cache.get(&1), Some(&1));
+        clock.advance(secs(5));
+        assert_eq!(cache.peek(&2), None);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.stats().expirations, 1);
+
+        // the more used entry goes first once idle
+        let mut cache = TinyUFO::new(2, 10)
+            .with_clock(clock.clone())
+            .expire_after_access(secs(10));
+        cache.put(1, 1, 1);
+        cache.get(&1);
+        cache.get(&1);
+        clock.advance(secs(5));
+        cache.put(2, 1, 2);
+        cache.get(&2);
+        clock.advance(secs(6));
+        let mut evicted = vec![];
+        cache.put_evicting(3, 1, 3, |_, data| evicted.push(data));
+        assert_eq!(evicted, [1]);
+
+        // peeked once idle then put again, a hit
+        let mut cache = TinyUFO::new(10, 10)
+            .expire_after_access(secs(10))
+            .with_clock(clock.clone());
+        cache.put(1, 1, 1);
+        clock.advance(secs(11));
+        assert_eq!(cache.peek(&1), None);
+        cache.put(1, 1, 99);
+        assert_eq!(cache.get(&1), Some(&99));
+    }
+
+    #[test]
+    fn test_hasher() {
+        let config = CacheConfig::new(10, 10);
+        let mut cache: TinyUFO<&str, u64, (), _> =
+            TinyUFO::with_hasher(&config, std::collections::hash_map::RandomState::new());
+        cache.put("a", 1, 1);
+        cache.put("b", 1, 2);
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.remove("b"), Some(2));
+        assert_eq!(crate::Cache::get(&mut cache, &"a"), Some(1));
+    }
+
+    #[test]
+    fn test_non_clone_values() {
+        struct Payload(Vec<u8>);
+
+        let mut cache = TinyUFO::new(2, 2);
+        cache.put(1, 1, Payload(vec![1]));
+        assert_eq!(cache.get(&1).map(|p| p.0[0]), Some(1));
+        let mut evicted = vec![];
+        for i in 2..5u8 {
+            cache.put_evicting(i as u64, 1, Payload(vec![i]), |_, p| evicted.push(p.0));
+        }
+        assert!(!evicted.is_empty());
+        assert!(cache.remove(&4).is_some());
+    }
+
+    #[cfg(feature = "strict-checks")]
+    #[test]
+    #[should_panic(expected = "queue weight 0 short of the weight 5")]
+    fn test_strict_checks() {
+        let mut cache = TinyUFO::new(10, 10);
+        cache.put(1, 5, 1);
+        // drift the accounting the way a bug would
+        cache.queues.small_weight.store(0, Relaxed);
+        cache.remove(&1);
+    }
 }
@@ -1 +1,45 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+/// The hash a cache keeps of each key, 128 bits with the `key128` feature
+#[cfg(not(feature = "key128"))]
 pub type Key = u64;
+#[cfg(feature = "key128")]
+pub type Key = u128;
+pub type Weight = u64;
+
+/// Weight of an entry from its key and value, see
+/// [`TinyUFO::with_weigher`](crate::tinyufo::TinyUFO::with_weigher)
+pub type Weigher<K, T> = Arc<dyn Fn(&K, &T) -> Weight + Send + Sync>;
+
+/// `weight` in the `usize` of the weight limits and counters, saturating on 32 bit targets
+pub(crate) fn units(weight: Weight) -> usize {
+    usize::try_from(weight).unwrap_or(usize::MAX)
+}
+
+/// The [`Key`] of `key`
+#[cfg(not(feature = "key128"))]
+pub(crate) fn hash_key<S: BuildHasher, Q: Hash + ?Sized>(hasher: &S, key: &Q) -> Key {
+    hasher.hash_one(key)
+}
+
+/// The [`Key`] of `key`: two hashes of `hasher`, the second one salted
+#[cfg(feature = "key128")]
+pub(crate) fn hash_key<S: BuildHasher, Q: Hash + ?Sized>(hasher: &S, key: &Q) -> Key {
+    use std::hash::Hasher;
+    let mut salted = hasher.build_hasher();
+    salted.write_u8(0x5a);
+    key.hash(&mut salted);
+    (hasher.hash_one(key) as Key) << 64 | salted.finish() as Key
+}
+
+/// `key` folded to 64 bits, for the arithmetic mixing hashes
+#[cfg(not(feature = "key128"))]
+pub(crate) fn fold_key(key: Key) -> u64 {
+    key
+}
+
+#[cfg(feature = "key128")]
+pub(crate) fn fold_key(key: Key) -> u64 {
+    (key >> 64) as u64 ^ key as u64
+}
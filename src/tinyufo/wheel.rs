@@ -0,0 +1,165 @@
+use crate::tinyufo::types::Key;
+use std::time::Duration;
+
+/// Resolution of the wheel: an entry expires on the first tick at or after its deadline
+pub(crate) const TICK: Duration = Duration::from_secs(1);
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+// 64 ticks, ~68 minutes, ~72 hours, ~194 days. Later deadlines wait in the last level and
+// cascade down again when their slot comes round
+const LEVELS: usize = 4;
+
+/// Hierarchical timing wheel of the keys with a time to live.
+///
+/// Scheduling and expiring are O(1) per key, plus one cascade each time a key goes down a level.
+/// A key is never unscheduled: the wheel hands back stale ones too, the caller checks them
+/// against the deadline it keeps.
+pub(crate) struct TimerWheel {
+    // level 0 slots are one tick wide, level 1 slots a full turn of level 0, and so on
+    levels: Vec<Vec<Vec<(Key, u64)>>>,
+    // the last tick processed
+    now: u64,
+    len: usize,
+}
+
+impl TimerWheel {
+    /// An empty wheel whose ticks count from `now`
+    pub(crate) fn new(now: Duration) -> Self {
+        Self {
+            levels: vec![vec![Vec::new(); SLOTS]; LEVELS],
+            now: ticks(now),
+            len: 0,
+        }
+    }
+
+    /// Have `key` handed back by the [`Self::advance`] reaching `deadline`
+    pub(crate) fn schedule(&mut self, key: Key, deadline: Duration) {
+        // the current tick is already processed
+        let deadline = ticks_ceil(deadline).max(self.now + 1);
+        self.insert(key, deadline);
+        self.len += 1;
+    }
+
+    fn insert(&mut self, key: Key, deadline: u64) {
+        let delta = deadline - self.now;
+        let level = (0..LEVELS)
+            .find(|&level| delta < 1 << (SLOT_BITS * (level as u32 + 1)))
+            .unwrap_or(LEVELS - 1);
+        let slot = (deadline >> (SLOT_BITS * level as u32)) as usize % SLOTS;
+        self.levels[level][slot].push((key, deadline));
+    }
+
+    /// Move the wheel to `now`, appending the keys whose deadline passed to `due`
+    pub(crate) fn advance(&mut self, now: Duration, due: &mut Vec<Key>) {
+        let target = ticks(now);
+        while self.now < target {
+            if self.len == 0 {
+                self.now = target;
+                return;
+            }
+            self.now = self.next_due(target);
+            // a slot of an upper level comes round when the levels below it wrap
+            for level in (1..LEVELS).rev() {
+                let span = SLOT_BITS * level as u32;
+                if self.now & ((1 << span) - 1) == 0 {
+                    let slot = (self.now >> span) as usize % SLOTS;
+                    for (key, deadline) in std::mem::take(&mut self.levels[level][slot]) {
+                        if deadline <= self.now {
+                            due.push(key);
+                            self.len -= 1;
+                        } else {
+                            self.insert(key, deadline);
+                        }
+                    }
+                }
+            }
+            let slot = self.now as usize % SLOTS;
+            let fired = std::mem::take(&mut self.levels[0][slot]);
+            self.len -= fired.len();
+            due.extend(fired.into_iter().map(|(key, _)| key));
+        }
+    }
+
+    /// The first tick after the current one whose slot, of any level, holds keys, `target` if
+    /// it comes first. The ticks in between have nothing to do, they are skipped
+    fn next_due(&self, target: u64) -> u64 {
+        let mut next = target;
+        for (level, slots) in self.levels.iter().enumerate() {
+            let span = SLOT_BITS * level as u32;
+            // the ticks this level's slots come round at
+            let turn = self.now >> span;
+            let due = (1..=SLOTS as u64)
+                .map(|slots_ahead| (turn + slots_ahead) << span)
+                .take_while(|&tick| tick < next)
+                .find(|&tick| !slots[(tick >> span) as usize % SLOTS].is_empty());
+            if let Some(tick) = due {
+                next = tick;
+            }
+        }
+        next
+    }
+}
+
+/// Whole ticks in `time`
+pub(crate) fn ticks(time: Duration) -> u64 {
+    (time.as_nanos() / TICK.as_nanos()) as u64
+}
+
+/// Ticks in `time`, rounded up
+pub(crate) fn ticks_ceil(time: Duration) -> u64 {
+    time.as_nanos().div_ceil(TICK.as_nanos()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wheel() {
+        let mut wheel = TimerWheel::new(Duration::ZERO);
+        let secs = Duration::from_secs;
+        // one per level
+        for (key, deadline) in [(1, 5), (2, 100), (3, 10_000), (4, 300_000)] {
+            wheel.schedule(key, secs(deadline));
+        }
+        wheel.schedule(6, Duration::from_millis(4500));
+        assert_eq!(wheel.len, 5);
+
+        let mut due = vec![];
+        wheel.advance(secs(4), &mut due);
+        assert!(due.is_empty());
+        for (now, expected) in [
+            (5, vec![1, 6]),
+            (99, vec![]),
+            (100, vec![2]),
+            (10_000, vec![3]),
+            (299_999, vec![]),
+            (300_000, vec![4]),
+        ] {
+            wheel.advance(secs(now), &mut due);
+            due.sort();
+            assert_eq!(due, expected, "at {now}s");
+            due.clear();
+        }
+        assert_eq!(wheel.len, 0);
+
+        // a single advance far ahead fires every level
+        for (key, deadline) in [(1, 5), (2, 100), (3, 10_000), (4, 300_000)] {
+            wheel.schedule(key, secs(300_000 + deadline));
+        }
+        wheel.advance(secs(300_000 + 10_000), &mut due);
+        due.sort();
+        assert_eq!(due, [1, 2, 3]);
+        due.clear();
+        wheel.advance(secs(600_000), &mut due);
+        assert_eq!(due, [4]);
+        due.clear();
+
+        // an empty wheel jumps, a past deadline fires on the next tick
+        wheel.advance(secs(30_000_000), &mut due);
+        wheel.schedule(7, secs(1));
+        wheel.advance(secs(30_000_001), &mut due);
+        assert_eq!(due, [7]);
+    }
+}
@@ -0,0 +1,272 @@
+//! Seeded synthetic workloads, so that benches, trace replays and fuzzing run the same requests
+//! on every machine.
+//!
+//! ```
+//! use cachez::workload::{Keys, Op, Sizes, Workload};
+//!
+//! let workload = Workload {
+//!     keys: Keys::Zipf(1.1),
+//!     read_ratio: 0.9,
+//!     sizes: Sizes::Pareto { min: 100, shape: 1.5 },
+//!     ..Workload::new(10_000)
+//! };
+//! let requests = workload.generate(1000);
+//! assert_eq!(requests, workload.generate(1000));
+//! assert!(requests.iter().all(|request| request.key < 10_000));
+//! assert!(requests.iter().any(|request| request.op == Op::Set));
+//! ```
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// A read, filling the cache on a miss
+    Get,
+    Set,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    pub key: u64,
+    /// Size of the object in bytes
+    pub size: u32,
+    pub op: Op,
+}
+
+/// Popularity of the keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keys {
+    Uniform,
+    /// Zipf distributed with this exponent: 0 is uniform, around 1 is typical of web caches,
+    /// higher is more skewed
+    Zipf(f64),
+    /// Every key in turn, the classic LRU killer
+    Sequential,
+}
+
+/// Sizes of the objects, each key keeping its size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sizes {
+    Fixed(u32),
+    /// Uniform over `min..=max`
+    Uniform {
+        min: u32,
+        max: u32,
+    },
+    /// Heavy tailed from `min`, the lower `shape` the heavier
+    Pareto {
+        min: u32,
+        shape: f64,
+    },
+}
+
+/// A one hit scan over keys never requested otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanBursts {
+    /// Requests between the start of two bursts
+    pub every: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    /// Keys are drawn from `0..items`, scans go above
+    pub items: u64,
+    pub keys: Keys,
+    /// Share of reads, the other requests are writes
+    pub read_ratio: f64,
+    pub sizes: Sizes,
+    /// Move the popular keys somewhere else every that many requests
+    pub hotspot_shift: Option<usize>,
+    pub scans: Option<ScanBursts>,
+    pub seed: u64,
+}
+
+impl Workload {
+    /// Uniform reads of 1 byte objects over `items` keys
+    pub fn new(items: u64) -> Self {
+        Self {
+            items,
+            keys: Keys::Uniform,
+            read_ratio: 1.0,
+            sizes: Sizes::Fixed(1),
+            hotspot_shift: None,
+            scans: None,
+            seed: 42,
+        }
+    }
+
+    /// `len` requests of the workload, the same ones for the same workload
+    pub fn generate(&self, len: usize) -> Vec<Request> {
+        let items = self.items.max(1);
+        let mut rng = fastrand::Rng::with_seed(self.seed);
+        let zipf = match self.keys {
+            Keys::Zipf(skew) => Some(ZipfSampler::new(items, skew)),
+            _ => None,
+        };
+        let mut offset = 0;
+        let mut scanned = items;
+        let mut requests = Vec::with_capacity(len);
+        while requests.len() < len {
+            let i = requests.len();
+            if let Some(scans) = self.scans.filter(|scans| scans.every > 0) {
+                if i > 0 && i % scans.every == 0 {
+                    for _ in 0..scans.len.min(len - i) {
+                        requests.push(self.request(scanned, Op::Get));
+                        scanned += 1;
+                    }
+                    continue;
+                }
+            }
+            if let Some(every) = self.hotspot_shift.filter(|every| *every > 0) {
+                if i > 0 && i % every == 0 {
+                    offset = rng.u64(0..items);
+                }
+            }
+            let rank = match (&self.keys, &zipf) {
+                (_, Some(zipf)) => zipf.sample(&mut rng),
+                (Keys::Sequential, _) => i as u64 % items,
+                _ => rng.u64(0..items),
+            };
+            let op = if rng.f64() < self.read_ratio {
+                Op::Get
+            } else {
+                Op::Set
+            };
+            requests.push(self.request((rank + offset) % items, op));
+        }
+        requests
+    }
+
+    /// The keys of [`generate`](Self::generate)
+    pub fn keys(&self, len: usize) -> Vec<u64> {
+        self.generate(len)
+            .into_iter()
+            .map(|request| request.key)
+            .collect()
+    }
+
+    fn request(&self, key: u64, op: Op) -> Request {
+        Request {
+            key,
+            size: self.size(key),
+            op,
+        }
+    }
+
+    fn size(&self, key: u64) -> u32 {
+        let rng = || fastrand::Rng::with_seed(self.seed ^ key.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        match self.sizes {
+            Sizes::Fixed(size) => size,
+            Sizes::Uniform { min, max } => rng().u32(min..=max.max(min)),
+            Sizes::Pareto { min, shape } => {
+                let uniform = 1.0 - rng().f64();
+                let size = min.max(1) as f64 / uniform.powf(1.0 / shape.max(f64::EPSILON));
+                size.min(u32::MAX as f64) as u32
+            }
+        }
+    }
+}
+
+/// Zipf sampling by binary search over the precomputed CDF
+struct ZipfSampler {
+    cdf: Vec<f64>,
+}
+
+impl ZipfSampler {
+    fn new(items: u64, skew: f64) -> Self {
+        let mut sum = 0.0;
+        let cdf = (1..=items)
+            .map(|rank| {
+                sum += 1.0 / (rank as f64).powf(skew);
+                sum
+            })
+            .collect();
+        Self { cdf }
+    }
+
+    fn sample(&self, rng: &mut fastrand::Rng) -> u64 {
+        let total = self.cdf.last().copied().unwrap_or_default();
+        let target = rng.f64() * total;
+        let rank = self.cdf.partition_point(|&p| p < target);
+        rank.min(self.cdf.len() - 1) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hottest(requests: &[Request]) -> u64 {
+        let mut counts = std::collections::HashMap::new();
+        for request in requests {
+            *counts.entry(request.key).or_insert(0) += 1;
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_workload() {
+        let zipf = Workload {
+            keys: Keys::Zipf(1.2),
+            ..Workload::new(1000)
+        };
+        let requests = zipf.generate(10_000);
+        assert_eq!(hottest(&requests), 0);
+        let head = requests.iter().filter(|request| request.key < 10).count();
+        assert!(head > 5000, "{head}");
+        assert_ne!(
+            requests,
+            Workload {
+                seed: 7,
+                ..zipf.clone()
+            }
+            .generate(10_000)
+        );
+
+        let shifted = Workload {
+            hotspot_shift: Some(5000),
+            ..zipf.clone()
+        }
+        .generate(10_000);
+        assert_eq!(hottest(&shifted[..5000]), 0);
+        assert_ne!(hottest(&shifted[5000..]), 0);
+
+        let sequential = Workload {
+            keys: Keys::Sequential,
+            scans: Some(ScanBursts { every: 4, len: 2 }),
+            ..Workload::new(3)
+        };
+        assert_eq!(sequential.keys(8), vec![0, 1, 2, 0, 3, 4, 0, 1]);
+    }
+
+    #[test]
+    fn test_sizes_and_mix() {
+        let workload = Workload {
+            read_ratio: 0.75,
+            sizes: Sizes::Uniform { min: 10, max: 20 },
+            ..Workload::new(100)
+        };
+        let requests = workload.generate(10_000);
+        let reads = requests.iter().filter(|r| r.op == Op::Get).count();
+        assert!((7000..8000).contains(&reads), "{reads}");
+        for request in &requests {
+            assert!((10..=20).contains(&request.size));
+            assert_eq!(request.size, workload.size(request.key));
+        }
+
+        let pareto = Workload {
+            sizes: Sizes::Pareto {
+                min: 100,
+                shape: 1.2,
+            },
+            ..Workload::new(10_000)
+        };
+        let sizes: Vec<_> = (0..10_000).map(|key| pareto.size(key)).collect();
+        assert!(sizes.iter().all(|size| *size >= 100));
+        assert!(sizes.iter().any(|size| *size > 10_000));
+    }
+}
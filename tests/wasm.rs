@@ -0,0 +1,35 @@
+//! Smoke test for the wasm32 targets.
+//!
+//! wasm32-unknown-unknown: `wasm-pack test --node` (or `--headless --chrome`)
+//! wasm32-wasip1: `cargo test --target wasm32-wasip1` with a wasi runner such as wasmtime
+#![cfg(target_arch = "wasm32")]
+
+use cachez::clock::{Clock, ManualClock};
+use cachez::tinyufo::{Estimator, TinyUFO};
+use std::time::Duration;
+#[cfg(target_os = "unknown")]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+#[test]
+fn test_cache() {
+    let mut cache = TinyUFO::new(10, 10);
+    for i in 0..100u32 {
+        cache.put(i, 1, i);
+    }
+    cache.put(1000, 1, 1000);
+    assert_eq!(cache.get(&1000), Some(&1000));
+}
+
+#[test]
+fn test_estimator() {
+    let mut estimator = Estimator::new_optimal(64);
+    estimator.incr(1);
+    assert_eq!(estimator.get(1), 1);
+}
+
+#[test]
+fn test_clock() {
+    let clock = ManualClock::new();
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(clock.now(), Duration::from_secs(1));
+}